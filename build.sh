@@ -116,6 +116,7 @@ elif [[ ("$(uname -s)" == "Linux") ]]; then
         libtalpid_openvpn_plugin.so
         mullvad-setup
         mullvad-exclude
+        mullvad-netns
     )
 elif [[ ("$(uname -s)" == "MINGW"*) ]]; then
     BINARIES=(
@@ -2,6 +2,7 @@
 set -eu
 
 chmod u+s "/usr/bin/mullvad-exclude"
+chmod u+s "/usr/bin/mullvad-netns"
 
 if which systemctl &> /dev/null; then
     systemctl enable "/opt/Mullvad VPN/resources/mullvad-daemon.service"
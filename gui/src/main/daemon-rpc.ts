@@ -396,6 +396,11 @@ export class DaemonRpc {
     this.subscriptions.set(subscriptionId, call);
 
     call.on('data', (data: grpcTypes.DaemonEvent) => {
+      // Tunnel statistics are not displayed by the GUI
+      if (data.hasTunnelStats()) {
+        return;
+      }
+
       try {
         const daemonEvent = convertFromDaemonEvent(data);
         listener.onEvent(daemonEvent);
@@ -47,6 +47,28 @@ impl Command for Account {
                             .required(true),
                     ),
             )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("Lists the accounts stored on this device"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("switch")
+                    .about("Switches to an account stored on this device")
+                    .arg(
+                        clap::Arg::with_name("token")
+                            .help("The stored Mullvad account token to switch to")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("forget")
+                    .about("Removes a stored account from this device")
+                    .arg(
+                        clap::Arg::with_name("token")
+                            .help("The stored Mullvad account token to forget")
+                            .required(true),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -76,6 +98,14 @@ impl Command for Account {
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
             let voucher = value_t_or_exit!(matches.value_of("voucher"), String);
             self.redeem_voucher(voucher).await
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list().await
+        } else if let Some(matches) = matches.subcommand_matches("switch") {
+            let token = value_t_or_exit!(matches.value_of("token"), String);
+            self.switch(token.split_whitespace().join("")).await
+        } else if let Some(matches) = matches.subcommand_matches("forget") {
+            let token = value_t_or_exit!(matches.value_of("token"), String);
+            self.forget(token.split_whitespace().join("")).await
         } else {
             unreachable!("No account command given");
         }
@@ -121,6 +151,56 @@ impl Account {
         self.get().await
     }
 
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let accounts = rpc.list_accounts(()).await?.into_inner().accounts;
+        if accounts.is_empty() {
+            println!("No stored accounts");
+            return Ok(());
+        }
+        for account in accounts {
+            let marker = if account.active { "*" } else { " " };
+            let key = account
+                .wireguard_key
+                .map(|key| base64::encode(&key.key))
+                .unwrap_or_else(|| "no WireGuard key".to_string());
+            println!("{} {} ({})", marker, account.token, key);
+        }
+        Ok(())
+    }
+
+    async fn switch(&self, token: AccountToken) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        match rpc.switch_account(token.clone()).await {
+            Ok(_) => {
+                println!("Switched to Mullvad account \"{}\"", token);
+                Ok(())
+            }
+            Err(error) => Self::handle_unknown_account(error),
+        }
+    }
+
+    async fn forget(&self, token: AccountToken) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        match rpc.forget_account(token.clone()).await {
+            Ok(_) => {
+                println!("Mullvad account \"{}\" removed from this device", token);
+                Ok(())
+            }
+            Err(error) => Self::handle_unknown_account(error),
+        }
+    }
+
+    fn handle_unknown_account(error: mullvad_management_interface::Status) -> Result<()> {
+        match error.code() {
+            Code::Unauthenticated => {
+                eprintln!("The account is not stored on this device");
+                std::process::exit(1);
+            }
+            _ => Err(Error::RpcFailed(error)),
+        }
+    }
+
     async fn redeem_voucher(&self, mut voucher: String) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         voucher.retain(|c| c.is_alphanumeric());
@@ -0,0 +1,183 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{
+    blocked_packet::Direction, firewall_rules::Installed, BlockedPacket, Timestamp,
+};
+use std::net::IpAddr;
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect the internal state of the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("firewall").about(
+                "Display the applied firewall policy along with the intended and installed \
+                 firewall rules",
+            ))
+            .subcommand(
+                clap::SubCommand::with_name("blocked-traffic")
+                    .about(
+                        "Display statistics and recent samples of the traffic dropped by the \
+                         firewall",
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("audit")
+                            .about(
+                                "Control whether the traffic dropped by the firewall is counted \
+                                 and logged",
+                            )
+                            .arg(
+                                clap::Arg::with_name("policy")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(_matches) = matches.subcommand_matches("firewall") {
+            self.firewall().await
+        } else if let Some(matches) = matches.subcommand_matches("blocked-traffic") {
+            if let Some(audit_matches) = matches.subcommand_matches("audit") {
+                let audit = value_t_or_exit!(audit_matches.value_of("policy"), String);
+                self.set_audit_blocked_traffic(audit == "on").await
+            } else {
+                self.blocked_traffic().await
+            }
+        } else {
+            unreachable!("No debug command given");
+        }
+    }
+}
+
+impl Debug {
+    async fn firewall(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let rules = rpc
+            .get_firewall_rules(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain firewall rules", error))?
+            .into_inner();
+
+        match rules.policy {
+            Some(policy) => println!("Policy: {}", policy),
+            None => println!("Policy: none"),
+        }
+        println!("Backend: {}", rules.backend);
+        if let Some(intended_rules) = rules.intended_rules {
+            println!("\nIntended rules:\n{}", intended_rules);
+        }
+        match rules.installed {
+            Some(Installed::InstalledRules(installed_rules)) => {
+                println!("\nInstalled rules:\n{}", installed_rules)
+            }
+            Some(Installed::InstalledRulesError(error)) => {
+                println!("\nFailed to list installed rules: {}", error)
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    async fn blocked_traffic(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let stats = rpc
+            .get_blocked_traffic_stats(())
+            .await
+            .map_err(|error| {
+                Error::RpcFailedExt("Failed to obtain blocked traffic statistics", error)
+            })?
+            .into_inner();
+
+        if !stats.enabled {
+            println!(
+                "Blocked traffic auditing is disabled. Enable it with \"mullvad debug \
+                 blocked-traffic audit on\""
+            );
+            return Ok(());
+        }
+        if let Some(since) = stats.since {
+            println!("Auditing since: {}", format_timestamp(&since));
+        }
+        println!("Dropped packets: {}", stats.dropped_packets);
+        println!(
+            "Times the kernel dropped audit messages: {}",
+            stats.overruns
+        );
+        if !stats.samples.is_empty() {
+            println!("\nRecently dropped packets:");
+            for packet in &stats.samples {
+                println!("    {}", format_blocked_packet(packet));
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_audit_blocked_traffic(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_audit_blocked_traffic(enabled)
+            .await
+            .map_err(|error| {
+                Error::RpcFailedExt("Failed to change the blocked traffic audit setting", error)
+            })?;
+        println!("Changed the blocked traffic audit setting");
+        Ok(())
+    }
+}
+
+fn format_blocked_packet(packet: &BlockedPacket) -> String {
+    let time = packet
+        .time
+        .as_ref()
+        .map(format_timestamp)
+        .unwrap_or_default();
+    let direction = match Direction::from_i32(packet.direction) {
+        Some(Direction::Inbound) => "inbound",
+        Some(Direction::Outbound) => "outbound",
+        Some(Direction::Forward) => "forward",
+        None => "unknown",
+    };
+    let protocol = match packet.protocol {
+        1 | 58 => "ICMP".to_string(),
+        6 => "TCP".to_string(),
+        17 => "UDP".to_string(),
+        other => format!("protocol {}", other),
+    };
+    let mut output = format!(
+        "{} {} {} {} -> {}",
+        time,
+        direction,
+        protocol,
+        format_endpoint(&packet.source, packet.source_port),
+        format_endpoint(&packet.destination, packet.destination_port),
+    );
+    if let Some(uid) = packet.uid {
+        output.push_str(&format!(" uid {}", uid));
+    }
+    if let Some(gid) = packet.gid {
+        output.push_str(&format!(" gid {}", gid));
+    }
+    output
+}
+
+fn format_endpoint(address: &str, port: Option<u32>) -> String {
+    match (address.parse::<IpAddr>(), port) {
+        (Ok(IpAddr::V6(address)), Some(port)) => format!("[{}]:{}", address, port),
+        (_, Some(port)) => format!("{}:{}", address, port),
+        (_, None) => address.to_string(),
+    }
+}
+
+fn format_timestamp(timestamp: &Timestamp) -> String {
+    let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+    let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+    utc.with_timezone(&chrono::Local).to_string()
+}
@@ -0,0 +1,171 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types;
+use std::convert::TryFrom;
+use talpid_types::net::{AllowRule, AllowRuleDirection, TransportProtocol};
+
+pub struct Firewall;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Firewall {
+    fn name(&self) -> &'static str {
+        "firewall"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage custom firewall rules")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_allow_subcommand())
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("allow", Some(allow_matches)) => Self::handle_allow_cmd(allow_matches).await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+fn create_allow_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("allow")
+        .about(
+            "Manage traffic that is always allowed by the firewall, regardless of the \
+                tunnel state",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Add a rule")
+                .args(&create_rule_args()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove a rule")
+                .args(&create_rule_args()),
+        )
+        .subcommand(clap::SubCommand::with_name("clear").about("Remove all rules"))
+        .subcommand(clap::SubCommand::with_name("list").about("Display all rules"))
+}
+
+fn create_rule_args() -> Vec<clap::Arg<'static, 'static>> {
+    vec![
+        clap::Arg::with_name("direction")
+            .help("Whether connections are made from or to this host")
+            .required(true)
+            .possible_values(&["outbound", "inbound"]),
+        clap::Arg::with_name("network")
+            .help("The IP address or network (CIDR notation) on the remote side")
+            .required(true),
+        clap::Arg::with_name("port")
+            .help("The port that connections are made to. Requires a protocol")
+            .long("port")
+            .takes_value(true)
+            .requires("protocol"),
+        clap::Arg::with_name("protocol")
+            .long("protocol")
+            .takes_value(true)
+            .possible_values(&["udp", "tcp"]),
+        clap::Arg::with_name("cgroup")
+            .help("Only match traffic from processes in the net_cls cgroup with this class ID")
+            .long("cgroup")
+            .takes_value(true),
+        clap::Arg::with_name("uid")
+            .help("Only match traffic from sockets owned by this user")
+            .long("uid")
+            .takes_value(true),
+    ]
+}
+
+impl Firewall {
+    async fn handle_allow_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let rule = parse_rule(matches)?;
+                new_rpc_client()
+                    .await?
+                    .add_allow_rule(types::AllowRule::from(&rule))
+                    .await?;
+                println!("Added rule: {}", rule);
+                Ok(())
+            }
+            ("remove", Some(matches)) => {
+                let rule = parse_rule(matches)?;
+                new_rpc_client()
+                    .await?
+                    .remove_allow_rule(types::AllowRule::from(&rule))
+                    .await?;
+                println!("Removed rule: {}", rule);
+                Ok(())
+            }
+            ("clear", Some(_)) => {
+                new_rpc_client().await?.clear_allow_rules(()).await?;
+                println!("Removed all rules");
+                Ok(())
+            }
+            ("list", Some(_)) => {
+                let allow_rules = new_rpc_client()
+                    .await?
+                    .get_settings(())
+                    .await?
+                    .into_inner()
+                    .allow_rules;
+                println!("Allowed traffic:");
+                for rule in allow_rules {
+                    match AllowRule::try_from(rule.clone()) {
+                        Ok(rule) => println!("    {}", rule),
+                        Err(_) => println!("    {:?}", rule),
+                    }
+                }
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+fn parse_rule(matches: &clap::ArgMatches<'_>) -> Result<AllowRule> {
+    let direction = match matches.value_of("direction").unwrap() {
+        "outbound" => AllowRuleDirection::Outbound,
+        "inbound" => AllowRuleDirection::Inbound,
+        _ => unreachable!("invalid direction"),
+    };
+    let network = matches
+        .value_of("network")
+        .unwrap()
+        .parse()
+        .map_err(|_| Error::InvalidCommand("invalid IP address or network"))?;
+    let port = if matches.is_present("port") {
+        Some(value_t_or_exit!(matches.value_of("port"), u16))
+    } else {
+        None
+    };
+    let protocol = match matches.value_of("protocol") {
+        Some("udp") => Some(TransportProtocol::Udp),
+        Some("tcp") => Some(TransportProtocol::Tcp),
+        Some(_) => unreachable!("invalid protocol"),
+        None => None,
+    };
+    let cgroup = if matches.is_present("cgroup") {
+        Some(value_t_or_exit!(matches.value_of("cgroup"), u32))
+    } else {
+        None
+    };
+    let uid = if matches.is_present("uid") {
+        Some(value_t_or_exit!(matches.value_of("uid"), u32))
+    } else {
+        None
+    };
+
+    let rule = AllowRule {
+        direction,
+        network,
+        port,
+        protocol,
+        cgroup,
+        uid,
+    };
+    rule.validate()
+        .map_err(|_| Error::InvalidCommand("only outbound rules can match a cgroup or user"))?;
+    Ok(rule)
+}
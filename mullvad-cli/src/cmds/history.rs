@@ -0,0 +1,185 @@
+use crate::{format, new_rpc_client, Command, Result};
+use mullvad_management_interface::types::{
+    connection_session::EndReason, AfterDisconnect, ConnectionSession, Timestamp, TunnelType,
+};
+
+pub struct History;
+
+#[mullvad_management_interface::async_trait]
+impl Command for History {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Display past tunnel sessions, from oldest to newest")
+            .arg(
+                clap::Arg::with_name("json")
+                    .long("json")
+                    .help("Print the sessions as JSON"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("clear")
+                    .about("Remove all ended sessions from the history"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if matches.subcommand_matches("clear").is_some() {
+            self.clear().await
+        } else {
+            self.list(matches.is_present("json")).await
+        }
+    }
+}
+
+impl History {
+    async fn list(&self, json: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let sessions = rpc.get_connection_history(()).await?.into_inner().sessions;
+        if json {
+            let sessions: Vec<_> = sessions.iter().map(session_to_json).collect();
+            println!("{}", serde_json::to_string_pretty(&sessions).unwrap());
+            return Ok(());
+        }
+
+        if sessions.is_empty() {
+            println!("No sessions in the history");
+        }
+        for session in &sessions {
+            print_session(session);
+        }
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.clear_connection_history(()).await?;
+        println!("Removed all ended sessions from the history");
+        Ok(())
+    }
+}
+
+fn print_session(session: &ConnectionSession) {
+    println!(
+        "Started: {}",
+        session
+            .started
+            .as_ref()
+            .map(format_timestamp)
+            .unwrap_or_default()
+    );
+    let mut relay = format!(
+        "Relay: {} ({})",
+        if session.relay.is_empty() {
+            "custom"
+        } else {
+            session.relay.as_str()
+        },
+        tunnel_type_name(session.tunnel_type)
+    );
+    if !session.entry_relay.is_empty() {
+        relay += &format!(", entry relay: {}", session.entry_relay);
+    }
+    if !session.bridge.is_empty() {
+        relay += &format!(", bridge: {}", session.bridge);
+    }
+    println!("{}", relay);
+    match &session.connected {
+        Some(connected) => println!("Connected: {}", format_timestamp(connected)),
+        None => println!("Connected: never"),
+    }
+    match (&session.ended, &session.end_reason) {
+        (Some(ended), Some(reason)) => println!(
+            "Ended: {} ({})",
+            format_timestamp(ended),
+            end_reason_to_string(reason)
+        ),
+        _ => println!("Ended: active session"),
+    }
+    println!(
+        "Traffic: {} received, {} sent",
+        format::format_bytes(session.rx_bytes),
+        format::format_bytes(session.tx_bytes)
+    );
+    println!("Retry attempts: {}", session.retry_attempts);
+    println!();
+}
+
+fn session_to_json(session: &ConnectionSession) -> serde_json::Value {
+    let optional = |value: &str| {
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+    let end_reason = session.end_reason.as_ref().map(|reason| match reason {
+        EndReason::Disconnect(after_disconnect) => serde_json::json!({
+            "disconnect": after_disconnect_name(*after_disconnect),
+        }),
+        EndReason::Error(error_state) => serde_json::json!({
+            "error": format::error_state_to_string(error_state),
+        }),
+        EndReason::TunnelLost(()) => serde_json::json!("tunnel_lost"),
+    });
+    serde_json::json!({
+        "started": session.started.as_ref().map(format_rfc3339),
+        "connected": session.connected.as_ref().map(format_rfc3339),
+        "ended": session.ended.as_ref().map(format_rfc3339),
+        "relay": optional(&session.relay),
+        "tunnel_type": tunnel_type_name(session.tunnel_type),
+        "entry_relay": optional(&session.entry_relay),
+        "bridge": optional(&session.bridge),
+        "rx_bytes": session.rx_bytes,
+        "tx_bytes": session.tx_bytes,
+        "retry_attempts": session.retry_attempts,
+        "end_reason": end_reason,
+    })
+}
+
+fn end_reason_to_string(reason: &EndReason) -> String {
+    match reason {
+        EndReason::Disconnect(after_disconnect) => {
+            match AfterDisconnect::from_i32(*after_disconnect) {
+                Some(AfterDisconnect::Reconnect) => "reconnected".to_string(),
+                Some(AfterDisconnect::Block) => "disconnected and blocked".to_string(),
+                _ => "disconnected".to_string(),
+            }
+        }
+        EndReason::Error(error_state) => format::error_state_to_string(error_state),
+        EndReason::TunnelLost(()) => "tunnel lost".to_string(),
+    }
+}
+
+fn after_disconnect_name(after_disconnect: i32) -> &'static str {
+    match AfterDisconnect::from_i32(after_disconnect) {
+        Some(AfterDisconnect::Block) => "block",
+        Some(AfterDisconnect::Reconnect) => "reconnect",
+        _ => "nothing",
+    }
+}
+
+fn tunnel_type_name(tunnel_type: i32) -> &'static str {
+    match TunnelType::from_i32(tunnel_type).expect("invalid tunnel protocol") {
+        TunnelType::Wireguard => "WireGuard",
+        TunnelType::Openvpn => "OpenVPN",
+    }
+}
+
+fn to_datetime(timestamp: &Timestamp) -> chrono::DateTime<chrono::Utc> {
+    let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+    chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc)
+}
+
+fn format_timestamp(timestamp: &Timestamp) -> String {
+    to_datetime(timestamp)
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_rfc3339(timestamp: &Timestamp) -> String {
+    to_datetime(timestamp).to_rfc3339()
+}
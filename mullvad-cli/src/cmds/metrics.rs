@@ -0,0 +1,113 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{metrics_settings::ListenAddress, MetricsSettings};
+
+pub struct Metrics;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Metrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control the local endpoint that exports metrics in the Prometheus format")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Enable or disable the metrics endpoint")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("port")
+                            .help("Serve the metrics on this TCP port on the loopback interface")
+                            .long("port")
+                            .takes_value(true)
+                            .conflicts_with("socket"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("socket")
+                            .help(
+                                "Serve the metrics on a Unix socket with this file name in the \
+                                 cache directory of the daemon",
+                            )
+                            .long("socket")
+                            .takes_value(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current metrics endpoint settings"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let enabled = value_t_or_exit!(set_matches.value_of("policy"), String) == "on";
+            let listen_address = if set_matches.is_present("port") {
+                let port = value_t_or_exit!(set_matches.value_of("port"), u16);
+                Some(ListenAddress::Port(u32::from(port)))
+            } else {
+                set_matches
+                    .value_of("socket")
+                    .map(|name| ListenAddress::UnixSocketName(name.to_string()))
+            };
+            self.set(enabled, listen_address).await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No metrics command given");
+        }
+    }
+}
+
+impl Metrics {
+    async fn set(&self, enabled: bool, listen_address: Option<ListenAddress>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .metrics
+            .unwrap_or_default();
+        settings.enabled = enabled;
+        if listen_address.is_some() {
+            settings.listen_address = listen_address;
+        }
+        rpc.set_metrics_settings(settings.clone()).await?;
+        if enabled {
+            println!("Serving metrics on {}", format_listen_address(&settings));
+        } else {
+            println!("Disabled the metrics endpoint");
+        }
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .metrics
+            .unwrap_or_default();
+        println!(
+            "Metrics endpoint: {}",
+            if settings.enabled { "on" } else { "off" }
+        );
+        println!("Listen address: {}", format_listen_address(&settings));
+        Ok(())
+    }
+}
+
+fn format_listen_address(settings: &MetricsSettings) -> String {
+    match &settings.listen_address {
+        Some(ListenAddress::Port(port)) => format!("http://127.0.0.1:{}/metrics", port),
+        Some(ListenAddress::UnixSocketName(name)) => format!("unix:{}", name),
+        None => "none".to_string(),
+    }
+}
@@ -19,15 +19,31 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+#[cfg(target_os = "linux")]
+mod debug;
+#[cfg(target_os = "linux")]
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
 mod dns;
 pub use self::dns::Dns;
 
+#[cfg(target_os = "linux")]
+mod firewall;
+#[cfg(target_os = "linux")]
+pub use self::firewall::Firewall;
+
+mod history;
+pub use self::history::History;
+
 mod lan;
 pub use self::lan::Lan;
 
+mod metrics;
+pub use self::metrics::Metrics;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -37,6 +53,9 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
+mod settings;
+pub use self::settings::Settings;
+
 #[cfg(any(target_os = "linux", windows))]
 mod split_tunnel;
 #[cfg(any(target_os = "linux", windows))]
@@ -60,12 +79,19 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        #[cfg(target_os = "linux")]
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Dns),
+        #[cfg(target_os = "linux")]
+        Box::new(Firewall),
+        Box::new(History),
         Box::new(Reconnect),
         Box::new(Lan),
+        Box::new(Metrics),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(Settings),
         #[cfg(any(target_os = "linux", windows))]
         Box::new(SplitTunnel),
         Box::new(Status),
@@ -3,20 +3,23 @@ use clap::{value_t, values_t};
 use itertools::Itertools;
 use std::{
     fmt::Write,
+    fs,
     io::{self, BufRead},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
     str::FromStr,
 };
 
 use mullvad_management_interface::types::{
     connection_config::{self, OpenvpnConfig, WireguardConfig},
-    relay_settings, relay_settings_update, ConnectionConfig, CustomRelaySettings, IpVersion,
-    IpVersionConstraint, NormalRelaySettingsUpdate, OpenvpnConstraints, ProviderUpdate,
-    RelayListCountry, RelayLocation, RelaySettingsUpdate, TransportProtocol,
-    TransportProtocolConstraint, TunnelType, TunnelTypeConstraint, TunnelTypeUpdate,
-    WireguardConstraints,
+    custom_relay_import, relay_settings, relay_settings_update, ConnectionConfig,
+    CustomRelayImport, CustomRelaySettings, IpVersion, IpVersionConstraint,
+    NormalRelaySettingsUpdate, OpenvpnConstraints, ProviderUpdate, RelayListCountry, RelayLocation,
+    RelaySettingsUpdate, TransportProtocol, TransportProtocolConstraint, TunnelType,
+    TunnelTypeConstraint, TunnelTypeUpdate, WireguardConstraints,
 };
-use mullvad_types::relay_constraints::Constraint;
+use mullvad_management_interface::Code;
+use mullvad_types::{custom_tunnel::openvpn_config, relay_constraints::Constraint};
 use talpid_types::net::all_of_the_internet;
 
 pub struct Relay;
@@ -112,6 +115,23 @@ impl Command for Relay {
                                         .possible_values(&["udp", "tcp"]),
                                 )
                             )
+                            .subcommand(clap::SubCommand::with_name("import")
+                                .about("Import a custom relay from a wg-quick or OpenVPN config file")
+                                .arg(
+                                    clap::Arg::with_name("file")
+                                        .help("Path to the config file")
+                                        .required(true),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("format")
+                                        .help("Format of the config file. Detected from the file \
+                                               extension by default, where .conf is a wg-quick \
+                                               config and .ovpn is an OpenVPN config")
+                                        .long("format")
+                                        .takes_value(true)
+                                        .possible_values(&["wireguard", "openvpn"]),
+                                )
+                            )
                     )
                     .subcommand(
                         location::get_subcommand()
@@ -203,6 +223,10 @@ impl Command for Relay {
                 clap::SubCommand::with_name("update")
                     .about("Update the list of available countries and cities"),
             )
+            .subcommand(
+                clap::SubCommand::with_name("reset-health")
+                    .about("Forget past connection failures that relays are avoided for"),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -214,6 +238,8 @@ impl Command for Relay {
             self.list().await
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
+        } else if matches.subcommand_matches("reset-health").is_some() {
+            self.reset_health().await
         } else {
             unreachable!("No relay command given");
         }
@@ -258,6 +284,7 @@ impl Relay {
         let custom_endpoint = match matches.subcommand() {
             ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
             ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
+            ("import", Some(import_matches)) => return self.import_custom(import_matches).await,
             (_unknown_tunnel, _) => unreachable!("No set relay command given"),
         };
 
@@ -267,6 +294,59 @@ impl Relay {
         .await
     }
 
+    async fn import_custom(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let file = matches.value_of("file").unwrap();
+        let format = match matches.value_of("format") {
+            Some("wireguard") => custom_relay_import::Format::WgQuick,
+            Some("openvpn") => custom_relay_import::Format::Openvpn,
+            Some(_) => unreachable!("Invalid config format"),
+            None => match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+                Some("conf") => custom_relay_import::Format::WgQuick,
+                Some("ovpn") => custom_relay_import::Format::Openvpn,
+                _ => {
+                    return Err(Error::InvalidCommand(
+                        "Unknown file extension. Specify the format with --format",
+                    ))
+                }
+            },
+        };
+        let mut config =
+            fs::read_to_string(file).map_err(|error| Error::FileError(file.to_owned(), error))?;
+
+        // The daemon cannot read files next to the config, so credentials are inlined here
+        if format == custom_relay_import::Format::Openvpn {
+            if let Some(credentials_file) = openvpn_config::credentials_file(&config) {
+                let path = Path::new(file)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(credentials_file);
+                let credentials = fs::read_to_string(&path).map_err(|error| {
+                    Error::FileError(path.to_string_lossy().into_owned(), error)
+                })?;
+                config = openvpn_config::inline_credentials(&config, &credentials);
+            }
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        match rpc
+            .import_custom_relay(CustomRelayImport {
+                format: format as i32,
+                config,
+            })
+            .await
+        {
+            Ok(_) => {
+                println!("Relay constraints updated");
+                Ok(())
+            }
+            Err(error) if error.code() == Code::InvalidArgument => {
+                eprintln!("Failed to import {}: {}", file, error.message());
+                std::process::exit(1);
+            }
+            Err(error) => Err(Error::RpcFailedExt("Failed to import custom relay", error)),
+        }
+    }
+
     fn read_custom_openvpn_relay(matches: &clap::ArgMatches<'_>) -> CustomRelaySettings {
         let host = value_t!(matches.value_of("host"), String).unwrap_or_else(|e| e.exit());
         let port = value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
@@ -286,6 +366,7 @@ impl Relay {
                     password,
                 })),
             }),
+            mtu: 0,
         }
     }
 
@@ -337,6 +418,7 @@ impl Relay {
                         endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
                             .to_string(),
                         protocol: protocol as i32,
+                        psk: vec![],
                     }),
                     ipv4_gateway: ipv4_gateway.to_string(),
                     ipv6_gateway: ipv6_gateway
@@ -345,6 +427,7 @@ impl Relay {
                         .unwrap_or_default(),
                 })),
             }),
+            mtu: 0,
         }
     }
 
@@ -691,6 +774,12 @@ impl Relay {
         Ok(())
     }
 
+    async fn reset_health(&self) -> Result<()> {
+        new_rpc_client().await?.reset_relay_health(()).await?;
+        println!("Relay health reset");
+        Ok(())
+    }
+
     fn format_ip_version(protocol: Option<IpVersion>) -> &'static str {
         match protocol {
             None => "IPv4 or IPv6",
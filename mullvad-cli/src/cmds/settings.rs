@@ -0,0 +1,218 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::{
+    types::{settings_encryption::KeySource, SetSettingsEncryption},
+    Code,
+};
+use std::{
+    fs,
+    io::{self, Read, Write},
+};
+
+pub struct Settings;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage how the daemon settings are stored")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_encryption_subcommand())
+            .subcommand(
+                clap::SubCommand::with_name("unlock")
+                    .about("Decrypt the account token and WireGuard key in the settings"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Write the settings to a file, or to stdout if no file is given")
+                    .arg(
+                        clap::Arg::with_name("include secrets")
+                            .long("include-secrets")
+                            .help("Include the account token"),
+                    )
+                    .arg(clap::Arg::with_name("file")),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("import")
+                    .about("Replace the settings with previously exported settings")
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("The exported settings, or '-' to read them from stdin")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("encryption", Some(encryption_matches)) => {
+                Self::handle_encryption_cmd(encryption_matches).await
+            }
+            ("unlock", Some(_)) => Self::unlock().await,
+            ("export", Some(export_matches)) => {
+                Self::export(
+                    export_matches.is_present("include secrets"),
+                    export_matches.value_of("file"),
+                )
+                .await
+            }
+            ("import", Some(import_matches)) => {
+                let file = value_t_or_exit!(import_matches.value_of("file"), String);
+                Self::import(&file).await
+            }
+            _ => {
+                unreachable!("unhandled comand");
+            }
+        }
+    }
+}
+
+fn create_encryption_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("encryption")
+        .about("Control encryption of the account token and WireGuard key in the settings")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("key source")
+                    .help("Source of the encryption key, or 'none' to disable encryption")
+                    .required(true)
+                    .possible_values(&["none", "key-file", "kernel-keyring", "passphrase"]),
+            ),
+        )
+}
+
+impl Settings {
+    async fn handle_encryption_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => Self::get_encryption().await,
+            ("set", Some(matches)) => {
+                let key_source =
+                    match value_t_or_exit!(matches.value_of("key source"), String).as_str() {
+                        "none" => KeySource::None,
+                        "key-file" => KeySource::KeyFile,
+                        "kernel-keyring" => KeySource::KernelKeyring,
+                        "passphrase" => KeySource::Passphrase,
+                        _ => unreachable!("invalid key source"),
+                    };
+                Self::set_encryption(key_source).await
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn get_encryption() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let state = rpc.get_settings_encryption(()).await?.into_inner();
+        let key_source = match KeySource::from_i32(state.key_source) {
+            Some(KeySource::None) | None => "none",
+            Some(KeySource::KeyFile) => "key file",
+            Some(KeySource::KernelKeyring) => "kernel keyring",
+            Some(KeySource::Passphrase) => "passphrase",
+        };
+        println!("Settings encryption: {}", key_source);
+        if state.locked {
+            println!("The settings are locked. Use 'mullvad settings unlock' to unlock them");
+        }
+        Ok(())
+    }
+
+    async fn set_encryption(key_source: KeySource) -> Result<()> {
+        let passphrase = if key_source == KeySource::Passphrase {
+            Self::read_passphrase()
+        } else {
+            String::new()
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_settings_encryption(SetSettingsEncryption {
+            key_source: key_source as i32,
+            passphrase,
+        })
+        .await
+        .map_err(|error| Error::RpcFailedExt("Failed to change settings encryption", error))?;
+        println!("Changed settings encryption");
+        Ok(())
+    }
+
+    async fn unlock() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let state = rpc.get_settings_encryption(()).await?.into_inner();
+        if !state.locked {
+            println!("The settings are not locked");
+            return Ok(());
+        }
+        let passphrase = if state.key_source == KeySource::Passphrase as i32 {
+            Self::read_passphrase()
+        } else {
+            String::new()
+        };
+        match rpc.unlock_settings(passphrase).await {
+            Ok(_) => {
+                println!("Unlocked settings");
+                Ok(())
+            }
+            Err(error) if error.code() == Code::Unauthenticated => {
+                eprintln!("Failed to unlock settings: {}", error.message());
+                std::process::exit(1);
+            }
+            Err(error) => Err(Error::RpcFailed(error)),
+        }
+    }
+
+    async fn export(include_secrets: bool, file: Option<&str>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.export_settings(include_secrets).await?.into_inner();
+        match file {
+            Some(file) => {
+                fs::write(file, settings)
+                    .map_err(|error| Error::FileError(file.to_owned(), error))?;
+                println!("Exported settings to {}", file);
+            }
+            None => println!("{}", settings),
+        }
+        Ok(())
+    }
+
+    async fn import(file: &str) -> Result<()> {
+        let settings = if file == "-" {
+            let mut settings = String::new();
+            io::stdin()
+                .read_to_string(&mut settings)
+                .expect("Failed to read from STDIN");
+            settings
+        } else {
+            fs::read_to_string(file).map_err(|error| Error::FileError(file.to_owned(), error))?
+        };
+        let mut rpc = new_rpc_client().await?;
+        match rpc.import_settings(settings).await {
+            Ok(_) => {
+                println!("Imported settings");
+                Ok(())
+            }
+            Err(error)
+                if error.code() == Code::InvalidArgument
+                    || error.code() == Code::FailedPrecondition =>
+            {
+                eprintln!("Failed to import settings: {}", error.message());
+                std::process::exit(1);
+            }
+            Err(error) => Err(Error::RpcFailed(error)),
+        }
+    }
+
+    fn read_passphrase() -> String {
+        let mut passphrase = String::new();
+        io::stdout()
+            .write_all(b"Enter passphrase: ")
+            .expect("Failed to write to STDOUT");
+        let _ = io::stdout().flush();
+        io::stdin()
+            .read_line(&mut passphrase)
+            .expect("Failed to read from STDIN");
+        passphrase.trim_end_matches(&['\r', '\n'][..]).to_string()
+    }
+}
@@ -17,11 +17,13 @@ impl Command for SplitTunnel {
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_pid_subcommand())
+            .subcommand(create_uid_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
             ("pid", Some(pid_matches)) => Self::handle_pid_cmd(pid_matches).await,
+            ("uid", Some(uid_matches)) => Self::handle_uid_cmd(uid_matches).await,
             _ => unreachable!("unhandled comand"),
         }
     }
@@ -41,6 +43,19 @@ fn create_pid_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("list"))
 }
 
+fn create_uid_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("uid")
+        .about("Manage users whose traffic is excluded from the tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add").arg(clap::Arg::with_name("uid").required(true)),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove").arg(clap::Arg::with_name("uid").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("list"))
+}
+
 impl SplitTunnel {
     async fn handle_pid_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
@@ -84,4 +99,32 @@ impl SplitTunnel {
             _ => unreachable!("unhandled command"),
         }
     }
+    async fn handle_uid_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let uid = value_t_or_exit!(matches.value_of("uid"), u32);
+                new_rpc_client().await?.add_split_tunnel_uid(uid).await?;
+                Ok(())
+            }
+            ("remove", Some(matches)) => {
+                let uid = value_t_or_exit!(matches.value_of("uid"), u32);
+                new_rpc_client().await?.remove_split_tunnel_uid(uid).await?;
+                Ok(())
+            }
+            ("list", Some(_)) => {
+                let excluded_uids = new_rpc_client()
+                    .await?
+                    .get_settings(())
+                    .await?
+                    .into_inner()
+                    .excluded_uids;
+                println!("Excluded UIDs:");
+                for uid in excluded_uids {
+                    println!("    {}", uid);
+                }
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
 }
@@ -1,4 +1,8 @@
-use crate::{format, format::print_keygen_event, new_rpc_client, Command, Error, Result};
+use crate::{
+    format,
+    format::{print_keygen_event, print_tunnel_stats},
+    new_rpc_client, Command, Error, Result,
+};
 use mullvad_management_interface::{
     types::daemon_event::Event as EventType, ManagementServiceClient,
 };
@@ -20,6 +24,12 @@ impl Command for Status {
                     .short("l")
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .long("verbose")
+                    .short("v")
+                    .help("Prints traffic statistics of the tunnel"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -39,6 +49,10 @@ impl Command for Status {
         if matches.is_present("location") {
             print_location(&mut rpc).await?;
         }
+        if matches.is_present("verbose") {
+            let stats = rpc.get_tunnel_stats(()).await?.into_inner();
+            print_tunnel_stats(&stats);
+        }
 
         if let Some(listen_matches) = matches.subcommand_matches("listen") {
             let verbose = listen_matches.is_present("verbose");
@@ -80,6 +94,11 @@ impl Command for Status {
                             print_keygen_event(&key_event);
                         }
                     }
+                    EventType::TunnelStats(stats) => {
+                        if verbose {
+                            print_tunnel_stats(&stats);
+                        }
+                    }
                 }
             }
         }
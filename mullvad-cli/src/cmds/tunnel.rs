@@ -1,8 +1,19 @@
-use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
+use crate::{format::print_keygen_event, location, new_rpc_client, Command, Error, Result};
 use clap::value_t;
-use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
+use mullvad_management_interface::types::{
+    self, connectivity_check_options::ProbeMode, ConnectivityCheckOptions, RelayLocation,
+    Timestamp, TunnelOptions, WireguardConfigExport, WireguardConfigExportRequest, WireguardDevice,
+    WireguardDeviceRotationInterval, WireguardRotationPolicy,
+};
 use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
-use std::{convert::TryFrom, time::Duration};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Write},
+    time::Duration,
+};
 
 pub struct Tunnel;
 
@@ -34,11 +45,129 @@ impl Command for Tunnel {
 }
 
 fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
-    clap::SubCommand::with_name("wireguard")
+    let subcommand = clap::SubCommand::with_name("wireguard")
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_connectivity_check_subcommand())
+        .subcommand(create_wireguard_export_subcommand())
+        .subcommand(create_wireguard_devices_subcommand());
+    #[cfg(target_os = "linux")]
+    let subcommand = subcommand.subcommand(create_wireguard_netns_subcommand());
+    subcommand
+}
+
+#[cfg(target_os = "linux")]
+fn create_wireguard_netns_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("netns")
+        .about(
+            "Move the WireGuard tunnel into a network namespace. Only programs started with \
+             `mullvad-netns exec` use the tunnel. The firewall still blocks the rest of the \
+             system from reaching the internet outside the tunnel",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("unset").about("Use the tunnel for the whole system"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("name")
+                    .help("Name of the network namespace")
+                    .default_value(talpid_types::netns::DEFAULT_NETNS_NAME),
+            ),
+        )
+}
+
+fn create_wireguard_export_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("export")
+        .about(
+            "Export a wg-quick config for the current relay, or for all WireGuard relays in a \
+             location. The config contains the private WireGuard key of this device",
+        )
+        .arg(
+            clap::Arg::with_name("country")
+                .help("The two letter country code")
+                .index(1)
+                .validator(location::country_code_validator),
+        )
+        .arg(
+            clap::Arg::with_name("city")
+                .help("The three letter city code")
+                .index(2)
+                .validator(location::city_code_validator),
+        )
+        .arg(
+            clap::Arg::with_name("hostname")
+                .help("The hostname")
+                .index(3),
+        )
+        .arg(
+            clap::Arg::with_name("output")
+                .help(
+                    "File to write the config to. When several relays are exported, the configs \
+                     are written to a zip archive. Prints a single config by default",
+                )
+                .long("output")
+                .short("o")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("device")
+                .help("Use the key of the given WireGuard device instead of the key of this device")
+                .long("device")
+                .takes_value(true),
+        )
+}
+
+fn create_wireguard_devices_subcommand() -> clap::App<'static, 'static> {
+    let name_arg = || {
+        clap::Arg::with_name("name")
+            .help("Name of the device")
+            .required(true)
+    };
+    clap::SubCommand::with_name("device")
+        .about(
+            "Manage additional WireGuard keys for containers and virtual machines. The devices \
+             are removed when the account is changed",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Add a device with a new key")
+                .arg(
+                    name_arg().help(
+                        "Name of the device. Up to 15 letters, digits, dashes and underscores",
+                    ),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List all devices"))
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove a device and its key from the account")
+                .arg(name_arg()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("rotate")
+                .about("Replace the key of a device")
+                .arg(name_arg()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("rotation-interval")
+                .about("Manage automatic key rotation of a device (given in hours)")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    clap::SubCommand::with_name("reset")
+                        .about("Use the rotation interval of this device")
+                        .arg(name_arg()),
+                )
+                .subcommand(
+                    clap::SubCommand::with_name("set")
+                        .arg(name_arg())
+                        .arg(clap::Arg::with_name("interval").required(true)),
+                ),
+        )
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -59,6 +188,7 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("check"))
         .subcommand(clap::SubCommand::with_name("regenerate"))
         .subcommand(create_wireguard_keys_rotation_interval_subcommand())
+        .subcommand(create_wireguard_keys_rotation_policy_subcommand())
 }
 
 fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 'static> {
@@ -72,6 +202,104 @@ fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 's
         )
 }
 
+fn create_wireguard_keys_rotation_policy_subcommand() -> clap::App<'static, 'static> {
+    let limit_arg = |name, value_name, help| {
+        clap::Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .value_name(value_name)
+            .help(help)
+    };
+    clap::SubCommand::with_name("rotation-policy")
+        .about("Manage conditions besides the interval that trigger automatic key rotation")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("reset").about("Only rotate keys by interval"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change the given conditions, keeping the others. Use \"off\" to remove one")
+                .arg(limit_arg(
+                    "data-limit",
+                    "GB",
+                    "Rotate the key once this much data has been transferred using it",
+                ))
+                .arg(limit_arg(
+                    "relay-switch-limit",
+                    "COUNT",
+                    "Rotate the key once the tunnel has switched relays this many times",
+                ))
+                .arg(limit_arg(
+                    "time",
+                    "HH:MM",
+                    "Rotate the key every day at this local time",
+                )),
+        )
+}
+
+fn create_wireguard_connectivity_check_subcommand() -> clap::App<'static, 'static> {
+    let timeout_arg = |name, help| {
+        clap::Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help(help)
+    };
+    clap::SubCommand::with_name("connectivity-check")
+        .about("Configure how the tunnel is verified to be working")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("reset").about("Use the default connectivity check"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change the given options, keeping the others")
+                .arg(timeout_arg(
+                    "rx-timeout",
+                    "Time to wait for a response to outgoing traffic before probing",
+                ))
+                .arg(timeout_arg(
+                    "traffic-timeout",
+                    "Time to wait for any traffic before probing",
+                ))
+                .arg(timeout_arg(
+                    "probe-timeout",
+                    "Time to wait for a response to probes before reconnecting",
+                ))
+                .arg(timeout_arg("probe-interval", "Time between probes"))
+                .arg(
+                    clap::Arg::with_name("target")
+                        .long("target")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("IPV4")
+                        .help("Address inside the tunnel to probe. Replaces the current targets"),
+                )
+                .arg(
+                    clap::Arg::with_name("gateway")
+                        .long("gateway")
+                        .conflicts_with("target")
+                        .help("Probe the tunnel gateway instead of custom targets"),
+                )
+                .arg(
+                    clap::Arg::with_name("mode")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(&["icmp", "tcp", "http"])
+                        .requires_if("tcp", "port")
+                        .requires_if("http", "port")
+                        .help("How to probe the targets"),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .requires("mode")
+                        .help("Port to probe in the tcp and http modes"),
+                ),
+        )
+}
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
@@ -144,6 +372,50 @@ impl Tunnel {
                     ("reset", _) => Self::process_wireguard_rotation_interval_reset().await,
                     _ => unreachable!("unhandled command"),
                 },
+                ("rotation-policy", Some(matches)) => match matches.subcommand() {
+                    ("get", _) => Self::process_wireguard_rotation_policy_get().await,
+                    ("set", Some(matches)) => {
+                        Self::process_wireguard_rotation_policy_set(matches).await
+                    }
+                    ("reset", _) => Self::process_wireguard_rotation_policy_reset().await,
+                    _ => unreachable!("unhandled command"),
+                },
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("connectivity-check", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_connectivity_check_get().await,
+                ("set", Some(matches)) => {
+                    Self::process_wireguard_connectivity_check_set(matches).await
+                }
+                ("reset", _) => Self::process_wireguard_connectivity_check_reset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("export", Some(matches)) => Self::process_wireguard_export(matches).await,
+
+            #[cfg(target_os = "linux")]
+            ("netns", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_netns_get().await,
+                ("set", Some(matches)) => Self::process_wireguard_netns_set(matches).await,
+                ("unset", _) => Self::process_wireguard_netns_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("device", Some(matches)) => match matches.subcommand() {
+                ("add", Some(matches)) => Self::process_wireguard_device_add(matches).await,
+                ("list", _) => Self::process_wireguard_device_list().await,
+                ("remove", Some(matches)) => Self::process_wireguard_device_remove(matches).await,
+                ("rotate", Some(matches)) => Self::process_wireguard_device_rotate(matches).await,
+                ("rotation-interval", Some(matches)) => match matches.subcommand() {
+                    ("set", Some(matches)) => {
+                        Self::process_wireguard_device_rotation_interval_set(matches).await
+                    }
+                    ("reset", Some(matches)) => {
+                        Self::process_wireguard_device_rotation_interval_reset(matches).await
+                    }
+                    _ => unreachable!("unhandled command"),
+                },
                 _ => unreachable!("unhandled command"),
             },
 
@@ -180,6 +452,41 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_netns_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let netns = tunnel_options.wireguard.unwrap().netns;
+        println!(
+            "Network namespace: {}",
+            if !netns.is_empty() { &netns } else { "unset" }
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_netns_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let name = matches.value_of("name").unwrap();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_netns(name.to_string()).await?;
+        println!(
+            "WireGuard tunnels will be moved into the network namespace {}",
+            name
+        );
+        println!(
+            "Run programs in it with: mullvad-netns exec -n {} COMMAND",
+            name
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_netns_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_netns(String::new()).await?;
+        println!("WireGuard tunnels will no longer be moved into a network namespace");
+        Ok(())
+    }
+
     async fn process_wireguard_key_check() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let key = rpc.get_wireguard_key(()).await;
@@ -257,6 +564,329 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_rotation_policy_get() -> Result<()> {
+        let policy = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .unwrap()
+            .rotation_policy
+            .unwrap_or_default();
+        let off = || "off".to_string();
+        println!(
+            "Data limit        : {}",
+            policy
+                .max_bytes
+                .map(|bytes| format!("{} GB", bytes / BYTES_PER_GB))
+                .unwrap_or_else(off)
+        );
+        println!(
+            "Relay switch limit: {}",
+            policy
+                .max_relay_switches
+                .map(|switches| switches.to_string())
+                .unwrap_or_else(off)
+        );
+        println!(
+            "Time of day       : {}",
+            policy.time_of_day.unwrap_or_else(off)
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_rotation_policy_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut policy = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .unwrap()
+            .rotation_policy
+            .unwrap_or_default();
+
+        let is_off = |name| matches.value_of(name) == Some("off");
+        if is_off("data-limit") {
+            policy.max_bytes = None;
+        } else if matches.is_present("data-limit") {
+            let gigabytes =
+                value_t!(matches.value_of("data-limit"), u64).unwrap_or_else(|e| e.exit());
+            policy.max_bytes = Some(gigabytes.saturating_mul(BYTES_PER_GB));
+        }
+        if is_off("relay-switch-limit") {
+            policy.max_relay_switches = None;
+        } else if matches.is_present("relay-switch-limit") {
+            policy.max_relay_switches = Some(
+                value_t!(matches.value_of("relay-switch-limit"), u32).unwrap_or_else(|e| e.exit()),
+            );
+        }
+        if is_off("time") {
+            policy.time_of_day = None;
+        } else if let Some(time) = matches.value_of("time") {
+            policy.time_of_day = Some(time.to_string());
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_rotation_policy(policy).await?;
+        println!("Updated the key rotation policy");
+        Ok(())
+    }
+
+    async fn process_wireguard_rotation_policy_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_rotation_policy(WireguardRotationPolicy::default())
+            .await?;
+        println!("Keys are rotated by interval only");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let options = tunnel_options
+            .wireguard
+            .unwrap()
+            .connectivity_check
+            .unwrap_or_default();
+        let duration =
+            |duration: Option<types::Duration>| Duration::try_from(duration.unwrap()).unwrap();
+        println!("RX timeout     : {:?}", duration(options.rx_timeout));
+        println!("Traffic timeout: {:?}", duration(options.traffic_timeout));
+        println!("Probe timeout  : {:?}", duration(options.probe_timeout));
+        println!("Probe interval : {:?}", duration(options.probe_interval));
+        if options.probe_targets.is_empty() {
+            println!("Probe targets  : tunnel gateway");
+        } else {
+            println!("Probe targets  : {}", options.probe_targets.join(", "));
+        }
+        let mode = match options.probe_mode {
+            Some(ProbeMode::TcpPort(port)) => format!("TCP port {}", port),
+            Some(ProbeMode::HttpPort(port)) => format!("HTTP port {}", port),
+            Some(ProbeMode::Icmp(())) | None => "ICMP".to_string(),
+        };
+        println!("Probe mode     : {}", mode);
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_set(
+        matches: &clap::ArgMatches<'_>,
+    ) -> Result<()> {
+        let mut options = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .unwrap()
+            .connectivity_check
+            .unwrap_or_default();
+
+        let timeout = |name| {
+            matches.value_of(name).map(|_| {
+                let seconds = value_t!(matches.value_of(name), u64).unwrap_or_else(|e| e.exit());
+                types::Duration::from(Duration::from_secs(seconds))
+            })
+        };
+        options.rx_timeout = timeout("rx-timeout").or(options.rx_timeout);
+        options.traffic_timeout = timeout("traffic-timeout").or(options.traffic_timeout);
+        options.probe_timeout = timeout("probe-timeout").or(options.probe_timeout);
+        options.probe_interval = timeout("probe-interval").or(options.probe_interval);
+
+        if let Some(targets) = matches.values_of("target") {
+            options.probe_targets = targets.map(str::to_string).collect();
+        } else if matches.is_present("gateway") {
+            options.probe_targets.clear();
+        }
+
+        if let Some(mode) = matches.value_of("mode") {
+            let port = || value_t!(matches.value_of("port"), u32).unwrap_or_else(|e| e.exit());
+            options.probe_mode = Some(match mode {
+                "icmp" => ProbeMode::Icmp(()),
+                "tcp" => ProbeMode::TcpPort(port()),
+                "http" => ProbeMode::HttpPort(port()),
+                _ => unreachable!("invalid probe mode"),
+            });
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_connectivity_check(options).await?;
+        println!("Updated the connectivity check");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_connectivity_check(ConnectivityCheckOptions::default())
+            .await?;
+        println!("Reset the connectivity check to the defaults");
+        Ok(())
+    }
+
+    async fn process_wireguard_export(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let location = if matches.value_of("country").is_some() {
+            location::get_constraint_from_args(matches)
+        } else {
+            RelayLocation::default()
+        };
+        let output = matches.value_of("output");
+        let device = matches.value_of("device").unwrap_or_default().to_owned();
+
+        let mut rpc = new_rpc_client().await?;
+        let configs = rpc
+            .export_wireguard_config(WireguardConfigExportRequest {
+                location: Some(location),
+                device,
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to export WireGuard config", error))?
+            .into_inner()
+            .configs;
+
+        match (configs.as_slice(), output) {
+            ([export], None) => print!("{}", export.config),
+            ([export], Some(path)) => {
+                Self::write_private_file(path, export.config.as_bytes())
+                    .map_err(|error| Error::FileError(path.to_owned(), error))?;
+                println!("Exported config for {} to {}", export.hostname, path);
+            }
+            (_, None) => {
+                return Err(Error::InvalidCommand(
+                    "Exporting several relays requires an output file",
+                ))
+            }
+            (configs, Some(path)) => {
+                Self::write_config_archive(path, configs)
+                    .map_err(|error| Error::FileError(path.to_owned(), error))?;
+                println!("Exported configs for {} relays to {}", configs.len(), path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the configs to a zip archive, with one `<hostname>.conf` file per relay.
+    fn write_config_archive(path: &str, configs: &[WireguardConfigExport]) -> io::Result<()> {
+        let mut archive = zip::ZipWriter::new(Self::create_private_file(path)?);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .unix_permissions(0o600);
+        for export in configs {
+            archive.start_file(format!("{}.conf", export.hostname), options)?;
+            archive.write_all(export.config.as_bytes())?;
+        }
+        archive.finish()?;
+        Ok(())
+    }
+
+    fn write_private_file(path: &str, contents: &[u8]) -> io::Result<()> {
+        Self::create_private_file(path)?.write_all(contents)
+    }
+
+    /// Creates a file that is only readable by the current user, since configs contain the
+    /// private key.
+    fn create_private_file(path: &str) -> io::Result<fs::File> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(path)
+    }
+
+    async fn process_wireguard_device_add(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        let device = rpc
+            .add_wireguard_device(name)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to add WireGuard device", error))?
+            .into_inner();
+        println!("Added WireGuard device");
+        Self::print_wireguard_device(&device);
+        Ok(())
+    }
+
+    async fn process_wireguard_device_list() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let devices = rpc.list_wireguard_devices(()).await?.into_inner().devices;
+        if devices.is_empty() {
+            println!("No WireGuard devices");
+        }
+        for device in &devices {
+            Self::print_wireguard_device(device);
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_device_remove(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_wireguard_device(name.clone())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove WireGuard device", error))?;
+        println!("Removed WireGuard device {}", name);
+        Ok(())
+    }
+
+    async fn process_wireguard_device_rotate(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        let device = rpc
+            .rotate_wireguard_device_key(name)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to rotate WireGuard device key", error))?
+            .into_inner();
+        println!("Replaced the key of the WireGuard device");
+        Self::print_wireguard_device(&device);
+        Ok(())
+    }
+
+    async fn process_wireguard_device_rotation_interval_set(
+        matches: &clap::ArgMatches<'_>,
+    ) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let rotate_interval =
+            value_t!(matches.value_of("interval"), u64).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_device_rotation_interval(WireguardDeviceRotationInterval {
+            name,
+            interval: Some(types::Duration::from(Duration::from_secs(
+                60 * 60 * rotate_interval,
+            ))),
+        })
+        .await?;
+        println!("Set key rotation interval: {} hour(s)", rotate_interval);
+        Ok(())
+    }
+
+    async fn process_wireguard_device_rotation_interval_reset(
+        matches: &clap::ArgMatches<'_>,
+    ) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_device_rotation_interval(WireguardDeviceRotationInterval {
+            name,
+            interval: None,
+        })
+        .await?;
+        println!("Set key rotation interval: same as this device");
+        Ok(())
+    }
+
+    fn print_wireguard_device(device: &WireguardDevice) {
+        println!("{}", device.name);
+        if let Some(key) = &device.key {
+            println!("    Public key        : {}", base64::encode(&key.key));
+            if let Some(created) = &key.created {
+                println!(
+                    "    Key created on    : {}",
+                    Self::format_key_timestamp(created)
+                );
+            }
+        }
+        println!(
+            "    Addresses         : {}, {}",
+            device.ipv4_address, device.ipv6_address
+        );
+        match &device.rotation_interval {
+            Some(interval) => println!(
+                "    Rotation interval : {} hour(s)",
+                duration_hours(&Duration::try_from(interval.clone()).unwrap())
+            ),
+            None => println!("    Rotation interval : same as this device"),
+        }
+    }
+
     async fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get().await
@@ -339,6 +969,8 @@ impl Tunnel {
     }
 }
 
+const BYTES_PER_GB: u64 = 1_000_000_000;
+
 fn duration_hours(duration: &Duration) -> u64 {
     duration.as_secs() / 60 / 60
 }
@@ -5,10 +5,14 @@ use mullvad_management_interface::types::{
     },
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, KeygenEvent, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
+    ErrorState, KeygenEvent, ProxyType, Timestamp, TransportProtocol, TunnelEndpoint, TunnelState,
+    TunnelStats, TunnelType,
 };
 use mullvad_types::auth_failed::AuthFailed;
-use std::fmt::Write;
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub fn print_keygen_event(key_event: &KeygenEvent) {
     use mullvad_management_interface::types::keygen_event::KeygenEvent as EventType;
@@ -56,6 +60,71 @@ pub fn print_state(state: &TunnelState) {
     }
 }
 
+pub fn print_tunnel_stats(stats: &TunnelStats) {
+    if let Some(connected_since) = &stats.connected_since {
+        println!("Connected for: {}", format_age(connected_since));
+        println!(
+            "Traffic: {} received, {} sent",
+            format_bytes(stats.rx_bytes),
+            format_bytes(stats.tx_bytes)
+        );
+        if let Some(last_handshake) = &stats.last_handshake {
+            println!("Last handshake: {} ago", format_age(last_handshake));
+        }
+        if stats.ipv6_degraded {
+            println!("IPv6: not working inside the tunnel");
+        }
+        if !stats.remote.is_empty() {
+            println!("Relay address: {}", stats.remote);
+        }
+        if !stats.data_channel_cipher.is_empty() {
+            println!("Data channel cipher: {}", stats.data_channel_cipher);
+        }
+        if !stats.control_channel.is_empty() {
+            println!("Control channel: {}", stats.control_channel);
+        }
+    }
+    println!(
+        "Total traffic: {} received, {} sent",
+        format_bytes(stats.total_rx_bytes),
+        format_bytes(stats.total_tx_bytes)
+    );
+    println!("Reconnects: {}", stats.reconnects);
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_age(timestamp: &Timestamp) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+    let mut seconds = (now - timestamp.seconds).max(0);
+
+    let mut out = String::new();
+    for (unit, unit_seconds) in &[("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if seconds >= *unit_seconds {
+            write!(&mut out, "{}{} ", seconds / unit_seconds, unit).unwrap();
+            seconds %= unit_seconds;
+        }
+    }
+    write!(&mut out, "{}s", seconds).unwrap();
+    out
+}
+
 fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
     let tunnel_type = TunnelType::from_i32(endpoint.tunnel_type).expect("invalid tunnel protocol");
     let mut out = format!(
@@ -130,7 +199,7 @@ fn print_error_state(error_state: &ErrorState) {
     }
 }
 
-fn error_state_to_string(error_state: &ErrorState) -> String {
+pub fn error_state_to_string(error_state: &ErrorState) -> String {
     use ErrorStateCause::*;
 
     let error_str = match ErrorStateCause::from_i32(error_state.cause).expect("unknown error cause")
@@ -1,7 +1,7 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_description};
-use mullvad_management_interface::async_trait;
+use mullvad_management_interface::{async_trait, Code};
 use std::{collections::HashMap, io};
 use talpid_types::ErrorExt;
 
@@ -43,6 +43,9 @@ pub enum Error {
 
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
+
+    #[error(display = "Failed to access {}", _0)]
+    FileError(String, #[error(source)] io::Error),
 }
 
 #[tokio::main]
@@ -51,6 +54,11 @@ async fn main() {
         Ok(_) => 0,
         Err(error) => {
             match &error {
+                Error::RpcFailed(status) | Error::RpcFailedExt(_, status)
+                    if status.code() == Code::PermissionDenied =>
+                {
+                    eprintln!("{}", status.message())
+                }
                 Error::RpcFailed(status) => {
                     eprintln!("{}: {:?}: {}", error, status.code(), status.message())
                 }
@@ -1,10 +1,17 @@
-use crate::settings::SettingsPersister;
-use mullvad_types::{account::AccountToken, wireguard::WireguardData};
+use crate::{
+    settings::SettingsPersister,
+    settings_encryption::{self, SealedSecrets, SecretsCipher},
+};
+use mullvad_types::{
+    account::{AccountToken, StoredAccount},
+    relay_constraints::RelaySettings,
+    wireguard::WireguardData,
+};
 use regex::Regex;
 use std::{
     fs,
     io::{self, Read, Seek, Write},
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 use talpid_types::ErrorExt;
@@ -25,13 +32,59 @@ pub enum Error {
 
     #[error(display = "Write task panicked or was cancelled")]
     WriteCancelled(#[error(source)] tokio::task::JoinError),
+
+    #[error(display = "Unable to encrypt or decrypt the account history")]
+    Encryption(#[error(source)] settings_encryption::Error),
+
+    #[error(display = "The account history is encrypted and must be unlocked first")]
+    Locked,
 }
 
 static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 
+/// An account stored in the history, along with the data that is needed to switch back to it.
+/// The WireGuard key of the currently active account is kept in the settings rather than here.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountEntry {
+    pub account: AccountToken,
+    #[serde(default)]
+    pub wireguard: Option<WireguardData>,
+    #[serde(default)]
+    pub relay_settings: Option<RelaySettings>,
+}
+
+impl AccountEntry {
+    fn new(account: AccountToken) -> Self {
+        AccountEntry {
+            account,
+            wireguard: None,
+            relay_settings: None,
+        }
+    }
+}
+
+/// On-disk format of the account history, ordered from most to least recently used.
+#[derive(Serialize, Deserialize, Default)]
+struct AccountKeyring {
+    entries: Vec<AccountEntry>,
+}
+
+/// On-disk format of the account history when settings encryption is enabled. The entries
+/// contain account tokens and WireGuard keys, so they are encrypted like the settings secrets.
+#[derive(Serialize, Deserialize)]
+struct SealedAccountKeyring {
+    encrypted_entries: SealedSecrets,
+}
+
 pub struct AccountHistory {
     file: Arc<Mutex<io::BufWriter<fs::File>>>,
-    token: Option<AccountToken>,
+    entries: Vec<AccountEntry>,
+    settings_dir: PathBuf,
+    /// Cipher used to encrypt the history, if settings encryption is enabled.
+    cipher: Option<Arc<SecretsCipher>>,
+    /// Encrypted entries that could not be decrypted when the history was loaded. These are
+    /// written back unchanged until they are unlocked.
+    locked_entries: Option<SealedSecrets>,
 }
 
 lazy_static::lazy_static! {
@@ -60,7 +113,8 @@ impl AccountHistory {
             options.share_mode(0);
         }
         let path = settings_dir.join(ACCOUNT_HISTORY_FILE);
-        let (file, token) = if path.is_file() {
+        let mut locked_entries = None;
+        let (file, entries) = if path.is_file() {
             log::info!("Opening account history file in {}", path.display());
             let mut reader = options
                 .write(true)
@@ -70,12 +124,39 @@ impl AccountHistory {
                 .map_err(Error::Read)?;
 
             let mut buffer = String::new();
-            let token: Option<AccountToken> = match reader.read_to_string(&mut buffer) {
-                Ok(0) => None,
-                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => Some(buffer),
-                Ok(_) | Err(_) => {
+            let keyring = match reader.read_to_string(&mut buffer) {
+                Ok(0) => Some(vec![]),
+                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => Some(vec![AccountEntry::new(buffer)]),
+                Ok(_) => match serde_json::from_str::<SealedAccountKeyring>(&buffer) {
+                    Ok(sealed) => {
+                        let result = if settings.secrets_locked() {
+                            Err(settings_encryption::Error::PassphraseRequired)
+                        } else {
+                            Self::open_entries(settings_dir, &sealed.encrypted_entries, None)
+                        };
+                        match result {
+                            Ok(entries) => Some(entries),
+                            Err(error) => {
+                                log::info!(
+                                    "{}",
+                                    error.display_chain_with_msg(
+                                        "Unable to decrypt account history. It must be unlocked"
+                                    )
+                                );
+                                locked_entries = Some(sealed.encrypted_entries);
+                                Some(vec![])
+                            }
+                        }
+                    }
+                    Err(_) => Self::try_format_v4(&buffer),
+                },
+                Err(_) => None,
+            };
+            let entries = match keyring {
+                Some(entries) => entries,
+                None => {
                     log::warn!("Failed to parse account history. Trying old formats",);
-                    match Self::try_format_v2(&mut reader)? {
+                    let token = match Self::try_format_v2(&mut reader)? {
                         Some((token, migrated_data)) => {
                             if let Err(error) = settings.set_wireguard(migrated_data).await {
                                 log::error!(
@@ -88,11 +169,12 @@ impl AccountHistory {
                             Some(token)
                         }
                         None => Self::try_format_v1(&mut reader)?,
-                    }
+                    };
+                    token.into_iter().map(AccountEntry::new).collect()
                 }
             };
 
-            (reader.into_inner(), token)
+            (reader.into_inner(), entries)
         } else {
             log::info!("Creating account history file in {}", path.display());
             (
@@ -101,13 +183,20 @@ impl AccountHistory {
                     .create(true)
                     .open(path)
                     .map_err(Error::Read)?,
-                settings.get_account_token(),
+                settings
+                    .get_account_token()
+                    .into_iter()
+                    .map(AccountEntry::new)
+                    .collect(),
             )
         };
         let file = io::BufWriter::new(file);
         let mut history = AccountHistory {
             file: Arc::new(Mutex::new(file)),
-            token,
+            entries,
+            settings_dir: settings_dir.to_path_buf(),
+            cipher: settings.cipher(),
+            locked_entries,
         };
         if let Err(e) = history.save_to_disk().await {
             log::error!("Failed to save account cache after opening it: {}", e);
@@ -134,6 +223,57 @@ impl AccountHistory {
         }
     }
 
+    /// Decrypts sealed entries. The cipher is recreated from `sealed`, since it may have been
+    /// encrypted with a different salt than the settings.
+    fn open_entries(
+        settings_dir: &Path,
+        sealed: &SealedSecrets,
+        passphrase: Option<&str>,
+    ) -> std::result::Result<Vec<AccountEntry>, settings_encryption::Error> {
+        let cipher = SecretsCipher::for_sealed(settings_dir, sealed, passphrase)?;
+        cipher
+            .open(sealed)
+            .map(|keyring: AccountKeyring| keyring.entries)
+    }
+
+    /// Sets the cipher used to encrypt the history, or disables encryption if `cipher` is `None`,
+    /// and rewrites the history file.
+    pub async fn set_cipher(&mut self, cipher: Option<Arc<SecretsCipher>>) -> Result<()> {
+        if self.locked_entries.is_some() {
+            return Err(Error::Locked);
+        }
+        self.cipher = cipher;
+        self.save_to_disk().await
+    }
+
+    /// Decrypts entries that could not be decrypted when the history was loaded, and reencrypts
+    /// them using `cipher`. The boolean in the result indicates whether any entries were
+    /// unlocked.
+    pub async fn unlock(
+        &mut self,
+        passphrase: Option<&str>,
+        cipher: Option<Arc<SecretsCipher>>,
+    ) -> Result<bool> {
+        let locked_entries = match self.locked_entries.take() {
+            Some(locked_entries) => locked_entries,
+            None => {
+                self.cipher = cipher;
+                return Ok(false);
+            }
+        };
+        match Self::open_entries(&self.settings_dir, &locked_entries, passphrase) {
+            Ok(entries) => {
+                self.entries = entries;
+                self.cipher = cipher;
+                self.save_to_disk().await.map(|()| true)
+            }
+            Err(error) => {
+                self.locked_entries = Some(locked_entries);
+                Err(Error::Encryption(error))
+            }
+        }
+    }
+
     fn try_format_v1(reader: &mut io::BufReader<fs::File>) -> Result<Option<AccountToken>> {
         #[derive(Deserialize)]
         struct OldFormat {
@@ -145,6 +285,12 @@ impl AccountHistory {
             .unwrap_or_else(|_| None))
     }
 
+    fn try_format_v4(buffer: &str) -> Option<Vec<AccountEntry>> {
+        serde_json::from_str(buffer)
+            .map(|keyring: AccountKeyring| keyring.entries)
+            .ok()
+    }
+
     fn try_format_v2(
         reader: &mut io::BufReader<fs::File>,
     ) -> Result<Option<(AccountToken, Option<WireguardData>)>> {
@@ -163,34 +309,119 @@ impl AccountHistory {
             .unwrap_or_else(|_| None))
     }
 
-    /// Gets the account token in the history
+    /// Gets the most recently used account token in the history
     pub fn get(&self) -> Option<AccountToken> {
-        self.token.clone()
+        self.entries.first().map(|entry| entry.account.clone())
+    }
+
+    /// Returns the stored entry for an account, if there is one
+    pub fn get_entry(&self, account: &str) -> Option<&AccountEntry> {
+        self.entries.iter().find(|entry| entry.account == account)
     }
 
-    /// Replace the account token in the history
+    /// Lists all stored accounts, marking the one that matches `active_account`
+    pub fn list(&self, active_account: Option<&str>) -> Vec<StoredAccount> {
+        self.entries
+            .iter()
+            .map(|entry| StoredAccount {
+                token: entry.account.clone(),
+                wireguard_key: entry.wireguard.as_ref().map(|data| data.get_public_key()),
+                has_relay_settings: entry.relay_settings.is_some(),
+                active: Some(entry.account.as_str()) == active_account,
+            })
+            .collect()
+    }
+
+    /// Marks an account as the most recently used one, adding it to the history if needed
     pub async fn set(&mut self, new_entry: AccountToken) -> Result<()> {
-        self.token = Some(new_entry);
+        self.ensure_unlocked()?;
+        let entry = match self
+            .entries
+            .iter()
+            .position(|entry| entry.account == new_entry)
+        {
+            Some(index) => self.entries.remove(index),
+            None => AccountEntry::new(new_entry),
+        };
+        self.entries.insert(0, entry);
         self.save_to_disk().await
     }
 
-    /// Remove account history
-    pub async fn clear(&mut self) -> Result<()> {
-        self.token = None;
+    /// Replaces the stored data for an account. The account is appended to the history if it is
+    /// not already present.
+    pub async fn store(&mut self, new_entry: AccountEntry) -> Result<()> {
+        self.ensure_unlocked()?;
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.account == new_entry.account)
+        {
+            Some(entry) => *entry = new_entry,
+            None => self.entries.push(new_entry),
+        }
         self.save_to_disk().await
     }
 
+    /// Removes an account from the history, returning its stored data
+    pub async fn remove(&mut self, account: &str) -> Result<Option<AccountEntry>> {
+        self.ensure_unlocked()?;
+        let index = match self
+            .entries
+            .iter()
+            .position(|entry| entry.account == account)
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let entry = self.entries.remove(index);
+        self.save_to_disk().await?;
+        Ok(Some(entry))
+    }
+
+    /// Remove account history, returning the entries that were removed
+    pub async fn clear(&mut self) -> Result<Vec<AccountEntry>> {
+        self.locked_entries = None;
+        let entries = std::mem::take(&mut self.entries);
+        self.save_to_disk().await?;
+        Ok(entries)
+    }
+
+    fn ensure_unlocked(&self) -> Result<()> {
+        if self.locked_entries.is_some() {
+            return Err(Error::Locked);
+        }
+        Ok(())
+    }
+
+    /// Serializes the history. If encryption is enabled, the entries are never included in plain
+    /// text.
+    fn serialize(&self) -> Result<String> {
+        let keyring = AccountKeyring {
+            entries: self.entries.clone(),
+        };
+        let sealed_entries = match (&self.locked_entries, &self.cipher) {
+            (Some(locked_entries), _) => Some(locked_entries.clone()),
+            (None, Some(cipher)) => Some(cipher.seal(&keyring).map_err(Error::Encryption)?),
+            (None, None) => None,
+        };
+        match sealed_entries {
+            Some(encrypted_entries) => {
+                serde_json::to_string(&SealedAccountKeyring { encrypted_entries })
+            }
+            None => serde_json::to_string(&keyring),
+        }
+        .map_err(Error::Serialize)
+    }
+
     async fn save_to_disk(&mut self) -> Result<()> {
         let file = self.file.clone();
-        let token = self.token.clone();
+        let buffer = self.serialize()?;
 
         tokio::task::spawn_blocking(move || {
             let mut file = file.lock().unwrap();
             file.get_mut().set_len(0).map_err(Error::Write)?;
             file.seek(io::SeekFrom::Start(0)).map_err(Error::Write)?;
-            if let Some(token) = token {
-                write!(&mut file, "{}", token).map_err(Error::Write)?;
-            }
+            file.write_all(buffer.as_bytes()).map_err(Error::Write)?;
             file.flush().map_err(Error::Write)?;
             file.get_mut().sync_all().map_err(Error::Write)
         })
@@ -198,3 +429,135 @@ impl AccountHistory {
         .map_err(Error::WriteCancelled)?
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+    use mullvad_types::wireguard::AssociatedAddresses;
+    use talpid_types::net::wireguard::PrivateKey;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn open_history(dir: &Path) -> AccountHistory {
+        let mut settings = SettingsPersister::load(dir).await;
+        AccountHistory::new(dir, dir, &mut settings).await.unwrap()
+    }
+
+    fn wireguard_data() -> WireguardData {
+        WireguardData {
+            private_key: PrivateKey::new_from_random(),
+            addresses: AssociatedAddresses {
+                ipv4_address: "10.64.0.2/32".parse().unwrap(),
+                ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+            },
+            created: Utc::now(),
+            usage: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_keyring_round_trip() {
+        let dir = temp_dir();
+        run(async {
+            let entry = AccountEntry {
+                account: "1111".to_owned(),
+                wireguard: Some(wireguard_data()),
+                relay_settings: None,
+            };
+
+            let mut history = open_history(&dir).await;
+            history.store(entry.clone()).await.unwrap();
+            history.set("2222".to_owned()).await.unwrap();
+            drop(history);
+
+            let history = open_history(&dir).await;
+            assert_eq!(history.get(), Some("2222".to_owned()));
+            assert_eq!(history.get_entry("1111"), Some(&entry));
+            assert_eq!(
+                history.entries,
+                vec![AccountEntry::new("2222".to_owned()), entry]
+            );
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_single_token() {
+        let dir = temp_dir();
+        let path = dir.join(ACCOUNT_HISTORY_FILE);
+        fs::write(&path, "1234567890123456").unwrap();
+        run(async {
+            let history = open_history(&dir).await;
+            assert_eq!(history.get(), Some("1234567890123456".to_owned()));
+            assert_eq!(history.entries.len(), 1);
+        });
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let keyring: AccountKeyring = serde_json::from_str(&contents).unwrap();
+        assert_eq!(
+            keyring.entries,
+            vec![AccountEntry::new("1234567890123456".to_owned())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_remove_list() {
+        let dir = temp_dir();
+        run(async {
+            let mut history = open_history(&dir).await;
+            history.set("1111".to_owned()).await.unwrap();
+            history
+                .store(AccountEntry::new("2222".to_owned()))
+                .await
+                .unwrap();
+
+            // Storing an existing account replaces its entry in place
+            let data = wireguard_data();
+            history
+                .store(AccountEntry {
+                    account: "1111".to_owned(),
+                    wireguard: Some(data.clone()),
+                    relay_settings: None,
+                })
+                .await
+                .unwrap();
+
+            let accounts = history.list(Some("1111"));
+            assert_eq!(accounts.len(), 2);
+            assert_eq!(accounts[0].token, "1111");
+            assert_eq!(
+                accounts[0]
+                    .wireguard_key
+                    .as_ref()
+                    .map(|key| key.key.to_base64()),
+                Some(data.private_key.public_key().to_base64())
+            );
+            assert!(accounts[0].active);
+            assert_eq!(accounts[1].token, "2222");
+            assert!(accounts[1].wireguard_key.is_none());
+            assert!(!accounts[1].active);
+
+            let removed = history.remove("1111").await.unwrap().unwrap();
+            assert_eq!(removed.wireguard, Some(data));
+            assert_eq!(history.remove("1111").await.unwrap(), None);
+            assert_eq!(history.get(), Some("2222".to_owned()));
+            assert!(history.list(None).iter().all(|account| !account.active));
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,621 @@
+//! Read-only file, maintained by an administrator, that restricts some settings and commands to
+//! administrators, and assigns roles to the clients of the management interface. Clients are
+//! identified by the peer credentials of their connection to the management interface socket.
+//! The `metrics` setting is admin-only unless `admin_only_settings` is given. Example:
+//!
+//! ```json
+//! {
+//!     "admin_group": "wheel",
+//!     "admin_only_commands": ["disconnect", "factory_reset", "account_change"],
+//!     "admin_only_settings": ["block_when_disconnected", "allow_lan"],
+//!     "default_role": "control",
+//!     "roles": [{ "group": "monitoring", "role": "monitor" }]
+//! }
+//! ```
+
+use mullvad_management_interface::{Authorizer, PeerCredentials, Status};
+use serde::Deserialize;
+use std::{collections::HashSet, fmt, path::Path};
+use tokio::{fs, io};
+
+/// Name of the policy file in the settings directory.
+pub const POLICY_FILE: &str = "admin-policy.json";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read the admin policy file")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Unable to parse the admin policy file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "The group \"{}\" does not exist", _0)]
+    NoSuchGroup(String),
+
+    #[error(display = "The user \"{}\" does not exist", _0)]
+    NoSuchUser(String),
+
+    #[error(display = "A role must be assigned to either a user or a group")]
+    InvalidRoleMapping,
+
+    #[cfg(unix)]
+    #[error(display = "Failed to obtain the ID of a group")]
+    ObtainGid(#[error(source)] nix::Error),
+
+    #[cfg(unix)]
+    #[error(display = "Failed to obtain the ID of a user")]
+    ObtainUid(#[error(source)] nix::Error),
+}
+
+/// Settings that are admin-only unless the policy file lists the admin-only settings. Changing the
+/// metrics endpoint makes the daemon create a socket as root.
+const DEFAULT_ADMIN_ONLY_SETTINGS: &[&str] = &["metrics"];
+
+/// Methods that clients with the [`Role::Monitor`] role may call.
+const MONITOR_METHODS: &[&str] = &["GetTunnelState", "EventsListen", "GetSettings"];
+
+/// Roles of management interface clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// May only observe the tunnel state and the settings.
+    Monitor,
+    /// May call every method, subject to the admin-only commands and settings.
+    Control,
+}
+
+impl Role {
+    /// Returns whether the role may call `method`, e.g. `GetTunnelState`.
+    pub fn may_call(self, method: &str) -> bool {
+        match self {
+            Role::Monitor => MONITOR_METHODS.contains(&method),
+            Role::Control => true,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Monitor => f.write_str("monitor"),
+            Role::Control => f.write_str("control"),
+        }
+    }
+}
+
+/// Commands that can be restricted to administrators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminCommand {
+    Connect,
+    Disconnect,
+    Reconnect,
+    Shutdown,
+    FactoryReset,
+    /// Logging in, creating, switching or forgetting accounts, and clearing the account history.
+    AccountChange,
+    /// Changing how the settings secrets are encrypted.
+    SettingsEncryption,
+    /// Exporting the settings including the account token and WireGuard key.
+    ExportSecrets,
+    /// Excluding processes from the tunnel, or no longer excluding them.
+    SplitTunnel,
+}
+
+impl fmt::Display for AdminCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AdminCommand::Connect => "Connecting",
+            AdminCommand::Disconnect => "Disconnecting",
+            AdminCommand::Reconnect => "Reconnecting",
+            AdminCommand::Shutdown => "Shutting down the daemon",
+            AdminCommand::FactoryReset => "Factory reset",
+            AdminCommand::AccountChange => "Changing the account",
+            AdminCommand::SettingsEncryption => "Changing the settings encryption",
+            AdminCommand::ExportSecrets => "Exporting the settings secrets",
+            AdminCommand::SplitTunnel => "Excluding processes from the tunnel",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An action that may be restricted by the policy.
+#[derive(Debug, Clone, Copy)]
+pub enum Action<'a> {
+    Command(AdminCommand),
+    /// Changing the setting with the given name, e.g. `tunnel_options.dns_options`.
+    ChangeSetting(&'a str),
+    /// Replacing all settings.
+    ImportSettings,
+}
+
+impl<'a> fmt::Display for Action<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Command(command) => command.fmt(f),
+            Action::ChangeSetting(setting) => write!(f, "Changing \"{}\"", setting),
+            Action::ImportSettings => f.write_str("Importing settings"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    admin_group: Option<String>,
+    admin_only_commands: HashSet<AdminCommand>,
+    #[serde(default = "default_admin_only_settings")]
+    admin_only_settings: Vec<String>,
+    default_role: Option<Role>,
+    roles: Vec<RoleMapping>,
+}
+
+fn default_admin_only_settings() -> Vec<String> {
+    DEFAULT_ADMIN_ONLY_SETTINGS
+        .iter()
+        .map(|setting| setting.to_string())
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleMapping {
+    user: Option<String>,
+    group: Option<String>,
+    role: Role,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Principal {
+    User(u32),
+    /// Matches clients that are members of the given group, either as their primary group or as
+    /// a supplementary group.
+    Group(u32),
+}
+
+impl Principal {
+    fn matches(self, peer: PeerCredentials) -> bool {
+        match self {
+            Principal::User(uid) => peer.uid == uid,
+            Principal::Group(gid) => is_member_of(peer, gid),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AdminPolicy {
+    /// Clients that are members of this group are administrators, in addition to root.
+    admin_gid: Option<u32>,
+    admin_only_commands: HashSet<AdminCommand>,
+    /// Names of settings, where a setting also covers all settings nested within it.
+    admin_only_settings: Vec<String>,
+    /// Role of clients that don't match any entry in `roles`.
+    default_role: Role,
+    /// Roles of specific clients. The first matching entry applies.
+    roles: Vec<(Principal, Role)>,
+}
+
+impl Default for AdminPolicy {
+    fn default() -> Self {
+        AdminPolicy {
+            admin_gid: None,
+            admin_only_commands: HashSet::new(),
+            admin_only_settings: default_admin_only_settings(),
+            default_role: Role::Control,
+            roles: Vec::new(),
+        }
+    }
+}
+
+impl AdminPolicy {
+    /// Loads the policy file from the settings directory. Only the default admin-only settings are
+    /// restricted if there is no policy file.
+    pub async fn load(settings_dir: &Path) -> Result<Self> {
+        let path = settings_dir.join(POLICY_FILE);
+        match fs::read(&path).await {
+            Ok(data) => {
+                log::info!("Loading admin policy from {}", path.display());
+                Self::parse(&data)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(Error::Read(error)),
+        }
+    }
+
+    fn parse(data: &[u8]) -> Result<Self> {
+        let file: PolicyFile = serde_json::from_slice(data).map_err(Error::Parse)?;
+        let admin_gid = match file.admin_group {
+            Some(group) => Some(group_id(&group)?),
+            None => None,
+        };
+        let roles = file
+            .roles
+            .iter()
+            .map(|mapping| {
+                let principal = match (&mapping.user, &mapping.group) {
+                    (Some(user), None) => Principal::User(user_id(user)?),
+                    (None, Some(group)) => Principal::Group(group_id(group)?),
+                    _ => return Err(Error::InvalidRoleMapping),
+                };
+                Ok((principal, mapping.role))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        #[cfg(windows)]
+        {
+            if !file.admin_only_commands.is_empty()
+                || file.admin_only_settings != default_admin_only_settings()
+                || !roles.is_empty()
+                || file.default_role.is_some()
+            {
+                log::warn!("The admin policy is not enforced on Windows");
+            }
+        }
+        Ok(AdminPolicy {
+            admin_gid,
+            admin_only_commands: file.admin_only_commands,
+            admin_only_settings: file.admin_only_settings,
+            default_role: file.default_role.unwrap_or(Role::Control),
+            roles,
+        })
+    }
+
+    /// Returns the role of a client with the given credentials. Root always has the
+    /// [`Role::Control`] role.
+    pub fn role(&self, peer: Option<PeerCredentials>) -> Role {
+        if cfg!(windows) {
+            return Role::Control;
+        }
+        match peer {
+            Some(peer) if peer.uid == 0 => Role::Control,
+            Some(peer) => self
+                .roles
+                .iter()
+                .find(|(principal, _)| principal.matches(peer))
+                .map(|(_, role)| *role)
+                .unwrap_or(self.default_role),
+            None => self.default_role,
+        }
+    }
+
+    /// Returns whether a client with the given credentials may perform `action`.
+    pub fn is_allowed(&self, peer: Option<PeerCredentials>, action: Action<'_>) -> bool {
+        !self.is_restricted(action) || self.is_admin(peer)
+    }
+
+    fn is_restricted(&self, action: Action<'_>) -> bool {
+        if cfg!(windows) {
+            return false;
+        }
+        match action {
+            Action::Command(command) => self.admin_only_commands.contains(&command),
+            Action::ChangeSetting(setting) => self
+                .admin_only_settings
+                .iter()
+                .any(|restricted| is_same_or_nested(setting, restricted)),
+            Action::ImportSettings => !self.admin_only_settings.is_empty(),
+        }
+    }
+
+    fn is_admin(&self, peer: Option<PeerCredentials>) -> bool {
+        match peer {
+            Some(peer) => {
+                peer.uid == 0
+                    || self
+                        .admin_gid
+                        .map(|gid| is_member_of(peer, gid))
+                        .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns whether `setting` is `restricted` or nested within it, or the other way around.
+fn is_same_or_nested(setting: &str, restricted: &str) -> bool {
+    let (shorter, longer) = if setting.len() <= restricted.len() {
+        (setting, restricted)
+    } else {
+        (restricted, setting)
+    };
+    longer == shorter || (longer.starts_with(shorter) && longer[shorter.len()..].starts_with('.'))
+}
+
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<u32> {
+    nix::unistd::Group::from_name(group)
+        .map_err(Error::ObtainGid)?
+        .map(|group| group.gid.as_raw())
+        .ok_or_else(|| Error::NoSuchGroup(group.to_owned()))
+}
+
+#[cfg(unix)]
+fn user_id(user: &str) -> Result<u32> {
+    nix::unistd::User::from_name(user)
+        .map_err(Error::ObtainUid)?
+        .map(|user| user.uid.as_raw())
+        .ok_or_else(|| Error::NoSuchUser(user.to_owned()))
+}
+
+/// Returns whether the client is a member of the group, either as its primary group or as a
+/// supplementary group of the user.
+fn is_member_of(peer: PeerCredentials, gid: u32) -> bool {
+    peer.gid == gid || supplementary_groups(peer).contains(&gid)
+}
+
+/// Returns the groups of the user of the client, as listed in the group database. The peer
+/// credentials only contain the primary group.
+#[cfg(unix)]
+fn supplementary_groups(peer: PeerCredentials) -> Vec<u32> {
+    use nix::unistd::{Gid, Uid, User};
+    use std::ffi::CString;
+
+    let user = match User::from_uid(Uid::from_raw(peer.uid)) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Vec::new(),
+        Err(error) => {
+            log::error!("Failed to look up the user {}: {}", peer.uid, error);
+            return Vec::new();
+        }
+    };
+    let name = match CString::new(user.name) {
+        Ok(name) => name,
+        Err(_) => return Vec::new(),
+    };
+    match get_group_list(&name, Gid::from_raw(peer.gid)) {
+        Ok(groups) => groups,
+        Err(error) => {
+            log::error!(
+                "Failed to obtain the groups of the user {}: {}",
+                peer.uid,
+                error
+            );
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn get_group_list(name: &std::ffi::CStr, gid: nix::unistd::Gid) -> nix::Result<Vec<u32>> {
+    nix::unistd::getgrouplist(name, gid)
+        .map(|groups| groups.into_iter().map(|group| group.as_raw()).collect())
+}
+
+/// `nix` doesn't provide `getgrouplist` on macOS, where the groups are `c_int`s.
+#[cfg(target_os = "macos")]
+fn get_group_list(name: &std::ffi::CStr, gid: nix::unistd::Gid) -> nix::Result<Vec<u32>> {
+    let mut groups: Vec<libc::c_int> = vec![0; 64];
+    loop {
+        let mut len = groups.len() as libc::c_int;
+        let result = unsafe {
+            libc::getgrouplist(
+                name.as_ptr(),
+                gid.as_raw() as libc::c_int,
+                groups.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        if result != -1 {
+            groups.truncate(len as usize);
+            return Ok(groups.into_iter().map(|group| group as u32).collect());
+        }
+        if groups.len() >= 4096 {
+            return Err(nix::Error::Sys(nix::errno::Errno::ERANGE));
+        }
+        let new_len = groups.len() * 2;
+        groups.resize(new_len, 0);
+    }
+}
+
+#[cfg(windows)]
+fn supplementary_groups(_peer: PeerCredentials) -> Vec<u32> {
+    Vec::new()
+}
+
+#[cfg(windows)]
+fn group_id(group: &str) -> Result<u32> {
+    Err(Error::NoSuchGroup(group.to_owned()))
+}
+
+#[cfg(windows)]
+fn user_id(user: &str) -> Result<u32> {
+    Err(Error::NoSuchUser(user.to_owned()))
+}
+
+impl Authorizer for AdminPolicy {
+    fn authorize(
+        &self,
+        method: &str,
+        peer: Option<PeerCredentials>,
+    ) -> std::result::Result<(), Status> {
+        let role = self.role(peer);
+        if role.may_call(method) {
+            Ok(())
+        } else {
+            log::warn!(
+                "Denied call to {} from {:?} with role {}",
+                method,
+                peer,
+                role
+            );
+            Err(Status::permission_denied(format!(
+                "Clients with the {} role may not call {}",
+                role, method
+            )))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_management_interface::Code;
+
+    const USER: Option<PeerCredentials> = Some(PeerCredentials {
+        uid: 1000,
+        gid: 1000,
+    });
+    const ROOT: Option<PeerCredentials> = Some(PeerCredentials { uid: 0, gid: 0 });
+    const MONITORING_AGENT: Option<PeerCredentials> = Some(PeerCredentials { uid: 998, gid: 998 });
+
+    /// Returns the names of all methods in the management interface.
+    fn management_methods() -> Vec<&'static str> {
+        include_str!("../../mullvad-management-interface/proto/management_interface.proto")
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("rpc "))
+            .map(|rpc| rpc.split('(').next().unwrap().trim())
+            .collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_restricted_actions() {
+        let policy = AdminPolicy::parse(
+            br#"{
+                "admin_only_commands": ["disconnect", "factory_reset"],
+                "admin_only_settings": ["allow_lan", "tunnel_options.dns_options"]
+            }"#,
+        )
+        .unwrap();
+
+        let disconnect = Action::Command(AdminCommand::Disconnect);
+        assert!(!policy.is_allowed(USER, disconnect));
+        assert!(!policy.is_allowed(None, disconnect));
+        assert!(policy.is_allowed(ROOT, disconnect));
+        assert!(policy.is_allowed(USER, Action::Command(AdminCommand::Connect)));
+
+        assert!(!policy.is_allowed(USER, Action::ChangeSetting("allow_lan")));
+        assert!(policy.is_allowed(ROOT, Action::ChangeSetting("allow_lan")));
+        assert!(policy.is_allowed(USER, Action::ChangeSetting("allow_lan_extra")));
+        assert!(!policy.is_allowed(USER, Action::ChangeSetting("tunnel_options")));
+        assert!(!policy.is_allowed(
+            USER,
+            Action::ChangeSetting("tunnel_options.dns_options.custom_options")
+        ));
+        assert!(policy.is_allowed(USER, Action::ChangeSetting("tunnel_options.generic")));
+        assert!(!policy.is_allowed(USER, Action::ImportSettings));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_admin_group() {
+        let mut policy =
+            AdminPolicy::parse(br#"{ "admin_only_commands": ["disconnect"] }"#).unwrap();
+        policy.admin_gid = Some(1000);
+        assert!(policy.is_allowed(USER, Action::Command(AdminCommand::Disconnect)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_supplementary_admin_group() {
+        // The group list of an existing user contains at least the primary group that was passed
+        // to `getgrouplist`.
+        let peer = PeerCredentials {
+            uid: nix::unistd::getuid().as_raw(),
+            gid: nix::unistd::getgid().as_raw(),
+        };
+        let groups = supplementary_groups(peer);
+        assert!(groups.contains(&peer.gid));
+        for gid in groups {
+            assert!(is_member_of(peer, gid));
+        }
+
+        let unknown_user = PeerCredentials {
+            uid: u32::MAX - 1,
+            gid: 1000,
+        };
+        assert!(supplementary_groups(unknown_user).is_empty());
+        assert!(is_member_of(unknown_user, 1000));
+        assert!(!is_member_of(unknown_user, 0));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_role_enforcement_for_every_method() {
+        let mut policy = AdminPolicy::parse(br#"{ "default_role": "monitor" }"#).unwrap();
+        policy.roles.push((Principal::Group(1000), Role::Control));
+
+        let methods = management_methods();
+        assert!(methods.len() > MONITOR_METHODS.len());
+        for method in MONITOR_METHODS {
+            assert!(methods.contains(method), "{} is not a method", method);
+        }
+
+        for method in methods {
+            let monitor_may_call = MONITOR_METHODS.contains(&method);
+            match policy.authorize(method, MONITORING_AGENT) {
+                Ok(()) => assert!(monitor_may_call, "monitor may call {}", method),
+                Err(status) => {
+                    assert!(!monitor_may_call, "monitor may not call {}", method);
+                    assert_eq!(status.code(), Code::PermissionDenied);
+                }
+            }
+            assert_eq!(
+                policy.authorize(method, None).is_ok(),
+                monitor_may_call,
+                "{}",
+                method
+            );
+            assert!(policy.authorize(method, USER).is_ok(), "{}", method);
+            assert!(policy.authorize(method, ROOT).is_ok(), "{}", method);
+        }
+    }
+
+    #[test]
+    fn test_default_role_may_call_every_method() {
+        let policy = AdminPolicy::parse(b"{}").unwrap();
+        for method in management_methods() {
+            assert!(policy.authorize(method, USER).is_ok(), "{}", method);
+            assert!(policy.authorize(method, None).is_ok(), "{}", method);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_role_mapping() {
+        let mut policy = AdminPolicy::parse(br#"{ "default_role": "monitor" }"#).unwrap();
+        policy.roles = vec![
+            (Principal::User(1000), Role::Monitor),
+            (Principal::Group(1000), Role::Control),
+        ];
+        assert_eq!(policy.role(USER), Role::Monitor);
+        assert_eq!(
+            policy.role(Some(PeerCredentials {
+                uid: 1001,
+                gid: 1000
+            })),
+            Role::Control
+        );
+        assert_eq!(policy.role(MONITORING_AGENT), Role::Monitor);
+        assert_eq!(policy.role(ROOT), Role::Control);
+
+        assert!(matches!(
+            AdminPolicy::parse(br#"{ "roles": [{ "role": "monitor" }] }"#),
+            Err(Error::InvalidRoleMapping)
+        ));
+    }
+
+    #[test]
+    fn test_empty_policy() {
+        let policy = AdminPolicy::parse(b"{}").unwrap();
+        assert!(policy.is_allowed(USER, Action::Command(AdminCommand::FactoryReset)));
+        assert!(policy.is_allowed(USER, Action::ChangeSetting("allow_lan")));
+        assert_eq!(
+            policy.is_allowed(None, Action::ImportSettings),
+            cfg!(windows)
+        );
+        assert_eq!(
+            policy.is_allowed(None, Action::ChangeSetting("metrics")),
+            cfg!(windows)
+        );
+        assert!(policy.is_allowed(ROOT, Action::ChangeSetting("metrics")));
+
+        let policy = AdminPolicy::parse(br#"{ "admin_only_settings": [] }"#).unwrap();
+        assert!(policy.is_allowed(USER, Action::ChangeSetting("metrics")));
+        assert!(policy.is_allowed(None, Action::ImportSettings));
+        assert!(AdminPolicy::parse(br#"{ "admin_only_commands": ["fly"] }"#).is_err());
+    }
+}
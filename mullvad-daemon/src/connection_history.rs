@@ -0,0 +1,344 @@
+//! Persisted journal of past tunnel sessions.
+
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+};
+use talpid_core::tunnel_state_machine::TunnelStats;
+use talpid_types::{
+    net::TunnelType,
+    tunnel::{ActionAfterDisconnect, ErrorStateCause},
+    ErrorExt,
+};
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read connection history file")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse connection history")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize connection history")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write connection history file")]
+    Write(#[error(source)] io::Error),
+}
+
+static CONNECTION_HISTORY_FILE: &str = "connection-history.json";
+
+/// Maximum number of sessions kept in the history. The oldest sessions are dropped first.
+const MAX_ENTRIES: usize = 500;
+
+/// A tunnel session, starting when the tunnel begins connecting and ending when it disconnects,
+/// enters the error state or starts connecting again after having been connected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionEntry {
+    pub started: DateTime<Utc>,
+    /// When the tunnel was connected. `None` if it never connected.
+    pub connected: Option<DateTime<Utc>>,
+    /// When the session ended. `None` for the active session.
+    pub ended: Option<DateTime<Utc>>,
+    /// Hostname of the exit relay. `None` for custom tunnel endpoints.
+    pub relay: Option<String>,
+    pub tunnel_type: TunnelType,
+    /// Hostname of the entry relay when using WireGuard multihop.
+    pub entry_relay: Option<String>,
+    /// Hostname of the bridge relay, if a bridge was used.
+    pub bridge: Option<String>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub end_reason: Option<EndReason>,
+    /// Number of retry attempts that were made before the relay of this session was selected.
+    pub retry_attempts: u32,
+}
+
+/// Why a session ended.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// The tunnel was disconnected, followed by the given action.
+    Disconnect(ActionAfterDisconnect),
+    /// The tunnel entered the error state.
+    Error(ErrorStateCause),
+    /// The tunnel was lost and a new one is being connected.
+    TunnelLost,
+}
+
+/// The relays that a session is connecting through.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionRelays {
+    pub relay: Option<String>,
+    pub tunnel_type: TunnelType,
+    pub entry_relay: Option<String>,
+    pub bridge: Option<String>,
+    pub retry_attempt: u32,
+}
+
+pub struct ConnectionHistory {
+    path: PathBuf,
+    /// Ended sessions, ordered from oldest to newest.
+    entries: VecDeque<SessionEntry>,
+    active: Option<SessionEntry>,
+    /// Total number of bytes received and sent by all tunnels when the active session was
+    /// connected.
+    traffic_start: Option<(u64, u64)>,
+}
+
+impl ConnectionHistory {
+    /// Loads the history from the cache directory. A missing or unreadable history file results
+    /// in an empty history.
+    pub async fn new(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(CONNECTION_HISTORY_FILE);
+        let entries = match Self::load(&path).await {
+            Ok(entries) => entries,
+            Err(Error::Read(ref error)) if error.kind() == io::ErrorKind::NotFound => {
+                VecDeque::new()
+            }
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to load connection history")
+                );
+                VecDeque::new()
+            }
+        };
+        ConnectionHistory {
+            path,
+            entries,
+            active: None,
+            traffic_start: None,
+        }
+    }
+
+    async fn load(path: &Path) -> Result<VecDeque<SessionEntry>, Error> {
+        let content = tokio::fs::read_to_string(path).await.map_err(Error::Read)?;
+        serde_json::from_str(&content).map_err(Error::Parse)
+    }
+
+    /// Writes all ended sessions to disk.
+    pub async fn save(&self) -> Result<(), Error> {
+        let buffer = serde_json::to_vec(&self.entries).map_err(Error::Serialize)?;
+        tokio::fs::write(&self.path, buffer)
+            .await
+            .map_err(Error::Write)
+    }
+
+    /// Returns all sessions, ordered from oldest to newest. The active session, if any, is last.
+    pub fn list(&self) -> Vec<SessionEntry> {
+        self.entries
+            .iter()
+            .chain(self.active.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Removes all ended sessions. The active session is kept.
+    pub async fn clear(&mut self) -> Result<(), Error> {
+        self.entries.clear();
+        self.save().await
+    }
+
+    /// Records that the tunnel is connecting through the given relays. Returns whether a
+    /// previous session was ended.
+    pub fn connecting(&mut self, relays: SessionRelays, now: DateTime<Utc>) -> bool {
+        let mut ended = false;
+        if let Some(active) = &mut self.active {
+            if active.connected.is_none() {
+                active.relay = relays.relay;
+                active.tunnel_type = relays.tunnel_type;
+                active.entry_relay = relays.entry_relay;
+                active.bridge = relays.bridge;
+                active.retry_attempts = relays.retry_attempt;
+                return false;
+            }
+            ended = self.end(EndReason::TunnelLost, now);
+        }
+        self.traffic_start = None;
+        self.active = Some(SessionEntry {
+            started: now,
+            connected: None,
+            ended: None,
+            relay: relays.relay,
+            tunnel_type: relays.tunnel_type,
+            entry_relay: relays.entry_relay,
+            bridge: relays.bridge,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            end_reason: None,
+            retry_attempts: relays.retry_attempt,
+        });
+        ended
+    }
+
+    /// Records that the active session is connected.
+    pub fn connected(&mut self, now: DateTime<Utc>) {
+        if let Some(active) = &mut self.active {
+            active.connected.get_or_insert(now);
+        }
+    }
+
+    /// Updates the number of bytes transferred by the active session, if it is connected. The
+    /// traffic is computed from the totals, since the counters of the current tunnel are reset
+    /// when its peers are replaced.
+    pub fn update_traffic(&mut self, stats: &TunnelStats) {
+        let active = match &mut self.active {
+            Some(active) if active.connected.is_some() => active,
+            _ => return,
+        };
+        let (start_rx_bytes, start_tx_bytes) = *self.traffic_start.get_or_insert((
+            stats.total_rx_bytes.saturating_sub(stats.rx_bytes),
+            stats.total_tx_bytes.saturating_sub(stats.tx_bytes),
+        ));
+        active.rx_bytes = stats.total_rx_bytes.saturating_sub(start_rx_bytes);
+        active.tx_bytes = stats.total_tx_bytes.saturating_sub(start_tx_bytes);
+    }
+
+    /// Ends the active session, if there is one. Returns whether a session was ended.
+    pub fn end(&mut self, reason: EndReason, now: DateTime<Utc>) -> bool {
+        let mut session = match self.active.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.traffic_start = None;
+        session.ended = Some(now);
+        session.end_reason = Some(reason);
+        self.entries.push_back(session);
+        while self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    fn empty_history() -> ConnectionHistory {
+        ConnectionHistory {
+            path: PathBuf::new(),
+            entries: VecDeque::new(),
+            active: None,
+            traffic_start: None,
+        }
+    }
+
+    fn stats(
+        rx_bytes: u64,
+        tx_bytes: u64,
+        total_rx_bytes: u64,
+        total_tx_bytes: u64,
+    ) -> TunnelStats {
+        TunnelStats {
+            rx_bytes,
+            tx_bytes,
+            total_rx_bytes,
+            total_tx_bytes,
+            ..TunnelStats::default()
+        }
+    }
+
+    fn relays(relay: &str, retry_attempt: u32) -> SessionRelays {
+        SessionRelays {
+            relay: Some(relay.to_string()),
+            tunnel_type: TunnelType::Wireguard,
+            entry_relay: None,
+            bridge: None,
+            retry_attempt,
+        }
+    }
+
+    #[test]
+    fn connect_attempts_share_session() {
+        let mut history = empty_history();
+        let start = Utc::now();
+        assert!(!history.connecting(relays("se-got-001", 0), start));
+        assert!(!history.connecting(relays("se-got-002", 1), start + Duration::seconds(5)));
+        history.connected(start + Duration::seconds(6));
+        history.update_traffic(&stats(100, 200, 100, 200));
+        assert!(history.end(
+            EndReason::Disconnect(ActionAfterDisconnect::Nothing),
+            start + Duration::seconds(10)
+        ));
+
+        let sessions = history.list();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.started, start);
+        assert_eq!(session.connected, Some(start + Duration::seconds(6)));
+        assert_eq!(session.relay.as_deref(), Some("se-got-002"));
+        assert_eq!(session.retry_attempts, 1);
+        assert_eq!((session.rx_bytes, session.tx_bytes), (100, 200));
+        assert_eq!(
+            session.end_reason,
+            Some(EndReason::Disconnect(ActionAfterDisconnect::Nothing))
+        );
+    }
+
+    #[test]
+    fn reconnecting_after_connected_ends_session() {
+        let mut history = empty_history();
+        let start = Utc::now();
+        history.connecting(relays("se-got-001", 0), start);
+        history.connected(start);
+        assert!(history.connecting(relays("se-got-002", 0), start + Duration::seconds(60)));
+
+        let sessions = history.list();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].end_reason, Some(EndReason::TunnelLost));
+        assert_eq!(sessions[1].relay.as_deref(), Some("se-got-002"));
+        assert_eq!(sessions[1].ended, None);
+    }
+
+    #[test]
+    fn traffic_is_ignored_until_connected() {
+        let mut history = empty_history();
+        history.update_traffic(&stats(1, 1, 1, 1));
+        history.connecting(relays("se-got-001", 0), Utc::now());
+        history.update_traffic(&stats(1, 1, 1, 1));
+        assert_eq!(history.list()[0].rx_bytes, 0);
+    }
+
+    #[test]
+    fn traffic_is_counted_per_session() {
+        let mut history = empty_history();
+        let start = Utc::now();
+        history.connecting(relays("se-got-001", 0), start);
+        history.connected(start);
+        history.update_traffic(&stats(100, 200, 1100, 1200));
+        // Replacing the key resets the counters of the tunnel, but not the totals
+        history.update_traffic(&stats(10, 20, 1150, 1250));
+        assert_eq!(
+            (history.list()[0].rx_bytes, history.list()[0].tx_bytes),
+            (150, 250)
+        );
+
+        history.connecting(relays("se-got-002", 0), start);
+        history.connected(start);
+        history.update_traffic(&stats(5, 5, 1200, 1300));
+        let sessions = history.list();
+        assert_eq!((sessions[0].rx_bytes, sessions[0].tx_bytes), (150, 250));
+        assert_eq!((sessions[1].rx_bytes, sessions[1].tx_bytes), (5, 5));
+    }
+
+    #[test]
+    fn drops_oldest_sessions() {
+        let mut history = empty_history();
+        let start = Utc::now();
+        for i in 0..MAX_ENTRIES + 1 {
+            history.connecting(relays(&i.to_string(), 0), start);
+            history.end(EndReason::Error(ErrorStateCause::IsOffline), start);
+        }
+        let sessions = history.list();
+        assert_eq!(sessions.len(), MAX_ENTRIES);
+        assert_eq!(sessions[0].relay.as_deref(), Some("1"));
+        assert!(!history.end(EndReason::TunnelLost, start));
+    }
+}
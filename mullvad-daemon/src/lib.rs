@@ -6,16 +6,22 @@ extern crate serde;
 
 
 pub mod account_history;
+pub mod admin_policy;
+pub mod connection_history;
 pub mod exception_logging;
 mod geoip;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
+mod metrics;
+mod relay_health;
 mod relays;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
 pub mod settings;
+pub mod settings_encryption;
+pub mod settings_override;
 pub mod version;
 mod version_check;
 
@@ -27,18 +33,21 @@ use futures::{
 use log::{debug, error, info, warn};
 use mullvad_rpc::AccountsProxy;
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{AccountData, AccountToken, StoredAccount, VoucherSubmission},
+    custom_tunnel::wg_quick,
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
-        RelaySettingsUpdate,
+        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint,
+        RelayConstraints, RelaySettings, RelaySettingsUpdate,
     },
     relay_list::{Relay, RelayList},
-    settings::{DnsOptions, DnsState, Settings},
+    settings::{
+        DnsOptions, DnsState, MetricsSettings, SecretsKeySource, Settings, SettingsEncryptionState,
+    },
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
-    wireguard::{KeygenEvent, RotationInterval},
+    wireguard::{KeygenEvent, RotationInterval, RotationPolicy, WireguardData, WireguardDevice},
 };
 use settings::SettingsPersister;
 #[cfg(target_os = "android")]
@@ -54,17 +63,26 @@ use std::{
     sync::{mpsc as sync_mpsc, Arc, Weak},
     time::Duration,
 };
+#[cfg(target_os = "linux")]
+use talpid_core::firewall::{BlockedTrafficStats, FirewallRules};
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
     mpsc::Sender,
-    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
+    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator, TunnelStats},
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
 use talpid_types::{
-    net::{openvpn, Endpoint, TransportProtocol, TunnelEndpoint, TunnelParameters, TunnelType},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    net::{
+        openvpn, wireguard::ConnectivityCheckOptions, Endpoint, TransportProtocol, TunnelEndpoint,
+        TunnelParameters, TunnelType,
+    },
+    tunnel::{
+        ActionAfterDisconnect, ErrorStateCause, ParameterGenerationError, TunnelStateTransition,
+    },
     ErrorExt,
 };
 use tokio::{fs, io};
@@ -82,6 +100,9 @@ const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// How often tunnel statistics are broadcast to clients while connected
+const TUNNEL_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
 lazy_static::lazy_static! {
     static ref DNS_AD_BLOCKING_SERVERS: [IpAddr; 1] = ["100.64.0.1".parse().unwrap()];
     static ref DNS_TRACKER_BLOCKING_SERVERS: [IpAddr; 1] = ["100.64.0.2".parse().unwrap()];
@@ -122,12 +143,24 @@ pub enum Error {
     #[error(display = "No wireguard private key available")]
     NoKeyAvailable,
 
+    #[error(display = "Invalid WireGuard device name")]
+    InvalidWireguardDeviceName,
+
+    #[error(display = "A WireGuard device named {} already exists", _0)]
+    WireguardDeviceExists(String),
+
+    #[error(display = "No WireGuard device named {}", _0)]
+    UnknownWireguardDevice(String),
+
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
     #[error(display = "No matching entry relay was found")]
     NoEntryRelayAvailable,
 
+    #[error(display = "No matching relay was found")]
+    NoRelayAvailable,
+
     #[error(display = "No account token is set")]
     NoAccountToken,
 
@@ -149,6 +182,12 @@ pub enum Error {
     #[error(display = "Failed to clear account history")]
     ClearAccountHistoryError(#[error(source)] account_history::Error),
 
+    #[error(display = "Failed to clear connection history")]
+    ClearConnectionHistoryError(#[error(source)] connection_history::Error),
+
+    #[error(display = "Failed to reset relay health")]
+    ResetRelayHealthError(#[error(source)] relay_health::Error),
+
     #[error(display = "Failed to clear settings")]
     ClearSettingsError(#[error(source)] settings::Error),
 
@@ -191,6 +230,8 @@ pub enum DaemonCommand {
     Reconnect(oneshot::Sender<bool>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
+    /// Request traffic statistics of the current and previous tunnels.
+    GetTunnelStats(oneshot::Sender<TunnelStats>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
     CreateNewAccount(ResponseTx<String, Error>),
@@ -205,13 +246,26 @@ pub enum DaemonCommand {
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
     /// Request account history
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
-    /// Remove the last used account, if there is one
+    /// Remove all accounts from the account history
     ClearAccountHistory(ResponseTx<(), Error>),
+    /// List the past tunnel sessions, ordered from oldest to newest
+    GetConnectionHistory(oneshot::Sender<Vec<connection_history::SessionEntry>>),
+    /// Remove all ended sessions from the connection history
+    ClearConnectionHistory(ResponseTx<(), Error>),
+    /// List the accounts stored in the account history
+    ListAccounts(oneshot::Sender<Vec<StoredAccount>>),
+    /// Switch to an account stored in the account history, restoring its WireGuard key and
+    /// relay settings
+    SwitchAccount(ResponseTx<(), Error>, AccountToken),
+    /// Remove an account from the account history, along with its WireGuard key
+    ForgetAccount(ResponseTx<(), Error>, AccountToken),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(oneshot::Sender<RelayList>),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
     /// updated.
     UpdateRelayLocations,
+    /// Forget all connection failures that relays are penalized for during relay selection
+    ResetRelayHealth(ResponseTx<(), Error>),
     /// Set which account token to use for subsequent connection attempts.
     SetAccount(ResponseTx<(), settings::Error>, Option<AccountToken>),
     /// Place constraints on the type of tunnel and relay
@@ -220,6 +274,8 @@ pub enum DaemonCommand {
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
+    /// Set the settings of the local metrics endpoint.
+    SetMetricsSettings(ResponseTx<(), settings::Error>, MetricsSettings),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
@@ -236,16 +292,58 @@ pub enum DaemonCommand {
     SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the network namespace to move WireGuard tunnels into
+    #[cfg(target_os = "linux")]
+    SetWireguardNetns(ResponseTx<(), settings::Error>, Option<String>),
+    /// Set the timing and probes of the WireGuard connectivity check
+    SetWireguardConnectivityCheck(ResponseTx<(), settings::Error>, ConnectivityCheckOptions),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
+    /// Set the conditions besides the interval that trigger automatic key rotation
+    SetWireguardRotationPolicy(ResponseTx<(), settings::Error>, RotationPolicy),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
+    /// Encrypt the secrets in the settings file using a key from the given source, or store them
+    /// in plain text if no source is given. The passphrase is only used by
+    /// `SecretsKeySource::Passphrase`.
+    SetSettingsEncryption(
+        ResponseTx<(), settings::Error>,
+        Option<SecretsKeySource>,
+        Option<String>,
+    ),
+    /// Get whether the secrets in the settings file are encrypted
+    GetSettingsEncryption(oneshot::Sender<SettingsEncryptionState>),
+    /// Decrypt secrets in the settings file that could not be decrypted when the daemon started
+    UnlockSettings(ResponseTx<(), Error>, Option<String>),
+    /// Serialize the settings, optionally including the account token
+    ExportSettings(ResponseTx<String, settings::Error>, bool),
+    /// Replace the settings with previously exported settings
+    ImportSettings(ResponseTx<(), Error>, String),
     /// Generate new wireguard key
     GenerateWireguardKey(ResponseTx<wireguard::KeygenEvent, Error>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(ResponseTx<Option<wireguard::PublicKey>, Error>),
     /// Verify if the currently set wireguard key is valid.
     VerifyWireguardKey(ResponseTx<bool, Error>),
+    /// Return `wg-quick` configs for the current relay, or for all WireGuard relays in the given
+    /// location, as pairs of hostnames and configs. The configs use the key of the given
+    /// WireGuard device, or the key of the tunnel if no device is given.
+    ExportWireguardConfig(
+        ResponseTx<Vec<(String, String)>, Error>,
+        Constraint<LocationConstraint>,
+        Option<String>,
+    ),
+    /// Add a WireGuard device with its own key to the account, for use by containers or virtual
+    /// machines
+    AddWireguardDevice(ResponseTx<WireguardDevice, Error>, String),
+    /// List the WireGuard devices of the account
+    ListWireguardDevices(oneshot::Sender<Vec<WireguardDevice>>),
+    /// Remove a WireGuard device and its key from the account
+    RemoveWireguardDevice(ResponseTx<(), Error>, String),
+    /// Replace the key of a WireGuard device
+    RotateWireguardDeviceKey(ResponseTx<WireguardDevice, Error>, String),
+    /// Set the automatic key rotation interval of a WireGuard device
+    SetWireguardDeviceRotationInterval(ResponseTx<(), Error>, String, Option<RotationInterval>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Get current version of the app
@@ -265,6 +363,30 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Exclude traffic of a user (UID) from the tunnel
+    #[cfg(target_os = "linux")]
+    AddSplitTunnelUid(ResponseTx<(), settings::Error>, u32),
+    /// Remove user (UID) from list of users excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    RemoveSplitTunnelUid(ResponseTx<(), settings::Error>, u32),
+    /// Return the applied firewall policy along with the intended and installed firewall rules
+    #[cfg(target_os = "linux")]
+    GetFirewallRules(oneshot::Sender<FirewallRules>),
+    /// Return counters and samples of the traffic blocked by the firewall
+    #[cfg(target_os = "linux")]
+    GetBlockedTrafficStats(oneshot::Sender<BlockedTrafficStats>),
+    /// Set whether the traffic blocked by the firewall is counted and logged
+    #[cfg(target_os = "linux")]
+    SetAuditBlockedTraffic(ResponseTx<(), settings::Error>, bool),
+    /// Add a custom firewall rule for traffic that is always allowed
+    #[cfg(target_os = "linux")]
+    AddAllowRule(ResponseTx<(), settings::Error>, AllowRule),
+    /// Remove a custom firewall rule
+    #[cfg(target_os = "linux")]
+    RemoveAllowRule(ResponseTx<(), settings::Error>, AllowRule),
+    /// Remove all custom firewall rules
+    #[cfg(target_os = "linux")]
+    ClearAllowRules(ResponseTx<(), settings::Error>),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
@@ -306,10 +428,20 @@ pub(crate) enum InternalDaemonEvent {
             Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
         ),
     ),
+    /// Automatic key rotation event of a WireGuard device
+    WgDeviceKeyEvent(
+        (
+            AccountToken,
+            String,
+            Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
+        ),
+    ),
     /// New Account created
     NewAccountEvent(AccountToken, oneshot::Sender<Result<String, Error>>),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// Traffic statistics were polled from the connected tunnel.
+    TunnelStats(TunnelStats),
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -493,6 +625,9 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify clients of the current tunnel statistics. Sent periodically while connected.
+    fn notify_tunnel_stats(&self, tunnel_stats: TunnelStats);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -506,17 +641,31 @@ pub struct Daemon<L: EventListener> {
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    tunnel_stats_job: Option<AbortHandle>,
+    key_swap_job: Option<AbortHandle>,
+    metrics: metrics::Metrics,
+    metrics_sources: metrics::MetricsSources,
+    metrics_server: Option<AbortHandle>,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
+    connection_history: connection_history::ConnectionHistory,
     accounts_proxy: AccountsProxy,
     rpc_runtime: mullvad_rpc::MullvadRpcRuntime,
     rpc_handle: mullvad_rpc::rest::MullvadRestHandle,
     wireguard_key_manager: wireguard::KeyManager,
+    key_usage: wireguard::KeyUsageTracker,
     version_updater_handle: version_check::VersionUpdaterHandle,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
     last_generated_bridge_relay: Option<Relay>,
+    last_generated_entry_relay: Option<Relay>,
+    last_retry_attempt: u32,
+    /// Hostnames of the relays that the current tunnel connected to.
+    connected_relays: Vec<String>,
+    /// Whether the daemon asked the tunnel state machine to reconnect since the last tunnel state
+    /// transition, in which case leaving the connected state is not a failure of the relays.
+    reconnect_requested: bool,
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
@@ -602,6 +751,7 @@ where
             account_history::AccountHistory::new(&cache_dir, &settings_dir, &mut settings)
                 .await
                 .map_err(Error::LoadAccountHistory)?;
+        let connection_history = connection_history::ConnectionHistory::new(&cache_dir).await;
 
         // Restore the tunnel to a previous state
         let target_cache = cache_dir.join(TARGET_START_STATE_FILE);
@@ -681,6 +831,12 @@ where
             android_context,
             #[cfg(windows)]
             exclude_apps,
+            #[cfg(target_os = "linux")]
+            settings.allow_rules.clone(),
+            #[cfg(target_os = "linux")]
+            settings.excluded_uids.clone(),
+            #[cfg(target_os = "linux")]
+            settings.audit_blocked_traffic,
         )
         .await
         .map_err(Error::TunnelError)?;
@@ -696,8 +852,18 @@ where
             }
         });
 
-        let wireguard_key_manager =
-            wireguard::KeyManager::new(internal_event_tx.clone(), rpc_handle.clone());
+        let metrics = metrics::Metrics::default();
+        let metrics_sources = metrics::MetricsSources {
+            tunnel_command_tx: Arc::downgrade(&tunnel_command_tx),
+            relay_list: relay_selector.last_updated_handle(),
+            request_stats: rpc_runtime.request_stats(),
+        };
+
+        let wireguard_key_manager = wireguard::KeyManager::new(
+            internal_event_tx.clone(),
+            rpc_handle.clone(),
+            metrics.clone(),
+        );
 
         // Attempt to download a fresh relay list
         relay_selector.update().await;
@@ -713,17 +879,28 @@ where
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            tunnel_stats_job: None,
+            key_swap_job: None,
+            metrics,
+            metrics_sources,
+            metrics_server: None,
             event_listener,
             settings,
             account_history,
+            connection_history,
             rpc_runtime,
             accounts_proxy: AccountsProxy::new(rpc_handle.clone()),
             rpc_handle,
             wireguard_key_manager,
+            key_usage: wireguard::KeyUsageTracker::default(),
             version_updater_handle,
             relay_selector,
             last_generated_relay: None,
             last_generated_bridge_relay: None,
+            last_generated_entry_relay: None,
+            last_retry_attempt: 0,
+            connected_relays: vec![],
+            reconnect_requested: false,
             app_version_info,
             shutdown_tasks: vec![],
             tunnel_state_machine_shutdown_signal,
@@ -731,6 +908,7 @@ where
         };
 
         daemon.ensure_wireguard_keys_for_current_account().await;
+        daemon.restart_metrics_server();
 
         Ok(daemon)
     }
@@ -870,12 +1048,14 @@ where
             Command(command) => self.handle_command(command).await,
             TriggerShutdown => self.trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event).await,
+            WgDeviceKeyEvent(key_event) => self.handle_wireguard_device_key_event(key_event).await,
             NewAccountEvent(account_token, tx) => {
                 self.handle_new_account_event(account_token, tx).await
             }
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            TunnelStats(tunnel_stats) => self.handle_tunnel_stats(tunnel_stats).await,
         }
     }
 
@@ -927,6 +1107,38 @@ where
             _ => {}
         }
 
+        let reconnect_requested = std::mem::take(&mut self.reconnect_requested);
+        if self.tunnel_state.is_connected() && !tunnel_state.is_connected() {
+            let relays = std::mem::take(&mut self.connected_relays);
+            if !reconnect_requested && Self::is_tunnel_failure(&tunnel_state) {
+                // The tunnel went down, e.g. since the connectivity monitor timed out
+                log::debug!("Connected tunnel failed, penalizing its relays");
+                self.record_relay_outcome(&relays, false).await;
+            }
+        }
+
+        if let TunnelState::Connected { ref endpoint, .. } = tunnel_state {
+            if !self.tunnel_state.is_connected() {
+                let relays = self.last_generated_hostnames();
+                self.record_relay_outcome(&relays, true).await;
+                self.connected_relays = relays;
+            }
+            self.start_tunnel_stats_job();
+            if endpoint.tunnel_type == TunnelType::Wireguard {
+                let switched = self
+                    .key_usage
+                    .record_connection(endpoint.endpoint.address.ip());
+                self.update_key_usage(switched).await;
+            }
+        } else {
+            self.stop_tunnel_stats_job();
+            // A new tunnel uses the new key from the start
+            self.stop_key_swap_job();
+            self.update_key_usage(true).await;
+        }
+
+        self.metrics.tunnel_state_changed(&tunnel_state);
+        self.update_connection_history(&tunnel_state).await;
         self.tunnel_state = tunnel_state.clone();
         self.event_listener.notify_new_state(tunnel_state);
     }
@@ -951,10 +1163,17 @@ where
         >,
         retry_attempt: u32,
     ) {
+        if retry_attempt > 0 {
+            // The previous attempt failed before the tunnel came up
+            let relays = self.last_generated_hostnames();
+            self.record_relay_outcome(&relays, false).await;
+        }
+        self.last_retry_attempt = retry_attempt;
         if let Some(account_token) = self.settings.get_account_token() {
             let result = match self.settings.get_relay_settings() {
                 RelaySettings::CustomTunnelEndpoint(custom_relay) => {
                     self.last_generated_relay = None;
+                    self.last_generated_entry_relay = None;
                     custom_relay
                         // TODO(emilsp): generate proxy settings for custom tunnels
                         .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
@@ -974,6 +1193,16 @@ where
                         )
                         .ok();
                     if let Some((relay, endpoint)) = endpoint {
+                        self.metrics
+                            .relay_selected(&relay.hostname, endpoint.tunnel_type());
+                        self.last_generated_entry_relay = match &endpoint {
+                            MullvadEndpoint::Wireguard {
+                                peer,
+                                exit_peer: Some(_),
+                                ..
+                            } => self.relay_selector.get_wireguard_relay(&peer.public_key),
+                            _ => None,
+                        };
                         let result = self
                             .create_tunnel_parameters(
                                 &relay,
@@ -1014,6 +1243,48 @@ where
         }
     }
 
+    /// Returns the hostnames of the most recently selected exit and entry relays.
+    fn last_generated_hostnames(&self) -> Vec<String> {
+        self.last_generated_relay
+            .iter()
+            .chain(self.last_generated_entry_relay.iter())
+            .map(|relay| relay.hostname.clone())
+            .collect()
+    }
+
+    /// Returns whether leaving the connected state for `new_state` means that the tunnel failed,
+    /// as opposed to being disconnected or blocked.
+    fn is_tunnel_failure(new_state: &TunnelState) -> bool {
+        matches!(
+            new_state,
+            TunnelState::Connecting { .. }
+                | TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect)
+        )
+    }
+
+    /// Records whether connecting to the given relays succeeded.
+    async fn record_relay_outcome(&mut self, hostnames: &[String], success: bool) {
+        if hostnames.is_empty() {
+            return;
+        }
+
+        let now = std::time::SystemTime::now();
+        let health = self.relay_selector.health_mut();
+        for hostname in hostnames {
+            if success {
+                health.record_success(hostname, now);
+            } else {
+                health.record_failure(hostname, now);
+            }
+        }
+        if let Err(error) = health.save().await {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save relay health")
+            );
+        }
+    }
+
     async fn create_tunnel_parameters(
         &mut self,
         relay: &Relay,
@@ -1094,13 +1365,7 @@ where
                 ipv6_gateway,
             } => {
                 let wg_data = self.settings.get_wireguard().ok_or(Error::NoKeyAvailable)?;
-                let tunnel = wireguard::TunnelConfig {
-                    private_key: wg_data.private_key,
-                    addresses: vec![
-                        wg_data.addresses.ipv4_address.ip().into(),
-                        wg_data.addresses.ipv6_address.ip().into(),
-                    ],
-                };
+                let tunnel = wireguard::tunnel_config(&wg_data);
                 Ok(wireguard::TunnelParameters {
                     connection: wireguard::ConnectionConfig {
                         tunnel,
@@ -1136,6 +1401,191 @@ where
         }
     }
 
+    fn start_tunnel_stats_job(&mut self) {
+        self.stop_tunnel_stats_job();
+
+        let tunnel_command_tx = self.tunnel_command_tx.clone();
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            let mut interval = tokio::time::interval(TUNNEL_STATS_INTERVAL);
+            loop {
+                interval.tick().await;
+                let (tx, rx) = oneshot::channel();
+                if tunnel_command_tx
+                    .unbounded_send(TunnelCommand::GetTunnelStats(tx))
+                    .is_err()
+                {
+                    break;
+                }
+                match rx.await {
+                    Ok(tunnel_stats) => {
+                        if daemon_tx
+                            .send(InternalDaemonEvent::TunnelStats(tunnel_stats))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        }));
+
+        tokio::spawn(future);
+        self.tunnel_stats_job = Some(abort_handle);
+    }
+
+    fn stop_tunnel_stats_job(&mut self) {
+        if let Some(job) = self.tunnel_stats_job.take() {
+            job.abort();
+        }
+    }
+
+    /// Swaps the key of the connected WireGuard tunnel to `new_data` after a grace period, instead
+    /// of reconnecting. Returns `false` if the key can't be swapped in place, which requires the
+    /// new key to use the same tunnel addresses as the old one.
+    fn schedule_key_swap(
+        &mut self,
+        old_data: Option<mullvad_types::wireguard::WireguardData>,
+        new_data: &mullvad_types::wireguard::WireguardData,
+    ) -> bool {
+        let old_data = match old_data {
+            Some(old_data) if old_data.addresses == new_data.addresses => old_data,
+            _ => return false,
+        };
+        if self.get_connected_tunnel_type() != Some(TunnelType::Wireguard) {
+            return false;
+        }
+        self.stop_key_swap_job();
+
+        let old_key = wireguard::tunnel_config(&old_data);
+        let new_key = wireguard::tunnel_config(new_data);
+        let tunnel_command_tx = self.tunnel_command_tx.clone();
+        let daemon_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            if !wireguard::KeyManager::swap_tunnel_key(tunnel_command_tx, old_key, new_key).await {
+                log::info!("Reconnecting to use the new WireGuard key");
+                let (tx, _) = oneshot::channel();
+                let _ = daemon_tx.send(DaemonCommand::Reconnect(tx));
+            }
+        }));
+
+        tokio::spawn(future);
+        self.key_swap_job = Some(abort_handle);
+        true
+    }
+
+    fn stop_key_swap_job(&mut self) {
+        if let Some(job) = self.key_swap_job.take() {
+            job.abort();
+        }
+    }
+
+    async fn handle_tunnel_stats(&mut self, tunnel_stats: TunnelStats) {
+        self.connection_history.update_traffic(&tunnel_stats);
+        self.key_usage.record_traffic(
+            tunnel_stats
+                .total_rx_bytes
+                .saturating_add(tunnel_stats.total_tx_bytes),
+            self.get_connected_tunnel_type() == Some(TunnelType::Wireguard),
+        );
+        self.update_key_usage(false).await;
+        self.event_listener.notify_tunnel_stats(tunnel_stats);
+    }
+
+    /// Reports the usage of the current WireGuard key to the key manager, and saves it to the
+    /// settings if it is due or `force_save` is set.
+    async fn update_key_usage(&mut self, force_save: bool) {
+        let mut data = match self.settings.get_wireguard() {
+            Some(data) => data,
+            None => return,
+        };
+        let usage = self.key_usage.usage(&data);
+        self.wireguard_key_manager
+            .update_key_usage(data.private_key.public_key(), usage.clone());
+
+        if self.key_usage.should_save(force_save) {
+            data.usage = usage;
+            match self.settings.set_wireguard(Some(data)).await {
+                Ok(_) => self.key_usage.saved(),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save WireGuard key usage")
+                ),
+            }
+        }
+    }
+
+    async fn update_connection_history(&mut self, tunnel_state: &TunnelState) {
+        use connection_history::{EndReason, SessionRelays};
+
+        let now = chrono::Utc::now();
+        let session_ended = match tunnel_state {
+            TunnelState::Connecting { endpoint, .. } => {
+                let hostname =
+                    |relay: &Option<Relay>| relay.as_ref().map(|relay| relay.hostname.clone());
+                let relays = SessionRelays {
+                    relay: hostname(&self.last_generated_relay),
+                    tunnel_type: endpoint.tunnel_type,
+                    entry_relay: hostname(&self.last_generated_entry_relay),
+                    bridge: hostname(&self.last_generated_bridge_relay),
+                    retry_attempt: self.last_retry_attempt,
+                };
+                self.connection_history.connecting(relays, now)
+            }
+            TunnelState::Connected { .. } => {
+                self.connection_history.connected(now);
+                false
+            }
+            TunnelState::Disconnecting(after_disconnect) => self
+                .connection_history
+                .end(EndReason::Disconnect(*after_disconnect), now),
+            TunnelState::Error(error_state) => self
+                .connection_history
+                .end(EndReason::Error(error_state.cause().clone()), now),
+            TunnelState::Disconnected => false,
+        };
+        if session_ended {
+            if let Err(error) = self.connection_history.save().await {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save connection history")
+                );
+            }
+        }
+    }
+
+    /// Stops the metrics endpoint, if it is running, and starts it again if it is enabled.
+    fn restart_metrics_server(&mut self) {
+        if let Some(server) = self.metrics_server.take() {
+            server.abort();
+        }
+
+        let settings = &self.settings.metrics;
+        if !settings.enabled {
+            return;
+        }
+        match metrics::serve(
+            &settings.listen_address,
+            &self.cache_dir,
+            self.metrics.clone(),
+            self.metrics_sources.clone(),
+        ) {
+            Ok(server) => {
+                info!("Serving metrics on {}", settings.listen_address);
+                let (future, abort_handle) = abortable(server);
+                tokio::spawn(future);
+                self.metrics_server = Some(abort_handle);
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start metrics endpoint")
+                );
+            }
+        }
+    }
+
 
     async fn handle_command(&mut self, command: DaemonCommand) {
         use self::DaemonCommand::*;
@@ -1147,6 +1597,7 @@ where
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
             Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
+            GetTunnelStats(tx) => self.on_get_tunnel_stats(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
@@ -1154,12 +1605,19 @@ where
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
             UpdateRelayLocations => self.on_update_relay_locations().await,
+            ResetRelayHealth(tx) => self.on_reset_relay_health(tx).await,
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token).await,
             GetAccountHistory(tx) => self.on_get_account_history(tx),
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
+            GetConnectionHistory(tx) => self.on_get_connection_history(tx),
+            ClearConnectionHistory(tx) => self.on_clear_connection_history(tx).await,
+            ListAccounts(tx) => self.on_list_accounts(tx),
+            SwitchAccount(tx, account_token) => self.on_switch_account(tx, account_token).await,
+            ForgetAccount(tx, account_token) => self.on_forget_account(tx, account_token).await,
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
+            SetMetricsSettings(tx, metrics) => self.on_set_metrics_settings(tx, metrics).await,
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
@@ -1173,13 +1631,42 @@ where
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            #[cfg(target_os = "linux")]
+            SetWireguardNetns(tx, netns) => self.on_set_wireguard_netns(tx, netns).await,
+            SetWireguardConnectivityCheck(tx, options) => {
+                self.on_set_wireguard_connectivity_check(tx, options).await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
+            SetWireguardRotationPolicy(tx, policy) => {
+                self.on_set_wireguard_rotation_policy(tx, policy).await
+            }
             GetSettings(tx) => self.on_get_settings(tx),
+            SetSettingsEncryption(tx, key_source, passphrase) => {
+                self.on_set_settings_encryption(tx, key_source, passphrase)
+                    .await
+            }
+            GetSettingsEncryption(tx) => self.on_get_settings_encryption(tx),
+            UnlockSettings(tx, passphrase) => self.on_unlock_settings(tx, passphrase).await,
+            ExportSettings(tx, include_secrets) => self.on_export_settings(tx, include_secrets),
+            ImportSettings(tx, data) => self.on_import_settings(tx, data).await,
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx).await,
+            ExportWireguardConfig(tx, location, device) => {
+                self.on_export_wireguard_config(tx, location, device)
+            }
+            AddWireguardDevice(tx, name) => self.on_add_wireguard_device(tx, name).await,
+            ListWireguardDevices(tx) => self.on_list_wireguard_devices(tx),
+            RemoveWireguardDevice(tx, name) => self.on_remove_wireguard_device(tx, name).await,
+            RotateWireguardDeviceKey(tx, name) => {
+                self.on_rotate_wireguard_device_key(tx, name).await
+            }
+            SetWireguardDeviceRotationInterval(tx, name, interval) => {
+                self.on_set_wireguard_device_rotation_interval(tx, name, interval)
+                    .await
+            }
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             #[cfg(not(target_os = "android"))]
@@ -1192,6 +1679,24 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            AddSplitTunnelUid(tx, uid) => self.on_add_split_tunnel_uid(tx, uid).await,
+            #[cfg(target_os = "linux")]
+            RemoveSplitTunnelUid(tx, uid) => self.on_remove_split_tunnel_uid(tx, uid).await,
+            #[cfg(target_os = "linux")]
+            GetFirewallRules(tx) => self.on_get_firewall_rules(tx),
+            #[cfg(target_os = "linux")]
+            GetBlockedTrafficStats(tx) => self.on_get_blocked_traffic_stats(tx),
+            #[cfg(target_os = "linux")]
+            SetAuditBlockedTraffic(tx, enabled) => {
+                self.on_set_audit_blocked_traffic(tx, enabled).await
+            }
+            #[cfg(target_os = "linux")]
+            AddAllowRule(tx, rule) => self.on_add_allow_rule(tx, rule).await,
+            #[cfg(target_os = "linux")]
+            RemoveAllowRule(tx, rule) => self.on_remove_allow_rule(tx, rule).await,
+            #[cfg(target_os = "linux")]
+            ClearAllowRules(tx) => self.on_clear_allow_rules(tx).await,
             #[cfg(windows)]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
             #[cfg(windows)]
@@ -1223,6 +1728,25 @@ where
             .map(|current_account| current_account != account)
             .unwrap_or(true)
         {
+            // A key that was rotated for an account that has since been switched away from must
+            // still be kept, since the old key is no longer valid.
+            if let Ok(data) = result {
+                if let Some(entry) = self.account_history.get_entry(&account) {
+                    if entry.wireguard.is_some() {
+                        let mut entry = entry.clone();
+                        entry.wireguard = Some(data);
+                        if let Err(error) = self.account_history.store(entry).await {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg(
+                                    "Failed to store new WireGuard key for inactive account"
+                                )
+                            );
+                        }
+                        return;
+                    }
+                }
+            }
             log::info!("Dropping wireguard key event since account has been changed");
             return;
         }
@@ -1230,11 +1754,14 @@ where
         match result {
             Ok(data) => {
                 let public_key = data.get_public_key();
-                let is_first_key = self.settings.get_wireguard().is_none();
-                match self.settings.set_wireguard(Some(data)).await {
+                let old_data = self.settings.get_wireguard();
+                let is_first_key = old_data.is_none();
+                match self.settings.set_wireguard(Some(data.clone())).await {
                     Ok(_) => {
                         if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                            self.schedule_reconnect(WG_RECONNECT_DELAY).await;
+                            if !self.schedule_key_swap(old_data, &data) {
+                                self.schedule_reconnect(WG_RECONNECT_DELAY).await;
+                            }
                         }
                         self.event_listener
                             .notify_key_event(KeygenEvent::NewKey(public_key));
@@ -1269,6 +1796,56 @@ where
         }
     }
 
+    async fn handle_wireguard_device_key_event(
+        &mut self,
+        event: (
+            AccountToken,
+            String,
+            Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
+        ),
+    ) {
+        let (account, name, result) = event;
+        let data = match result {
+            Ok(data) => data,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to rotate key of WireGuard device {}",
+                        name
+                    ))
+                );
+                return;
+            }
+        };
+
+        let device = match self.settings.get_wireguard_device(&name) {
+            Some(device) if self.settings.get_account_token().as_ref() == Some(&account) => {
+                device.clone()
+            }
+            _ => {
+                // The device or the account was removed while the key was being rotated, so the
+                // new key is not used by anything
+                log::info!("Removing rotated key of removed WireGuard device {}", name);
+                self.spawn_remove_device_key(account, name, &data);
+                return;
+            }
+        };
+        if let Err(error) = self
+            .settings
+            .set_wireguard_device(WireguardDevice { data, ..device })
+            .await
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to save new key of WireGuard device {}",
+                    name
+                ))
+            );
+        }
+    }
+
     async fn ensure_key_rotation(&mut self) {
         let token = match self.settings.get_account_token() {
             Some(token) => token,
@@ -1278,6 +1855,13 @@ where
             Some(data) => data.get_public_key(),
             None => return,
         };
+        self.wireguard_key_manager.set_rotation_policy(
+            self.settings
+                .tunnel_options
+                .wireguard
+                .rotation_policy
+                .clone(),
+        );
         self.wireguard_key_manager
             .set_rotation_interval(
                 public_key,
@@ -1285,6 +1869,7 @@ where
                 self.settings.tunnel_options.wireguard.rotation_interval,
             )
             .await;
+        self.update_key_usage(false).await;
     }
 
     async fn handle_new_account_event(
@@ -1339,6 +1924,10 @@ where
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_tunnel_stats(&mut self, tx: oneshot::Sender<TunnelStats>) {
+        self.send_tunnel_command(TunnelCommand::GetTunnelStats(tx));
+    }
+
     async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
         use self::TunnelState::*;
 
@@ -1488,6 +2077,13 @@ where
         self.relay_selector.update().await;
     }
 
+    async fn on_reset_relay_health(&mut self, tx: ResponseTx<(), Error>) {
+        let health = self.relay_selector.health_mut();
+        health.reset();
+        let result = health.save().await.map_err(Error::ResetRelayHealthError);
+        Self::oneshot_send(tx, result, "reset_relay_health response");
+    }
+
     async fn on_set_account(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1530,7 +2126,7 @@ where
                 .notify_settings(self.settings.to_settings());
 
             let history_token = match account_token {
-                Some(token) => token,
+                Some(ref token) => token.clone(),
                 None => previous_token.clone().unwrap_or("".to_string()),
             };
             if let Err(error) = self.account_history.set(history_token).await {
@@ -1541,6 +2137,7 @@ where
             }
 
             if let Some(previous_token) = previous_token {
+                self.remove_wireguard_devices(previous_token.clone()).await;
                 if let Some(previous_key) = self
                     .settings
                     .get_wireguard()
@@ -1561,7 +2158,12 @@ where
                     });
                 }
             }
-            if let Err(error) = self.settings.set_wireguard(None).await {
+            // Reuse the key kept for the new account if it was previously switched away from
+            let stored_key = match account_token {
+                Some(token) => self.take_stored_wireguard_key(&token).await,
+                None => None,
+            };
+            if let Err(error) = self.settings.set_wireguard(stored_key).await {
                 log::error!(
                     "{}",
                     error.display_chain_with_msg("Error resetting WireGuard key")
@@ -1572,57 +2174,267 @@ where
         Ok(account_changed)
     }
 
-    fn on_get_account_history(&mut self, tx: oneshot::Sender<Option<AccountToken>>) {
+    /// Removes the WireGuard key kept in the account history for an account and returns it.
+    async fn take_stored_wireguard_key(
+        &mut self,
+        account_token: &str,
+    ) -> Option<mullvad_types::wireguard::WireguardData> {
+        let mut entry = self.account_history.get_entry(account_token)?.clone();
+        let wireguard = entry.wireguard.take()?;
+        if let Err(error) = self.account_history.store(entry).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update account history")
+            );
+        }
+        Some(wireguard)
+    }
+
+    fn on_list_accounts(&mut self, tx: oneshot::Sender<Vec<StoredAccount>>) {
+        let active_account = self.settings.get_account_token();
         Self::oneshot_send(
             tx,
-            self.account_history.get(),
-            "get_account_history response",
+            self.account_history.list(active_account.as_deref()),
+            "list_accounts response",
         );
     }
 
-    async fn on_clear_account_history(&mut self, tx: ResponseTx<(), Error>) {
-        let result = self
-            .account_history
-            .clear()
-            .await
-            .map_err(Error::AccountHistory);
-        Self::oneshot_send(tx, result, "clear_account_history response");
+    async fn on_switch_account(&mut self, tx: ResponseTx<(), Error>, account_token: AccountToken) {
+        let result = self.switch_account(account_token).await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to switch account")
+            );
+        }
+        Self::oneshot_send(tx, result, "switch_account response");
     }
 
-    // Remove the key associated with the current account, if there is one.
-    // This does not modify settings or account history.
-    #[cfg(not(target_os = "android"))]
-    fn remove_current_key_rpc(&self) -> impl std::future::Future<Output = Result<(), Error>> {
-        let remove_key = if let Some(token) = self.settings.get_account_token() {
-            if let Some(wg_data) = self.settings.get_wireguard() {
-                Some(
-                    self.wireguard_key_manager
-                        .remove_key(token, wg_data.private_key.public_key()),
-                )
-            } else {
-                None
-            }
-        } else {
-            None
+    async fn switch_account(&mut self, account_token: AccountToken) -> Result<(), Error> {
+        let mut entry = self
+            .account_history
+            .get_entry(&account_token)
+            .cloned()
+            .ok_or(Error::NoAccountTokenHistory)?;
+        let previous_token = self.settings.get_account_token();
+        if previous_token.as_ref() == Some(&account_token) {
+            return Ok(());
+        }
+
+        // Keep the key and relay settings of the current account so that it can be switched back
+        // to later. WireGuard devices are not kept, so their keys are removed.
+        if let Some(previous_token) = previous_token {
+            self.remove_wireguard_devices(previous_token.clone()).await;
+            self.account_history
+                .store(account_history::AccountEntry {
+                    account: previous_token,
+                    wireguard: self.settings.get_wireguard(),
+                    relay_settings: Some(self.settings.get_relay_settings()),
+                })
+                .await
+                .map_err(Error::AccountHistory)?;
+        }
+
+        let wireguard = match entry.wireguard.take() {
+            Some(data) => Some(self.replace_expired_key(&account_token, data).await),
+            None => None,
         };
+        self.account_history
+            .store(entry.clone())
+            .await
+            .map_err(Error::AccountHistory)?;
+        self.account_history
+            .set(account_token.clone())
+            .await
+            .map_err(Error::AccountHistory)?;
 
-        async move {
-            if let Some(task) = remove_key {
-                match task.await {
-                    Err(wireguard::Error::RestError(error)) => Err(Error::RestError(error)),
-                    // This result should never occur
-                    Err(wireguard::Error::TooManyKeys) => Err(Error::TooManyKeys),
-                    _ => Ok(()),
-                }
-            } else {
-                Ok(())
-            }
+        self.settings
+            .set_account_token(Some(account_token))
+            .await
+            .map_err(Error::SettingsError)?;
+        self.settings
+            .set_wireguard(wireguard)
+            .await
+            .map_err(Error::SettingsError)?;
+        if let Some(relay_settings) = entry.relay_settings {
+            self.settings
+                .update_relay_settings(relay_settings.into())
+                .await
+                .map_err(Error::SettingsError)?;
         }
+        self.event_listener
+            .notify_settings(self.settings.to_settings());
+
+        self.ensure_wireguard_keys_for_current_account().await;
+        info!("Initiating tunnel restart because the account was switched");
+        self.reconnect_tunnel();
+        Ok(())
     }
 
-    async fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
-        if self.app_version_info.is_none() {
-            log::debug!("No version cache found. Fetching new info");
+    /// Replaces the stored key of an account that is being switched to if it has expired. Keys of
+    /// inactive accounts are not rotated automatically, so this keeps them from being used past
+    /// the rotation interval. The old key is kept if it cannot be replaced, in which case the
+    /// automatic rotation replaces it later.
+    async fn replace_expired_key(
+        &mut self,
+        account_token: &AccountToken,
+        data: WireguardData,
+    ) -> WireguardData {
+        let rotation_interval = *self
+            .settings
+            .tunnel_options
+            .wireguard
+            .rotation_interval
+            .unwrap_or_default()
+            .as_duration();
+        if !wireguard::key_expired(
+            &data.get_public_key(),
+            rotation_interval,
+            chrono::Utc::now(),
+        ) {
+            return data;
+        }
+
+        log::info!("Replacing the expired WireGuard key of the account that is switched to");
+        match self
+            .wireguard_key_manager
+            .replace_key(account_token.clone(), data.get_public_key())
+            .await
+        {
+            Ok(new_data) => new_data,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to replace expired WireGuard key")
+                );
+                data
+            }
+        }
+    }
+
+    async fn on_forget_account(&mut self, tx: ResponseTx<(), Error>, account_token: AccountToken) {
+        let result = self.forget_account(account_token).await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to forget account")
+            );
+        }
+        Self::oneshot_send(tx, result, "forget_account response");
+    }
+
+    async fn forget_account(&mut self, account_token: AccountToken) -> Result<(), Error> {
+        if self.account_history.get_entry(&account_token).is_none() {
+            return Err(Error::NoAccountTokenHistory);
+        }
+        if self.settings.get_account_token().as_ref() == Some(&account_token) {
+            self.set_account(None).await.map_err(Error::SettingsError)?;
+            info!("Disconnecting because the active account was forgotten");
+            self.set_target_state(TargetState::Unsecured).await;
+        }
+        let entry = self
+            .account_history
+            .remove(&account_token)
+            .await
+            .map_err(Error::AccountHistory)?;
+        self.remove_stored_keys(entry.into_iter());
+        Ok(())
+    }
+
+    /// Removes the WireGuard keys kept for accounts in the account history from the API.
+    fn remove_stored_keys(&self, entries: impl Iterator<Item = account_history::AccountEntry>) {
+        for entry in entries {
+            if let Some(wireguard) = entry.wireguard {
+                let remove_key = self
+                    .wireguard_key_manager
+                    .remove_key(entry.account, wireguard.private_key.public_key());
+                tokio::spawn(async move {
+                    if let Err(error) = remove_key.await {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to remove WireGuard key for stored account"
+                            )
+                        );
+                    }
+                });
+            }
+        }
+    }
+
+    fn on_get_account_history(&mut self, tx: oneshot::Sender<Option<AccountToken>>) {
+        Self::oneshot_send(
+            tx,
+            self.account_history.get(),
+            "get_account_history response",
+        );
+    }
+
+    async fn on_clear_account_history(&mut self, tx: ResponseTx<(), Error>) {
+        let result = match self.account_history.clear().await {
+            Ok(entries) => {
+                self.remove_stored_keys(entries.into_iter());
+                Ok(())
+            }
+            Err(error) => Err(Error::AccountHistory(error)),
+        };
+        Self::oneshot_send(tx, result, "clear_account_history response");
+    }
+
+    fn on_get_connection_history(
+        &mut self,
+        tx: oneshot::Sender<Vec<connection_history::SessionEntry>>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.connection_history.list(),
+            "get_connection_history response",
+        );
+    }
+
+    async fn on_clear_connection_history(&mut self, tx: ResponseTx<(), Error>) {
+        let result = self
+            .connection_history
+            .clear()
+            .await
+            .map_err(Error::ClearConnectionHistoryError);
+        Self::oneshot_send(tx, result, "clear_connection_history response");
+    }
+
+    // Remove the key associated with the current account, if there is one.
+    // This does not modify settings or account history.
+    #[cfg(not(target_os = "android"))]
+    fn remove_current_key_rpc(&self) -> impl std::future::Future<Output = Result<(), Error>> {
+        let remove_key = if let Some(token) = self.settings.get_account_token() {
+            if let Some(wg_data) = self.settings.get_wireguard() {
+                Some(
+                    self.wireguard_key_manager
+                        .remove_key(token, wg_data.private_key.public_key()),
+                )
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        async move {
+            if let Some(task) = remove_key {
+                match task.await {
+                    Err(wireguard::Error::RestError(error)) => Err(Error::RestError(error)),
+                    // This result should never occur
+                    Err(wireguard::Error::TooManyKeys) => Err(Error::TooManyKeys),
+                    _ => Ok(()),
+                }
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    async fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
+        if self.app_version_info.is_none() {
+            log::debug!("No version cache found. Fetching new info");
             let mut handle = self.version_updater_handle.clone();
             handle.run_version_check().await;
         }
@@ -1646,6 +2458,10 @@ where
     async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
         let mut last_error = Ok(());
 
+        if let Some(token) = self.settings.get_account_token() {
+            self.remove_wireguard_devices(token).await;
+        }
+
         let remove_key = self.remove_current_key_rpc();
         tokio::spawn(async move {
             if let Err(error) = remove_key.await {
@@ -1658,18 +2474,27 @@ where
             }
         });
 
-        if let Err(error) = self.account_history.clear().await {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to clear account history")
-            );
-            last_error = Err(Error::ClearAccountHistoryError(error));
+        match self.account_history.clear().await {
+            Ok(entries) => self.remove_stored_keys(entries.into_iter()),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to clear account history")
+                );
+                last_error = Err(Error::ClearAccountHistoryError(error));
+            }
         }
 
         if let Err(e) = self.settings.reset().await {
             log::error!("Failed to reset settings - {}", e);
             last_error = Err(Error::ClearSettingsError(e));
         }
+        if let Err(error) = self.account_history.set_cipher(None).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to disable account history encryption")
+            );
+        }
 
         // Shut the daemon down.
         self.trigger_shutdown_event();
@@ -1734,6 +2559,131 @@ where
         Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_add_split_tunnel_uid(&mut self, tx: ResponseTx<(), settings::Error>, uid: u32) {
+        let mut excluded_uids = self.settings.excluded_uids.clone();
+        if !excluded_uids.contains(&uid) {
+            excluded_uids.push(uid);
+        }
+        self.set_excluded_uids(tx, "add_split_tunnel_uid response", excluded_uids)
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_remove_split_tunnel_uid(&mut self, tx: ResponseTx<(), settings::Error>, uid: u32) {
+        let mut excluded_uids = self.settings.excluded_uids.clone();
+        excluded_uids.retain(|excluded_uid| *excluded_uid != uid);
+        self.set_excluded_uids(tx, "remove_split_tunnel_uid response", excluded_uids)
+            .await;
+    }
+
+    /// Update the excluded users in both the settings and the tunnel state machine
+    #[cfg(target_os = "linux")]
+    async fn set_excluded_uids(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        response_msg: &'static str,
+        excluded_uids: Vec<u32>,
+    ) {
+        let save_result = self.settings.set_excluded_uids(excluded_uids.clone()).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetExcludedUids(excluded_uids));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), response_msg);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_firewall_rules(&mut self, tx: oneshot::Sender<FirewallRules>) {
+        self.send_tunnel_command(TunnelCommand::GetFirewallRules(tx));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_blocked_traffic_stats(&mut self, tx: oneshot::Sender<BlockedTrafficStats>) {
+        self.send_tunnel_command(TunnelCommand::GetBlockedTrafficStats(tx));
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_audit_blocked_traffic(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_audit_blocked_traffic(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_audit_blocked_traffic response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetAuditBlockedTraffic(enabled));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_audit_blocked_traffic response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_add_allow_rule(&mut self, tx: ResponseTx<(), settings::Error>, rule: AllowRule) {
+        let mut allow_rules = self.settings.allow_rules.clone();
+        if !allow_rules.contains(&rule) {
+            allow_rules.push(rule);
+        }
+        self.set_allow_rules(tx, "add_allow_rule response", allow_rules)
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_remove_allow_rule(&mut self, tx: ResponseTx<(), settings::Error>, rule: AllowRule) {
+        let mut allow_rules = self.settings.allow_rules.clone();
+        allow_rules.retain(|existing| existing != &rule);
+        self.set_allow_rules(tx, "remove_allow_rule response", allow_rules)
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_clear_allow_rules(&mut self, tx: ResponseTx<(), settings::Error>) {
+        self.set_allow_rules(tx, "clear_allow_rules response", vec![])
+            .await;
+    }
+
+    /// Update the custom firewall allow rules in both the settings and the tunnel state machine
+    #[cfg(target_os = "linux")]
+    async fn set_allow_rules(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        response_msg: &'static str,
+        allow_rules: Vec<AllowRule>,
+    ) {
+        let save_result = self.settings.set_allow_rules(allow_rules.clone()).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetAllowRules(allow_rules));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), response_msg);
+            }
+        }
+    }
+
     /// Update the split app paths in both the settings and tunnel
     #[cfg(windows)]
     async fn set_split_tunnel_paths(
@@ -1951,6 +2901,28 @@ where
         }
     }
 
+    async fn on_set_metrics_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        metrics: MetricsSettings,
+    ) {
+        let save_result = self.settings.set_metrics(metrics).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_metrics_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.restart_metrics_server();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_metrics_settings response");
+            }
+        }
+    }
+
     async fn on_set_block_when_disconnected(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2147,45 +3119,128 @@ where
         }
     }
 
-    async fn on_set_wireguard_rotation_interval(
+    #[cfg(target_os = "linux")]
+    async fn on_set_wireguard_netns(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        interval: Option<RotationInterval>,
+        netns: Option<String>,
+    ) {
+        let save_result = self.settings.set_wireguard_netns(netns).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_netns response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard network namespace changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_netns response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_connectivity_check(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        options: ConnectivityCheckOptions,
     ) {
         let save_result = self
             .settings
-            .set_wireguard_rotation_interval(interval)
+            .set_wireguard_connectivity_check(options)
             .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_connectivity_check response");
                 if settings_changed {
-                    self.ensure_key_rotation().await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard connectivity check \
+                             settings changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_interval response");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_connectivity_check response");
             }
         }
     }
 
-    async fn ensure_wireguard_keys_for_current_account(&mut self) {
-        if let Some(account) = self.settings.get_account_token() {
-            if self.settings.get_wireguard().is_none() {
-                log::info!("Generating new WireGuard key for account");
-                self.wireguard_key_manager
-                    .spawn_key_generation_task(account, Some(FIRST_KEY_PUSH_TIMEOUT))
-                    .await;
-            } else {
-                log::info!("Account already has WireGuard key");
-                self.ensure_key_rotation().await;
+    async fn on_set_wireguard_rotation_interval(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<RotationInterval>,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_rotation_interval(interval)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
+                if settings_changed {
+                    self.ensure_key_rotation().await;
+                    self.start_device_key_rotations(true);
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_interval response");
             }
         }
     }
 
+    async fn on_set_wireguard_rotation_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: RotationPolicy,
+    ) {
+        let save_result = self.settings.set_wireguard_rotation_policy(policy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_policy response");
+                if settings_changed {
+                    self.ensure_key_rotation().await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_policy response");
+            }
+        }
+    }
+
+    async fn ensure_wireguard_keys_for_current_account(&mut self) {
+        if let Some(account) = self.settings.get_account_token() {
+            if self.settings.get_wireguard().is_none() {
+                log::info!("Generating new WireGuard key for account");
+                self.wireguard_key_manager
+                    .spawn_key_generation_task(account, Some(FIRST_KEY_PUSH_TIMEOUT))
+                    .await;
+            } else {
+                log::info!("Account already has WireGuard key");
+                self.ensure_key_rotation().await;
+            }
+            self.start_device_key_rotations(false);
+        }
+    }
+
     async fn on_generate_wireguard_key(&mut self, tx: ResponseTx<KeygenEvent, Error>) {
         match self.on_generate_wireguard_key_inner().await {
             Ok(key_event) => {
@@ -2210,9 +3265,13 @@ where
 
         let gen_result = match &wireguard_data {
             Some(wireguard_data) => {
-                self.wireguard_key_manager
+                let result = self
+                    .wireguard_key_manager
                     .replace_key(account_token.clone(), wireguard_data.get_public_key())
-                    .await
+                    .await;
+                self.metrics
+                    .key_rotated(metrics::KeyRotationTrigger::Manual, result.is_ok());
+                result
             }
             None => {
                 self.wireguard_key_manager
@@ -2225,11 +3284,13 @@ where
             Ok(new_data) => {
                 let public_key = new_data.get_public_key();
                 self.settings
-                    .set_wireguard(Some(new_data))
+                    .set_wireguard(Some(new_data.clone()))
                     .await
                     .map_err(Error::SettingsError)?;
                 if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
-                    self.schedule_reconnect(WG_RECONNECT_DELAY).await;
+                    if !self.schedule_key_swap(wireguard_data, &new_data) {
+                        self.schedule_reconnect(WG_RECONNECT_DELAY).await;
+                    }
                 }
                 let keygen_event = KeygenEvent::NewKey(public_key.clone());
                 self.event_listener.notify_key_event(keygen_event.clone());
@@ -2292,10 +3353,622 @@ where
         });
     }
 
+    fn on_export_wireguard_config(
+        &mut self,
+        tx: ResponseTx<Vec<(String, String)>, Error>,
+        location: Constraint<LocationConstraint>,
+        device: Option<String>,
+    ) {
+        let result = self.export_wireguard_config(location, device);
+        Self::oneshot_send(tx, result, "export_wireguard_config response");
+    }
+
+    fn export_wireguard_config(
+        &mut self,
+        location: Constraint<LocationConstraint>,
+        device: Option<String>,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let wg_data = match device {
+            Some(name) => self
+                .settings
+                .get_wireguard_device(&name)
+                .map(|device| device.data.clone())
+                .ok_or(Error::UnknownWireguardDevice(name))?,
+            None => self.settings.get_wireguard().ok_or(Error::NoKeyAvailable)?,
+        };
+
+        // Port and IP version constraints are kept, but multihop cannot be expressed in a single
+        // config file
+        let mut constraints = match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => RelayConstraints::default(),
+        };
+        constraints.tunnel_protocol = Constraint::Only(TunnelType::Wireguard);
+        constraints.wireguard_constraints.entry_location = None;
+
+        let locations = match location {
+            Constraint::Any => {
+                let current_relay = self
+                    .last_generated_relay
+                    .as_ref()
+                    .filter(|relay| !relay.tunnels.wireguard.is_empty());
+                vec![match current_relay {
+                    Some(relay) => {
+                        Self::relay_location_constraint(relay).ok_or(Error::NoRelayAvailable)?
+                    }
+                    None => constraints.location.clone(),
+                }]
+            }
+            Constraint::Only(location) => {
+                constraints.providers = Constraint::Any;
+                self.relay_selector
+                    .get_wireguard_relays(&location)
+                    .iter()
+                    .filter_map(Self::relay_location_constraint)
+                    .collect()
+            }
+        };
+        if locations.is_empty() {
+            return Err(Error::NoRelayAvailable);
+        }
+
+        let dns_servers = Self::get_dns_resolvers(&self.settings.tunnel_options.dns_options);
+        let mtu = self.settings.tunnel_options.wireguard.options.mtu;
+        let mut configs = Vec::with_capacity(locations.len());
+        for location in locations {
+            constraints.location = location;
+            // A relay that does not match the remaining constraints is skipped rather than failing
+            // the whole export
+            let (relay, endpoint) = match self.relay_selector.get_tunnel_endpoint(
+                &constraints,
+                BridgeState::Off,
+                0,
+                true,
+            ) {
+                Ok(selected) => selected,
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Skipping {:?} in WireGuard config export",
+                            constraints.location
+                        ))
+                    );
+                    continue;
+                }
+            };
+            let (peer, ipv4_gateway, ipv6_gateway) = match endpoint {
+                MullvadEndpoint::Wireguard {
+                    peer,
+                    ipv4_gateway,
+                    ipv6_gateway,
+                    ..
+                } => (peer, ipv4_gateway, ipv6_gateway),
+                MullvadEndpoint::OpenVpn(_) => {
+                    log::warn!(
+                        "Skipping {} in WireGuard config export: not a WireGuard relay",
+                        relay.hostname
+                    );
+                    continue;
+                }
+            };
+            let config = wireguard::ConnectionConfig {
+                tunnel: wireguard::tunnel_config(&wg_data),
+                peer,
+                exit_peer: None,
+                ipv4_gateway,
+                ipv6_gateway: Some(ipv6_gateway),
+            };
+            let dns_servers = dns_servers
+                .clone()
+                .unwrap_or_else(|| vec![ipv4_gateway.into(), ipv6_gateway.into()]);
+            configs.push((relay.hostname, wg_quick::format(&config, &dns_servers, mtu)));
+        }
+        if configs.is_empty() {
+            return Err(Error::NoRelayAvailable);
+        }
+        Ok(configs)
+    }
+
+    /// Returns a location constraint that only matches the given relay.
+    fn relay_location_constraint(relay: &Relay) -> Option<Constraint<LocationConstraint>> {
+        let location = relay.location.as_ref()?;
+        Some(Constraint::Only(LocationConstraint::Hostname(
+            location.country_code.clone(),
+            location.city_code.clone(),
+            relay.hostname.clone(),
+        )))
+    }
+
+    async fn on_add_wireguard_device(
+        &mut self,
+        tx: ResponseTx<WireguardDevice, Error>,
+        name: String,
+    ) {
+        let result = self.add_wireguard_device(name).await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to add WireGuard device")
+            );
+        }
+        Self::oneshot_send(tx, result, "add_wireguard_device response");
+    }
+
+    async fn add_wireguard_device(&mut self, name: String) -> Result<WireguardDevice, Error> {
+        if !WireguardDevice::is_valid_name(&name) {
+            return Err(Error::InvalidWireguardDeviceName);
+        }
+        if self.settings.get_wireguard_device(&name).is_some() {
+            return Err(Error::WireguardDeviceExists(name));
+        }
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+
+        let data = self
+            .wireguard_key_manager
+            .generate_device_key(account_token.clone())
+            .await
+            .map_err(Self::map_wireguard_error)?;
+        let device = WireguardDevice {
+            name,
+            data,
+            rotation_interval: None,
+        };
+        if let Err(error) = self.settings.set_wireguard_device(device.clone()).await {
+            self.spawn_remove_device_key(account_token, device.name, &device.data);
+            return Err(Error::SettingsError(error));
+        }
+        self.start_device_key_rotation(&account_token, &device);
+        Ok(device)
+    }
+
+    fn on_list_wireguard_devices(&self, tx: oneshot::Sender<Vec<WireguardDevice>>) {
+        Self::oneshot_send(
+            tx,
+            self.settings.get_wireguard_devices().to_vec(),
+            "list_wireguard_devices response",
+        );
+    }
+
+    async fn on_remove_wireguard_device(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        let result = self.remove_wireguard_device(name).await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove WireGuard device")
+            );
+        }
+        Self::oneshot_send(tx, result, "remove_wireguard_device response");
+    }
+
+    async fn remove_wireguard_device(&mut self, name: String) -> Result<(), Error> {
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+        let device = self
+            .settings
+            .get_wireguard_device(&name)
+            .cloned()
+            .ok_or(Error::UnknownWireguardDevice(name))?;
+
+        match self
+            .wireguard_key_manager
+            .remove_key(account_token, device.data.private_key.public_key())
+            .await
+        {
+            Ok(()) => (),
+            // The key has already been removed from the account
+            Err(wireguard::Error::RestError(mullvad_rpc::rest::Error::ApiError(status, _)))
+                if status == mullvad_rpc::StatusCode::NOT_FOUND => {}
+            Err(error) => return Err(Self::map_wireguard_error(error)),
+        }
+        self.wireguard_key_manager
+            .stop_device_rotation(&device.name);
+        self.settings
+            .remove_wireguard_device(&device.name)
+            .await
+            .map_err(Error::SettingsError)?;
+        Ok(())
+    }
+
+    async fn on_rotate_wireguard_device_key(
+        &mut self,
+        tx: ResponseTx<WireguardDevice, Error>,
+        name: String,
+    ) {
+        let result = self.rotate_wireguard_device_key(name).await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to rotate key of WireGuard device")
+            );
+        }
+        Self::oneshot_send(tx, result, "rotate_wireguard_device_key response");
+    }
+
+    async fn rotate_wireguard_device_key(
+        &mut self,
+        name: String,
+    ) -> Result<WireguardDevice, Error> {
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+        let mut device = self
+            .settings
+            .get_wireguard_device(&name)
+            .cloned()
+            .ok_or(Error::UnknownWireguardDevice(name))?;
+
+        device.data = self
+            .wireguard_key_manager
+            .replace_device_key(account_token.clone(), device.data.get_public_key())
+            .await
+            .map_err(Self::map_wireguard_error)?;
+        self.settings
+            .set_wireguard_device(device.clone())
+            .await
+            .map_err(Error::SettingsError)?;
+        self.start_device_key_rotation(&account_token, &device);
+        Ok(device)
+    }
+
+    async fn on_set_wireguard_device_rotation_interval(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        name: String,
+        interval: Option<RotationInterval>,
+    ) {
+        let result = self
+            .set_wireguard_device_rotation_interval(name, interval)
+            .await;
+        if let Err(ref error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set rotation interval of WireGuard device")
+            );
+        }
+        Self::oneshot_send(
+            tx,
+            result,
+            "set_wireguard_device_rotation_interval response",
+        );
+    }
+
+    async fn set_wireguard_device_rotation_interval(
+        &mut self,
+        name: String,
+        interval: Option<RotationInterval>,
+    ) -> Result<(), Error> {
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+        let device = self
+            .settings
+            .get_wireguard_device(&name)
+            .cloned()
+            .ok_or(Error::UnknownWireguardDevice(name))?;
+
+        let device = WireguardDevice {
+            rotation_interval: interval,
+            ..device
+        };
+        if self
+            .settings
+            .set_wireguard_device(device.clone())
+            .await
+            .map_err(Error::SettingsError)?
+        {
+            self.start_device_key_rotation(&account_token, &device);
+        }
+        Ok(())
+    }
+
+    /// Starts automatic rotation of the key of a WireGuard device. Devices without a rotation
+    /// interval of their own use the interval of the tunnel key.
+    fn start_device_key_rotation(
+        &mut self,
+        account_token: &AccountToken,
+        device: &WireguardDevice,
+    ) {
+        let rotation_interval =
+            device
+                .rotation_interval
+                .or(self.settings.tunnel_options.wireguard.rotation_interval);
+        self.wireguard_key_manager.set_device_rotation(
+            device.name.clone(),
+            device.data.get_public_key(),
+            account_token.clone(),
+            rotation_interval,
+        );
+    }
+
+    /// Starts automatic rotation of the keys of all devices. If `inherited_only` is set, only the
+    /// devices that use the rotation interval of the tunnel key are (re)started.
+    fn start_device_key_rotations(&mut self, inherited_only: bool) {
+        let token = match self.settings.get_account_token() {
+            Some(token) => token,
+            None => return,
+        };
+        for device in self.settings.get_wireguard_devices().to_vec() {
+            if !inherited_only || device.rotation_interval.is_none() {
+                self.start_device_key_rotation(&token, &device);
+            }
+        }
+    }
+
+    /// Removes the keys of all WireGuard devices from the given account and forgets the devices.
+    async fn remove_wireguard_devices(&mut self, account_token: AccountToken) {
+        self.wireguard_key_manager.stop_device_rotations();
+        for device in self.settings.get_wireguard_devices() {
+            self.spawn_remove_device_key(account_token.clone(), device.name.clone(), &device.data);
+        }
+        if let Err(error) = self.settings.clear_wireguard_devices().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove WireGuard devices")
+            );
+        }
+    }
+
+    /// Removes the key of a WireGuard device from the account in the background.
+    fn spawn_remove_device_key(
+        &self,
+        account_token: AccountToken,
+        name: String,
+        data: &mullvad_types::wireguard::WireguardData,
+    ) {
+        let remove_key = self
+            .wireguard_key_manager
+            .remove_key(account_token, data.private_key.public_key());
+        tokio::spawn(async move {
+            if let Err(error) = remove_key.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to remove key of WireGuard device {}",
+                        name
+                    ))
+                );
+            }
+        });
+    }
+
+    fn map_wireguard_error(error: wireguard::Error) -> Error {
+        match error {
+            wireguard::Error::RestError(error) => Error::RestError(error),
+            wireguard::Error::TooManyKeys => Error::TooManyKeys,
+        }
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
 
+    async fn on_set_settings_encryption(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        key_source: Option<SecretsKeySource>,
+        passphrase: Option<String>,
+    ) {
+        let result = self
+            .settings
+            .set_encryption(key_source, passphrase)
+            .await
+            .map(|_| ());
+        match result {
+            Ok(()) => {
+                if let Err(error) = self
+                    .account_history
+                    .set_cipher(self.settings.cipher())
+                    .await
+                {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to reencrypt account history")
+                    );
+                }
+            }
+            Err(ref e) => error!(
+                "{}",
+                e.display_chain_with_msg("Unable to change settings encryption")
+            ),
+        }
+        Self::oneshot_send(tx, result, "set_settings_encryption response");
+    }
+
+    fn on_get_settings_encryption(&self, tx: oneshot::Sender<SettingsEncryptionState>) {
+        Self::oneshot_send(
+            tx,
+            self.settings.get_encryption_state(),
+            "get_settings_encryption response",
+        );
+    }
+
+    fn on_export_settings(&self, tx: ResponseTx<String, settings::Error>, include_secrets: bool) {
+        Self::oneshot_send(
+            tx,
+            self.settings.export(include_secrets),
+            "export_settings response",
+        );
+    }
+
+    async fn on_import_settings(&mut self, tx: ResponseTx<(), Error>, data: String) {
+        let result = self.import_settings(data).await;
+        if let Err(ref error) = result {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Unable to import settings")
+            );
+        }
+        Self::oneshot_send(tx, result, "import_settings response");
+    }
+
+    /// Imports exported settings. An account token in the imported settings replaces the current
+    /// account, but WireGuard keys are never imported since they must not be shared between
+    /// devices.
+    async fn import_settings(&mut self, data: String) -> Result<(), Error> {
+        let imported =
+            SettingsPersister::parse_import(data.as_bytes()).map_err(Error::SettingsError)?;
+        let previous = self.settings.to_settings();
+
+        if self
+            .settings
+            .import(imported.clone())
+            .await
+            .map_err(Error::SettingsError)?
+        {
+            self.event_listener
+                .notify_settings(self.settings.to_settings());
+            self.apply_imported_settings(&previous).await;
+        }
+
+        if let Some(account_token) = imported.get_account_token() {
+            if self
+                .set_account(Some(account_token))
+                .await
+                .map_err(Error::SettingsError)?
+            {
+                info!("Initiating tunnel restart because the account token changed");
+                self.reconnect_tunnel();
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagates the difference between `previous` and the current settings to the rest of the
+    /// daemon.
+    async fn apply_imported_settings(&mut self, previous: &Settings) {
+        let settings = self.settings.to_settings();
+
+        if settings.allow_lan != previous.allow_lan {
+            self.send_tunnel_command(TunnelCommand::AllowLan(settings.allow_lan));
+        }
+        if settings.block_when_disconnected != previous.block_when_disconnected {
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                settings.block_when_disconnected,
+            ));
+        }
+        if settings.tunnel_options.dns_options != previous.tunnel_options.dns_options {
+            let resolvers = Self::get_dns_resolvers(&settings.tunnel_options.dns_options);
+            self.send_tunnel_command(TunnelCommand::Dns(resolvers));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if settings.allow_rules != previous.allow_rules {
+                self.send_tunnel_command(TunnelCommand::SetAllowRules(
+                    settings.allow_rules.clone(),
+                ));
+            }
+            if settings.excluded_uids != previous.excluded_uids {
+                self.send_tunnel_command(TunnelCommand::SetExcludedUids(
+                    settings.excluded_uids.clone(),
+                ));
+            }
+            if settings.audit_blocked_traffic != previous.audit_blocked_traffic {
+                self.send_tunnel_command(TunnelCommand::SetAuditBlockedTraffic(
+                    settings.audit_blocked_traffic,
+                ));
+            }
+        }
+        if settings.show_beta_releases != previous.show_beta_releases {
+            let mut handle = self.version_updater_handle.clone();
+            handle
+                .set_show_beta_releases(settings.show_beta_releases)
+                .await;
+        }
+        let rotation_interval_changed = settings.tunnel_options.wireguard.rotation_interval
+            != previous.tunnel_options.wireguard.rotation_interval;
+        if rotation_interval_changed
+            || settings.tunnel_options.wireguard.rotation_policy
+                != previous.tunnel_options.wireguard.rotation_policy
+        {
+            self.ensure_key_rotation().await;
+        }
+        if rotation_interval_changed {
+            self.start_device_key_rotations(true);
+        }
+        if settings.metrics != previous.metrics {
+            self.restart_metrics_server();
+        }
+        #[cfg(windows)]
+        {
+            if settings.split_tunnel != previous.split_tunnel {
+                let excluded_apps = if settings.split_tunnel.enable_exclusions {
+                    settings.split_tunnel.apps.clone()
+                } else {
+                    HashSet::new()
+                };
+                let (result_tx, result_rx) = oneshot::channel();
+                self.send_tunnel_command(TunnelCommand::SetExcludedApps(
+                    result_tx,
+                    excluded_apps
+                        .iter()
+                        .map(|app| OsString::from(app))
+                        .collect(),
+                ));
+                match result_rx.await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(error)) => log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to set excluded apps list")
+                    ),
+                    Err(_) => log::error!("The tunnel failed to return a result"),
+                }
+            }
+        }
+
+        if settings.get_relay_settings() != previous.get_relay_settings()
+            || settings.bridge_settings != previous.bridge_settings
+            || settings.get_bridge_state() != previous.get_bridge_state()
+            || settings.tunnel_options.openvpn != previous.tunnel_options.openvpn
+            || settings.tunnel_options.wireguard.options
+                != previous.tunnel_options.wireguard.options
+            || settings.tunnel_options.generic != previous.tunnel_options.generic
+        {
+            info!("Initiating tunnel restart because the settings were imported");
+            self.reconnect_tunnel();
+        }
+    }
+
+    async fn on_unlock_settings(&mut self, tx: ResponseTx<(), Error>, passphrase: Option<String>) {
+        match self.settings.unlock(passphrase.clone()).await {
+            Ok(unlocked) => {
+                // The settings are unlocked even if the account history is not, so the result is
+                // reported before acting on the unlocked settings.
+                let result = self
+                    .account_history
+                    .unlock(passphrase.as_deref(), self.settings.cipher())
+                    .await
+                    .map(|_| ())
+                    .map_err(Error::AccountHistory);
+                if let Err(ref error) = result {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to unlock account history")
+                    );
+                }
+                Self::oneshot_send(tx, result, "unlock_settings response");
+                if unlocked {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.ensure_wireguard_keys_for_current_account().await;
+                    info!("Initiating tunnel restart because the settings were unlocked");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to unlock settings"));
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), "unlock_settings response");
+            }
+        }
+    }
+
     fn oneshot_send<T>(tx: oneshot::Sender<T>, t: T, msg: &'static str) {
         if tx.send(t).is_err() {
             warn!("Unable to send {} to the daemon command sender", msg);
@@ -2432,11 +4105,25 @@ where
     }
 
     fn send_tunnel_command(&mut self, command: TunnelCommand) {
+        if self.tunnel_state.is_connected() && Self::may_reconnect(&command) {
+            self.reconnect_requested = true;
+        }
         self.tunnel_command_tx
             .unbounded_send(command)
             .expect("Tunnel state machine has stopped");
     }
 
+    /// Returns whether the command may make the tunnel state machine leave the connected state
+    /// in order to reconnect.
+    fn may_reconnect(command: &TunnelCommand) -> bool {
+        match command {
+            TunnelCommand::Connect => true,
+            #[cfg(target_os = "android")]
+            TunnelCommand::AllowLan(_) | TunnelCommand::Dns(_) => true,
+            _ => false,
+        }
+    }
+
     #[cfg(not(target_os = "android"))]
     async fn clear_log_directory() -> Result<(), Error> {
         let log_dir = mullvad_paths::get_log_dir().map_err(Error::PathError)?;
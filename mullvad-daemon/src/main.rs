@@ -2,6 +2,7 @@
 
 use log::{debug, error, info, warn};
 use mullvad_daemon::{
+    admin_policy::AdminPolicy,
     logging,
     management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
     rpc_uniqueness_check,
@@ -137,8 +138,12 @@ async fn create_daemon(
     let cache_dir = mullvad_paths::cache_dir()
         .map_err(|e| e.display_chain_with_msg("Unable to get cache dir"))?;
 
+    let policy = AdminPolicy::load(&settings_dir)
+        .await
+        .map_err(|e| e.display_chain_with_msg("Unable to load admin policy"))?;
+
     let command_channel = DaemonCommandChannel::new();
-    let event_listener = spawn_management_interface(command_channel.sender()).await?;
+    let event_listener = spawn_management_interface(command_channel.sender(), policy).await?;
 
     Daemon::start(
         log_dir,
@@ -154,8 +159,9 @@ async fn create_daemon(
 
 async fn spawn_management_interface(
     command_sender: DaemonCommandSender,
+    policy: AdminPolicy,
 ) -> Result<ManagementInterfaceEventBroadcaster, String> {
-    let server = ManagementInterfaceServer::start(command_sender)
+    let server = ManagementInterfaceServer::start(command_sender, policy)
         .await
         .map_err(|error| {
             error.display_chain_with_msg("Unable to start management interface server")
@@ -1,4 +1,9 @@
-use crate::{account_history, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    account_history,
+    admin_policy::{Action, AdminCommand, AdminPolicy, Role},
+    connection_history, settings, settings_encryption, DaemonCommand, DaemonCommandSender,
+    EventListener,
+};
 use futures::channel::oneshot;
 use mullvad_management_interface::{
     types::{self, daemon_event, management_service_server::ManagementService},
@@ -10,12 +15,13 @@ use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
     account::AccountToken,
-    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
+    custom_tunnel::CustomTunnelEndpoint,
+    relay_constraints::{BridgeSettings, BridgeState, Constraint, RelaySettingsUpdate},
     relay_list::RelayList,
-    settings::Settings,
+    settings::{MetricsSettings, Settings},
     states::{TargetState, TunnelState},
     version,
-    wireguard::{RotationInterval, RotationIntervalError},
+    wireguard::{RotationInterval, RotationIntervalError, RotationPolicy},
 };
 use parking_lot::RwLock;
 #[cfg(windows)]
@@ -26,7 +32,10 @@ use std::{
     sync::{mpsc, Arc},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_core::tunnel_state_machine::TunnelStats;
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
+use talpid_types::{net::wireguard::ConnectivityCheckOptions, ErrorExt};
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 #[derive(err_derive::Error, Debug)]
@@ -39,13 +48,19 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsSubscription>>>,
+    policy: Arc<AdminPolicy>,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
 type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
 type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
+struct EventsSubscription {
+    tx: EventsListenerSender,
+    role: Role,
+}
+
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 
@@ -58,7 +73,8 @@ impl ManagementService for ManagementServiceImpl {
     // Control and get the tunnel state
     //
 
-    async fn connect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
+    async fn connect_tunnel(&self, request: Request<()>) -> ServiceResult<bool> {
+        self.check_policy(&request, Action::Command(AdminCommand::Connect))?;
         log::debug!("connect_tunnel");
 
         let (tx, rx) = oneshot::channel();
@@ -67,7 +83,8 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(connect_issued))
     }
 
-    async fn disconnect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
+    async fn disconnect_tunnel(&self, request: Request<()>) -> ServiceResult<bool> {
+        self.check_policy(&request, Action::Command(AdminCommand::Disconnect))?;
         log::debug!("disconnect_tunnel");
 
         let (tx, rx) = oneshot::channel();
@@ -76,7 +93,8 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(disconnect_issued))
     }
 
-    async fn reconnect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
+    async fn reconnect_tunnel(&self, request: Request<()>) -> ServiceResult<bool> {
+        self.check_policy(&request, Action::Command(AdminCommand::Reconnect))?;
         log::debug!("reconnect_tunnel");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::Reconnect(tx))?;
@@ -92,31 +110,66 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn get_tunnel_stats(&self, _: Request<()>) -> ServiceResult<types::TunnelStats> {
+        log::debug!("get_tunnel_stats");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetTunnelStats(tx))?;
+        let stats = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_tunnel_stats(stats)))
+    }
+
+    async fn get_connection_history(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectionHistory> {
+        log::debug!("get_connection_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetConnectionHistory(tx))?;
+        let sessions = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectionHistory {
+            sessions: sessions.into_iter().map(convert_session).collect(),
+        }))
+    }
+
+    async fn clear_connection_history(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_connection_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearConnectionHistory(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // Control the daemon and receive events
     //
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
+    async fn events_listen(&self, request: Request<()>) -> ServiceResult<Self::EventsListenStream> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let role = self.client_role(&request);
 
         let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+        subscriptions.push(EventsSubscription { tx, role });
 
         Ok(Response::new(UnboundedReceiverStream::new(rx)))
     }
 
-    async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn prepare_restart(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::Shutdown))?;
         log::debug!("prepare_restart");
         self.send_command_to_daemon(DaemonCommand::PrepareRestart)?;
         Ok(Response::new(()))
     }
 
-    async fn shutdown(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn shutdown(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::Shutdown))?;
         log::debug!("shutdown");
         self.send_command_to_daemon(DaemonCommand::Shutdown)?;
         Ok(Response::new(()))
     }
 
-    async fn factory_reset(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn factory_reset(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::FactoryReset))?;
         #[cfg(not(target_os = "android"))]
         {
             log::debug!("factory_reset");
@@ -162,10 +215,21 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    async fn reset_relay_health(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("reset_relay_health");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ResetRelayHealth(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     async fn update_relay_settings(
         &self,
         request: Request<types::RelaySettingsUpdate>,
     ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("relay_settings"))?;
         log::debug!("update_relay_settings");
         let (tx, rx) = oneshot::channel();
         let constraints_update =
@@ -183,6 +247,37 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn import_custom_relay(
+        &self,
+        request: Request<types::CustomRelayImport>,
+    ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("relay_settings"))?;
+        let request = request.into_inner();
+        let format = types::custom_relay_import::Format::from_i32(request.format)
+            .ok_or_else(|| Status::invalid_argument("unknown config format"))?;
+        log::debug!("import_custom_relay({:?})", format);
+        let endpoint = match format {
+            types::custom_relay_import::Format::WgQuick => {
+                CustomTunnelEndpoint::from_wg_quick(&request.config)
+            }
+            types::custom_relay_import::Format::Openvpn => {
+                CustomTunnelEndpoint::from_openvpn_config(&request.config)
+            }
+        }
+        .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        let (tx, rx) = oneshot::channel();
+        let message = DaemonCommand::UpdateRelaySettings(
+            tx,
+            RelaySettingsUpdate::CustomTunnelEndpoint(endpoint),
+        );
+        self.send_command_to_daemon(message)?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn get_relay_locations(
         &self,
         _: Request<()>,
@@ -228,6 +323,7 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<types::BridgeSettings>,
     ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("bridge_settings"))?;
         let settings =
             BridgeSettings::try_from(request.into_inner()).map_err(|error| match error {
                 types::FromProtobufTypeError::InvalidArgument(error) => {
@@ -246,6 +342,7 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("bridge_state"))?;
         let bridge_state =
             BridgeState::try_from(request.into_inner()).map_err(|error| match error {
                 types::FromProtobufTypeError::InvalidArgument(error) => {
@@ -265,16 +362,102 @@ impl ManagementService for ManagementServiceImpl {
     // Settings
     //
 
-    async fn get_settings(&self, _: Request<()>) -> ServiceResult<types::Settings> {
+    async fn get_settings(&self, request: Request<()>) -> ServiceResult<types::Settings> {
         log::debug!("get_settings");
+        let role = self.client_role(&request);
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
+        self.wait_for_result(rx).await.map(|settings| {
+            let settings = match role {
+                Role::Monitor => settings.without_secrets(),
+                Role::Control => settings,
+            };
+            Response::new(types::Settings::from(&settings))
+        })
+    }
+
+    async fn get_settings_encryption(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::SettingsEncryption> {
+        log::debug!("get_settings_encryption");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettingsEncryption(tx))?;
         self.wait_for_result(rx)
             .await
-            .map(|settings| Response::new(types::Settings::from(&settings)))
+            .map(|state| Response::new(types::SettingsEncryption::from(state)))
+    }
+
+    async fn set_settings_encryption(
+        &self,
+        request: Request<types::SetSettingsEncryption>,
+    ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::SettingsEncryption))?;
+        let request = request.into_inner();
+        let key_source = types::settings_encryption::KeySource::from_i32(request.key_source)
+            .ok_or_else(|| Status::invalid_argument("unknown key source"))?;
+        let passphrase = if request.passphrase.is_empty() {
+            None
+        } else {
+            Some(request.passphrase)
+        };
+        log::debug!("set_settings_encryption({:?})", key_source);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSettingsEncryption(
+            tx,
+            key_source.into(),
+            passphrase,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn unlock_settings(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("unlock_settings");
+        self.check_policy(&request, Action::Command(AdminCommand::SettingsEncryption))?;
+        let passphrase = request.into_inner();
+        let passphrase = if passphrase.is_empty() {
+            None
+        } else {
+            Some(passphrase)
+        };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::UnlockSettings(tx, passphrase))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn export_settings(&self, request: Request<bool>) -> ServiceResult<String> {
+        if *request.get_ref() {
+            self.check_policy(&request, Action::Command(AdminCommand::ExportSecrets))?;
+        }
+        let include_secrets = request.into_inner();
+        log::debug!("export_settings({})", include_secrets);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettings(tx, include_secrets))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn import_settings(&self, request: Request<String>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ImportSettings)?;
+        log::debug!("import_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportSettings(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
     }
 
     async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("allow_lan"))?;
         let allow_lan = request.into_inner();
         log::debug!("set_allow_lan({})", allow_lan);
         let (tx, rx) = oneshot::channel();
@@ -286,6 +469,7 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("show_beta_releases"))?;
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
         let (tx, rx) = oneshot::channel();
@@ -297,6 +481,7 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("block_when_disconnected"))?;
         let block_when_disconnected = request.into_inner();
         log::debug!("set_block_when_disconnected({})", block_when_disconnected);
         let (tx, rx) = oneshot::channel();
@@ -311,6 +496,7 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("auto_connect"))?;
         let auto_connect = request.into_inner();
         log::debug!("set_auto_connect({})", auto_connect);
         let (tx, rx) = oneshot::channel();
@@ -322,6 +508,10 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.openvpn.mssfix"),
+        )?;
         let mssfix = request.into_inner();
         let mssfix = if mssfix != 0 {
             Some(mssfix as u16)
@@ -338,6 +528,10 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.mtu"),
+        )?;
         let mtu = request.into_inner();
         let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
         log::debug!("set_wireguard_mtu({:?})", mtu);
@@ -349,7 +543,63 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_wireguard_netns(&self, request: Request<String>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.netns"),
+        )?;
+        let netns = request.into_inner();
+        let netns = if netns.is_empty() { None } else { Some(netns) };
+        if let Some(ref name) = netns {
+            if !talpid_types::netns::is_valid_name(name) {
+                return Err(Status::invalid_argument("invalid network namespace name"));
+            }
+        }
+        log::debug!("set_wireguard_netns({:?})", netns);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardNetns(tx, netns))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_wireguard_netns(&self, _: Request<String>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Network namespaces are only supported on Linux",
+        ))
+    }
+
+    async fn set_wireguard_connectivity_check(
+        &self,
+        request: Request<types::ConnectivityCheckOptions>,
+    ) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.connectivity_check"),
+        )?;
+        let options = ConnectivityCheckOptions::try_from(request.into_inner()).map_err(
+            |error| match error {
+                types::FromProtobufTypeError::InvalidArgument(error) => {
+                    Status::invalid_argument(error)
+                }
+            },
+        )?;
+        log::debug!("set_wireguard_connectivity_check({:?})", options);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardConnectivityCheck(tx, options))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.generic.enable_ipv6"),
+        )?;
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
         let (tx, rx) = oneshot::channel();
@@ -362,6 +612,10 @@ impl ManagementService for ManagementServiceImpl {
 
     #[cfg(not(target_os = "android"))]
     async fn set_dns_options(&self, request: Request<types::DnsOptions>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.dns_options"),
+        )?;
         let options = DnsOptions::try_from(request.into_inner()).map_err(|error| match error {
             types::FromProtobufTypeError::InvalidArgument(error) => Status::invalid_argument(error),
         })?;
@@ -379,10 +633,32 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    async fn set_metrics_settings(
+        &self,
+        request: Request<types::MetricsSettings>,
+    ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("metrics"))?;
+        let settings =
+            MetricsSettings::try_from(request.into_inner()).map_err(|error| match error {
+                types::FromProtobufTypeError::InvalidArgument(error) => {
+                    Status::invalid_argument(error)
+                }
+            })?;
+        log::debug!("set_metrics_settings({:?})", settings);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetMetricsSettings(tx, settings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     // Account management
     //
 
-    async fn create_new_account(&self, _: Request<()>) -> ServiceResult<String> {
+    async fn create_new_account(&self, request: Request<()>) -> ServiceResult<String> {
+        self.check_policy(&request, Action::Command(AdminCommand::AccountChange))?;
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::CreateNewAccount(tx))?;
         self.wait_for_result(rx)
@@ -392,6 +668,7 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::AccountChange))?;
         log::debug!("set_account");
         let account_token = request.into_inner();
         let account_token = if account_token == "" {
@@ -443,7 +720,8 @@ impl ManagementService for ManagementServiceImpl {
             .map(|history| Response::new(types::AccountHistory { token: history }))
     }
 
-    async fn clear_account_history(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn clear_account_history(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::AccountChange))?;
         log::debug!("clear_account_history");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::ClearAccountHistory(tx))?;
@@ -453,6 +731,42 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn list_accounts(&self, _: Request<()>) -> ServiceResult<types::AccountList> {
+        log::debug!("list_accounts");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListAccounts(tx))?;
+        self.wait_for_result(rx).await.map(|accounts| {
+            Response::new(types::AccountList {
+                accounts: accounts
+                    .into_iter()
+                    .map(types::StoredAccount::from)
+                    .collect(),
+            })
+        })
+    }
+
+    async fn switch_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::AccountChange))?;
+        log::debug!("switch_account");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SwitchAccount(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn forget_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::AccountChange))?;
+        log::debug!("forget_account");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ForgetAccount(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_www_auth_token");
         let (tx, rx) = oneshot::channel();
@@ -499,6 +813,10 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<types::Duration>,
     ) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.rotation_interval"),
+        )?;
         let interval: RotationInterval = Duration::try_from(request.into_inner())
             .map_err(|_| Status::invalid_argument("unexpected negative rotation interval"))?
             .try_into()
@@ -518,7 +836,11 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
-    async fn reset_wireguard_rotation_interval(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn reset_wireguard_rotation_interval(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.rotation_interval"),
+        )?;
         log::debug!("reset_wireguard_rotation_interval");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetWireguardRotationInterval(tx, None))?;
@@ -528,6 +850,33 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_wireguard_rotation_policy(
+        &self,
+        request: Request<types::WireguardRotationPolicy>,
+    ) -> ServiceResult<()> {
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("tunnel_options.wireguard.rotation_policy"),
+        )?;
+        let policy =
+            RotationPolicy::try_from(request.into_inner()).map_err(|error| match error {
+                types::FromProtobufTypeError::InvalidArgument(error) => {
+                    Status::invalid_argument(error)
+                }
+            })?;
+        policy
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.display_chain()))?;
+
+        log::debug!("set_wireguard_rotation_policy({:?})", policy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardRotationPolicy(tx, policy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn generate_wireguard_key(&self, _: Request<()>) -> ServiceResult<types::KeygenEvent> {
         // TODO: return error for TooManyKeys, GenerationFailure
         // on success, simply return the new key or nil
@@ -561,6 +910,126 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn export_wireguard_config(
+        &self,
+        request: Request<types::WireguardConfigExportRequest>,
+    ) -> ServiceResult<types::WireguardConfigExports> {
+        self.check_policy(&request, Action::Command(AdminCommand::ExportSecrets))?;
+        let request = request.into_inner();
+        let location = request
+            .location
+            .map(Constraint::from)
+            .unwrap_or(Constraint::Any);
+        let device = Some(request.device).filter(|device| !device.is_empty());
+        log::debug!("export_wireguard_config({:?}, {:?})", location, device);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportWireguardConfig(tx, location, device))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|configs| {
+                Response::new(types::WireguardConfigExports {
+                    configs: configs
+                        .into_iter()
+                        .map(|(hostname, config)| types::WireguardConfigExport { hostname, config })
+                        .collect(),
+                })
+            })
+            .map_err(map_daemon_error)
+    }
+
+    async fn add_wireguard_device(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::WireguardDevice> {
+        self.check_policy(&request, Action::ChangeSetting("wireguard_devices"))?;
+        let name = request.into_inner();
+        log::debug!("add_wireguard_device({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddWireguardDevice(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|device| Response::new(types::WireguardDevice::from(device)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn list_wireguard_devices(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::WireguardDevices> {
+        log::debug!("list_wireguard_devices");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListWireguardDevices(tx))?;
+        let devices = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::WireguardDevices {
+            devices: devices
+                .into_iter()
+                .map(types::WireguardDevice::from)
+                .collect(),
+        }))
+    }
+
+    async fn remove_wireguard_device(&self, request: Request<String>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("wireguard_devices"))?;
+        let name = request.into_inner();
+        log::debug!("remove_wireguard_device({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveWireguardDevice(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn rotate_wireguard_device_key(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::WireguardDevice> {
+        self.check_policy(&request, Action::ChangeSetting("wireguard_devices"))?;
+        let name = request.into_inner();
+        log::debug!("rotate_wireguard_device_key({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RotateWireguardDeviceKey(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|device| Response::new(types::WireguardDevice::from(device)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_wireguard_device_rotation_interval(
+        &self,
+        request: Request<types::WireguardDeviceRotationInterval>,
+    ) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("wireguard_devices"))?;
+        let request = request.into_inner();
+        let interval = match request.interval {
+            Some(interval) => Some(
+                Duration::try_from(interval)
+                    .map_err(|_| Status::invalid_argument("unexpected negative rotation interval"))?
+                    .try_into()
+                    .map_err(|error: RotationIntervalError| {
+                        Status::invalid_argument(error.display_chain())
+                    })?,
+            ),
+            None => None,
+        };
+
+        log::debug!(
+            "set_wireguard_device_rotation_interval({}, {:?})",
+            request.name,
+            interval
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardDeviceRotationInterval(
+            tx,
+            request.name,
+            interval,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // Split tunneling
     //
 
@@ -596,6 +1065,7 @@ impl ManagementService for ManagementServiceImpl {
 
     #[cfg(target_os = "linux")]
     async fn add_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::SplitTunnel))?;
         let pid = request.into_inner();
         log::debug!("add_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
@@ -612,6 +1082,7 @@ impl ManagementService for ManagementServiceImpl {
 
     #[cfg(target_os = "linux")]
     async fn remove_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::SplitTunnel))?;
         let pid = request.into_inner();
         log::debug!("remove_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
@@ -626,26 +1097,64 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn clear_split_tunnel_processes(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::Command(AdminCommand::SplitTunnel))?;
+        log::debug!("clear_split_tunnel_processes");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearSplitTunnelProcesses(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(|error| Status::failed_precondition(error.to_string()))?;
+        Ok(Response::new(()))
+    }
+    #[cfg(not(target_os = "linux"))]
     async fn clear_split_tunnel_processes(&self, _: Request<()>) -> ServiceResult<()> {
-        #[cfg(target_os = "linux")]
-        {
-            log::debug!("clear_split_tunnel_processes");
-            let (tx, rx) = oneshot::channel();
-            self.send_command_to_daemon(DaemonCommand::ClearSplitTunnelProcesses(tx))?;
-            self.wait_for_result(rx)
-                .await?
-                .map_err(|error| Status::failed_precondition(error.to_string()))?;
-            Ok(Response::new(()))
-        }
-        #[cfg(not(target_os = "linux"))]
-        {
-            Ok(Response::new(()))
-        }
+        Ok(Response::new(()))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn add_split_tunnel_uid(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("excluded_uids"))?;
+        let uid = request.into_inner();
+        log::debug!("add_split_tunnel_uid({})", uid);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddSplitTunnelUid(tx, uid))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn add_split_tunnel_uid(&self, _: Request<u32>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Excluding users from the tunnel is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn remove_split_tunnel_uid(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("excluded_uids"))?;
+        let uid = request.into_inner();
+        log::debug!("remove_split_tunnel_uid({})", uid);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelUid(tx, uid))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn remove_split_tunnel_uid(&self, _: Request<u32>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Excluding users from the tunnel is only supported on Linux",
+        ))
     }
 
     #[cfg(windows)]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
+        self.check_policy(&request, Action::ChangeSetting("split_tunnel.apps"))?;
         let path = PathBuf::from(request.into_inner());
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::AddSplitTunnelApp(tx, path))?;
@@ -662,6 +1171,7 @@ impl ManagementService for ManagementServiceImpl {
     #[cfg(windows)]
     async fn remove_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("remove_split_tunnel_app");
+        self.check_policy(&request, Action::ChangeSetting("split_tunnel.apps"))?;
         let path = PathBuf::from(request.into_inner());
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelApp(tx, path))?;
@@ -676,8 +1186,9 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     #[cfg(windows)]
-    async fn clear_split_tunnel_apps(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn clear_split_tunnel_apps(&self, request: Request<()>) -> ServiceResult<()> {
         log::debug!("clear_split_tunnel_apps");
+        self.check_policy(&request, Action::ChangeSetting("split_tunnel.apps"))?;
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::ClearSplitTunnelApps(tx))?;
         self.wait_for_result(rx)
@@ -693,6 +1204,10 @@ impl ManagementService for ManagementServiceImpl {
     #[cfg(windows)]
     async fn set_split_tunnel_state(&self, request: Request<bool>) -> ServiceResult<()> {
         log::debug!("set_split_tunnel_state");
+        self.check_policy(
+            &request,
+            Action::ChangeSetting("split_tunnel.enable_exclusions"),
+        )?;
         let enabled = request.into_inner();
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetSplitTunnelState(tx, enabled))?;
@@ -705,6 +1220,150 @@ impl ManagementService for ManagementServiceImpl {
     async fn set_split_tunnel_state(&self, _: Request<bool>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
+
+    // Custom firewall rules
+    //
+
+    #[cfg(target_os = "linux")]
+    async fn add_allow_rule(&self, request: Request<types::AllowRule>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("allow_rules"))?;
+        let rule = AllowRule::try_from(request.into_inner()).map_err(|error| match error {
+            types::FromProtobufTypeError::InvalidArgument(error) => Status::invalid_argument(error),
+        })?;
+        log::debug!("add_allow_rule({})", rule);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddAllowRule(tx, rule))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn add_allow_rule(&self, _: Request<types::AllowRule>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Custom firewall rules are only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn remove_allow_rule(&self, request: Request<types::AllowRule>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("allow_rules"))?;
+        let rule = AllowRule::try_from(request.into_inner()).map_err(|error| match error {
+            types::FromProtobufTypeError::InvalidArgument(error) => Status::invalid_argument(error),
+        })?;
+        log::debug!("remove_allow_rule({})", rule);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveAllowRule(tx, rule))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn remove_allow_rule(&self, _: Request<types::AllowRule>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Custom firewall rules are only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn clear_allow_rules(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("allow_rules"))?;
+        log::debug!("clear_allow_rules");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearAllowRules(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn clear_allow_rules(&self, _: Request<()>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Custom firewall rules are only supported on Linux",
+        ))
+    }
+
+    // Debugging
+    //
+
+    #[cfg(target_os = "linux")]
+    async fn get_firewall_rules(&self, _: Request<()>) -> ServiceResult<types::FirewallRules> {
+        log::debug!("get_firewall_rules");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFirewallRules(tx))?;
+        let rules = self.wait_for_result(rx).await?;
+        let installed = match rules.installed_rules {
+            Ok(installed_rules) => {
+                types::firewall_rules::Installed::InstalledRules(installed_rules)
+            }
+            Err(error) => {
+                types::firewall_rules::Installed::InstalledRulesError(error.display_chain())
+            }
+        };
+        Ok(Response::new(types::FirewallRules {
+            policy: rules.policy,
+            intended_rules: rules.intended_rules,
+            installed: Some(installed),
+            backend: rules.backend.to_string(),
+        }))
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn get_firewall_rules(&self, _: Request<()>) -> ServiceResult<types::FirewallRules> {
+        Err(Status::unimplemented(
+            "Firewall rule inspection is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_blocked_traffic_stats(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::BlockedTrafficStats> {
+        log::debug!("get_blocked_traffic_stats");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetBlockedTrafficStats(tx))?;
+        let stats = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::BlockedTrafficStats {
+            enabled: stats.enabled,
+            since: stats.since.map(types::Timestamp::from),
+            dropped_packets: stats.dropped_packets,
+            overruns: stats.overruns,
+            samples: stats
+                .samples
+                .into_iter()
+                .map(convert_blocked_packet)
+                .collect(),
+        }))
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn get_blocked_traffic_stats(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::BlockedTrafficStats> {
+        Err(Status::unimplemented(
+            "Blocked traffic auditing is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_audit_blocked_traffic(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_policy(&request, Action::ChangeSetting("audit_blocked_traffic"))?;
+        let enabled = request.into_inner();
+        log::debug!("set_audit_blocked_traffic({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAuditBlockedTraffic(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_audit_blocked_traffic(&self, _: Request<bool>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Blocked traffic auditing is only supported on Linux",
+        ))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -718,10 +1377,31 @@ impl ManagementServiceImpl {
     async fn wait_for_result<T>(&self, rx: oneshot::Receiver<T>) -> Result<T, Status> {
         rx.await.map_err(|_| Status::internal("sender was dropped"))
     }
+
+    /// Returns the role of the client that sent `request`.
+    fn client_role<T>(&self, request: &Request<T>) -> Role {
+        self.policy
+            .role(mullvad_management_interface::peer_credentials(request))
+    }
+
+    /// Fails with `PermissionDenied` if the admin policy does not allow the client to perform
+    /// `action`. No other errors are mapped to `PermissionDenied`.
+    fn check_policy<T>(&self, request: &Request<T>, action: Action<'_>) -> Result<(), Status> {
+        let peer = mullvad_management_interface::peer_credentials(request);
+        if self.policy.is_allowed(peer, action) {
+            Ok(())
+        } else {
+            log::warn!("Denied request from {:?}: {}", peer, action);
+            Err(Status::permission_denied(format!(
+                "{} requires administrator privileges",
+                action
+            )))
+        }
+    }
 }
 
 pub struct ManagementInterfaceServer {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsSubscription>>>,
     socket_path: String,
     server_abort_tx: triggered::Trigger,
     server_join_handle: Option<
@@ -730,8 +1410,9 @@ pub struct ManagementInterfaceServer {
 }
 
 impl ManagementInterfaceServer {
-    pub async fn start(tunnel_tx: DaemonCommandSender) -> Result<Self, Error> {
-        let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+    pub async fn start(tunnel_tx: DaemonCommandSender, policy: AdminPolicy) -> Result<Self, Error> {
+        let policy = Arc::new(policy);
+        let subscriptions = Arc::<RwLock<Vec<EventsSubscription>>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -742,9 +1423,11 @@ impl ManagementInterfaceServer {
         let server = ManagementServiceImpl {
             daemon_tx: tunnel_tx,
             subscriptions: subscriptions.clone(),
+            policy: policy.clone(),
         };
         let server_join_handle = tokio::spawn(mullvad_management_interface::spawn_rpc_server(
             server,
+            policy,
             start_tx,
             server_abort_rx,
         ));
@@ -790,7 +1473,7 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsSubscription>>>,
     close_handle: triggered::Trigger,
 }
 
@@ -807,11 +1490,18 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
     /// Sends settings to all `settings` subscribers of the management interface.
     fn notify_settings(&self, settings: Settings) {
         log::debug!("Broadcasting new settings");
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::Settings(types::Settings::from(
-                &settings,
-            ))),
-        })
+        self.notify_by_role(
+            types::DaemonEvent {
+                event: Some(daemon_event::Event::Settings(types::Settings::from(
+                    &settings,
+                ))),
+            },
+            types::DaemonEvent {
+                event: Some(daemon_event::Event::Settings(types::Settings::from(
+                    &settings.without_secrets(),
+                ))),
+            },
+        )
     }
 
     /// Sends relays to all subscribers of the management interface.
@@ -848,13 +1538,33 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             ))),
         })
     }
+
+    fn notify_tunnel_stats(&self, tunnel_stats: TunnelStats) {
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::TunnelStats(convert_tunnel_stats(
+                tunnel_stats,
+            ))),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
     fn notify(&self, value: types::DaemonEvent) {
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.retain(|subscription| subscription.tx.send(Ok(value.clone())).is_ok());
+    }
+
+    /// Sends `monitor_value` to subscribers with the monitor role, and `value` to all others.
+    fn notify_by_role(&self, value: types::DaemonEvent, monitor_value: types::DaemonEvent) {
+        let mut subscriptions = self.subscriptions.write();
+        subscriptions.retain(|subscription| {
+            let value = match subscription.role {
+                Role::Monitor => &monitor_value,
+                Role::Control => &value,
+            };
+            subscription.tx.send(Ok(value.clone())).is_ok()
+        });
     }
 }
 
@@ -864,6 +1574,82 @@ impl Drop for ManagementInterfaceEventBroadcaster {
     }
 }
 
+fn convert_tunnel_stats(stats: TunnelStats) -> types::TunnelStats {
+    types::TunnelStats {
+        rx_bytes: stats.rx_bytes,
+        tx_bytes: stats.tx_bytes,
+        total_rx_bytes: stats.total_rx_bytes,
+        total_tx_bytes: stats.total_tx_bytes,
+        last_handshake: stats.last_handshake.map(types::Timestamp::from),
+        connected_since: stats.connected_since.map(types::Timestamp::from),
+        reconnects: stats.reconnects,
+        ipv6_degraded: stats.ipv6_degraded,
+        remote: stats
+            .connection_details
+            .remote
+            .map(|remote| remote.to_string())
+            .unwrap_or_default(),
+        data_channel_cipher: stats
+            .connection_details
+            .data_channel_cipher
+            .unwrap_or_default(),
+        control_channel: stats.connection_details.control_channel.unwrap_or_default(),
+    }
+}
+
+fn convert_session(session: connection_history::SessionEntry) -> types::ConnectionSession {
+    use connection_history::EndReason;
+    use types::connection_session::EndReason as ProtoEndReason;
+
+    let timestamp = |time: chrono::DateTime<chrono::Utc>| types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    };
+    types::ConnectionSession {
+        started: Some(timestamp(session.started)),
+        connected: session.connected.map(timestamp),
+        ended: session.ended.map(timestamp),
+        relay: session.relay.unwrap_or_default(),
+        tunnel_type: i32::from(types::TunnelType::from(session.tunnel_type)),
+        entry_relay: session.entry_relay.unwrap_or_default(),
+        bridge: session.bridge.unwrap_or_default(),
+        rx_bytes: session.rx_bytes,
+        tx_bytes: session.tx_bytes,
+        retry_attempts: session.retry_attempts,
+        end_reason: session.end_reason.map(|reason| match reason {
+            EndReason::Disconnect(after_disconnect) => ProtoEndReason::Disconnect(i32::from(
+                types::AfterDisconnect::from(after_disconnect),
+            )),
+            EndReason::Error(cause) => ProtoEndReason::Error(types::ErrorState::from(
+                talpid_types::tunnel::ErrorState::new(cause, None),
+            )),
+            EndReason::TunnelLost => ProtoEndReason::TunnelLost(()),
+        }),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn convert_blocked_packet(packet: talpid_core::firewall::BlockedPacket) -> types::BlockedPacket {
+    use talpid_core::firewall::BlockedDirection;
+
+    let direction = match packet.direction {
+        BlockedDirection::Inbound => types::blocked_packet::Direction::Inbound,
+        BlockedDirection::Outbound => types::blocked_packet::Direction::Outbound,
+        BlockedDirection::Forward => types::blocked_packet::Direction::Forward,
+    };
+    types::BlockedPacket {
+        time: Some(types::Timestamp::from(packet.time)),
+        direction: i32::from(direction),
+        protocol: u32::from(packet.protocol),
+        source: packet.source.to_string(),
+        destination: packet.destination.to_string(),
+        source_port: packet.source_port.map(u32::from),
+        destination_port: packet.destination_port.map(u32::from),
+        uid: packet.uid,
+        gid: packet.gid,
+    }
+}
+
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
@@ -877,6 +1663,13 @@ fn map_daemon_error(error: crate::Error) -> Status {
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
+        DaemonError::NoKeyAvailable => Status::failed_precondition(error.to_string()),
+        DaemonError::NoRelayAvailable | DaemonError::UnknownWireguardDevice(_) => {
+            Status::not_found(error.to_string())
+        }
+        DaemonError::InvalidWireguardDeviceName => Status::invalid_argument(error.to_string()),
+        DaemonError::WireguardDeviceExists(_) => Status::already_exists(error.to_string()),
+        DaemonError::TooManyKeys => Status::resource_exhausted(error.to_string()),
         error => Status::unknown(error.to_string()),
     }
 }
@@ -937,6 +1730,18 @@ fn map_settings_error(error: settings::Error) -> Status {
             Status::new(Code::FailedPrecondition, error.to_string())
         }
         settings::Error::SerializeError(..) => Status::new(Code::Internal, error.to_string()),
+        settings::Error::SecretsLocked => Status::new(Code::FailedPrecondition, error.to_string()),
+        settings::Error::PinnedByAdmin(..) => {
+            Status::new(Code::FailedPrecondition, error.to_string())
+        }
+        settings::Error::ImportError(..) | settings::Error::InvalidConnectivityCheck(..) => {
+            Status::new(Code::InvalidArgument, error.to_string())
+        }
+        settings::Error::Encryption(settings_encryption::Error::PassphraseRequired)
+        | settings::Error::Encryption(settings_encryption::Error::Open) => {
+            Status::new(Code::Unauthenticated, error.to_string())
+        }
+        settings::Error::Encryption(..) => Status::new(Code::FailedPrecondition, error.to_string()),
     }
 }
 
@@ -949,5 +1754,13 @@ fn map_account_history_error(error: account_history::Error) -> Status {
         account_history::Error::Serialize(..) | account_history::Error::WriteCancelled(..) => {
             Status::new(Code::Internal, error.to_string())
         }
+        account_history::Error::Locked => Status::new(Code::FailedPrecondition, error.to_string()),
+        account_history::Error::Encryption(settings_encryption::Error::PassphraseRequired)
+        | account_history::Error::Encryption(settings_encryption::Error::Open) => {
+            Status::new(Code::Unauthenticated, error.to_string())
+        }
+        account_history::Error::Encryption(..) => {
+            Status::new(Code::FailedPrecondition, error.to_string())
+        }
     }
 }
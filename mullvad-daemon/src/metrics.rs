@@ -0,0 +1,667 @@
+//! Local HTTP endpoint that exports metrics of the daemon in the Prometheus text format. The
+//! endpoint is disabled by default and only listens on the loopback interface or a Unix socket.
+
+use crate::relays::LastUpdatedHandle;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+};
+use hyper::{
+    header::{self, HeaderValue},
+    server::{accept::Accept, conn::AddrIncoming},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use mullvad_rpc::stats::{LatencyHistogram, RequestKey, RequestStats, LATENCY_BUCKETS};
+use mullvad_types::{settings::MetricsListenAddress, states::TunnelState};
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::{self, Write},
+    io,
+    path::Path,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+use talpid_core::tunnel_state_machine::{TunnelCommand, TunnelStats};
+use talpid_types::{net::TunnelType, tunnel::ErrorStateCause, ErrorExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Path that the metrics are served on.
+const METRICS_PATH: &str = "/metrics";
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// How long to wait for the tunnel state machine to report traffic statistics during a scrape.
+const TUNNEL_STATS_TIMEOUT: Duration = Duration::from_secs(1);
+
+const TUNNEL_STATES: &[&str] = &[
+    "disconnected",
+    "connecting",
+    "connected",
+    "disconnecting",
+    "error",
+];
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to listen on the metrics TCP port")]
+    BindTcp(#[error(source)] hyper::Error),
+
+    #[error(display = "Invalid metrics listen address")]
+    InvalidListenAddress(#[error(source)] mullvad_types::settings::Error),
+
+    #[error(display = "Failed to remove stale metrics socket")]
+    RemoveStaleSocket(#[error(source)] io::Error),
+
+    #[error(display = "A file that is not a socket exists at the metrics socket path")]
+    NotASocket,
+
+    #[error(display = "Failed to listen on the metrics socket")]
+    BindUnixSocket(#[error(source)] io::Error),
+
+    #[error(display = "Unix sockets are not supported on this platform")]
+    UnixSocketUnsupported,
+}
+
+/// How a WireGuard key rotation was started.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyRotationTrigger {
+    /// The key expired and was rotated by the key manager.
+    Automatic,
+    /// The user asked for a new key.
+    Manual,
+}
+
+/// Counters of the events reported by the daemon. Cloning the value returns a handle to the same
+/// counters.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+struct Counters {
+    tunnel_state: &'static str,
+    tunnel_type: Option<TunnelType>,
+    connect_attempts: u64,
+    connect_failures: BTreeMap<&'static str, u64>,
+    relay_selections: BTreeMap<(String, &'static str), u64>,
+    key_rotations: BTreeMap<(&'static str, &'static str), u64>,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters {
+            tunnel_state: "disconnected",
+            tunnel_type: None,
+            connect_attempts: 0,
+            connect_failures: BTreeMap::new(),
+            relay_selections: BTreeMap::new(),
+            key_rotations: BTreeMap::new(),
+        }
+    }
+}
+
+/// Values that are read from the rest of the daemon when the endpoint is scraped.
+#[derive(Default)]
+struct Snapshot {
+    tunnel_stats: Option<TunnelStats>,
+    relay_list_updated: Option<SystemTime>,
+    requests: BTreeMap<RequestKey, LatencyHistogram>,
+}
+
+/// Sources of the metrics that are not reported to `Metrics` as events.
+#[derive(Clone)]
+pub struct MetricsSources {
+    pub tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    pub relay_list: LastUpdatedHandle,
+    pub request_stats: RequestStats,
+}
+
+impl Metrics {
+    /// Records a tunnel state transition.
+    pub fn tunnel_state_changed(&self, tunnel_state: &TunnelState) {
+        let mut counters = self.counters.lock();
+        counters.tunnel_state = match tunnel_state {
+            TunnelState::Disconnected => "disconnected",
+            TunnelState::Connecting { endpoint, .. } => {
+                counters.connect_attempts += 1;
+                counters.tunnel_type = Some(endpoint.tunnel_type);
+                "connecting"
+            }
+            TunnelState::Connected { endpoint, .. } => {
+                counters.tunnel_type = Some(endpoint.tunnel_type);
+                "connected"
+            }
+            TunnelState::Disconnecting(_) => "disconnecting",
+            TunnelState::Error(error_state) => {
+                *counters
+                    .connect_failures
+                    .entry(error_cause_label(error_state.cause()))
+                    .or_default() += 1;
+                "error"
+            }
+        };
+    }
+
+    /// Records that a relay was selected for a new tunnel.
+    pub fn relay_selected(&self, hostname: &str, tunnel_type: TunnelType) {
+        *self
+            .counters
+            .lock()
+            .relay_selections
+            .entry((hostname.to_owned(), tunnel_type_label(tunnel_type)))
+            .or_default() += 1;
+    }
+
+    /// Records the result of a WireGuard key rotation.
+    pub fn key_rotated(&self, trigger: KeyRotationTrigger, success: bool) {
+        let trigger = match trigger {
+            KeyRotationTrigger::Automatic => "automatic",
+            KeyRotationTrigger::Manual => "manual",
+        };
+        let result = if success { "success" } else { "failure" };
+        *self
+            .counters
+            .lock()
+            .key_rotations
+            .entry((trigger, result))
+            .or_default() += 1;
+    }
+
+    async fn scrape(&self, sources: &MetricsSources) -> String {
+        let snapshot = Snapshot {
+            tunnel_stats: Self::get_tunnel_stats(&sources.tunnel_command_tx).await,
+            relay_list_updated: Some(sources.relay_list.last_updated())
+                .filter(|last_updated| *last_updated != SystemTime::UNIX_EPOCH),
+            requests: sources.request_stats.snapshot(),
+        };
+        self.render(&snapshot)
+    }
+
+    async fn get_tunnel_stats(
+        tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    ) -> Option<TunnelStats> {
+        let (tx, rx) = oneshot::channel();
+        tunnel_command_tx
+            .upgrade()?
+            .unbounded_send(TunnelCommand::GetTunnelStats(tx))
+            .ok()?;
+        tokio::time::timeout(TUNNEL_STATS_TIMEOUT, rx)
+            .await
+            .ok()?
+            .ok()
+    }
+
+    fn render(&self, snapshot: &Snapshot) -> String {
+        let counters = self.counters.lock();
+        let mut output = Output::default();
+
+        output.family(
+            "mullvad_tunnel_state",
+            "gauge",
+            "Whether the tunnel is in the given state",
+        );
+        for &state in TUNNEL_STATES {
+            let value = if state == counters.tunnel_state { 1 } else { 0 };
+            output.sample("mullvad_tunnel_state", &[("state", state)], value);
+        }
+
+        output.family(
+            "mullvad_connect_attempts_total",
+            "counter",
+            "Number of times the tunnel started connecting",
+        );
+        output.sample(
+            "mullvad_connect_attempts_total",
+            &[],
+            counters.connect_attempts,
+        );
+
+        output.family(
+            "mullvad_connect_failures_total",
+            "counter",
+            "Number of times the tunnel entered the error state, by cause",
+        );
+        for (cause, count) in &counters.connect_failures {
+            output.sample(
+                "mullvad_connect_failures_total",
+                &[("cause", *cause)],
+                count,
+            );
+        }
+
+        output.family(
+            "mullvad_relay_selections_total",
+            "counter",
+            "Number of times a relay was selected for a tunnel",
+        );
+        for ((relay, tunnel_type), count) in &counters.relay_selections {
+            output.sample(
+                "mullvad_relay_selections_total",
+                &[("relay", relay.as_str()), ("tunnel_type", *tunnel_type)],
+                count,
+            );
+        }
+
+        if let Some(stats) = &snapshot.tunnel_stats {
+            if let (Some(tunnel_type), Some(_)) = (counters.tunnel_type, stats.connected_since) {
+                let labels = [("tunnel_type", tunnel_type_label(tunnel_type))];
+                output.family(
+                    "mullvad_tunnel_receive_bytes",
+                    "gauge",
+                    "Bytes received through the connected tunnel",
+                );
+                output.sample("mullvad_tunnel_receive_bytes", &labels, stats.rx_bytes);
+                output.family(
+                    "mullvad_tunnel_transmit_bytes",
+                    "gauge",
+                    "Bytes sent through the connected tunnel",
+                );
+                output.sample("mullvad_tunnel_transmit_bytes", &labels, stats.tx_bytes);
+            }
+            output.family(
+                "mullvad_tunnel_receive_bytes_total",
+                "counter",
+                "Bytes received through all tunnels since the daemon started",
+            );
+            output.sample(
+                "mullvad_tunnel_receive_bytes_total",
+                &[],
+                stats.total_rx_bytes,
+            );
+            output.family(
+                "mullvad_tunnel_transmit_bytes_total",
+                "counter",
+                "Bytes sent through all tunnels since the daemon started",
+            );
+            output.sample(
+                "mullvad_tunnel_transmit_bytes_total",
+                &[],
+                stats.total_tx_bytes,
+            );
+        }
+
+        output.family(
+            "mullvad_wireguard_key_rotations_total",
+            "counter",
+            "Number of WireGuard key rotations, by trigger and result",
+        );
+        for ((trigger, result), count) in &counters.key_rotations {
+            output.sample(
+                "mullvad_wireguard_key_rotations_total",
+                &[("trigger", *trigger), ("result", *result)],
+                count,
+            );
+        }
+
+        if let Some(age) = snapshot
+            .relay_list_updated
+            .and_then(|last_updated| SystemTime::now().duration_since(last_updated).ok())
+        {
+            output.family(
+                "mullvad_relay_list_age_seconds",
+                "gauge",
+                "Time since the relay list was last updated",
+            );
+            output.sample("mullvad_relay_list_age_seconds", &[], age.as_secs());
+        }
+
+        output.family(
+            "mullvad_api_request_duration_seconds",
+            "histogram",
+            "Latency of requests to the Mullvad API",
+        );
+        for (key, histogram) in &snapshot.requests {
+            let status = key.outcome.to_string();
+            let labels = [
+                ("method", key.method.as_str()),
+                ("endpoint", key.endpoint.as_str()),
+                ("status", status.as_str()),
+            ];
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                let bound = bound.to_string();
+                let mut bucket_labels = labels.to_vec();
+                bucket_labels.push(("le", bound.as_str()));
+                output.sample(
+                    "mullvad_api_request_duration_seconds_bucket",
+                    &bucket_labels,
+                    count,
+                );
+            }
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", "+Inf"));
+            output.sample(
+                "mullvad_api_request_duration_seconds_bucket",
+                &bucket_labels,
+                histogram.count,
+            );
+            output.sample(
+                "mullvad_api_request_duration_seconds_sum",
+                &labels,
+                histogram.sum.as_secs_f64(),
+            );
+            output.sample(
+                "mullvad_api_request_duration_seconds_count",
+                &labels,
+                histogram.count,
+            );
+        }
+
+        output.text
+    }
+}
+
+#[derive(Default)]
+struct Output {
+    text: String,
+}
+
+impl Output {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+                .collect::<Vec<_>>();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn tunnel_type_label(tunnel_type: TunnelType) -> &'static str {
+    match tunnel_type {
+        TunnelType::OpenVpn => "openvpn",
+        TunnelType::Wireguard => "wireguard",
+    }
+}
+
+fn error_cause_label(cause: &ErrorStateCause) -> &'static str {
+    match cause {
+        ErrorStateCause::AuthFailed(_) => "auth_failed",
+        ErrorStateCause::Ipv6Unavailable => "ipv6_unavailable",
+        ErrorStateCause::SetFirewallPolicyError(_) => "set_firewall_policy_error",
+        ErrorStateCause::SetDnsError => "set_dns_error",
+        #[cfg(target_os = "android")]
+        ErrorStateCause::InvalidDnsServers(_) => "invalid_dns_servers",
+        ErrorStateCause::StartTunnelError => "start_tunnel_error",
+        ErrorStateCause::TunnelParameterError(_) => "tunnel_parameter_error",
+        ErrorStateCause::IsOffline => "is_offline",
+        #[cfg(target_os = "android")]
+        ErrorStateCause::VpnPermissionDenied => "vpn_permission_denied",
+        #[cfg(target_os = "windows")]
+        ErrorStateCause::SplitTunnelError => "split_tunnel_error",
+    }
+}
+
+/// Starts listening on `listen_address` and returns a future that serves the metrics endpoint.
+/// Unix sockets are created in `socket_dir`.
+pub fn serve(
+    listen_address: &MetricsListenAddress,
+    #[cfg_attr(not(unix), allow(unused_variables))] socket_dir: &Path,
+    metrics: Metrics,
+    sources: MetricsSources,
+) -> Result<BoxFuture<'static, ()>, Error> {
+    match listen_address {
+        MetricsListenAddress::Localhost(_) => {
+            let address = listen_address.socket_addr().unwrap();
+            let incoming = AddrIncoming::bind(&address).map_err(Error::BindTcp)?;
+            Ok(serve_incoming(incoming, metrics, sources))
+        }
+        #[cfg(unix)]
+        MetricsListenAddress::UnixSocket(name) => {
+            listen_address
+                .validate()
+                .map_err(Error::InvalidListenAddress)?;
+            let path = socket_dir.join(name);
+            remove_stale_socket(&path)?;
+            let listener = tokio::net::UnixListener::bind(&path).map_err(Error::BindUnixSocket)?;
+            let incoming = hyper::server::accept::from_stream(
+                tokio_stream::wrappers::UnixListenerStream::new(listener),
+            );
+            Ok(serve_incoming(incoming, metrics, sources))
+        }
+        #[cfg(not(unix))]
+        MetricsListenAddress::UnixSocket(_) => Err(Error::UnixSocketUnsupported),
+    }
+}
+
+/// Removes the socket left behind by a previous instance of the endpoint. Anything that is not a
+/// socket is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path).map_err(Error::RemoveStaleSocket)
+        }
+        Ok(_) => Err(Error::NotASocket),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(Error::RemoveStaleSocket(error)),
+    }
+}
+
+fn serve_incoming<I>(
+    incoming: I,
+    metrics: Metrics,
+    sources: MetricsSources,
+) -> BoxFuture<'static, ()>
+where
+    I: Accept + Send + 'static,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let make_service = make_service_fn(move |_: &I::Conn| {
+        let metrics = metrics.clone();
+        let sources = sources.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, metrics.clone(), sources.clone())
+            }))
+        }
+    });
+    let server = Server::builder(incoming)
+        .http1_only(true)
+        .serve(make_service);
+    Box::pin(async move {
+        if let Err(error) = server.await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Metrics endpoint stopped unexpectedly")
+            );
+        }
+    })
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    metrics: Metrics,
+    sources: MetricsSources,
+) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != METRICS_PATH {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    }
+    if request.method() != Method::GET {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let mut response = Response::new(Body::from(metrics.scrape(&sources).await));
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE));
+    Ok(response)
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelEndpoint},
+        tunnel::ErrorState,
+    };
+
+    fn connected_state() -> TunnelState {
+        TunnelState::Connected {
+            endpoint: TunnelEndpoint {
+                endpoint: Endpoint::new(
+                    std::net::Ipv4Addr::new(10, 0, 0, 1),
+                    51820,
+                    TransportProtocol::Udp,
+                ),
+                tunnel_type: TunnelType::Wireguard,
+                proxy: None,
+                entry_endpoint: None,
+            },
+            location: None,
+        }
+    }
+
+    #[test]
+    fn renders_counters() {
+        let metrics = Metrics::default();
+        metrics.tunnel_state_changed(&TunnelState::Error(ErrorState::new(
+            ErrorStateCause::IsOffline,
+            None,
+        )));
+        metrics.tunnel_state_changed(&connected_state());
+        metrics.relay_selected("se-got-wg-001", TunnelType::Wireguard);
+        metrics.relay_selected("se-got-wg-001", TunnelType::Wireguard);
+        metrics.key_rotated(KeyRotationTrigger::Automatic, true);
+
+        let output = metrics.render(&Snapshot::default());
+        assert!(output.contains("mullvad_tunnel_state{state=\"connected\"} 1\n"));
+        assert!(output.contains("mullvad_tunnel_state{state=\"error\"} 0\n"));
+        assert!(output.contains("mullvad_connect_failures_total{cause=\"is_offline\"} 1\n"));
+        assert!(output.contains(
+            "mullvad_relay_selections_total{relay=\"se-got-wg-001\",tunnel_type=\"wireguard\"} 2\n"
+        ));
+        assert!(output.contains(
+            "mullvad_wireguard_key_rotations_total{trigger=\"automatic\",result=\"success\"} 1\n"
+        ));
+        assert!(!output.contains("mullvad_relay_list_age_seconds"));
+    }
+
+    #[test]
+    fn renders_tunnel_stats() {
+        let metrics = Metrics::default();
+        metrics.tunnel_state_changed(&connected_state());
+
+        let snapshot = Snapshot {
+            tunnel_stats: Some(TunnelStats {
+                rx_bytes: 100,
+                tx_bytes: 50,
+                total_rx_bytes: 1100,
+                total_tx_bytes: 550,
+                connected_since: Some(SystemTime::now()),
+                ..TunnelStats::default()
+            }),
+            relay_list_updated: Some(SystemTime::now() - Duration::from_secs(120)),
+            ..Snapshot::default()
+        };
+        let output = metrics.render(&snapshot);
+        assert!(output.contains("mullvad_tunnel_receive_bytes{tunnel_type=\"wireguard\"} 100\n"));
+        assert!(output.contains("mullvad_tunnel_transmit_bytes_total 550\n"));
+        assert!(output.contains("mullvad_relay_list_age_seconds 120\n"));
+    }
+
+    /// Sends `GET <path>` to the endpoint and returns the raw HTTP response.
+    fn http_get(address: std::net::SocketAddr, path: &str) -> String {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics_over_http() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let metrics = Metrics::default();
+        metrics.tunnel_state_changed(&connected_state());
+        metrics.relay_selected("se-got-wg-001", TunnelType::Wireguard);
+        let sources = MetricsSources {
+            tunnel_command_tx: Weak::new(),
+            relay_list: LastUpdatedHandle::empty(),
+            request_stats: RequestStats::default(),
+        };
+
+        let address = runtime.block_on(async move {
+            let incoming = AddrIncoming::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let address = incoming.local_addr();
+            tokio::spawn(serve_incoming(incoming, metrics, sources));
+            address
+        });
+
+        let response = http_get(address, METRICS_PATH);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response
+            .to_lowercase()
+            .contains(&format!("content-type: {}\r\n", CONTENT_TYPE)));
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        assert!(body.starts_with("# HELP mullvad_tunnel_state "), "{}", body);
+        assert!(body.contains("# TYPE mullvad_tunnel_state gauge\n"));
+        assert!(body.contains("mullvad_tunnel_state{state=\"connected\"} 1\n"));
+        assert!(body.contains(
+            "mullvad_relay_selections_total{relay=\"se-got-wg-001\",tunnel_type=\"wireguard\"} 1\n"
+        ));
+        // The tunnel state machine isn't running, so there are no traffic statistics
+        assert!(!body.contains("mullvad_tunnel_receive_bytes"));
+
+        assert!(http_get(address, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn removes_only_stale_sockets() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+
+        let socket = dir.join("metrics.sock");
+        remove_stale_socket(&socket).unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        remove_stale_socket(&socket).unwrap();
+        assert!(!socket.exists());
+
+        let file = dir.join("settings.json");
+        std::fs::write(&file, b"{}").unwrap();
+        assert!(matches!(remove_stale_socket(&file), Err(Error::NotASocket)));
+        let link = dir.join("link.sock");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        assert!(matches!(remove_stale_socket(&link), Err(Error::NotASocket)));
+        assert!(file.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Tracks connection failures per relay, so that the relay selector can avoid relays that have
+//! recently failed. Failures add a penalty that decays over time, and relays that fail several
+//! times in a row are quarantined for a while.
+
+use mullvad_types::relay_list::Relay;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use talpid_types::ErrorExt;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read relay health file")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse relay health file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize relay health")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write relay health file")]
+    Write(#[error(source)] io::Error),
+}
+
+const RELAY_HEALTH_FILE: &str = "relay-health.json";
+
+/// Time it takes for a penalty to decay to half of its value.
+const PENALTY_HALF_LIFE: Duration = Duration::from_secs(60 * 60);
+/// Penalty added for each failure.
+const FAILURE_PENALTY: f64 = 1.0;
+/// Penalties below this value are forgotten.
+const MIN_PENALTY: f64 = 0.01;
+/// Scale applied to relay weights, so that penalized weights keep some precision.
+const WEIGHT_SCALE: f64 = 1000.0;
+
+/// Number of consecutive failures after which a relay is quarantined.
+const QUARANTINE_THRESHOLD: u32 = 3;
+/// How long a relay is quarantined for after reaching the threshold. The duration doubles for
+/// every further failure, up to `MAX_QUARANTINE_DURATION`.
+const QUARANTINE_DURATION: Duration = Duration::from_secs(15 * 60);
+const MAX_QUARANTINE_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Observed health of a single relay.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct RelayScore {
+    /// Penalty at the time of `updated`.
+    penalty: f64,
+    updated: SystemTime,
+    consecutive_failures: u32,
+    quarantined_until: Option<SystemTime>,
+}
+
+impl RelayScore {
+    fn new(now: SystemTime) -> Self {
+        RelayScore {
+            penalty: 0.0,
+            updated: now,
+            consecutive_failures: 0,
+            quarantined_until: None,
+        }
+    }
+
+    fn penalty(&self, now: SystemTime) -> f64 {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        self.penalty * 0.5f64.powf(elapsed.as_secs_f64() / PENALTY_HALF_LIFE.as_secs_f64())
+    }
+
+    fn decay(&mut self, now: SystemTime) {
+        self.penalty = self.penalty(now);
+        self.updated = now;
+    }
+
+    fn is_quarantined(&self, now: SystemTime) -> bool {
+        self.quarantined_until
+            .map(|until| now < until)
+            .unwrap_or(false)
+    }
+
+    fn is_forgotten(&self, now: SystemTime) -> bool {
+        self.penalty(now) < MIN_PENALTY && !self.is_quarantined(now)
+    }
+}
+
+/// Health scores of all relays that have failed recently, keyed by hostname.
+#[derive(Debug, Default)]
+pub struct RelayHealth {
+    scores: HashMap<String, RelayScore>,
+    cache_path: Option<PathBuf>,
+}
+
+impl RelayHealth {
+    /// Loads the scores cached in `cache_dir`. Missing or unreadable scores are ignored.
+    pub fn load(cache_dir: &Path) -> Self {
+        let cache_path = cache_dir.join(RELAY_HEALTH_FILE);
+        let scores = match Self::read_scores(&cache_path) {
+            Ok(scores) => scores,
+            Err(Error::Read(ref error)) if error.kind() == io::ErrorKind::NotFound => {
+                HashMap::new()
+            }
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to load relay health scores")
+                );
+                HashMap::new()
+            }
+        };
+        RelayHealth {
+            scores,
+            cache_path: Some(cache_path),
+        }
+    }
+
+    fn read_scores(path: &Path) -> Result<HashMap<String, RelayScore>, Error> {
+        let file = std::fs::File::open(path).map_err(Error::Read)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(Error::Parse)
+    }
+
+    /// Writes the scores to the cache directory, leaving out relays whose penalty has decayed.
+    pub async fn save(&mut self) -> Result<(), Error> {
+        self.prune(SystemTime::now());
+
+        let cache_path = match &self.cache_path {
+            Some(cache_path) => cache_path,
+            None => return Ok(()),
+        };
+        let buffer = serde_json::to_vec(&self.scores).map_err(Error::Serialize)?;
+        tokio::fs::write(cache_path, buffer)
+            .await
+            .map_err(Error::Write)
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        self.scores.retain(|_, score| !score.is_forgotten(now));
+    }
+
+    /// Forgets all failures.
+    pub fn reset(&mut self) {
+        self.scores.clear();
+    }
+
+    /// Records a failed connection attempt to the given relay.
+    pub fn record_failure(&mut self, hostname: &str, now: SystemTime) {
+        let score = self
+            .scores
+            .entry(hostname.to_owned())
+            .or_insert_with(|| RelayScore::new(now));
+        score.decay(now);
+        score.penalty += FAILURE_PENALTY;
+        score.consecutive_failures += 1;
+
+        if score.consecutive_failures >= QUARANTINE_THRESHOLD {
+            let doublings = (score.consecutive_failures - QUARANTINE_THRESHOLD).min(16);
+            let duration = (QUARANTINE_DURATION * 2u32.pow(doublings)).min(MAX_QUARANTINE_DURATION);
+            log::info!(
+                "Quarantining relay {} for {} minutes after {} consecutive failures",
+                hostname,
+                duration.as_secs() / 60,
+                score.consecutive_failures
+            );
+            score.quarantined_until = Some(now + duration);
+        }
+    }
+
+    /// Records a successful connection to the given relay. This lifts any quarantine and halves
+    /// the penalty.
+    pub fn record_success(&mut self, hostname: &str, now: SystemTime) {
+        if let Some(score) = self.scores.get_mut(hostname) {
+            score.decay(now);
+            score.penalty /= 2.0;
+            score.consecutive_failures = 0;
+            score.quarantined_until = None;
+        }
+    }
+
+    /// Returns the current penalty of a relay. Relays without failures have a penalty of zero.
+    pub fn penalty(&self, hostname: &str, now: SystemTime) -> f64 {
+        self.scores
+            .get(hostname)
+            .map(|score| score.penalty(now))
+            .unwrap_or(0.0)
+    }
+
+    /// Returns whether a relay should not be selected at all, unless there are no alternatives.
+    pub fn is_quarantined(&self, hostname: &str, now: SystemTime) -> bool {
+        self.scores
+            .get(hostname)
+            .map(|score| score.is_quarantined(now))
+            .unwrap_or(false)
+    }
+
+    /// Returns the weight of a relay when selecting randomly, scaled down by its penalty.
+    pub fn weight(&self, relay: &Relay, now: SystemTime) -> u64 {
+        let penalty = self.penalty(&relay.hostname, now);
+        (relay.weight as f64 * WEIGHT_SCALE / (1.0 + penalty)) as u64
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RELAY: &str = "se-got-wg-001";
+
+    #[test]
+    fn penalty_decays() {
+        let mut health = RelayHealth::default();
+        let start = SystemTime::now();
+        health.record_failure(RELAY, start);
+        health.record_failure(RELAY, start);
+        assert_eq!(health.penalty(RELAY, start), 2.0);
+
+        let later = start + PENALTY_HALF_LIFE;
+        assert!((health.penalty(RELAY, later) - 1.0).abs() < 1e-9);
+
+        health.record_failure(RELAY, later);
+        assert!((health.penalty(RELAY, later) - 2.0).abs() < 1e-9);
+        assert_eq!(health.penalty("se-got-wg-002", later), 0.0);
+    }
+
+    #[test]
+    fn consecutive_failures_quarantine_relay() {
+        let mut health = RelayHealth::default();
+        let start = SystemTime::now();
+        for _ in 0..QUARANTINE_THRESHOLD - 1 {
+            health.record_failure(RELAY, start);
+        }
+        assert!(!health.is_quarantined(RELAY, start));
+
+        health.record_failure(RELAY, start);
+        assert!(health.is_quarantined(RELAY, start));
+        assert!(health.is_quarantined(RELAY, start + QUARANTINE_DURATION / 2));
+        assert!(!health.is_quarantined(RELAY, start + QUARANTINE_DURATION));
+
+        // Another failure doubles the quarantine
+        health.record_failure(RELAY, start);
+        assert!(health.is_quarantined(RELAY, start + QUARANTINE_DURATION));
+        assert!(!health.is_quarantined(RELAY, start + QUARANTINE_DURATION * 2));
+    }
+
+    #[test]
+    fn success_lifts_quarantine() {
+        let mut health = RelayHealth::default();
+        let start = SystemTime::now();
+        for _ in 0..QUARANTINE_THRESHOLD {
+            health.record_failure(RELAY, start);
+        }
+        health.record_success(RELAY, start);
+        assert!(!health.is_quarantined(RELAY, start));
+        assert_eq!(health.penalty(RELAY, start), 1.5);
+
+        // Failures must be consecutive to quarantine the relay again
+        health.record_failure(RELAY, start);
+        assert!(!health.is_quarantined(RELAY, start));
+    }
+
+    #[test]
+    fn prune_forgets_decayed_scores() {
+        let mut health = RelayHealth::default();
+        let now = SystemTime::now();
+        health.record_failure(RELAY, now - PENALTY_HALF_LIFE * 10);
+        health.record_failure("se-got-wg-002", now);
+
+        health.prune(now);
+        assert!(!health.scores.contains_key(RELAY));
+        assert!(health.scores.contains_key("se-got-wg-002"));
+    }
+}
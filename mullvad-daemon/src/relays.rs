@@ -1,6 +1,7 @@
 //! When changing relay selection, please verify if `docs/relay-selector.md` needs to be
 //! updated as well.
 
+use crate::relay_health::RelayHealth;
 use chrono::{DateTime, Local};
 use futures::{
     channel::mpsc,
@@ -156,8 +157,25 @@ impl ParsedRelays {
     }
 }
 
+/// Reads the time when the relay list of a `RelaySelector` was last updated.
+#[derive(Clone)]
+pub struct LastUpdatedHandle(Arc<Mutex<ParsedRelays>>);
+
+impl LastUpdatedHandle {
+    pub fn last_updated(&self) -> SystemTime {
+        self.0.lock().last_updated()
+    }
+
+    /// Returns a handle to a relay list that has never been updated.
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        LastUpdatedHandle(Arc::new(Mutex::new(ParsedRelays::empty())))
+    }
+}
+
 pub struct RelaySelector {
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    health: RelayHealth,
     rng: ThreadRng,
     updater: Option<RelayListUpdaterHandle>,
 }
@@ -199,6 +217,7 @@ impl RelaySelector {
 
         RelaySelector {
             parsed_relays,
+            health: RelayHealth::load(cache_dir),
             rng: rand::thread_rng(),
             updater: Some(updater),
         }
@@ -215,6 +234,51 @@ impl RelaySelector {
         }
     }
 
+    /// Returns a handle for reading when the relay list was last updated, which remains valid as
+    /// the relay list is updated.
+    pub fn last_updated_handle(&self) -> LastUpdatedHandle {
+        LastUpdatedHandle(self.parsed_relays.clone())
+    }
+
+    /// Returns the observed health of the relays, which is taken into account when selecting
+    /// relays.
+    pub fn health_mut(&mut self) -> &mut RelayHealth {
+        &mut self.health
+    }
+
+    /// Returns the relay that has a WireGuard endpoint with the given public key, if any.
+    pub fn get_wireguard_relay(&self, public_key: &wireguard::PublicKey) -> Option<Relay> {
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .find(|relay| {
+                relay
+                    .tunnels
+                    .wireguard
+                    .iter()
+                    .any(|endpoint| &endpoint.public_key == public_key)
+            })
+            .cloned()
+    }
+
+    /// Returns all active relays in the given location that have WireGuard endpoints, sorted by
+    /// hostname.
+    pub fn get_wireguard_relays(&self, location: &LocationConstraint) -> Vec<Relay> {
+        let mut relays: Vec<Relay> = self
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| {
+                relay.active && !relay.tunnels.wireguard.is_empty() && location.matches(relay)
+            })
+            .cloned()
+            .collect();
+        relays.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        relays
+    }
+
     /// Returns all countries and cities. The cities in the object returned does not have any
     /// relays in them.
     pub fn get_locations(&mut self) -> RelayList {
@@ -757,22 +821,38 @@ impl RelaySelector {
     }
 
     /// Pick a random relay from the given slice. Will return `None` if the given slice is empty
-    /// or all relays in it has zero weight.
+    /// or all relays in it has zero weight. Quarantined relays are only picked if all relays are
+    /// quarantined, and relays that have failed recently are less likely to be picked.
     fn pick_random_relay<'a>(&mut self, relays: &'a [Relay]) -> Option<&'a Relay> {
-        let total_weight: u64 = relays.iter().map(|relay| relay.weight).sum();
+        let now = SystemTime::now();
+        let mut candidates: Vec<&Relay> = relays
+            .iter()
+            .filter(|relay| !self.health.is_quarantined(&relay.hostname, now))
+            .collect();
+        if candidates.is_empty() {
+            candidates = relays.iter().collect();
+        }
+        let weights: Vec<u64> = candidates
+            .iter()
+            .map(|relay| self.health.weight(relay, now))
+            .collect();
+
+        let total_weight: u64 = weights.iter().sum();
         if total_weight == 0 {
             None
         } else {
             // Pick a random number in the range 0 - total_weight. This choses the relay.
             let mut i: u64 = self.rng.gen_range(0, total_weight + 1);
             Some(
-                relays
-                    .iter()
-                    .find(|relay| {
-                        i = i.saturating_sub(relay.weight);
+                candidates
+                    .into_iter()
+                    .zip(weights)
+                    .find(|(_, weight)| {
+                        i = i.saturating_sub(*weight);
                         i == 0
                     })
-                    .unwrap(),
+                    .unwrap()
+                    .0,
             )
         }
     }
@@ -859,6 +939,7 @@ impl RelaySelector {
             endpoint: SocketAddr::new(host, port),
             allowed_ips: all_of_the_internet(),
             protocol: TransportProtocol::Udp,
+            psk: None,
         };
         Some(MullvadEndpoint::Wireguard {
             peer: peer_config,
@@ -1218,6 +1299,7 @@ mod test {
                 RELAYS.clone(),
                 SystemTime::now(),
             ))),
+            health: RelayHealth::default(),
             rng: rand::thread_rng(),
             updater: None,
         }
@@ -1311,4 +1393,39 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_quarantined_relay_is_avoided() {
+        let mut relay_selector = new_relay_selector();
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::City(
+                "se".to_string(),
+                "got".to_string(),
+            )),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        for _ in 0..3 {
+            relay_selector
+                .health_mut()
+                .record_failure("se9-wireguard", SystemTime::now());
+        }
+        for _ in 0..10 {
+            let (relay, _endpoint) = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+                .expect("Failed to select relay");
+            assert_eq!(relay.hostname, "se10-wireguard");
+        }
+
+        // A quarantined relay is still used if there are no alternatives
+        for _ in 0..3 {
+            relay_selector
+                .health_mut()
+                .record_failure("se10-wireguard", SystemTime::now());
+        }
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_ok());
+    }
 }
@@ -1,17 +1,26 @@
+use crate::{
+    settings_encryption::{self, SealedSecrets, Secrets, SecretsCipher},
+    settings_override::SettingsOverride,
+};
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
 use log::{debug, error, info};
 use mullvad_types::{
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
-    wireguard::{RotationInterval, WireguardData},
+    settings::{DnsOptions, MetricsSettings, SecretsKeySource, Settings, SettingsEncryptionState},
+    wireguard::{RotationInterval, RotationPolicy, WireguardData, WireguardDevice},
 };
+use serde::{Deserialize, Serialize};
 #[cfg(target_os = "windows")]
 use std::collections::HashSet;
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
+    sync::Arc,
 };
+use talpid_types::net::wireguard::{ConnectivityCheckOptions, InvalidConnectivityCheckOptions};
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
 use talpid_types::ErrorExt;
 use tokio::{
     fs,
@@ -36,6 +45,21 @@ pub enum Error {
 
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "Unable to encrypt or decrypt the settings secrets")]
+    Encryption(#[error(source)] settings_encryption::Error),
+
+    #[error(display = "The settings secrets are encrypted and must be unlocked first")]
+    SecretsLocked,
+
+    #[error(display = "The setting \"{}\" is pinned by the administrator", _0)]
+    PinnedByAdmin(String),
+
+    #[error(display = "Unable to parse imported settings")]
+    ImportError(#[error(source)] mullvad_types::settings::Error),
+
+    #[error(display = "Invalid connectivity check options")]
+    InvalidConnectivityCheck(#[error(source)] InvalidConnectivityCheckOptions),
 }
 
 #[derive(err_derive::Error, Debug)]
@@ -55,17 +79,49 @@ enum LoadSettingsError {
 }
 
 
+/// Settings file contents when the secrets are encrypted. The account token and WireGuard keys
+/// are removed from `settings` and stored in `encrypted_secrets` instead.
+#[derive(Serialize)]
+struct SealedSettings<'a> {
+    #[serde(flatten)]
+    settings: &'a Settings,
+    encrypted_secrets: &'a SealedSecrets,
+}
+
+#[derive(Deserialize)]
+struct EncryptedSecretsField {
+    encrypted_secrets: Option<SealedSecrets>,
+}
+
+
 #[derive(Debug)]
 pub struct SettingsPersister {
     settings: Settings,
     path: PathBuf,
+    /// Cipher used to encrypt the secrets, if encryption is enabled and the secrets are unlocked.
+    cipher: Option<Arc<SecretsCipher>>,
+    /// Encrypted secrets that could not be decrypted when the settings were loaded. These are
+    /// written back unchanged until they are unlocked.
+    locked_secrets: Option<SealedSecrets>,
+    /// Settings pinned by the administrator, which can't be changed.
+    settings_override: Option<SettingsOverride>,
 }
 
 impl SettingsPersister {
     /// Loads user settings from file. If no file is present it returns the defaults.
     pub async fn load(settings_dir: &Path) -> Self {
         let path = settings_dir.join(SETTINGS_FILE);
-        let (mut settings, mut should_save) = Self::load_settings(&path).await;
+        let (mut settings, sealed_secrets, mut should_save) = Self::load_settings(&path).await;
+
+        let connectivity_check = &mut settings.tunnel_options.wireguard.options.connectivity_check;
+        if let Err(error) = connectivity_check.validate() {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Resetting invalid connectivity check options")
+            );
+            *connectivity_check = ConnectivityCheckOptions::default();
+            should_save = true;
+        }
 
         // Force IPv6 to be enabled on Android
         if cfg!(target_os = "android") {
@@ -73,7 +129,54 @@ impl SettingsPersister {
                 Self::update_field(&mut settings.tunnel_options.generic.enable_ipv6, true);
         }
 
-        let mut persister = SettingsPersister { settings, path };
+        let mut persister = SettingsPersister {
+            settings,
+            path,
+            cipher: None,
+            locked_secrets: None,
+            settings_override: None,
+        };
+        if let Some(sealed_secrets) = sealed_secrets {
+            match SecretsCipher::for_sealed(settings_dir, &sealed_secrets, None)
+                .and_then(|cipher| Ok((cipher.open::<Secrets>(&sealed_secrets)?, cipher)))
+            {
+                Ok((secrets, cipher)) => {
+                    persister.apply_secrets(secrets);
+                    persister.cipher = Some(Arc::new(cipher));
+                }
+                Err(error) => {
+                    info!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Unable to decrypt settings secrets. They must be unlocked"
+                        )
+                    );
+                    persister.locked_secrets = Some(sealed_secrets);
+                }
+            }
+        }
+
+        match SettingsOverride::load(settings_dir).await {
+            Ok(Some(settings_override)) => {
+                info!(
+                    "Pinned settings: {}",
+                    settings_override.pinned_settings().join(", ")
+                );
+                match settings_override.apply(&mut persister.settings) {
+                    Ok(changed) => should_save |= changed,
+                    Err(error) => error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to apply settings override")
+                    ),
+                }
+                persister.settings_override = Some(settings_override);
+            }
+            Ok(None) => (),
+            Err(error) => error!(
+                "{}",
+                error.display_chain_with_msg("Failed to load settings override")
+            ),
+        }
 
         if should_save {
             if let Err(error) = persister.save().await {
@@ -87,7 +190,7 @@ impl SettingsPersister {
         persister
     }
 
-    async fn load_settings(path: &Path) -> (Settings, bool) {
+    async fn load_settings(path: &Path) -> (Settings, Option<SealedSecrets>, bool) {
         let error = match Self::load_settings_from_file(path).await {
             Ok(value) => return value,
             Err(error) => error,
@@ -119,10 +222,12 @@ impl SettingsPersister {
             );
         }
 
-        (Settings::default(), true)
+        (Settings::default(), None, true)
     }
 
-    async fn load_settings_from_file(path: &Path) -> Result<(Settings, bool), LoadSettingsError> {
+    async fn load_settings_from_file(
+        path: &Path,
+    ) -> Result<(Settings, Option<SealedSecrets>, bool), LoadSettingsError> {
         info!("Loading settings from {}", path.display());
 
         let settings_bytes = fs::read(path).await.map_err(|error| {
@@ -133,19 +238,49 @@ impl SettingsPersister {
             }
         })?;
 
+        let sealed_secrets = serde_json::from_slice::<EncryptedSecretsField>(&settings_bytes)
+            .ok()
+            .and_then(|field| field.encrypted_secrets);
+
         Settings::load_from_bytes(&settings_bytes)
-            .map(|settings| (settings, false))
+            .map(|settings| (settings, sealed_secrets, false))
             .or_else(|_| {
-                Settings::migrate_from_bytes(&settings_bytes).map(|settings| (settings, true))
+                Settings::migrate_from_bytes(&settings_bytes).map(|settings| (settings, None, true))
             })
             .map_err(LoadSettingsError::ParseError)
     }
 
+    /// Serializes the settings. If encryption is enabled, the secrets are encrypted and are never
+    /// included in plain text.
+    fn serialize(&self) -> Result<String, Error> {
+        let sealed_secrets = match (&self.cipher, &self.locked_secrets) {
+            (Some(cipher), _) => {
+                let secrets = Secrets {
+                    account_token: self.settings.get_account_token(),
+                    wireguard: self.settings.get_wireguard(),
+                    wireguard_devices: self.settings.get_wireguard_devices().to_vec(),
+                };
+                Some(cipher.seal(&secrets).map_err(Error::Encryption)?)
+            }
+            (None, Some(locked_secrets)) => Some(locked_secrets.clone()),
+            (None, None) => None,
+        };
+
+        match sealed_secrets {
+            Some(sealed_secrets) => serde_json::to_string_pretty(&SealedSettings {
+                settings: &self.settings.without_secrets(),
+                encrypted_secrets: &sealed_secrets,
+            }),
+            None => serde_json::to_string_pretty(&self.settings),
+        }
+        .map_err(Error::SerializeError)
+    }
+
     /// Serializes the settings and saves them to the file it was loaded from.
     async fn save(&mut self) -> Result<(), Error> {
         debug!("Writing settings to {}", self.path.display());
 
-        let buffer = serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)?;
+        let buffer = self.serialize()?;
         let mut options = fs::OpenOptions::new();
         #[cfg(unix)]
         {
@@ -190,6 +325,16 @@ impl SettingsPersister {
     #[cfg(not(target_os = "android"))]
     pub async fn reset(&mut self) -> Result<(), Error> {
         self.settings = Settings::default();
+        self.cipher = None;
+        self.locked_secrets = None;
+        if let Some(ref settings_override) = self.settings_override {
+            if let Err(error) = settings_override.apply(&mut self.settings) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to apply settings override")
+                );
+            }
+        }
         let path = self.path.clone();
         self.save()
             .or_else(|e| async move {
@@ -209,21 +354,175 @@ impl SettingsPersister {
         self.settings.clone()
     }
 
+    /// Serializes the settings for import on another machine. The account token is only included
+    /// if `include_secrets` is set. WireGuard keys are never exported, since they must not be
+    /// shared between devices.
+    pub fn export(&self, include_secrets: bool) -> Result<String, Error> {
+        let mut settings = self.settings.without_secrets();
+        if include_secrets {
+            settings.set_account_token(self.settings.get_account_token());
+        }
+        serde_json::to_string_pretty(&settings).map_err(Error::SerializeError)
+    }
+
+    /// Parses and validates exported settings, migrating them from older formats if needed.
+    pub fn parse_import(data: &[u8]) -> Result<Settings, Error> {
+        let settings = Settings::load_from_bytes(data)
+            .or_else(|_| Settings::migrate_from_bytes(data))
+            .map_err(Error::ImportError)?;
+        settings.validate().map_err(Error::ImportError)?;
+        Ok(settings)
+    }
+
+    /// Replaces all settings except the account token and WireGuard keys with `settings`.
+    pub async fn import(&mut self, mut settings: Settings) -> Result<bool, Error> {
+        if let Some(ref settings_override) = self.settings_override {
+            if let Some(setting) = settings_override.find_violation(&settings) {
+                return Err(Error::PinnedByAdmin(setting));
+            }
+        }
+        settings.set_account_token(self.settings.get_account_token());
+        settings.set_wireguard(self.settings.get_wireguard());
+        settings.set_wireguard_devices(self.settings.get_wireguard_devices().to_vec());
+
+        let should_save = settings != self.settings;
+        self.settings = settings;
+        self.update(should_save).await
+    }
+
+    pub fn get_encryption_state(&self) -> SettingsEncryptionState {
+        SettingsEncryptionState {
+            key_source: self
+                .cipher
+                .as_ref()
+                .map(|cipher| cipher.key_source())
+                .or_else(|| self.locked_secrets.as_ref().map(|sealed| sealed.key_source)),
+            locked: self.locked_secrets.is_some(),
+        }
+    }
+
+    /// Enables encryption of the secrets using the given key source, or disables it if
+    /// `key_source` is `None`. A new salt and nonce are used every time this is called.
+    pub async fn set_encryption(
+        &mut self,
+        key_source: Option<SecretsKeySource>,
+        passphrase: Option<String>,
+    ) -> Result<bool, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
+        if key_source.is_none() && self.cipher.is_none() {
+            return Ok(false);
+        }
+        let settings_dir = self.settings_dir();
+        self.cipher = match key_source {
+            Some(key_source) => Some(Arc::new(
+                SecretsCipher::new(&settings_dir, key_source, passphrase.as_deref())
+                    .map_err(Error::Encryption)?,
+            )),
+            None => None,
+        };
+        self.save().await.map(|_| true)
+    }
+
+    /// Decrypts secrets that could not be decrypted when the settings were loaded. The boolean
+    /// in the result indicates whether any secrets were unlocked.
+    pub async fn unlock(&mut self, passphrase: Option<String>) -> Result<bool, Error> {
+        let locked_secrets = match self.locked_secrets.take() {
+            Some(locked_secrets) => locked_secrets,
+            None => return Ok(false),
+        };
+        let result =
+            SecretsCipher::for_sealed(&self.settings_dir(), &locked_secrets, passphrase.as_deref())
+                .and_then(|cipher| Ok((cipher.open::<Secrets>(&locked_secrets)?, cipher)));
+        match result {
+            Ok((secrets, cipher)) => {
+                self.apply_secrets(secrets);
+                self.cipher = Some(Arc::new(cipher));
+                Ok(true)
+            }
+            Err(error) => {
+                self.locked_secrets = Some(locked_secrets);
+                Err(Error::Encryption(error))
+            }
+        }
+    }
+
+    /// Returns the cipher that secrets are encrypted with, if encryption is enabled and the
+    /// secrets are unlocked.
+    pub fn cipher(&self) -> Option<Arc<SecretsCipher>> {
+        self.cipher.clone()
+    }
+
+    /// Returns whether the secrets are encrypted and could not be decrypted yet.
+    pub fn secrets_locked(&self) -> bool {
+        self.locked_secrets.is_some()
+    }
+
+    fn apply_secrets(&mut self, secrets: Secrets) {
+        self.settings.set_account_token(secrets.account_token);
+        self.settings.set_wireguard(secrets.wireguard);
+        self.settings
+            .set_wireguard_devices(secrets.wireguard_devices);
+    }
+
+    fn settings_dir(&self) -> PathBuf {
+        self.path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
     /// Changes account number to the one given. Also saves the new settings to disk.
     /// The boolean in the Result indicates if the account token changed or not
     pub async fn set_account_token(
         &mut self,
         account_token: Option<String>,
     ) -> Result<bool, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
         let should_save = self.settings.set_account_token(account_token);
         self.update(should_save).await
     }
 
     pub async fn set_wireguard(&mut self, wireguard: Option<WireguardData>) -> Result<bool, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
         let should_save = self.settings.set_wireguard(wireguard);
         self.update(should_save).await
     }
 
+    /// Adds a WireGuard device, or replaces the device with the same name.
+    pub async fn set_wireguard_device(&mut self, device: WireguardDevice) -> Result<bool, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
+        let should_save = self.settings.set_wireguard_device(device);
+        self.update(should_save).await
+    }
+
+    pub async fn remove_wireguard_device(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<WireguardDevice>, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
+        let device = self.settings.remove_wireguard_device(name);
+        self.update(device.is_some()).await?;
+        Ok(device)
+    }
+
+    pub async fn clear_wireguard_devices(&mut self) -> Result<bool, Error> {
+        if self.locked_secrets.is_some() {
+            return Err(Error::SecretsLocked);
+        }
+        let should_save = self.settings.set_wireguard_devices(vec![]);
+        self.update(should_save).await
+    }
+
     pub async fn update_relay_settings(
         &mut self,
         update: RelaySettingsUpdate,
@@ -281,6 +580,34 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn set_wireguard_netns(&mut self, netns: Option<String>) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.options.netns,
+            netns,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_connectivity_check(
+        &mut self,
+        options: ConnectivityCheckOptions,
+    ) -> Result<bool, Error> {
+        options
+            .validate()
+            .map_err(Error::InvalidConnectivityCheck)?;
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .connectivity_check,
+            options,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -292,6 +619,17 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_rotation_policy(
+        &mut self,
+        policy: RotationPolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.rotation_policy,
+            policy,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_show_beta_releases(
         &mut self,
         show_beta_releases: bool,
@@ -330,6 +668,29 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn set_allow_rules(&mut self, allow_rules: Vec<AllowRule>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.allow_rules, allow_rules);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_excluded_uids(&mut self, excluded_uids: Vec<u32>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.excluded_uids, excluded_uids);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_audit_blocked_traffic(&mut self, enabled: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.audit_blocked_traffic, enabled);
+        self.update(should_save).await
+    }
+
+    pub async fn set_metrics(&mut self, metrics: MetricsSettings) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.metrics, metrics);
+        self.update(should_save).await
+    }
+
     fn update_field<T: Eq>(field: &mut T, new_value: T) -> bool {
         if *field != new_value {
             *field = new_value;
@@ -341,11 +702,28 @@ impl SettingsPersister {
 
     async fn update(&mut self, should_save: bool) -> Result<bool, Error> {
         if should_save {
+            self.check_pinned_settings()?;
             self.save().await.map(|_| true)
         } else {
             Ok(false)
         }
     }
+
+    /// Rejects changes to settings pinned by the administrator, restoring the pinned values.
+    fn check_pinned_settings(&mut self) -> Result<(), Error> {
+        if let Some(ref settings_override) = self.settings_override {
+            if let Some(setting) = settings_override.find_violation(&self.settings) {
+                if let Err(error) = settings_override.apply(&mut self.settings) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to apply settings override")
+                    );
+                }
+                return Err(Error::PinnedByAdmin(setting));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Deref for SettingsPersister {
@@ -551,3 +929,214 @@ mod windows {
         unsafe { IsWellKnownSid(sid as *const SID as *mut _, well_known_sid_type) == TRUE }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::wireguard::AssociatedAddresses;
+    use talpid_types::net::wireguard::PrivateKey;
+
+    const ACCOUNT_TOKEN: &str = "1234567890123456";
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn wireguard_data() -> WireguardData {
+        WireguardData {
+            private_key: PrivateKey::new_from_random(),
+            addresses: AssociatedAddresses {
+                ipv4_address: "10.64.0.2/32".parse().unwrap(),
+                ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+            },
+            created: chrono::Utc::now(),
+            usage: Default::default(),
+        }
+    }
+
+    /// Loads plain text settings containing secrets from `dir`.
+    async fn load_with_secrets(dir: &Path) -> (SettingsPersister, WireguardData) {
+        let data = wireguard_data();
+        let mut persister = SettingsPersister::load(dir).await;
+        persister
+            .set_account_token(Some(ACCOUNT_TOKEN.to_owned()))
+            .await
+            .unwrap();
+        persister.set_wireguard(Some(data.clone())).await.unwrap();
+        (persister, data)
+    }
+
+    fn read_settings_file(dir: &Path) -> String {
+        std::fs::read_to_string(dir.join(SETTINGS_FILE)).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir();
+        run(async {
+            let (mut persister, data) = load_with_secrets(&dir).await;
+            persister.set_allow_lan(true).await.unwrap();
+
+            let loaded = SettingsPersister::load(&dir).await;
+            assert_eq!(loaded.to_settings(), persister.to_settings());
+            assert_eq!(loaded.get_account_token(), Some(ACCOUNT_TOKEN.to_owned()));
+            assert_eq!(loaded.get_wireguard(), Some(data));
+            assert!(loaded.allow_lan);
+            assert_eq!(loaded.get_encryption_state().key_source, None);
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = temp_dir();
+        run(async {
+            let (mut persister, data) = load_with_secrets(&dir).await;
+            persister
+                .set_wireguard_device(WireguardDevice {
+                    name: "laptop".to_owned(),
+                    data: wireguard_data(),
+                    rotation_interval: None,
+                })
+                .await
+                .unwrap();
+
+            let exported = persister.export(false).unwrap();
+            assert!(!exported.contains(ACCOUNT_TOKEN));
+            assert!(!exported.contains(&data.private_key.to_base64()));
+
+            let exported = persister.export(true).unwrap();
+            assert!(!exported.contains(&data.private_key.to_base64()));
+            let imported = SettingsPersister::parse_import(exported.as_bytes()).unwrap();
+            assert_eq!(imported.get_account_token(), Some(ACCOUNT_TOKEN.to_owned()));
+            assert_eq!(imported.get_wireguard(), None);
+            assert!(imported.get_wireguard_devices().is_empty());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_invalid() {
+        let mut settings = Settings::default();
+        settings
+            .tunnel_options
+            .wireguard
+            .rotation_policy
+            .max_relay_switches = Some(0);
+        let exported = serde_json::to_string(&settings).unwrap();
+        assert!(matches!(
+            SettingsPersister::parse_import(exported.as_bytes()),
+            Err(Error::ImportError(
+                mullvad_types::settings::Error::InvalidRotationPolicy(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_connectivity_check() {
+        let dir = temp_dir();
+        run(async {
+            let mut persister = SettingsPersister::load(&dir).await;
+            let options = ConnectivityCheckOptions {
+                probe_interval: std::time::Duration::ZERO,
+                ..ConnectivityCheckOptions::default()
+            };
+            assert!(matches!(
+                persister
+                    .set_wireguard_connectivity_check(options.clone())
+                    .await,
+                Err(Error::InvalidConnectivityCheck(
+                    InvalidConnectivityCheckOptions::ZeroTimeout
+                ))
+            ));
+
+            let mut settings = persister.to_settings();
+            settings.tunnel_options.wireguard.options.connectivity_check = options;
+            std::fs::write(
+                dir.join(SETTINGS_FILE),
+                serde_json::to_string(&settings).unwrap(),
+            )
+            .unwrap();
+            let loaded = SettingsPersister::load(&dir).await;
+            assert_eq!(
+                loaded.tunnel_options.wireguard.options.connectivity_check,
+                ConnectivityCheckOptions::default()
+            );
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_to_encrypted() {
+        let dir = temp_dir();
+        run(async {
+            let (mut persister, data) = load_with_secrets(&dir).await;
+            assert!(read_settings_file(&dir).contains(ACCOUNT_TOKEN));
+
+            assert!(persister
+                .set_encryption(Some(SecretsKeySource::KeyFile), None)
+                .await
+                .unwrap());
+
+            let loaded = SettingsPersister::load(&dir).await;
+            assert!(!loaded.secrets_locked());
+            assert_eq!(
+                loaded.get_encryption_state().key_source,
+                Some(SecretsKeySource::KeyFile)
+            );
+            assert_eq!(loaded.get_account_token(), Some(ACCOUNT_TOKEN.to_owned()));
+            assert_eq!(loaded.get_wireguard(), Some(data));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_plaintext_secrets() {
+        let dir = temp_dir();
+        run(async {
+            let (mut persister, data) = load_with_secrets(&dir).await;
+            persister
+                .set_encryption(
+                    Some(SecretsKeySource::Passphrase),
+                    Some("hunter2".to_owned()),
+                )
+                .await
+                .unwrap();
+            // Saving other settings must not write the secrets in plain text either
+            persister.set_allow_lan(true).await.unwrap();
+
+            let private_key = data.private_key.to_base64();
+            let contents = read_settings_file(&dir);
+            assert!(contents.contains("encrypted_secrets"));
+            assert!(!contents.contains(ACCOUNT_TOKEN));
+            assert!(!contents.contains(&private_key));
+
+            // The secrets stay locked until the right passphrase is given, and are written back
+            // unchanged in the meantime
+            let mut loaded = SettingsPersister::load(&dir).await;
+            assert!(loaded.secrets_locked());
+            assert_eq!(loaded.get_account_token(), None);
+            loaded.set_allow_lan(false).await.unwrap();
+            let contents = read_settings_file(&dir);
+            assert!(contents.contains("encrypted_secrets"));
+            assert!(!contents.contains(ACCOUNT_TOKEN));
+
+            assert!(loaded.unlock(Some("hunter3".to_owned())).await.is_err());
+            assert!(loaded.unlock(Some("hunter2".to_owned())).await.unwrap());
+            assert_eq!(loaded.get_account_token(), Some(ACCOUNT_TOKEN.to_owned()));
+            assert_eq!(loaded.get_wireguard(), Some(data));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
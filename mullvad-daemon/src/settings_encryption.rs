@@ -0,0 +1,358 @@
+//! Encryption at rest of the secrets in the settings file, i.e. the account token and the
+//! WireGuard private key. The remaining settings are still stored in plain text. The same cipher
+//! is used for the account history, which also contains account tokens and WireGuard keys.
+
+use mullvad_types::{
+    settings::SecretsKeySource,
+    wireguard::{WireguardData, WireguardDevice},
+};
+use ring::{
+    aead, hkdf, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    num::NonZeroU32,
+    path::Path,
+};
+
+/// Name of the file containing the random key material used by [`SecretsKeySource::KeyFile`].
+pub const KEY_FILE: &str = "settings.key";
+/// Description of the `user` key looked up by [`SecretsKeySource::KernelKeyring`].
+const KEYRING_KEY_DESCRIPTION: &str = "mullvad:settings";
+
+const KEY_FILE_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const KDF_INFO: &[u8] = b"mullvad-settings-secrets";
+const AAD: &[u8] = b"mullvad-settings-secrets-v1";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read the settings key file")]
+    ReadKeyFile(#[error(source)] io::Error),
+
+    #[error(display = "Unable to create the settings key file")]
+    CreateKeyFile(#[error(source)] io::Error),
+
+    #[error(display = "The settings key file is malformed")]
+    InvalidKeyFile,
+
+    #[error(display = "Unable to read the machine ID that the settings key file is bound to")]
+    ReadMachineId(#[error(source)] io::Error),
+
+    #[error(display = "Unable to read the settings key from the kernel keyring")]
+    ReadKeyring(#[error(source)] io::Error),
+
+    #[error(display = "The kernel keyring is not supported on this platform")]
+    KeyringNotSupported,
+
+    #[error(display = "A passphrase is required to decrypt the settings")]
+    PassphraseRequired,
+
+    #[error(display = "Unable to generate random data")]
+    Random,
+
+    #[error(display = "Unable to serialize the settings secrets")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to encrypt the settings secrets")]
+    Seal,
+
+    #[error(display = "Unable to decrypt the settings secrets, the key is likely wrong")]
+    Open,
+
+    #[error(display = "The encrypted settings secrets are malformed")]
+    Malformed,
+}
+
+/// The secrets that are removed from the settings before they are written to disk.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Secrets {
+    pub account_token: Option<String>,
+    pub wireguard: Option<WireguardData>,
+    #[serde(default)]
+    pub wireguard_devices: Vec<WireguardDevice>,
+}
+
+/// Encrypted [`Secrets`], as they are stored in the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedSecrets {
+    pub key_source: SecretsKeySource,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Encrypts and decrypts [`Secrets`], or any other serializable value, using a key from a
+/// [`SecretsKeySource`].
+#[derive(Debug)]
+pub struct SecretsCipher {
+    key_source: SecretsKeySource,
+    salt: Vec<u8>,
+    key: aead::LessSafeKey,
+}
+
+impl SecretsCipher {
+    /// Creates a cipher with a fresh salt. For [`SecretsKeySource::KeyFile`], the key file is
+    /// created if it does not exist.
+    pub fn new(
+        settings_dir: &Path,
+        key_source: SecretsKeySource,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        let mut salt = vec![0u8; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| Error::Random)?;
+        Self::with_salt(settings_dir, key_source, passphrase, salt, true)
+    }
+
+    /// Recreates the cipher that was used to produce `sealed`.
+    pub fn for_sealed(
+        settings_dir: &Path,
+        sealed: &SealedSecrets,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        let salt = base64::decode(&sealed.salt).map_err(|_| Error::Malformed)?;
+        Self::with_salt(settings_dir, sealed.key_source, passphrase, salt, false)
+    }
+
+    fn with_salt(
+        settings_dir: &Path,
+        key_source: SecretsKeySource,
+        passphrase: Option<&str>,
+        salt: Vec<u8>,
+        create_key_file: bool,
+    ) -> Result<Self> {
+        let key = match key_source {
+            SecretsKeySource::KeyFile => {
+                let mut key_material =
+                    read_key_file(&settings_dir.join(KEY_FILE), create_key_file)?;
+                // Bind the key to this machine, so that a copy of the settings directory can't be
+                // decrypted elsewhere.
+                key_material.extend(machine_id()?);
+                derive_key(&key_material, &salt)?
+            }
+            SecretsKeySource::KernelKeyring => derive_key(&read_keyring_key()?, &salt)?,
+            SecretsKeySource::Passphrase => {
+                let passphrase = passphrase.ok_or(Error::PassphraseRequired)?;
+                let mut key = [0u8; KEY_FILE_LEN];
+                pbkdf2::derive(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+                    &salt,
+                    passphrase.as_bytes(),
+                    &mut key,
+                );
+                let key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+                    .map_err(|_| Error::Seal)?;
+                aead::LessSafeKey::new(key)
+            }
+        };
+        Ok(SecretsCipher {
+            key_source,
+            salt,
+            key,
+        })
+    }
+
+    pub fn key_source(&self) -> SecretsKeySource {
+        self.key_source
+    }
+
+    pub fn seal<T: Serialize>(&self, secrets: &T) -> Result<SealedSecrets> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::Random)?;
+
+        let mut data = serde_json::to_vec(secrets).map_err(Error::Serialize)?;
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(AAD),
+                &mut data,
+            )
+            .map_err(|_| Error::Seal)?;
+
+        Ok(SealedSecrets {
+            key_source: self.key_source,
+            salt: base64::encode(&self.salt),
+            nonce: base64::encode(&nonce),
+            ciphertext: base64::encode(&data),
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, sealed: &SealedSecrets) -> Result<T> {
+        let nonce = base64::decode(&sealed.nonce).map_err(|_| Error::Malformed)?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| Error::Malformed)?;
+        let mut data = base64::decode(&sealed.ciphertext).map_err(|_| Error::Malformed)?;
+
+        let plaintext = self
+            .key
+            .open_in_place(nonce, aead::Aad::from(AAD), &mut data)
+            .map_err(|_| Error::Open)?;
+        serde_json::from_slice(plaintext).map_err(|_| Error::Malformed)
+    }
+}
+
+fn derive_key(key_material: &[u8], salt: &[u8]) -> Result<aead::LessSafeKey> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(key_material);
+    let okm = prk
+        .expand(&[KDF_INFO], &aead::CHACHA20_POLY1305)
+        .map_err(|_| Error::Seal)?;
+    Ok(aead::LessSafeKey::new(aead::UnboundKey::from(okm)))
+}
+
+fn read_key_file(path: &Path, create: bool) -> Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(key) if key.len() == KEY_FILE_LEN => Ok(key),
+        Ok(_) => Err(Error::InvalidKeyFile),
+        Err(error) if create && error.kind() == io::ErrorKind::NotFound => {
+            log::info!("Creating settings key file {}", path.display());
+            create_key_file(path)
+        }
+        Err(error) => Err(Error::ReadKeyFile(error)),
+    }
+}
+
+fn create_key_file(path: &Path) -> Result<Vec<u8>> {
+    let mut key = vec![0u8; KEY_FILE_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| Error::Random)?;
+
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(Error::CreateKeyFile)?;
+    file.write_all(&key).map_err(Error::CreateKeyFile)?;
+    file.sync_all().map_err(Error::CreateKeyFile)?;
+    Ok(key)
+}
+
+/// Returns the systemd or D-Bus machine ID. Deriving the key from the key file alone would
+/// silently drop the binding to the machine, so a missing or empty ID is an error.
+#[cfg(target_os = "linux")]
+fn machine_id() -> Result<Vec<u8>> {
+    let id = fs::read("/etc/machine-id")
+        .or_else(|_| fs::read("/var/lib/dbus/machine-id"))
+        .map_err(Error::ReadMachineId)?;
+    if id.iter().all(|byte| byte.is_ascii_whitespace()) {
+        return Err(Error::ReadMachineId(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The machine ID is empty",
+        )));
+    }
+    Ok(id)
+}
+
+/// The key file is only bound to the machine on Linux.
+#[cfg(not(target_os = "linux"))]
+fn machine_id() -> Result<Vec<u8>> {
+    Ok(Vec::new())
+}
+
+/// Reads the key material of the `user` key described by `KEYRING_KEY_DESCRIPTION` from the user
+/// keyring of the daemon. The key must be provisioned by the system, e.g.
+/// `keyctl padd user mullvad:settings @u < key`.
+#[cfg(target_os = "linux")]
+fn read_keyring_key() -> Result<Vec<u8>> {
+    use std::ffi::CString;
+
+    let key_type = CString::new("user").unwrap();
+    let description = CString::new(KEYRING_KEY_DESCRIPTION).unwrap();
+
+    let key_id = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_SEARCH,
+            libc::KEY_SPEC_USER_KEYRING,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            0,
+        )
+    };
+    if key_id < 0 {
+        return Err(Error::ReadKeyring(io::Error::last_os_error()));
+    }
+
+    let mut buffer = vec![0u8; 4096];
+    let len = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_READ,
+            key_id,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+        )
+    };
+    if len < 0 {
+        return Err(Error::ReadKeyring(io::Error::last_os_error()));
+    }
+    if len as usize > buffer.len() || len == 0 {
+        return Err(Error::Malformed);
+    }
+    buffer.truncate(len as usize);
+    Ok(buffer)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_keyring_key() -> Result<Vec<u8>> {
+    Err(Error::KeyringNotSupported)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn secrets() -> Secrets {
+        Secrets {
+            account_token: Some("1234567890123456".to_string()),
+            wireguard: None,
+            wireguard_devices: vec![],
+        }
+    }
+
+    #[test]
+    fn test_passphrase_round_trip() {
+        let dir = Path::new("/nonexistent");
+        let cipher =
+            SecretsCipher::new(dir, SecretsKeySource::Passphrase, Some("hunter2")).unwrap();
+        let sealed = cipher.seal(&secrets()).unwrap();
+        assert!(!sealed.ciphertext.contains("1234567890123456"));
+
+        let cipher = SecretsCipher::for_sealed(dir, &sealed, Some("hunter2")).unwrap();
+        let opened: Secrets = cipher.open(&sealed).unwrap();
+        assert_eq!(opened.account_token, secrets().account_token);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let dir = Path::new("/nonexistent");
+        let cipher =
+            SecretsCipher::new(dir, SecretsKeySource::Passphrase, Some("hunter2")).unwrap();
+        let sealed = cipher.seal(&secrets()).unwrap();
+
+        let cipher = SecretsCipher::for_sealed(dir, &sealed, Some("hunter3")).unwrap();
+        assert!(matches!(cipher.open::<Secrets>(&sealed), Err(Error::Open)));
+        assert!(matches!(
+            SecretsCipher::for_sealed(dir, &sealed, None),
+            Err(Error::PassphraseRequired)
+        ));
+    }
+}
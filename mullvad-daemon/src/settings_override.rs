@@ -0,0 +1,186 @@
+//! Read-only file, maintained by an administrator, that pins settings to fixed values. The file
+//! contains a subset of the settings JSON, e.g. `{ "allow_lan": false }`. Pinned settings are
+//! applied on top of the user settings and can't be changed through the management interface.
+
+use mullvad_types::settings::Settings;
+use serde_json::Value;
+use std::path::Path;
+use tokio::{fs, io};
+
+/// Name of the override file in the settings directory.
+pub const OVERRIDE_FILE: &str = "settings-override.json";
+
+/// Settings that can't be pinned, since they are either secret or managed by the daemon.
+const FORBIDDEN_FIELDS: &[&str] = &["account_token", "wireguard", "settings_version"];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read the settings override file")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Unable to parse the settings override file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "The settings override file must contain a JSON object")]
+    NotAnObject,
+
+    #[error(display = "The setting \"{}\" can't be overridden", _0)]
+    ForbiddenField(String),
+
+    #[error(display = "The settings override file does not match the settings format")]
+    Apply(#[error(source)] serde_json::Error),
+}
+
+#[derive(Debug)]
+pub struct SettingsOverride {
+    values: Value,
+    /// JSON pointers to every pinned value in `values`.
+    pinned: Vec<String>,
+}
+
+impl SettingsOverride {
+    /// Loads the override file from the settings directory, if there is one.
+    pub async fn load(settings_dir: &Path) -> Result<Option<Self>> {
+        let path = settings_dir.join(OVERRIDE_FILE);
+        match fs::read(&path).await {
+            Ok(data) => {
+                log::info!("Loading settings override from {}", path.display());
+                Self::parse(&data).map(Some)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::Read(error)),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let values: Value = serde_json::from_slice(data).map_err(Error::Parse)?;
+        let fields = values.as_object().ok_or(Error::NotAnObject)?;
+        if let Some(field) = FORBIDDEN_FIELDS
+            .iter()
+            .find(|field| fields.contains_key(**field))
+        {
+            return Err(Error::ForbiddenField(field.to_string()));
+        }
+
+        let mut pinned = vec![];
+        collect_pinned(&values, String::new(), &mut pinned);
+        let settings_override = SettingsOverride { values, pinned };
+
+        // Make sure that the pinned values can be applied at all.
+        settings_override.apply(&mut Settings::default())?;
+        Ok(settings_override)
+    }
+
+    /// Sets all pinned values in `settings`. Returns whether any value changed.
+    pub fn apply(&self, settings: &mut Settings) -> Result<bool> {
+        let mut value = serde_json::to_value(&*settings).map_err(Error::Apply)?;
+        merge(&mut value, &self.values);
+        let new_settings: Settings = serde_json::from_value(value).map_err(Error::Apply)?;
+        if new_settings != *settings {
+            *settings = new_settings;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns the name of the first pinned setting whose value differs in `settings`.
+    pub fn find_violation(&self, settings: &Settings) -> Option<String> {
+        let value = match serde_json::to_value(settings) {
+            Ok(value) => value,
+            Err(_) => return self.pinned.first().map(|pointer| pointer_to_name(pointer)),
+        };
+        self.pinned
+            .iter()
+            .find(|pointer| value.pointer(pointer) != self.values.pointer(pointer))
+            .map(|pointer| pointer_to_name(pointer))
+    }
+
+    /// Returns the names of all pinned settings, e.g. `tunnel_options.generic.enable_ipv6`.
+    pub fn pinned_settings(&self) -> Vec<String> {
+        self.pinned
+            .iter()
+            .map(|pointer| pointer_to_name(pointer))
+            .collect()
+    }
+}
+
+fn collect_pinned(value: &Value, pointer: String, pinned: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                collect_pinned(value, format!("{}/{}", pointer, key), pinned);
+            }
+        }
+        _ => pinned.push(pointer),
+    }
+}
+
+fn merge(target: &mut Value, overrides: &Value) {
+    match (target, overrides) {
+        (Value::Object(target), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, value) => *target = value.clone(),
+    }
+}
+
+fn pointer_to_name(pointer: &str) -> String {
+    pointer.trim_start_matches('/').replace('/', ".")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_violation() {
+        let settings_override = SettingsOverride::parse(
+            br#"{ "allow_lan": true, "tunnel_options": { "generic": { "enable_ipv6": true } } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings_override.pinned_settings(),
+            vec!["allow_lan", "tunnel_options.generic.enable_ipv6"]
+        );
+
+        let mut settings = Settings::default();
+        settings.tunnel_options.generic.enable_ipv6 = false;
+        assert!(settings_override.apply(&mut settings).unwrap());
+        assert!(settings.allow_lan);
+        assert!(settings.tunnel_options.generic.enable_ipv6);
+        assert!(!settings_override.apply(&mut settings).unwrap());
+        assert_eq!(settings_override.find_violation(&settings), None);
+
+        settings.auto_connect = !settings.auto_connect;
+        assert_eq!(settings_override.find_violation(&settings), None);
+
+        settings.tunnel_options.generic.enable_ipv6 = false;
+        assert_eq!(
+            settings_override.find_violation(&settings),
+            Some("tunnel_options.generic.enable_ipv6".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_override() {
+        assert!(matches!(
+            SettingsOverride::parse(br#"{ "account_token": "1234" }"#),
+            Err(Error::ForbiddenField(_))
+        ));
+        assert!(matches!(
+            SettingsOverride::parse(br#"[]"#),
+            Err(Error::NotAnObject)
+        ));
+        assert!(matches!(
+            SettingsOverride::parse(br#"{ "allow_lan": "yes" }"#),
+            Err(Error::Apply(_))
+        ));
+    }
+}
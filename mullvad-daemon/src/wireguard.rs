@@ -1,14 +1,31 @@
-use crate::{DaemonEventSender, InternalDaemonEvent};
-use chrono::offset::Utc;
+use crate::{
+    metrics::{KeyRotationTrigger, Metrics},
+    DaemonEventSender, InternalDaemonEvent,
+};
+use chrono::{
+    offset::{Local, Utc},
+    DateTime,
+};
 use mullvad_rpc::rest::{Error as RestError, MullvadRestHandle};
 use mullvad_types::account::AccountToken;
 pub use mullvad_types::wireguard::*;
-use std::{future::Future, pin::Pin, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
-use futures::future::{abortable, AbortHandle};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{abortable, AbortHandle},
+};
 use talpid_core::{
     future_retry::{retry_future_with_backoff, ExponentialBackoff, Jittered},
     mpsc::Sender,
+    tunnel_state_machine::TunnelCommand,
 };
 
 pub use talpid_types::net::wireguard::{
@@ -27,6 +44,25 @@ const RETRY_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_INTERVAL_FACTOR: u32 = 5;
 const RETRY_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
+/// How long the old key is kept on a connected tunnel after it has been replaced. The old key
+/// stays valid on the relays until the new key has propagated to them.
+const KEY_SWAP_GRACE_PERIOD: Duration = Duration::from_secs(4 * 60);
+
+/// How long to wait for the first handshake with a new key before restoring the old key.
+const KEY_SWAP_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often to check whether a handshake with a new key has completed.
+const KEY_SWAP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before retrying to swap the key after the old key has been restored.
+const KEY_SWAP_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of times to try swapping the key on a connected tunnel before reconnecting instead.
+const KEY_SWAP_ATTEMPTS: u32 = 3;
+
+/// How often the amount of data transferred using the key is saved to the settings.
+const KEY_USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "Unexpected HTTP request error")]
@@ -37,23 +73,130 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Returns whether `key` is at least `rotation_interval` old at `now`.
+pub fn key_expired(key: &PublicKey, rotation_interval: Duration, now: DateTime<Utc>) -> bool {
+    now.signed_duration_since(key.created)
+        .to_std()
+        .map(|age| age >= rotation_interval)
+        .unwrap_or(false)
+}
+
+/// Returns the tunnel config that uses the given key.
+pub fn tunnel_config(data: &WireguardData) -> TunnelConfig {
+    TunnelConfig {
+        private_key: data.private_key.clone(),
+        addresses: vec![
+            data.addresses.ipv4_address.ip().into(),
+            data.addresses.ipv6_address.ip().into(),
+        ],
+    }
+}
+
+/// Most recently reported usage of a key, along with the key it belongs to.
+type SharedKeyUsage = Arc<Mutex<Option<(talpid_types::net::wireguard::PublicKey, KeyUsage)>>>;
+
+/// Keeps track of how much the current key is used, between saving the counters to the settings.
+#[derive(Default)]
+pub struct KeyUsageTracker {
+    key: Option<talpid_types::net::wireguard::PublicKey>,
+    pending: KeyUsage,
+    last_total_bytes: u64,
+    last_relay: Option<IpAddr>,
+    last_saved: Option<Instant>,
+}
+
+impl KeyUsageTracker {
+    /// Records the total traffic counters of the tunnel state machine. The traffic since the
+    /// previous sample is attributed to the key if `uses_key` is set.
+    pub fn record_traffic(&mut self, total_bytes: u64, uses_key: bool) {
+        let transferred = total_bytes.saturating_sub(self.last_total_bytes);
+        self.last_total_bytes = total_bytes;
+        if uses_key {
+            self.pending.bytes_transferred =
+                self.pending.bytes_transferred.saturating_add(transferred);
+        }
+    }
+
+    /// Records that a tunnel using the key connected to `relay`. Returns whether this counted as
+    /// a relay switch.
+    pub fn record_connection(&mut self, relay: IpAddr) -> bool {
+        let switched = self.last_relay.map(|last| last != relay).unwrap_or(false);
+        if switched {
+            self.pending.relay_switches = self.pending.relay_switches.saturating_add(1);
+        }
+        self.last_relay = Some(relay);
+        switched
+    }
+
+    /// Returns the usage of `data` including the counters that have not been saved yet. Counters
+    /// recorded for a previous key are discarded.
+    pub fn usage(&mut self, data: &WireguardData) -> KeyUsage {
+        let key = data.private_key.public_key();
+        if self.key.as_ref() != Some(&key) {
+            if self.key.is_some() {
+                self.pending = KeyUsage::default();
+            }
+            self.key = Some(key);
+        }
+        KeyUsage {
+            bytes_transferred: data
+                .usage
+                .bytes_transferred
+                .saturating_add(self.pending.bytes_transferred),
+            relay_switches: data
+                .usage
+                .relay_switches
+                .saturating_add(self.pending.relay_switches),
+        }
+    }
+
+    /// Returns whether there are unsaved counters that should be saved now. Unless `force` is
+    /// set, the counters are saved at most once per save interval.
+    pub fn should_save(&self, force: bool) -> bool {
+        self.pending != KeyUsage::default()
+            && (force
+                || self
+                    .last_saved
+                    .map(|last_saved| last_saved.elapsed() >= KEY_USAGE_SAVE_INTERVAL)
+                    .unwrap_or(true))
+    }
+
+    /// Marks the pending counters as saved.
+    pub fn saved(&mut self) {
+        self.pending = KeyUsage::default();
+        self.last_saved = Some(Instant::now());
+    }
+}
+
 pub struct KeyManager {
     daemon_tx: DaemonEventSender,
     http_handle: MullvadRestHandle,
+    metrics: Metrics,
     current_job: Option<AbortHandle>,
 
     abort_scheduler_tx: Option<AbortHandle>,
     auto_rotation_interval: RotationInterval,
+    rotation_policy: RotationPolicy,
+    key_usage: SharedKeyUsage,
+    device_rotations: HashMap<String, AbortHandle>,
 }
 
 impl KeyManager {
-    pub(crate) fn new(daemon_tx: DaemonEventSender, http_handle: MullvadRestHandle) -> Self {
+    pub(crate) fn new(
+        daemon_tx: DaemonEventSender,
+        http_handle: MullvadRestHandle,
+        metrics: Metrics,
+    ) -> Self {
         Self {
             daemon_tx,
             http_handle,
+            metrics,
             current_job: None,
             abort_scheduler_tx: None,
             auto_rotation_interval: RotationInterval::default(),
+            rotation_policy: RotationPolicy::default(),
+            key_usage: Arc::new(Mutex::new(None)),
+            device_rotations: HashMap::new(),
         }
     }
 
@@ -76,6 +219,17 @@ impl KeyManager {
         self.reset_rotation(current_key, account_token).await;
     }
 
+    /// Update the conditions besides the interval that trigger automatic key rotation. Takes
+    /// effect the next time automatic rotation is reset.
+    pub fn set_rotation_policy(&mut self, rotation_policy: RotationPolicy) {
+        self.rotation_policy = rotation_policy;
+    }
+
+    /// Report how much `key` has been used, for the rotation policy to act on.
+    pub fn update_key_usage(&self, key: talpid_types::net::wireguard::PublicKey, usage: KeyUsage) {
+        *self.key_usage.lock().unwrap() = Some((key, usage));
+    }
+
     /// Stop current key generation
     pub fn reset(&mut self) {
         if let Some(job) = self.current_job.take() {
@@ -106,6 +260,72 @@ impl KeyManager {
         Self::replace_key_rpc(self.http_handle.clone(), account, old_key, new_key).await
     }
 
+    /// Generate a key for an additional device. This does not affect the key of the tunnel.
+    pub async fn generate_device_key(&self, account: AccountToken) -> Result<WireguardData> {
+        let private_key = PrivateKey::new_from_random();
+
+        self.push_future_generator(account, private_key, None)()
+            .await
+            .map_err(Self::map_rpc_error)
+    }
+
+    /// Replace the key of an additional device. This does not affect the key of the tunnel.
+    pub async fn replace_device_key(
+        &self,
+        account: AccountToken,
+        old_key: PublicKey,
+    ) -> Result<WireguardData> {
+        let new_key = PrivateKey::new_from_random();
+        Self::replace_key_rpc(self.http_handle.clone(), account, old_key, new_key).await
+    }
+
+    /// Starts automatic rotation of the key of an additional device, replacing any previous
+    /// rotation of the device. New keys are sent to the daemon as device key events. Passing
+    /// `None` for the interval will cause the default value to be used.
+    pub fn set_device_rotation(
+        &mut self,
+        name: String,
+        current_key: PublicKey,
+        account_token: AccountToken,
+        rotation_interval: Option<RotationInterval>,
+    ) {
+        self.stop_device_rotation(&name);
+
+        log::debug!("Starting automatic key rotation job for device {}", name);
+        let fut = Self::create_automatic_rotation(
+            self.daemon_tx.clone(),
+            self.http_handle.clone(),
+            self.metrics.clone(),
+            current_key,
+            rotation_interval
+                .unwrap_or_default()
+                .as_duration()
+                .as_secs(),
+            RotationPolicy::default(),
+            Arc::new(Mutex::new(None)),
+            account_token,
+            Some(name.clone()),
+        );
+        let (request, abort_handle) = abortable(Box::pin(fut));
+
+        tokio::spawn(request);
+        self.device_rotations.insert(name, abort_handle);
+    }
+
+    /// Stops automatic rotation of the key of an additional device.
+    pub fn stop_device_rotation(&mut self, name: &str) {
+        if let Some(abort_handle) = self.device_rotations.remove(name) {
+            abort_handle.abort();
+        }
+    }
+
+    /// Stops automatic rotation of the keys of all additional devices.
+    pub fn stop_device_rotations(&mut self) {
+        for (_, abort_handle) in self.device_rotations.drain() {
+            abort_handle.abort();
+        }
+    }
+
     /// Verifies whether a key is valid or not.
     pub fn verify_wireguard_key(
         &self,
@@ -247,6 +467,7 @@ impl KeyManager {
                         private_key: key,
                         addresses,
                         created: Utc::now(),
+                        usage: KeyUsage::default(),
                     })
                 })
             };
@@ -269,6 +490,7 @@ impl KeyManager {
             private_key: new_key,
             addresses,
             created: Utc::now(),
+            usage: KeyUsage::default(),
         })
     }
 
@@ -285,13 +507,28 @@ impl KeyManager {
         }
     }
 
-    async fn wait_for_key_expiry(key: &PublicKey, rotation_interval_secs: u64) {
+    async fn wait_for_key_expiry(
+        key: &PublicKey,
+        rotation_interval_secs: u64,
+        rotation_policy: &RotationPolicy,
+        key_usage: &SharedKeyUsage,
+    ) {
         let mut interval = tokio::time::interval(KEY_CHECK_INTERVAL);
         loop {
             interval.tick().await;
-            if (Utc::now().signed_duration_since(key.created)).num_seconds() as u64
-                >= rotation_interval_secs
-            {
+            if key_expired(key, Duration::from_secs(rotation_interval_secs), Utc::now()) {
+                return;
+            }
+
+            let usage = key_usage
+                .lock()
+                .unwrap()
+                .as_ref()
+                .filter(|(usage_key, _)| *usage_key == key.key)
+                .map(|(_, usage)| usage.clone())
+                .unwrap_or_default();
+            if rotation_policy.is_due(key.created, &usage, Local::now()) {
+                log::info!("Rotating WireGuard key according to the rotation policy");
                 return;
             }
         }
@@ -300,28 +537,44 @@ impl KeyManager {
     async fn create_automatic_rotation(
         daemon_tx: DaemonEventSender,
         http_handle: MullvadRestHandle,
+        metrics: Metrics,
         mut public_key: PublicKey,
         rotation_interval_secs: u64,
+        rotation_policy: RotationPolicy,
+        key_usage: SharedKeyUsage,
         account_token: AccountToken,
+        device: Option<String>,
     ) {
         tokio::time::sleep(ROTATION_START_DELAY).await;
 
+        let is_device = device.is_some();
         let rotate_key_for_account = move |old_key: &PublicKey| {
             Self::rotate_key(
                 daemon_tx.clone(),
                 http_handle.clone(),
                 account_token.clone(),
+                device.clone(),
                 old_key.clone(),
             )
         };
 
         loop {
-            Self::wait_for_key_expiry(&public_key, rotation_interval_secs).await;
+            Self::wait_for_key_expiry(
+                &public_key,
+                rotation_interval_secs,
+                &rotation_policy,
+                &key_usage,
+            )
+            .await;
 
             let rotate_key_for_account_copy = rotate_key_for_account.clone();
-            match Self::rotate_key_with_retries(public_key.clone(), rotate_key_for_account_copy)
-                .await
-            {
+            let result =
+                Self::rotate_key_with_retries(public_key.clone(), rotate_key_for_account_copy)
+                    .await;
+            if !is_device {
+                metrics.key_rotated(KeyRotationTrigger::Automatic, result.is_ok());
+            }
+            match result {
                 Ok(new_key) => public_key = new_key,
                 Err(error) => {
                     log::error!(
@@ -340,27 +593,27 @@ impl KeyManager {
         daemon_tx: DaemonEventSender,
         http_handle: MullvadRestHandle,
         account_token: AccountToken,
+        device: Option<String>,
         old_key: PublicKey,
     ) -> std::pin::Pin<Box<dyn Future<Output = Result<PublicKey>> + Send>> {
         let new_key = PrivateKey::new_from_random();
         let rpc_result =
             Self::replace_key_rpc(http_handle, account_token.clone(), old_key, new_key);
 
+        let key_event = move |result| match device {
+            Some(device) => InternalDaemonEvent::WgDeviceKeyEvent((account_token, device, result)),
+            None => InternalDaemonEvent::WgKeyEvent((account_token, result)),
+        };
+
         Box::pin(async move {
             match rpc_result.await {
                 Ok(data) => {
                     // Update account data
-                    let _ = daemon_tx.send(InternalDaemonEvent::WgKeyEvent((
-                        account_token,
-                        Ok(data.clone()),
-                    )));
+                    let _ = daemon_tx.send(key_event(Ok(data.clone())));
                     Ok(data.get_public_key())
                 }
                 Err(Error::TooManyKeys) => {
-                    let _ = daemon_tx.send(InternalDaemonEvent::WgKeyEvent((
-                        account_token,
-                        Err(Error::TooManyKeys),
-                    )));
+                    let _ = daemon_tx.send(key_event(Err(Error::TooManyKeys)));
                     Err(Error::TooManyKeys)
                 }
                 Err(unknown) => Err(unknown),
@@ -404,9 +657,13 @@ impl KeyManager {
         let fut = Self::create_automatic_rotation(
             self.daemon_tx.clone(),
             self.http_handle.clone(),
+            self.metrics.clone(),
             public_key,
             self.auto_rotation_interval.as_duration().as_secs(),
+            self.rotation_policy.clone(),
+            self.key_usage.clone(),
             account_token,
+            None,
         );
         let (request, abort_handle) = abortable(Box::pin(fut));
 
@@ -414,6 +671,89 @@ impl KeyManager {
         self.abort_scheduler_tx = Some(abort_handle);
     }
 
+    /// Replaces the key of the connected tunnel with `new_key` once the grace period has passed,
+    /// without reconnecting. If no handshake completes with the new key, the old key is restored
+    /// and the swap is retried later. Returns `false` if the key could not be swapped, in which
+    /// case the tunnel has to be reconnected to use the new key.
+    pub async fn swap_tunnel_key(
+        tunnel_command_tx: Arc<mpsc::UnboundedSender<TunnelCommand>>,
+        old_key: TunnelConfig,
+        new_key: TunnelConfig,
+    ) -> bool {
+        tokio::time::sleep(KEY_SWAP_GRACE_PERIOD).await;
+
+        for attempt in 1..=KEY_SWAP_ATTEMPTS {
+            let swap_time = SystemTime::now();
+            if !Self::replace_tunnel_key(&tunnel_command_tx, new_key.clone()).await {
+                return false;
+            }
+            if Self::wait_for_handshake(&tunnel_command_tx, swap_time).await {
+                log::info!("Completed a handshake using the new WireGuard key");
+                return true;
+            }
+
+            log::warn!(
+                "No handshake completed using the new WireGuard key, restoring the old key \
+                 (attempt {}/{})",
+                attempt,
+                KEY_SWAP_ATTEMPTS
+            );
+            if !Self::replace_tunnel_key(&tunnel_command_tx, old_key.clone()).await {
+                return false;
+            }
+            if attempt < KEY_SWAP_ATTEMPTS {
+                tokio::time::sleep(KEY_SWAP_RETRY_DELAY).await;
+            }
+        }
+        false
+    }
+
+    async fn replace_tunnel_key(
+        tunnel_command_tx: &mpsc::UnboundedSender<TunnelCommand>,
+        key: TunnelConfig,
+    ) -> bool {
+        let (result_tx, result_rx) = oneshot::channel();
+        if tunnel_command_tx
+            .unbounded_send(TunnelCommand::ReplaceKey(key, result_tx))
+            .is_err()
+        {
+            return false;
+        }
+        result_rx.await.unwrap_or(false)
+    }
+
+    /// Returns whether a handshake completes after `since`, within the handshake timeout.
+    async fn wait_for_handshake(
+        tunnel_command_tx: &mpsc::UnboundedSender<TunnelCommand>,
+        since: SystemTime,
+    ) -> bool {
+        let deadline = tokio::time::Instant::now() + KEY_SWAP_HANDSHAKE_TIMEOUT;
+        let mut interval = tokio::time::interval(KEY_SWAP_POLL_INTERVAL);
+        while tokio::time::Instant::now() < deadline {
+            interval.tick().await;
+            let (stats_tx, stats_rx) = oneshot::channel();
+            if tunnel_command_tx
+                .unbounded_send(TunnelCommand::GetTunnelStats(stats_tx))
+                .is_err()
+            {
+                return false;
+            }
+            match stats_rx.await {
+                Ok(stats) => {
+                    if stats
+                        .last_handshake
+                        .map(|handshake| handshake >= since)
+                        .unwrap_or(false)
+                    {
+                        return true;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+
     fn stop_automatic_rotation(&mut self) {
         if let Some(abort_handle) = self.abort_scheduler_tx.take() {
             log::info!("Stopping automatic key rotation");
@@ -421,3 +761,190 @@ impl KeyManager {
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    /// Stands in for the tunnel state machine of a connected WireGuard tunnel. Records every key
+    /// that is applied, and completes a handshake right away if `accepts_key` returns `true` for
+    /// the new key, given the number of times it has been applied before. Replacing the key fails
+    /// if `can_replace` is `false`.
+    fn spawn_mock_tunnel(
+        can_replace: bool,
+        accepts_key: impl Fn(&PublicKey, usize) -> bool + Send + 'static,
+    ) -> (
+        Arc<mpsc::UnboundedSender<TunnelCommand>>,
+        Arc<Mutex<Vec<PublicKey>>>,
+    ) {
+        let (command_tx, mut command_rx) = mpsc::unbounded();
+        let applied_keys = Arc::new(Mutex::new(vec![]));
+        let applied_keys_inner = applied_keys.clone();
+
+        tokio::spawn(async move {
+            let mut last_handshake = None;
+            while let Some(command) = command_rx.next().await {
+                match command {
+                    TunnelCommand::ReplaceKey(config, result_tx) => {
+                        if can_replace {
+                            let key = config.private_key.public_key();
+                            let mut applied_keys = applied_keys_inner.lock().unwrap();
+                            let times_applied = applied_keys
+                                .iter()
+                                .filter(|applied| **applied == key)
+                                .count();
+                            last_handshake = if accepts_key(&key, times_applied) {
+                                Some(SystemTime::now())
+                            } else {
+                                None
+                            };
+                            applied_keys.push(key);
+                        }
+                        let _ = result_tx.send(can_replace);
+                    }
+                    TunnelCommand::GetTunnelStats(stats_tx) => {
+                        let _ = stats_tx.send(talpid_core::tunnel_state_machine::TunnelStats {
+                            last_handshake,
+                            ..Default::default()
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        });
+
+        (Arc::new(command_tx), applied_keys)
+    }
+
+    fn tunnel_config() -> TunnelConfig {
+        TunnelConfig {
+            private_key: PrivateKey::new_from_random(),
+            addresses: vec!["10.64.0.2".parse().unwrap()],
+        }
+    }
+
+    /// Runs `future` with the clock paused, so that timeouts elapse as soon as nothing else can
+    /// make progress.
+    fn run_with_paused_time<F: Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::time::pause();
+            future.await
+        })
+    }
+
+    #[test]
+    fn test_key_swap() {
+        let old_key = tunnel_config();
+        let new_key = tunnel_config();
+        let new_public_key = new_key.private_key.public_key();
+
+        let (swapped, applied_keys) = run_with_paused_time(async move {
+            let accepted_key = new_public_key.clone();
+            let (command_tx, applied_keys) =
+                spawn_mock_tunnel(true, move |key, _| *key == accepted_key);
+            let swapped = KeyManager::swap_tunnel_key(command_tx, old_key, new_key).await;
+            (swapped, applied_keys)
+        });
+
+        assert!(swapped);
+        assert_eq!(*applied_keys.lock().unwrap(), vec![new_public_key.clone()]);
+    }
+
+    #[test]
+    fn test_key_swap_rollback() {
+        let old_key = tunnel_config();
+        let new_key = tunnel_config();
+        let old_public_key = old_key.private_key.public_key();
+        let new_public_key = new_key.private_key.public_key();
+
+        let (swapped, applied_keys) = run_with_paused_time(async move {
+            // The new key has not reached the relay, so it never handshakes
+            let accepted_key = old_public_key.clone();
+            let (command_tx, applied_keys) =
+                spawn_mock_tunnel(true, move |key, _| *key == accepted_key);
+            let swapped = KeyManager::swap_tunnel_key(command_tx, old_key, new_key).await;
+            (swapped, applied_keys)
+        });
+
+        assert!(!swapped);
+        let expected_keys: Vec<_> = (0..KEY_SWAP_ATTEMPTS)
+            .flat_map(|_| vec![new_public_key.clone(), old_public_key.clone()])
+            .collect();
+        assert_eq!(*applied_keys.lock().unwrap(), expected_keys);
+    }
+
+    #[test]
+    fn test_key_swap_retry() {
+        let old_key = tunnel_config();
+        let new_key = tunnel_config();
+        let old_public_key = old_key.private_key.public_key();
+        let new_public_key = new_key.private_key.public_key();
+
+        let (swapped, applied_keys) = run_with_paused_time(async move {
+            // The new key only handshakes on the second attempt
+            let accepted_key = new_public_key.clone();
+            let (command_tx, applied_keys) = spawn_mock_tunnel(true, move |key, times_applied| {
+                *key != accepted_key || times_applied > 0
+            });
+            let swapped = KeyManager::swap_tunnel_key(command_tx, old_key, new_key).await;
+            (swapped, applied_keys)
+        });
+
+        assert!(swapped);
+        assert_eq!(
+            *applied_keys.lock().unwrap(),
+            vec![
+                new_public_key.clone(),
+                old_public_key.clone(),
+                new_public_key.clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_swap_requires_reconnect() {
+        let old_key = tunnel_config();
+        let new_key = tunnel_config();
+
+        let (swapped, applied_keys) = run_with_paused_time(async move {
+            // E.g. the tunnel is no longer connected, or the addresses of the key differ
+            let (command_tx, applied_keys) = spawn_mock_tunnel(false, |_, _| true);
+            let swapped = KeyManager::swap_tunnel_key(command_tx, old_key, new_key).await;
+            (swapped, applied_keys)
+        });
+
+        assert!(!swapped);
+        assert!(applied_keys.lock().unwrap().is_empty());
+
+        let old_key = tunnel_config();
+        let new_key = tunnel_config();
+        let swapped = run_with_paused_time(async move {
+            // The tunnel state machine has shut down
+            let (command_tx, _) = mpsc::unbounded();
+            KeyManager::swap_tunnel_key(Arc::new(command_tx), old_key, new_key).await
+        });
+        assert!(!swapped);
+    }
+
+    #[test]
+    fn test_key_expired() {
+        let now = Utc::now();
+        let key = mullvad_types::wireguard::PublicKey {
+            key: PrivateKey::new_from_random().public_key(),
+            created: now - chrono::Duration::hours(2),
+        };
+        let hour = Duration::from_secs(60 * 60);
+        assert!(key_expired(&key, hour, now));
+        assert!(key_expired(&key, 2 * hour, now));
+        assert!(!key_expired(&key, 3 * hour, now));
+
+        // Keys created in the future, e.g. due to clock changes, have not expired
+        assert!(!key_expired(&key, hour, now - chrono::Duration::hours(3)));
+    }
+}
@@ -11,6 +11,7 @@ use mullvad_types::{
     wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
+use talpid_core::tunnel_state_machine::TunnelStats;
 use talpid_types::ErrorExt;
 
 #[derive(Debug, err_derive::Error)]
@@ -63,6 +64,10 @@ impl EventListener for JniEventListener {
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
         let _ = self.0.send(Event::AppVersionInfo(app_version_info));
     }
+
+    fn notify_tunnel_stats(&self, _tunnel_stats: TunnelStats) {
+        // Tunnel statistics are not displayed by the Android app
+    }
 }
 
 struct JniEventHandler<'env> {
@@ -1,16 +1,21 @@
 pub mod types;
 
+use futures::future;
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
 #[cfg(unix)]
-use std::{env, fs, os::unix::fs::PermissionsExt};
+use std::{env, fs, os::unix::fs::PermissionsExt, path::Path};
 use std::{
     io,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tonic::transport::{server::Connected, Endpoint, Server, Uri};
-use tower::service_fn;
+use tonic::{
+    body::BoxBody,
+    transport::{server::Connected, Body, Endpoint, NamedService, Server, Uri},
+};
+use tower::{service_fn, Service};
 
 pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
 
@@ -66,24 +71,45 @@ pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
     Ok(ManagementServiceClient::new(channel))
 }
 
-pub async fn spawn_rpc_server<T: ManagementService>(
+/// Credentials of the process on the other end of a management interface connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Returns the credentials of the client that sent `request`. This is only known for connections
+/// over a Unix socket.
+pub fn peer_credentials<T>(request: &Request<T>) -> Option<PeerCredentials> {
+    peer_credentials_from_extensions(request.extensions())
+}
+
+fn peer_credentials_from_extensions(extensions: &http::Extensions) -> Option<PeerCredentials> {
+    extensions
+        .get::<Option<PeerCredentials>>()
+        .copied()
+        .flatten()
+}
+
+/// Decides which management interface methods a client may call.
+pub trait Authorizer: Send + Sync + 'static {
+    /// Returns the status to fail with if the client may not call `method`, which is the name of
+    /// an RPC in `management_interface.proto`, e.g. `GetTunnelState`.
+    fn authorize(&self, method: &str, peer: Option<PeerCredentials>) -> Result<(), Status>;
+}
+
+pub async fn spawn_rpc_server<T: ManagementService, A: Authorizer>(
     service: T,
+    authorizer: Arc<A>,
     server_start_tx: std::sync::mpsc::Sender<()>,
     abort_rx: triggered::Listener,
 ) -> std::result::Result<(), Error> {
-    use futures::stream::TryStreamExt;
-    use parity_tokio_ipc::SecurityAttributes;
-
     let socket_path = mullvad_paths::get_rpc_socket_path();
 
-    let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
-    endpoint.set_security_attributes(
-        SecurityAttributes::allow_everyone_create()
-            .map_err(Error::SecurityAttributes)?
-            .set_mode(0o766)
-            .map_err(Error::SecurityAttributes)?,
-    );
-    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    #[cfg(unix)]
+    let incoming = unix_incoming(&socket_path)?;
+    #[cfg(windows)]
+    let incoming = pipe_incoming(&socket_path)?;
 
     #[cfg(unix)]
     if let Some(group_name) = &*MULLVAD_MANAGEMENT_SOCKET_GROUP {
@@ -98,19 +124,127 @@ pub async fn spawn_rpc_server<T: ManagementService>(
     let _ = server_start_tx.send(());
 
     Server::builder()
-        .add_service(ManagementServiceServer::new(service))
-        .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
+        .add_service(AuthorizedService {
+            inner: ManagementServiceServer::new(service),
+            authorizer,
+        })
+        .serve_with_incoming_shutdown(incoming, abort_rx)
         .await
         .map_err(Error::GrpcTransportError)
 }
 
+/// Listens on a Unix socket directly, rather than through `parity_tokio_ipc`, since the peer
+/// credentials of each connection are needed.
+#[cfg(unix)]
+fn unix_incoming(
+    socket_path: &Path,
+) -> Result<
+    Pin<Box<dyn futures::Stream<Item = io::Result<StreamBox<tokio::net::UnixStream>>> + Send>>,
+    Error,
+> {
+    let listener = tokio::net::UnixListener::bind(socket_path).map_err(Error::StartServerError)?;
+    fs::set_permissions(socket_path, PermissionsExt::from_mode(0o766))
+        .map_err(Error::PermissionsError)?;
+
+    Ok(Box::pin(futures::stream::unfold(
+        listener,
+        |listener| async move {
+            let connection = listener.accept().await.map(|(stream, _)| {
+                let peer_credentials = match stream.peer_cred() {
+                    Ok(credentials) => Some(PeerCredentials {
+                        uid: credentials.uid(),
+                        gid: credentials.gid(),
+                    }),
+                    Err(error) => {
+                        log::error!("Failed to obtain peer credentials: {}", error);
+                        None
+                    }
+                };
+                StreamBox {
+                    stream,
+                    peer_credentials,
+                }
+            });
+            Some((connection, listener))
+        },
+    )))
+}
+
+#[cfg(windows)]
+fn pipe_incoming(
+    socket_path: &std::path::Path,
+) -> Result<impl futures::Stream<Item = io::Result<StreamBox<parity_tokio_ipc::Connection>>>, Error>
+{
+    use futures::stream::TryStreamExt;
+    use parity_tokio_ipc::SecurityAttributes;
+
+    let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
+    endpoint.set_security_attributes(
+        SecurityAttributes::allow_everyone_create()
+            .map_err(Error::SecurityAttributes)?
+            .set_mode(0o766)
+            .map_err(Error::SecurityAttributes)?,
+    );
+    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    Ok(incoming.map_ok(|stream| StreamBox {
+        stream,
+        peer_credentials: None,
+    }))
+}
+
+/// Rejects calls that the [`Authorizer`] does not allow before they reach the wrapped service.
+struct AuthorizedService<S, A> {
+    inner: S,
+    authorizer: Arc<A>,
+}
+
+impl<S: Clone, A> Clone for AuthorizedService<S, A> {
+    fn clone(&self) -> Self {
+        AuthorizedService {
+            inner: self.inner.clone(),
+            authorizer: self.authorizer.clone(),
+        }
+    }
+}
+
+impl<S: NamedService, A> NamedService for AuthorizedService<S, A> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, A> Service<http::Request<Body>> for AuthorizedService<S, A>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    A: Authorizer,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = future::Either<future::Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        // The path is `/<package>.<service>/<method>`
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+        let peer = peer_credentials_from_extensions(request.extensions());
+        match self.authorizer.authorize(method, peer) {
+            Ok(()) => future::Either::Right(self.inner.call(request)),
+            Err(status) => future::Either::Left(future::ready(Ok(status.to_http()))),
+        }
+    }
+}
+
 #[derive(Debug)]
-struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
+struct StreamBox<T: AsyncRead + AsyncWrite> {
+    stream: T,
+    peer_credentials: Option<PeerCredentials>,
+}
 impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {
-    type ConnectInfo = Option<()>;
+    type ConnectInfo = Option<PeerCredentials>;
 
     fn connect_info(&self) -> Self::ConnectInfo {
-        None
+        self.peer_credentials
     }
 }
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
@@ -119,7 +253,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_read(cx, buf)
+        Pin::new(&mut self.stream).poll_read(cx, buf)
     }
 }
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
@@ -128,14 +262,14 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write(cx, buf)
+        Pin::new(&mut self.stream).poll_write(cx, buf)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_flush(cx)
+        Pin::new(&mut self.stream).poll_flush(cx)
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_shutdown(cx)
+        Pin::new(&mut self.stream).poll_shutdown(cx)
     }
 }
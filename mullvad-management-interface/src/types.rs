@@ -28,10 +28,7 @@ impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
         TunnelEndpoint {
             address: endpoint.endpoint.address.to_string(),
             protocol: i32::from(TransportProtocol::from(endpoint.endpoint.protocol)),
-            tunnel_type: match endpoint.tunnel_type {
-                net::TunnelType::Wireguard => i32::from(TunnelType::Wireguard),
-                net::TunnelType::OpenVpn => i32::from(TunnelType::Openvpn),
-            },
+            tunnel_type: i32::from(TunnelType::from(endpoint.tunnel_type)),
             proxy: endpoint.proxy.map(|proxy_ep| ProxyEndpoint {
                 address: proxy_ep.endpoint.address.to_string(),
                 protocol: i32::from(TransportProtocol::from(proxy_ep.endpoint.protocol)),
@@ -48,13 +45,33 @@ impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
     }
 }
 
-impl From<mullvad_types::states::TunnelState> for TunnelState {
-    fn from(state: mullvad_types::states::TunnelState) -> Self {
+impl From<talpid_types::net::TunnelType> for TunnelType {
+    fn from(tunnel_type: talpid_types::net::TunnelType) -> Self {
+        match tunnel_type {
+            talpid_types::net::TunnelType::Wireguard => TunnelType::Wireguard,
+            talpid_types::net::TunnelType::OpenVpn => TunnelType::Openvpn,
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::ActionAfterDisconnect> for AfterDisconnect {
+    fn from(action: talpid_types::tunnel::ActionAfterDisconnect) -> Self {
+        use talpid_types::tunnel::ActionAfterDisconnect;
+
+        match action {
+            ActionAfterDisconnect::Nothing => AfterDisconnect::Nothing,
+            ActionAfterDisconnect::Block => AfterDisconnect::Block,
+            ActionAfterDisconnect::Reconnect => AfterDisconnect::Reconnect,
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::ErrorState> for ErrorState {
+    fn from(error_state: talpid_types::tunnel::ErrorState) -> Self {
         use error_state::{
             firewall_policy_error::ErrorType as PolicyErrorType, Cause, FirewallPolicyError,
             GenerationError,
         };
-        use mullvad_types::states::TunnelState as MullvadTunnelState;
 
         use talpid_types::tunnel as talpid_tunnel;
 
@@ -79,6 +96,75 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                 }
             };
 
+        ErrorState {
+            cause: match error_state.cause() {
+                talpid_tunnel::ErrorStateCause::AuthFailed(_) => i32::from(Cause::AuthFailed),
+                talpid_tunnel::ErrorStateCause::Ipv6Unavailable => {
+                    i32::from(Cause::Ipv6Unavailable)
+                }
+                talpid_tunnel::ErrorStateCause::SetFirewallPolicyError(_) => {
+                    i32::from(Cause::SetFirewallPolicyError)
+                }
+                talpid_tunnel::ErrorStateCause::SetDnsError => i32::from(Cause::SetDnsError),
+                talpid_tunnel::ErrorStateCause::StartTunnelError => {
+                    i32::from(Cause::StartTunnelError)
+                }
+                talpid_tunnel::ErrorStateCause::TunnelParameterError(_) => {
+                    i32::from(Cause::TunnelParameterError)
+                }
+                talpid_tunnel::ErrorStateCause::IsOffline => i32::from(Cause::IsOffline),
+                #[cfg(target_os = "android")]
+                talpid_tunnel::ErrorStateCause::VpnPermissionDenied => {
+                    i32::from(Cause::VpnPermissionDenied)
+                }
+                #[cfg(target_os = "windows")]
+                talpid_tunnel::ErrorStateCause::SplitTunnelError => {
+                    i32::from(Cause::SplitTunnelError)
+                }
+            },
+            blocking_error: error_state.block_failure().map(map_firewall_error),
+            auth_fail_reason: if let talpid_tunnel::ErrorStateCause::AuthFailed(reason) =
+                error_state.cause()
+            {
+                reason.clone().unwrap_or_default()
+            } else {
+                "".to_string()
+            },
+            parameter_error: if let talpid_tunnel::ErrorStateCause::TunnelParameterError(reason) =
+                error_state.cause()
+            {
+                match reason {
+                    talpid_tunnel::ParameterGenerationError::NoMatchingRelay => {
+                        i32::from(GenerationError::NoMatchingRelay)
+                    }
+                    talpid_tunnel::ParameterGenerationError::NoMatchingBridgeRelay => {
+                        i32::from(GenerationError::NoMatchingBridgeRelay)
+                    }
+                    talpid_tunnel::ParameterGenerationError::NoWireguardKey => {
+                        i32::from(GenerationError::NoWireguardKey)
+                    }
+                    talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError => {
+                        i32::from(GenerationError::CustomTunnelHostResolutionError)
+                    }
+                }
+            } else {
+                0
+            },
+            policy_error: if let talpid_tunnel::ErrorStateCause::SetFirewallPolicyError(reason) =
+                error_state.cause()
+            {
+                Some(map_firewall_error(reason))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl From<mullvad_types::states::TunnelState> for TunnelState {
+    fn from(state: mullvad_types::states::TunnelState) -> Self {
+        use mullvad_types::states::TunnelState as MullvadTunnelState;
+
         let state = match state {
             MullvadTunnelState::Disconnected => {
                 tunnel_state::State::Disconnected(tunnel_state::Disconnected {})
@@ -101,92 +187,12 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
             }
             MullvadTunnelState::Disconnecting(after_disconnect) => {
                 tunnel_state::State::Disconnecting(tunnel_state::Disconnecting {
-                    after_disconnect: match after_disconnect {
-                        talpid_tunnel::ActionAfterDisconnect::Nothing => {
-                            i32::from(AfterDisconnect::Nothing)
-                        }
-                        talpid_tunnel::ActionAfterDisconnect::Block => {
-                            i32::from(AfterDisconnect::Block)
-                        }
-                        talpid_tunnel::ActionAfterDisconnect::Reconnect => {
-                            i32::from(AfterDisconnect::Reconnect)
-                        }
-                    },
+                    after_disconnect: i32::from(AfterDisconnect::from(after_disconnect)),
                 })
             }
             MullvadTunnelState::Error(error_state) => {
                 tunnel_state::State::Error(tunnel_state::Error {
-                    error_state: Some(ErrorState {
-                        cause: match error_state.cause() {
-                            talpid_tunnel::ErrorStateCause::AuthFailed(_) => {
-                                i32::from(Cause::AuthFailed)
-                            }
-                            talpid_tunnel::ErrorStateCause::Ipv6Unavailable => {
-                                i32::from(Cause::Ipv6Unavailable)
-                            }
-                            talpid_tunnel::ErrorStateCause::SetFirewallPolicyError(_) => {
-                                i32::from(Cause::SetFirewallPolicyError)
-                            }
-                            talpid_tunnel::ErrorStateCause::SetDnsError => {
-                                i32::from(Cause::SetDnsError)
-                            }
-                            talpid_tunnel::ErrorStateCause::StartTunnelError => {
-                                i32::from(Cause::StartTunnelError)
-                            }
-                            talpid_tunnel::ErrorStateCause::TunnelParameterError(_) => {
-                                i32::from(Cause::TunnelParameterError)
-                            }
-                            talpid_tunnel::ErrorStateCause::IsOffline => {
-                                i32::from(Cause::IsOffline)
-                            }
-                            #[cfg(target_os = "android")]
-                            talpid_tunnel::ErrorStateCause::VpnPermissionDenied => {
-                                i32::from(Cause::VpnPermissionDenied)
-                            }
-                            #[cfg(target_os = "windows")]
-                            talpid_tunnel::ErrorStateCause::SplitTunnelError => {
-                                i32::from(Cause::SplitTunnelError)
-                            }
-                        },
-                        blocking_error: error_state.block_failure().map(map_firewall_error),
-                        auth_fail_reason: if let talpid_tunnel::ErrorStateCause::AuthFailed(
-                            reason,
-                        ) = error_state.cause()
-                        {
-                            reason.clone().unwrap_or_default()
-                        } else {
-                            "".to_string()
-                        },
-                        parameter_error:
-                            if let talpid_tunnel::ErrorStateCause::TunnelParameterError(reason) =
-                                error_state.cause()
-                            {
-                                match reason {
-                            talpid_tunnel::ParameterGenerationError::NoMatchingRelay => {
-                                i32::from(GenerationError::NoMatchingRelay)
-                            }
-                            talpid_tunnel::ParameterGenerationError::NoMatchingBridgeRelay => {
-                                i32::from(GenerationError::NoMatchingBridgeRelay)
-                            }
-                            talpid_tunnel::ParameterGenerationError::NoWireguardKey => {
-                                i32::from(GenerationError::NoWireguardKey)
-                            }
-                            talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError => {
-                                i32::from(GenerationError::CustomTunnelHostResolutionError)
-                            }
-                        }
-                            } else {
-                                0
-                            },
-                        policy_error:
-                            if let talpid_tunnel::ErrorStateCause::SetFirewallPolicyError(reason) =
-                                error_state.cause()
-                            {
-                                Some(map_firewall_error(reason))
-                            } else {
-                                None
-                            },
-                    }),
+                    error_state: Some(ErrorState::from(error_state)),
                 })
             }
         };
@@ -227,6 +233,64 @@ impl From<mullvad_types::wireguard::PublicKey> for PublicKey {
     }
 }
 
+impl From<mullvad_types::wireguard::WireguardDevice> for WireguardDevice {
+    fn from(device: mullvad_types::wireguard::WireguardDevice) -> Self {
+        WireguardDevice {
+            key: Some(PublicKey::from(device.data.get_public_key())),
+            ipv4_address: device.data.addresses.ipv4_address.to_string(),
+            ipv6_address: device.data.addresses.ipv6_address.to_string(),
+            rotation_interval: device
+                .rotation_interval
+                .map(|ivl| Duration::from(std::time::Duration::from(ivl))),
+            name: device.name,
+        }
+    }
+}
+
+impl From<mullvad_types::account::StoredAccount> for StoredAccount {
+    fn from(account: mullvad_types::account::StoredAccount) -> Self {
+        StoredAccount {
+            token: account.token,
+            wireguard_key: account.wireguard_key.map(PublicKey::from),
+            has_relay_settings: account.has_relay_settings,
+            active: account.active,
+        }
+    }
+}
+
+impl From<mullvad_types::settings::SettingsEncryptionState> for SettingsEncryption {
+    fn from(state: mullvad_types::settings::SettingsEncryptionState) -> Self {
+        SettingsEncryption {
+            key_source: i32::from(settings_encryption::KeySource::from(state.key_source)),
+            locked: state.locked,
+        }
+    }
+}
+
+impl From<Option<mullvad_types::settings::SecretsKeySource>> for settings_encryption::KeySource {
+    fn from(key_source: Option<mullvad_types::settings::SecretsKeySource>) -> Self {
+        use mullvad_types::settings::SecretsKeySource;
+        match key_source {
+            None => settings_encryption::KeySource::None,
+            Some(SecretsKeySource::KeyFile) => settings_encryption::KeySource::KeyFile,
+            Some(SecretsKeySource::KernelKeyring) => settings_encryption::KeySource::KernelKeyring,
+            Some(SecretsKeySource::Passphrase) => settings_encryption::KeySource::Passphrase,
+        }
+    }
+}
+
+impl From<settings_encryption::KeySource> for Option<mullvad_types::settings::SecretsKeySource> {
+    fn from(key_source: settings_encryption::KeySource) -> Self {
+        use mullvad_types::settings::SecretsKeySource;
+        match key_source {
+            settings_encryption::KeySource::None => None,
+            settings_encryption::KeySource::KeyFile => Some(SecretsKeySource::KeyFile),
+            settings_encryption::KeySource::KernelKeyring => Some(SecretsKeySource::KernelKeyring),
+            settings_encryption::KeySource::Passphrase => Some(SecretsKeySource::Passphrase),
+        }
+    }
+}
+
 impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     fn from(version_info: mullvad_types::version::AppVersionInfo) -> Self {
         Self {
@@ -271,6 +335,11 @@ impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
                                 .collect(),
                             endpoint: config.peer.endpoint.to_string(),
                             protocol: i32::from(TransportProtocol::from(config.peer.protocol)),
+                            psk: config
+                                .peer
+                                .psk
+                                .map(|psk| psk.as_bytes().to_vec())
+                                .unwrap_or_default(),
                         }),
                         ipv4_gateway: config.ipv4_gateway.to_string(),
                         ipv6_gateway: config
@@ -383,6 +452,19 @@ impl From<&mullvad_types::settings::Settings> for Settings {
         #[cfg(not(windows))]
         let split_tunnel = None;
 
+        #[cfg(target_os = "linux")]
+        let allow_rules = settings.allow_rules.iter().map(AllowRule::from).collect();
+        #[cfg(not(target_os = "linux"))]
+        let allow_rules = vec![];
+        #[cfg(target_os = "linux")]
+        let excluded_uids = settings.excluded_uids.clone();
+        #[cfg(not(target_os = "linux"))]
+        let excluded_uids = vec![];
+        #[cfg(target_os = "linux")]
+        let audit_blocked_traffic = settings.audit_blocked_traffic;
+        #[cfg(not(target_os = "linux"))]
+        let audit_blocked_traffic = false;
+
         Self {
             account_token: settings.get_account_token().unwrap_or_default(),
             relay_settings: Some(RelaySettings::from(settings.get_relay_settings())),
@@ -394,6 +476,49 @@ impl From<&mullvad_types::settings::Settings> for Settings {
             tunnel_options: Some(TunnelOptions::from(&settings.tunnel_options)),
             show_beta_releases: settings.show_beta_releases,
             split_tunnel,
+            allow_rules,
+            excluded_uids,
+            metrics: Some(MetricsSettings::from(&settings.metrics)),
+            audit_blocked_traffic,
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::MetricsSettings> for MetricsSettings {
+    fn from(settings: &mullvad_types::settings::MetricsSettings) -> Self {
+        use metrics_settings::ListenAddress;
+        use mullvad_types::settings::MetricsListenAddress;
+
+        let listen_address = match &settings.listen_address {
+            MetricsListenAddress::Localhost(port) => ListenAddress::Port(u32::from(*port)),
+            MetricsListenAddress::UnixSocket(name) => {
+                ListenAddress::UnixSocketName(name.to_string_lossy().into_owned())
+            }
+        };
+        MetricsSettings {
+            enabled: settings.enabled,
+            listen_address: Some(listen_address),
+        }
+    }
+}
+
+impl From<&talpid_types::net::AllowRule> for AllowRule {
+    fn from(rule: &talpid_types::net::AllowRule) -> Self {
+        use talpid_types::net::AllowRuleDirection;
+
+        let direction = match rule.direction {
+            AllowRuleDirection::Outbound => allow_rule::Direction::Outbound,
+            AllowRuleDirection::Inbound => allow_rule::Direction::Inbound,
+        };
+        AllowRule {
+            direction: i32::from(direction),
+            network: rule.network.to_string(),
+            port: rule.port.map(u32::from),
+            protocol: rule.protocol.map(|protocol| {
+                TransportProtocolConstraint::from(TransportProtocol::from(protocol))
+            }),
+            cgroup: rule.cgroup,
+            uid: rule.uid,
         }
     }
 }
@@ -471,6 +596,7 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                 relay_settings::Endpoint::Custom(CustomRelaySettings {
                     host: endpoint.host,
                     config: Some(ConnectionConfig::from(endpoint.config)),
+                    mtu: u32::from(endpoint.mtu.unwrap_or_default()),
                 })
             }
             MullvadRelaySettings::Normal(constraints) => {
@@ -559,6 +685,13 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                     .wireguard
                     .rotation_interval
                     .map(|ivl| Duration::from(std::time::Duration::from(ivl))),
+                connectivity_check: Some(ConnectivityCheckOptions::from(
+                    &options.wireguard.options.connectivity_check,
+                )),
+                rotation_policy: Some(WireguardRotationPolicy::from(
+                    &options.wireguard.rotation_policy,
+                )),
+                netns: options.wireguard.options.netns.clone().unwrap_or_default(),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -571,6 +704,43 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
     }
 }
 
+impl From<&talpid_types::net::wireguard::ConnectivityCheckOptions> for ConnectivityCheckOptions {
+    fn from(options: &talpid_types::net::wireguard::ConnectivityCheckOptions) -> Self {
+        use talpid_types::net::wireguard::ProbeMode;
+
+        Self {
+            rx_timeout: Some(Duration::from(options.rx_timeout)),
+            traffic_timeout: Some(Duration::from(options.traffic_timeout)),
+            probe_timeout: Some(Duration::from(options.probe_timeout)),
+            probe_interval: Some(Duration::from(options.probe_interval)),
+            probe_targets: options
+                .probe_targets
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            probe_mode: Some(match options.probe_mode {
+                ProbeMode::Icmp => connectivity_check_options::ProbeMode::Icmp(()),
+                ProbeMode::Tcp(port) => {
+                    connectivity_check_options::ProbeMode::TcpPort(u32::from(port))
+                }
+                ProbeMode::Http(port) => {
+                    connectivity_check_options::ProbeMode::HttpPort(u32::from(port))
+                }
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::wireguard::RotationPolicy> for WireguardRotationPolicy {
+    fn from(policy: &mullvad_types::wireguard::RotationPolicy) -> Self {
+        Self {
+            max_bytes: policy.max_bytes,
+            max_relay_switches: policy.max_relay_switches,
+            time_of_day: policy.format_time_of_day(),
+        }
+    }
+}
+
 impl From<mullvad_types::relay_list::RelayListCountry> for RelayListCountry {
     fn from(country: mullvad_types::relay_list::RelayListCountry) -> Self {
         let mut proto_country = RelayListCountry {
@@ -673,6 +843,164 @@ impl From<TransportProtocol> for talpid_types::net::TransportProtocol {
     }
 }
 
+impl TryFrom<AllowRule> for talpid_types::net::AllowRule {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(rule: AllowRule) -> Result<Self, Self::Error> {
+        use talpid_types::net::AllowRuleDirection;
+
+        let direction = match allow_rule::Direction::from_i32(rule.direction) {
+            Some(allow_rule::Direction::Outbound) => AllowRuleDirection::Outbound,
+            Some(allow_rule::Direction::Inbound) => AllowRuleDirection::Inbound,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid allow rule direction",
+                ))
+            }
+        };
+        let network = rule
+            .network
+            .parse()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid network"))?;
+        let port = match rule.port {
+            Some(port) => Some(
+                u16::try_from(port)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+            ),
+            None => None,
+        };
+        let protocol = match rule.protocol {
+            Some(constraint) => Some(talpid_types::net::TransportProtocol::from(
+                TransportProtocol::from_i32(constraint.protocol).ok_or(
+                    FromProtobufTypeError::InvalidArgument("invalid transport protocol"),
+                )?,
+            )),
+            None => None,
+        };
+
+        let rule = talpid_types::net::AllowRule {
+            direction,
+            network,
+            port,
+            protocol,
+            cgroup: rule.cgroup,
+            uid: rule.uid,
+        };
+        rule.validate().map_err(|error| match error {
+            talpid_types::net::InvalidAllowRule::PortWithoutProtocol => {
+                FromProtobufTypeError::InvalidArgument("a port requires a protocol")
+            }
+            talpid_types::net::InvalidAllowRule::InboundOwner => {
+                FromProtobufTypeError::InvalidArgument(
+                    "inbound rules cannot match a cgroup or user",
+                )
+            }
+        })?;
+        Ok(rule)
+    }
+}
+
+impl TryFrom<MetricsSettings> for mullvad_types::settings::MetricsSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: MetricsSettings) -> Result<Self, Self::Error> {
+        use mullvad_types::settings::{Error, MetricsListenAddress};
+
+        let listen_address = match settings.listen_address {
+            Some(metrics_settings::ListenAddress::Port(port)) => MetricsListenAddress::Localhost(
+                u16::try_from(port)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+            ),
+            Some(metrics_settings::ListenAddress::UnixSocketName(name)) => {
+                MetricsListenAddress::UnixSocket(std::path::PathBuf::from(name))
+            }
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "missing listen address",
+                ))
+            }
+        };
+        listen_address.validate().map_err(|error| match error {
+            Error::InvalidValue(message) => FromProtobufTypeError::InvalidArgument(message),
+            _ => FromProtobufTypeError::InvalidArgument("invalid listen address"),
+        })?;
+        Ok(mullvad_types::settings::MetricsSettings {
+            enabled: settings.enabled,
+            listen_address,
+        })
+    }
+}
+
+impl TryFrom<ConnectivityCheckOptions> for talpid_types::net::wireguard::ConnectivityCheckOptions {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(options: ConnectivityCheckOptions) -> Result<Self, Self::Error> {
+        use talpid_types::net::wireguard::{InvalidConnectivityCheckOptions, ProbeMode};
+
+        let defaults = Self::default();
+        let timeout = |timeout: Option<Duration>, default| match timeout {
+            Some(timeout) => std::time::Duration::try_from(timeout)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("timeouts must be positive")),
+            None => Ok(default),
+        };
+        let port = |port: u32| {
+            u16::try_from(port).map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))
+        };
+
+        let probe_targets = options
+            .probe_targets
+            .iter()
+            .map(|target| target.parse())
+            .collect::<Result<Vec<std::net::Ipv4Addr>, _>>()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IPv4 probe target"))?;
+        let probe_mode = match options.probe_mode {
+            Some(connectivity_check_options::ProbeMode::Icmp(())) | None => ProbeMode::Icmp,
+            Some(connectivity_check_options::ProbeMode::TcpPort(tcp_port)) => {
+                ProbeMode::Tcp(port(tcp_port)?)
+            }
+            Some(connectivity_check_options::ProbeMode::HttpPort(http_port)) => {
+                ProbeMode::Http(port(http_port)?)
+            }
+        };
+
+        let options = Self {
+            rx_timeout: timeout(options.rx_timeout, defaults.rx_timeout)?,
+            traffic_timeout: timeout(options.traffic_timeout, defaults.traffic_timeout)?,
+            probe_timeout: timeout(options.probe_timeout, defaults.probe_timeout)?,
+            probe_interval: timeout(options.probe_interval, defaults.probe_interval)?,
+            probe_targets,
+            probe_mode,
+        };
+        options.validate().map_err(|error| match error {
+            InvalidConnectivityCheckOptions::ZeroTimeout => {
+                FromProtobufTypeError::InvalidArgument("timeouts must be positive")
+            }
+            InvalidConnectivityCheckOptions::InvalidPort => {
+                FromProtobufTypeError::InvalidArgument("invalid port")
+            }
+        })?;
+        Ok(options)
+    }
+}
+
+impl TryFrom<WireguardRotationPolicy> for mullvad_types::wireguard::RotationPolicy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(policy: WireguardRotationPolicy) -> Result<Self, Self::Error> {
+        let time_of_day = policy
+            .time_of_day
+            .map(|time| Self::parse_time_of_day(&time))
+            .transpose()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid time of day"))?;
+
+        Ok(Self {
+            max_bytes: policy.max_bytes,
+            max_relay_switches: policy.max_relay_switches,
+            time_of_day,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum FromProtobufTypeError {
     InvalidArgument(&'static str),
@@ -762,6 +1090,20 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                         let buffer = &peer.public_key[..public_key.len()];
                         public_key.copy_from_slice(buffer);
 
+                        let psk = match peer.psk.len() {
+                            0 => None,
+                            32 => {
+                                let mut psk = [0; 32];
+                                psk.copy_from_slice(&peer.psk);
+                                Some(wireguard::PresharedKey::from(psk))
+                            }
+                            _ => {
+                                return Err(FromProtobufTypeError::InvalidArgument(
+                                    "invalid preshared key",
+                                ))
+                            }
+                        };
+
                         let ipv4_gateway = match config.ipv4_gateway.parse() {
                             Ok(address) => address,
                             Err(_) => {
@@ -823,6 +1165,7 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                                         "invalid transport protocol",
                                     ))?
                                     .into(),
+                                psk,
                             },
                             exit_peer: None,
                             ipv4_gateway,
@@ -830,12 +1173,20 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                         })
                     }
                 };
+                let mtu = match settings.mtu {
+                    0 => None,
+                    mtu => Some(
+                        u16::try_from(mtu)
+                            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid MTU"))?,
+                    ),
+                };
 
                 Ok(
                     mullvad_constraints::RelaySettingsUpdate::CustomTunnelEndpoint(
                         CustomTunnelEndpoint {
                             host: settings.host,
                             config,
+                            mtu,
                         },
                     ),
                 )
@@ -0,0 +1,163 @@
+#[cfg(target_os = "linux")]
+use nix::{
+    mount::{mount, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+    unistd::{execvp, getgid, getuid, setgid, setuid},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    convert::Infallible,
+    env,
+    error::Error as StdError,
+    ffi::{CString, NulError, OsString},
+    fs, io,
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+};
+
+#[cfg(target_os = "linux")]
+use talpid_types::netns::{
+    is_created_by_daemon, is_valid_name, netns_path, resolv_conf_path, DEFAULT_NETNS_NAME,
+};
+
+#[cfg(target_os = "linux")]
+const PROGRAM_NAME: &str = "mullvad-netns";
+
+#[cfg(target_os = "linux")]
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+#[cfg(target_os = "linux")]
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+enum Error {
+    #[error(display = "Invalid arguments")]
+    InvalidArguments,
+
+    #[error(display = "Invalid network namespace name")]
+    InvalidNetnsName,
+
+    #[error(display = "Failed to open the network namespace. Is the tunnel connected?")]
+    OpenNetns(#[error(source)] io::Error),
+
+    #[error(display = "The network namespace was not created by the Mullvad daemon")]
+    NotCreatedByDaemon,
+
+    #[error(display = "Failed to verify that the network namespace was created by the daemon")]
+    CheckNetns(#[error(source)] io::Error),
+
+    #[error(display = "Failed to enter the network namespace")]
+    EnterNetns(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to create a mount namespace")]
+    CreateMountNamespace(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to bind mount the resolv.conf of the network namespace")]
+    MountResolvConf(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to drop root user privileges for the process")]
+    DropRootUid(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to drop root group privileges for the process")]
+    DropRootGid(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to launch the process")]
+    Exec(#[error(source)] nix::Error),
+
+    #[error(display = "An argument contains interior nul bytes")]
+    ArgumentNulError(#[error(source)] NulError),
+}
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    match run() {
+        Err(Error::InvalidArguments) => {
+            let mut args = env::args();
+            let program = args.next().unwrap_or(PROGRAM_NAME.to_string());
+            eprintln!("Usage: {} exec [-n NAME] COMMAND [ARGS]", program);
+            eprintln!();
+            eprintln!(
+                "Runs COMMAND inside the network namespace of the tunnel. NAME defaults to \"{}\" \
+                 and must be the namespace configured in the daemon.",
+                DEFAULT_NETNS_NAME
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            let mut s = format!("{}", e);
+            let mut source = e.source();
+            while let Some(error) = source {
+                s.push_str(&format!("\nCaused by: {}", error));
+                source = error.source();
+            }
+            eprintln!("{}", s);
+
+            std::process::exit(1);
+        }
+        _ => unreachable!("execv returned unexpectedly"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run() -> Result<Infallible, Error> {
+    let mut args_iter = env::args_os().skip(1).peekable();
+    if args_iter.next().ok_or(Error::InvalidArguments)? != "exec" {
+        return Err(Error::InvalidArguments);
+    }
+
+    let mut netns_name = DEFAULT_NETNS_NAME.to_string();
+    if args_iter.peek().map(|arg| arg == "-n").unwrap_or(false) {
+        args_iter.next();
+        netns_name = args_iter
+            .next()
+            .ok_or(Error::InvalidArguments)?
+            .into_string()
+            .map_err(|_| Error::InvalidNetnsName)?;
+    }
+    if !is_valid_name(&netns_name) {
+        return Err(Error::InvalidNetnsName);
+    }
+
+    let args: Vec<CString> = args_iter
+        .map(|arg: OsString| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<CString>, NulError>>()
+        .map_err(Error::ArgumentNulError)?;
+    let program = args.first().ok_or(Error::InvalidArguments)?.clone();
+
+    // Only the namespace of the tunnel may be entered, since this program runs as root
+    let netns = fs::File::open(netns_path(&netns_name)).map_err(Error::OpenNetns)?;
+    if !is_created_by_daemon(&netns_name, &netns).map_err(Error::CheckNetns)? {
+        return Err(Error::NotCreatedByDaemon);
+    }
+    setns(netns.as_raw_fd(), CloneFlags::CLONE_NEWNET).map_err(Error::EnterNetns)?;
+
+    // Use a private view of the file system so that the DNS config only applies to this process
+    unshare(CloneFlags::CLONE_NEWNS).map_err(Error::CreateMountNamespace)?;
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_SLAVE,
+        None::<&str>,
+    )
+    .map_err(Error::CreateMountNamespace)?;
+
+    let resolv_conf = resolv_conf_path(&netns_name);
+    if resolv_conf.exists() {
+        mount(
+            Some(resolv_conf.as_path()),
+            RESOLV_CONF_PATH,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(Error::MountResolvConf)?;
+    }
+
+    // Drop root privileges. The group must be changed first, since that requires root.
+    let real_gid = getgid();
+    setgid(real_gid).map_err(Error::DropRootGid)?;
+    let real_uid = getuid();
+    setuid(real_uid).map_err(Error::DropRootUid)?;
+
+    // Launch the process
+    execvp(&program, &args).map_err(Error::Exec)
+}
@@ -19,6 +19,7 @@ use talpid_types::{net::wireguard, ErrorExt};
 
 
 pub mod rest;
+pub mod stats;
 
 mod https_client_with_sni;
 use crate::https_client_with_sni::HttpsConnectorWithSni;
@@ -51,6 +52,7 @@ const API_ADDRESS: (IpAddr, u16) = (crate::API_IP, 443);
 pub struct MullvadRpcRuntime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
+    request_stats: stats::RequestStats,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -74,6 +76,7 @@ impl MullvadRpcRuntime {
                 None,
                 Arc::new(Box::new(|_| Ok(()))),
             )?,
+            request_stats: stats::RequestStats::default(),
             #[cfg(target_os = "android")]
             socket_bypass_tx: None,
         })
@@ -139,6 +142,7 @@ impl MullvadRpcRuntime {
         Ok(MullvadRpcRuntime {
             handle,
             address_cache,
+            request_stats: stats::RequestStats::default(),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -157,6 +161,7 @@ impl MullvadRpcRuntime {
             https_connector,
             self.handle.clone(),
             self.address_cache.clone(),
+            self.request_stats.clone(),
         );
         let handle = service.handle();
         self.handle.spawn(service.into_future());
@@ -180,6 +185,11 @@ impl MullvadRpcRuntime {
         self.new_request_service(None)
     }
 
+    /// Returns the latency statistics of all requests made through this runtime.
+    pub fn request_stats(&self) -> stats::RequestStats {
+        self.request_stats.clone()
+    }
+
     pub fn handle(&mut self) -> &mut tokio::runtime::Handle {
         &mut self.handle
     }
@@ -1,5 +1,7 @@
 use crate::{
-    address_cache::AddressCache, https_client_with_sni::HttpsConnectorWithSni,
+    address_cache::AddressCache,
+    https_client_with_sni::HttpsConnectorWithSni,
+    stats::{RequestKey, RequestOutcome, RequestStats},
     tcp_stream::TcpStreamHandle,
 };
 use futures::{
@@ -85,6 +87,7 @@ pub(crate) struct RequestService {
     next_id: u64,
     in_flight_requests: BTreeMap<u64, AbortHandle>,
     address_cache: AddressCache,
+    stats: RequestStats,
 }
 
 impl RequestService {
@@ -93,6 +96,7 @@ impl RequestService {
         mut connector: HttpsConnectorWithSni,
         handle: Handle,
         address_cache: AddressCache,
+        stats: RequestStats,
     ) -> RequestService {
         let (command_tx, command_rx) = mpsc::channel(1);
 
@@ -109,6 +113,7 @@ impl RequestService {
             next_id: 0,
             handle,
             address_cache,
+            stats,
         }
     }
 
@@ -129,19 +134,30 @@ impl RequestService {
 
                 let hyper_request = request.into_request();
                 let host_addr = get_request_socket_addr(&hyper_request);
+                let method = hyper_request.method().clone();
+                let uri = hyper_request.uri().clone();
 
                 let (request_future, abort_handle) =
                     abortable(self.client.request(hyper_request).map_err(Error::from));
                 let address_cache = self.address_cache.clone();
                 let handle = self.handle.clone();
+                let stats = self.stats.clone();
 
                 let future = async move {
+                    let start = Instant::now();
                     let response =
                         tokio::time::timeout(timeout, request_future.map_err(Error::Aborted))
                             .await
                             .map_err(Error::TimeoutError);
 
                     let response = flatten_result(flatten_result(response));
+                    let outcome = match &response {
+                        Ok(response) => RequestOutcome::Response(response.status().as_u16()),
+                        Err(Error::TimeoutError(_)) => RequestOutcome::Timeout,
+                        Err(Error::Aborted(_)) => RequestOutcome::Aborted,
+                        Err(_) => RequestOutcome::Failed,
+                    };
+                    stats.observe(RequestKey::new(&method, &uri, outcome), start.elapsed());
                     if let Some(host_addr) = host_addr {
                         if let Err(err) = &response {
                             match err {
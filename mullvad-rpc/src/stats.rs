@@ -0,0 +1,149 @@
+//! Latency statistics of the requests executed by a `RequestService`.
+
+use hyper::{Method, Uri};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Upper bounds, in seconds, of the latency buckets that requests are counted in.
+pub const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Identifies a group of requests that latencies are recorded for.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RequestKey {
+    /// HTTP method of the request.
+    pub method: String,
+    /// The first two segments of the request path, e.g. `/v1/wireguard-keys`. The rest of the
+    /// path is left out, since it may contain keys or other identifiers.
+    pub endpoint: String,
+    /// How the request completed.
+    pub outcome: RequestOutcome,
+}
+
+impl RequestKey {
+    pub(crate) fn new(method: &Method, uri: &Uri, outcome: RequestOutcome) -> Self {
+        let endpoint = uri
+            .path()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .take(2)
+            .fold(String::new(), |endpoint, segment| endpoint + "/" + segment);
+        RequestKey {
+            method: method.to_string(),
+            endpoint,
+            outcome,
+        }
+    }
+}
+
+/// The outcome of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RequestOutcome {
+    /// A response with the given status code was received.
+    Response(u16),
+    /// No response was received before the request timed out.
+    Timeout,
+    /// The request was cancelled, e.g. because the request service was reset.
+    Aborted,
+    /// The request failed for any other reason, such as a connection error.
+    Failed,
+}
+
+impl std::fmt::Display for RequestOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestOutcome::Response(status) => status.fmt(f),
+            RequestOutcome::Timeout => "timeout".fmt(f),
+            RequestOutcome::Aborted => "aborted".fmt(f),
+            RequestOutcome::Failed => "failed".fmt(f),
+        }
+    }
+}
+
+/// A cumulative histogram of request latencies, with the buckets in `LATENCY_BUCKETS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// Number of requests that completed within each bucket bound. Requests are counted in
+    /// every bucket that they fit in.
+    pub buckets: Vec<u64>,
+    /// Total number of requests.
+    pub count: u64,
+    /// Sum of the latencies of all requests.
+    pub sum: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: Duration::default(),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += latency;
+    }
+}
+
+/// Shared request latency statistics. Cloning the value returns a handle to the same statistics.
+#[derive(Debug, Clone, Default)]
+pub struct RequestStats {
+    histograms: Arc<Mutex<BTreeMap<RequestKey, LatencyHistogram>>>,
+}
+
+impl RequestStats {
+    pub(crate) fn observe(&self, key: RequestKey, latency: Duration) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Returns the latency histograms of all requests executed so far.
+    pub fn snapshot(&self) -> BTreeMap<RequestKey, LatencyHistogram> {
+        self.histograms.lock().unwrap().clone()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strips_identifiers_from_endpoint() {
+        let uri: Uri = "https://api.mullvad.net/v1/wireguard-keys/abc%2Fdef"
+            .parse()
+            .unwrap();
+        let key = RequestKey::new(&Method::GET, &uri, RequestOutcome::Response(200));
+        assert_eq!(key.endpoint, "/v1/wireguard-keys");
+    }
+
+    #[test]
+    fn counts_latency_in_cumulative_buckets() {
+        let stats = RequestStats::default();
+        let uri: Uri = "https://api.mullvad.net/v1/relays".parse().unwrap();
+        let key = RequestKey::new(&Method::GET, &uri, RequestOutcome::Timeout);
+        stats.observe(key.clone(), Duration::from_millis(300));
+        stats.observe(key.clone(), Duration::from_secs(20));
+
+        let histogram = &stats.snapshot()[&key];
+        assert_eq!(histogram.buckets, vec![0, 0, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(histogram.count, 2);
+        assert_eq!(histogram.sum, Duration::from_millis(20_300));
+    }
+}
@@ -155,6 +155,10 @@ async fn reset_firewall() -> Result<(), Error> {
         initialize_blocked: false,
         allow_lan: true,
         allowed_endpoint: None,
+        #[cfg(target_os = "linux")]
+        log_dir: None,
+        #[cfg(target_os = "linux")]
+        audit_blocked_traffic: false,
     })
     .map_err(Error::FirewallError)?;
 
@@ -12,14 +12,17 @@ mod mock_openvpn {
 #[cfg(not(target_os = "android"))]
 mod mock_openvpn {
     use mullvad_tests::{watch_event, PathWatcher};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
     use std::{
         env,
         fs::{self, File},
-        io::{self, Read, Write},
+        io::{self, BufRead, BufReader, Read, Write},
+        net::TcpStream,
         path::PathBuf,
         sync::mpsc,
         thread,
-        time::Duration,
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
 
     const MAX_EVENT_TIME: Duration = Duration::from_secs(60);
@@ -31,6 +34,10 @@ mod mock_openvpn {
 
         write_command_line(file);
 
+        if let Some((address, port, password_file)) = management_address() {
+            thread::spawn(move || serve_management_interface(&address, &port, password_file));
+        }
+
         wait_thread(wait_for_stdin_to_be_closed, finished_tx.clone());
         wait_thread(
             move || wait_for_file_to_be_deleted(watcher, MAX_EVENT_TIME),
@@ -81,4 +88,129 @@ mod mock_openvpn {
             .set_timeout(timeout)
             .find(|&event| event == watch_event::REMOVE);
     }
+
+    /// Returns the address, port and password file given to `--management`, if OpenVPN is
+    /// expected to connect to a management client.
+    fn management_address() -> Option<(String, String, Option<PathBuf>)> {
+        let args: Vec<String> = env::args().collect();
+        if !args.iter().any(|arg| arg == "--management-client") {
+            return None;
+        }
+        let position = args.iter().position(|arg| arg == "--management")?;
+        let password_file = args
+            .get(position + 3)
+            .filter(|arg| !arg.starts_with("--"))
+            .map(PathBuf::from);
+        Some((
+            args.get(position + 1)?.clone(),
+            args.get(position + 2)?.clone(),
+            password_file,
+        ))
+    }
+
+    /// Connects to the management client and answers its commands like OpenVPN would, reporting a
+    /// connected tunnel. Received commands are written to the file in
+    /// `MOCK_OPENVPN_MANAGEMENT_FILE`, which is created once the client has accepted the
+    /// connection and given the password, if any.
+    fn serve_management_interface(address: &str, port: &str, password_file: Option<PathBuf>) {
+        let (reader, mut writer) = connect_management_client(address, port)
+            .expect("Failed to connect to management client");
+        let mut commands = BufReader::new(reader).lines();
+
+        if let Some(password_file) = password_file {
+            let password = fs::read_to_string(password_file)
+                .expect("Failed to read management interface password file");
+            if writer.write_all(b"ENTER PASSWORD:").is_err() {
+                return;
+            }
+            match commands.next() {
+                Some(Ok(line)) if line == password.trim_end() => {
+                    if writer
+                        .write_all(b"SUCCESS: password is correct\r\n")
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                _ => {
+                    let _ = writer.write_all(b"ERROR: bad password\r\n");
+                    return;
+                }
+            }
+        }
+
+        let mut commands_file = env::var_os("MOCK_OPENVPN_MANAGEMENT_FILE").map(|path| {
+            File::create(path).expect("Failed to create mock OpenVPN management commands file")
+        });
+
+        if writer
+            .write_all(
+                b">INFO:OpenVPN Management Interface Version 3 -- type 'help' for more info\r\n",
+            )
+            .is_err()
+        {
+            return;
+        }
+
+        for command in commands {
+            let command = match command {
+                Ok(command) => command,
+                Err(_) => break,
+            };
+            if let Some(file) = commands_file.as_mut() {
+                writeln!(file, "{}", command).expect("Failed to write management command to file");
+            }
+            if writer
+                .write_all(management_response(&command).as_bytes())
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    fn connect_management_client(
+        address: &str,
+        port: &str,
+    ) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        #[cfg(unix)]
+        {
+            if port == "unix" {
+                let stream = UnixStream::connect(address)?;
+                return Ok((Box::new(stream.try_clone()?), Box::new(stream)));
+            }
+        }
+        let port: u16 = port
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid management port"))?;
+        let stream = TcpStream::connect((address, port))?;
+        Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+    }
+
+    fn management_response(command: &str) -> String {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        match command {
+            "state on" => format!(
+                "SUCCESS: real-time state notification set to ON\r\n\
+                 >STATE:{},CONNECTED,SUCCESS,10.8.0.2,192.168.0.100,1000,,\r\n",
+                time
+            ),
+            "log on" => format!(
+                "SUCCESS: real-time log notification set to ON\r\n\
+                 >LOG:{},I,Data Channel: using negotiated cipher 'AES-256-GCM'\r\n",
+                time
+            ),
+            "signal SIGUSR1" => format!(
+                "SUCCESS: signal SIGUSR1 thrown\r\n>STATE:{},RECONNECTING,SIGUSR1,,,,,\r\n",
+                time
+            ),
+            command if command.starts_with("bytecount ") => {
+                "SUCCESS: bytecount interval changed\r\n>BYTECOUNT:5678,1234\r\n".to_owned()
+            }
+            _ => "ERROR: unknown command, enter 'help' for more options\r\n".to_owned(),
+        }
+    }
 }
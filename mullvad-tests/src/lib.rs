@@ -1,6 +1,9 @@
 #![cfg(not(target_os = "android"))]
 
-use self::{mock_openvpn::MOCK_OPENVPN_ARGS_FILE, platform_specific::*};
+use self::{
+    mock_openvpn::{MOCK_OPENVPN_ARGS_FILE, MOCK_OPENVPN_MANAGEMENT_FILE},
+    platform_specific::*,
+};
 use jsonrpc_client_ipc::IpcTransport;
 use mullvad_ipc_client::DaemonRpcClient;
 use mullvad_rpc::API_IP_CACHE_FILENAME;
@@ -279,6 +282,7 @@ fn prepare_relay_list<T: AsRef<Path>>(path: T) {
 pub struct DaemonRunner {
     process: Option<duct::Handle>,
     mock_openvpn_args_file: PathBuf,
+    mock_openvpn_management_file: PathBuf,
     rpc_socket_path: PathBuf,
     _temp_dir: TempDir,
 }
@@ -291,6 +295,7 @@ impl DaemonRunner {
     fn spawn_internal() -> Self {
         let (temp_dir, cache_dir, resource_dir, settings_dir) = prepare_test_dirs();
         let mock_openvpn_args_file = temp_dir.path().join(MOCK_OPENVPN_ARGS_FILE);
+        let mock_openvpn_management_file = temp_dir.path().join(MOCK_OPENVPN_MANAGEMENT_FILE);
 
         let rpc_socket_path = temp_dir.path().join("rpc_socket");
 
@@ -301,6 +306,10 @@ impl DaemonRunner {
             .env("MULLVAD_RESOURCE_DIR", resource_dir)
             .env("MULLVAD_SETTINGS_DIR", settings_dir)
             .env("MOCK_OPENVPN_ARGS_FILE", mock_openvpn_args_file.clone())
+            .env(
+                "MOCK_OPENVPN_MANAGEMENT_FILE",
+                mock_openvpn_management_file.clone(),
+            )
             .stdout_null()
             .stderr_null();
 
@@ -310,6 +319,7 @@ impl DaemonRunner {
         DaemonRunner {
             process: Some(process),
             mock_openvpn_args_file,
+            mock_openvpn_management_file,
             rpc_socket_path,
             _temp_dir: temp_dir,
         }
@@ -319,6 +329,10 @@ impl DaemonRunner {
         &self.mock_openvpn_args_file
     }
 
+    pub fn mock_openvpn_management_file(&self) -> &Path {
+        &self.mock_openvpn_management_file
+    }
+
     pub fn rpc_client(&mut self) -> Result<DaemonRpcClient> {
         wait_for_file(&self.rpc_socket_path);
         let socket_path: String = self.rpc_socket_path.to_string_lossy().to_string();
@@ -5,6 +5,7 @@ use std::{
 };
 
 pub const MOCK_OPENVPN_ARGS_FILE: &str = "mock_openvpn_args";
+pub const MOCK_OPENVPN_MANAGEMENT_FILE: &str = "mock_openvpn_management";
 
 pub fn search_openvpn_args<P: AsRef<Path>>(
     openvpn_args_file_path: P,
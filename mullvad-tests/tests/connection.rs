@@ -56,6 +56,45 @@ fn respawns_openvpn_if_it_crashes() {
     openvpn_args_file_events.assert_create_write_close_sequence();
 }
 
+#[test]
+fn connects_to_openvpn_management_interface() {
+    let mut daemon = DaemonRunner::spawn();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+    let openvpn_args_file = daemon.mock_openvpn_args_file();
+    let management_file = daemon.mock_openvpn_management_file();
+    let mut openvpn_args_file_events = PathWatcher::watch(&openvpn_args_file).unwrap();
+    let mut management_file_events = PathWatcher::watch(&management_file).unwrap();
+
+    rpc_client.set_account(Some("123456".to_owned())).unwrap();
+    rpc_client.connect().unwrap();
+
+    openvpn_args_file_events.assert_create_write_close_sequence();
+    assert!(
+        search_openvpn_args(openvpn_args_file, "--management-client")
+            .next()
+            .is_some()
+    );
+
+    // The file is created once the daemon has accepted the management connection, but the
+    // commands are written as they arrive, so wait until all of them have been received
+    let expected_commands = ["state on", "bytecount 1", "log on"];
+    let has_received_commands = || {
+        fs::read_to_string(management_file)
+            .map(|commands| {
+                expected_commands
+                    .iter()
+                    .all(|expected| commands.lines().any(|command| command == *expected))
+            })
+            .unwrap_or(false)
+    };
+    while !has_received_commands() {
+        assert!(
+            management_file_events.next().is_some(),
+            "Timed out waiting for management interface commands"
+        );
+    }
+}
+
 #[test]
 fn changes_to_connecting_state() {
     let mut daemon = DaemonRunner::spawn();
@@ -165,6 +204,76 @@ fn returns_to_connecting_state() {
     );
 }
 
+#[test]
+fn restarts_openvpn_when_reconnecting() {
+    let mut daemon = DaemonRunner::spawn();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+    let openvpn_args_file = daemon.mock_openvpn_args_file();
+    let management_file = daemon.mock_openvpn_management_file();
+    let mut openvpn_args_file_events = PathWatcher::watch(&openvpn_args_file).unwrap();
+    let mut management_file_events = PathWatcher::watch(&management_file).unwrap();
+    let state_events = rpc_client.daemon_event_subscribe().wait().unwrap();
+
+    rpc_client.set_account(Some("123456".to_owned())).unwrap();
+    rpc_client.connect().unwrap();
+
+    let state_events = assert_state_event(
+        state_events,
+        TunnelState::Connecting {
+            endpoint: get_default_endpoint(),
+            location: get_default_location(),
+        },
+    );
+    openvpn_args_file_events.assert_create_write_close_sequence();
+
+    let mut mock_plugin_client = create_mock_openvpn_plugin_client(openvpn_args_file);
+
+    mock_plugin_client.up().unwrap();
+
+    let state_events = assert_state_event(
+        state_events,
+        TunnelState::Connected {
+            endpoint: get_default_endpoint(),
+            location: get_default_location(),
+        },
+    );
+
+    rpc_client.reconnect().unwrap();
+
+    let state_events = assert_state_event(
+        state_events,
+        TunnelState::Connecting {
+            endpoint: get_default_endpoint(),
+            location: get_default_location(),
+        },
+    );
+
+    let has_received_signal = || {
+        fs::read_to_string(management_file)
+            .map(|commands| commands.lines().any(|command| command == "signal SIGUSR1"))
+            .unwrap_or(false)
+    };
+    while !has_received_signal() {
+        assert!(
+            management_file_events.next().is_some(),
+            "Timed out waiting for the restart signal"
+        );
+    }
+
+    // OpenVPN reports that the tunnel went down and up again, without being respawned
+    mock_plugin_client.route_predown().unwrap();
+    mock_plugin_client.up().unwrap();
+
+    let _ = assert_state_event(
+        state_events,
+        TunnelState::Connected {
+            endpoint: get_default_endpoint(),
+            location: get_default_location(),
+        },
+    );
+    assert!(openvpn_args_file.exists());
+}
+
 #[test]
 fn disconnects() {
     let mut daemon = DaemonRunner::spawn();
@@ -1,3 +1,4 @@
+use crate::wireguard::PublicKey;
 use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -28,3 +29,15 @@ pub struct VoucherSubmission {
     #[cfg_attr(target_os = "android", jnix(map = "|expiry| expiry.to_string()"))]
     pub new_expiry: DateTime<Utc>,
 }
+
+/// An account that is stored in the daemon's account history and can be switched to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredAccount {
+    pub token: AccountToken,
+    /// The WireGuard key kept for the account while it is not the active one.
+    pub wireguard_key: Option<PublicKey>,
+    /// Whether relay settings are restored when switching to the account.
+    pub has_relay_settings: bool,
+    /// Whether this is the account currently in use.
+    pub active: bool,
+}
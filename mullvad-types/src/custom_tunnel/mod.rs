@@ -8,6 +8,9 @@ use std::{
 };
 use talpid_types::net::{openvpn, wireguard, Endpoint, TunnelParameters};
 
+pub mod openvpn_config;
+pub mod wg_quick;
+
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -18,6 +21,16 @@ pub enum Error {
     HostHasNoIpv4(String),
 }
 
+/// Errors encountered when importing a tunnel config file.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigImportError {
+    #[error(display = "Line {}: {}", _0, _1)]
+    InvalidLine(usize, String),
+
+    #[error(display = "{}", _0)]
+    Incomplete(String),
+}
+
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 // TODO: Remove this Java conversion once `jnix` supports skipping fields in enum tuple variants.
@@ -27,11 +40,28 @@ pub enum Error {
 pub struct CustomTunnelEndpoint {
     pub host: String,
     pub config: ConnectionConfig,
+    /// MTU for WireGuard tunnels, which overrides the MTU in the tunnel options.
+    #[serde(default)]
+    pub mtu: Option<u16>,
 }
 
 impl CustomTunnelEndpoint {
     pub fn new(host: String, config: ConnectionConfig) -> Self {
-        Self { host, config }
+        Self {
+            host,
+            config,
+            mtu: None,
+        }
+    }
+
+    /// Parses a `wg-quick` config file with a single peer.
+    pub fn from_wg_quick(config: &str) -> Result<Self, ConfigImportError> {
+        wg_quick::parse(config)
+    }
+
+    /// Parses an OpenVPN client config file that connects to a single remote.
+    pub fn from_openvpn_config(config: &str) -> Result<Self, ConfigImportError> {
+        openvpn_config::parse(config)
     }
 
     pub fn endpoint(&self) -> Endpoint {
@@ -58,12 +88,18 @@ impl CustomTunnelEndpoint {
                 proxy,
             }
             .into(),
-            ConnectionConfig::Wireguard(connection) => wireguard::TunnelParameters {
-                connection,
-                options: tunnel_options.wireguard.options.clone(),
-                generic_options: tunnel_options.generic.clone(),
+            ConnectionConfig::Wireguard(connection) => {
+                let mut options = tunnel_options.wireguard.options.clone();
+                if self.mtu.is_some() {
+                    options.mtu = self.mtu;
+                }
+                wireguard::TunnelParameters {
+                    connection,
+                    options,
+                    generic_options: tunnel_options.generic.clone(),
+                }
+                .into()
             }
-            .into(),
         };
         Ok(parameters)
     }
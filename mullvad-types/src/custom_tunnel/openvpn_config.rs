@@ -0,0 +1,463 @@
+//! Parser for the subset of OpenVPN client config files that can be expressed as a custom OpenVPN
+//! endpoint. The app always uses its own OpenVPN configuration, so only the remote, the transport
+//! protocol and the credentials are taken from the file.
+//!
+//! Since the daemon can't read files referenced by a config, credentials given in a file through
+//! `auth-user-pass <file>` must be inlined with [`inline_credentials`] before the config is
+//! imported.
+
+use super::{ConfigImportError, ConnectionConfig, CustomTunnelEndpoint};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
+
+const DEFAULT_PORT: u16 = 1194;
+
+/// Directives that are covered by the OpenVPN configuration of the app, and therefore ignored.
+/// This includes scripts, which are never run.
+const IGNORED_DIRECTIVES: &[&str] = &[
+    "auth-nocache",
+    "ca",
+    "cipher",
+    "client",
+    "data-ciphers",
+    "down",
+    "explicit-exit-notify",
+    "fast-io",
+    "key-direction",
+    "mute",
+    "mute-replay-warnings",
+    "nobind",
+    "persist-key",
+    "persist-tun",
+    "ping",
+    "ping-restart",
+    "rcvbuf",
+    "remote-cert-tls",
+    "remote-random",
+    "resolv-retry",
+    "script-security",
+    "sndbuf",
+    "tls-cipher",
+    "tls-client",
+    "tun-ipv6",
+    "up",
+    "verb",
+];
+
+/// Inline blocks that are covered by the OpenVPN configuration of the app, and therefore ignored.
+const IGNORED_BLOCKS: &[&str] = &["ca", "cert", "key", "tls-auth"];
+
+/// Parses a client config, using the first `remote` if there are several. Credentials must be given
+/// inline in an `<auth-user-pass>` block. Certificates, keys and scripts are ignored, while other
+/// directives that would change how the app configures OpenVPN are rejected.
+pub fn parse(config: &str) -> Result<CustomTunnelEndpoint, ConfigImportError> {
+    let mut remote = None;
+    let mut port = None;
+    let mut protocol = None;
+    let mut credentials = None;
+
+    let mut lines = config.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line_number = index + 1;
+        let invalid = |reason: String| ConfigImportError::InvalidLine(line_number, reason);
+
+        let line = line.trim();
+        if line.starts_with('<') {
+            match block_name(line) {
+                Some("auth-user-pass") => {
+                    if credentials.is_some() {
+                        return Err(invalid("Duplicate credentials".to_string()));
+                    }
+                    credentials = Some(parse_credentials(&mut lines, line_number)?);
+                }
+                Some(name) if IGNORED_BLOCKS.contains(&name) => {
+                    skip_block(&mut lines, name, line_number)?;
+                }
+                _ => return Err(invalid(format!("Inline block {} is not supported", line))),
+            }
+            continue;
+        }
+
+        let (directive, arguments) = match split_directive(line) {
+            Some(directive) => directive,
+            None => continue,
+        };
+
+        match directive {
+            "remote" => {
+                let (remote_port, remote_protocol) = match arguments.as_slice() {
+                    [_] => (None, None),
+                    [_, remote_port] => (Some(parse_port(remote_port).map_err(invalid)?), None),
+                    [_, remote_port, remote_protocol] => (
+                        Some(parse_port(remote_port).map_err(invalid)?),
+                        Some(parse_protocol(remote_protocol).map_err(invalid)?),
+                    ),
+                    _ => {
+                        return Err(invalid(
+                            "Expected `remote <host> [port] [protocol]`".to_string(),
+                        ))
+                    }
+                };
+                // Only the first remote is used
+                if remote.is_none() {
+                    remote = Some(arguments[0].to_string());
+                    port = remote_port.or(port);
+                    protocol = remote_protocol.or(protocol);
+                }
+            }
+            "port" | "rport" => match arguments.as_slice() {
+                [value] => {
+                    if port.is_none() {
+                        port = Some(parse_port(value).map_err(invalid)?);
+                    }
+                }
+                _ => return Err(invalid(format!("Expected `{} <port>`", directive))),
+            },
+            "proto" => match arguments.as_slice() {
+                [value] => {
+                    if protocol.is_none() {
+                        protocol = Some(parse_protocol(value).map_err(invalid)?);
+                    }
+                }
+                _ => return Err(invalid("Expected `proto <protocol>`".to_string())),
+            },
+            "dev" => {
+                if arguments.as_slice() != ["tun"] {
+                    return Err(invalid("Only `dev tun` is supported".to_string()));
+                }
+            }
+            "auth-user-pass" => {
+                if !arguments.is_empty() {
+                    return Err(invalid(
+                        "Credentials must be given inline in an <auth-user-pass> block, not in a \
+                         file"
+                            .to_string(),
+                    ));
+                }
+            }
+            directive if IGNORED_DIRECTIVES.contains(&directive) => (),
+            directive => {
+                return Err(invalid(format!("`{}` is not supported", directive)));
+            }
+        }
+    }
+
+    let host =
+        remote.ok_or_else(|| ConfigImportError::Incomplete("Missing `remote`".to_string()))?;
+    let (username, password) = credentials.ok_or_else(|| {
+        ConfigImportError::Incomplete("Missing <auth-user-pass> block".to_string())
+    })?;
+
+    Ok(CustomTunnelEndpoint::new(
+        host,
+        ConnectionConfig::OpenVpn(openvpn::ConnectionConfig::new(
+            Endpoint {
+                // The address is resolved from the host when connecting
+                address: SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    port.unwrap_or(DEFAULT_PORT),
+                ),
+                protocol: protocol.unwrap_or(TransportProtocol::Udp),
+            },
+            username,
+            password,
+        )),
+    ))
+}
+
+/// Reads the username and password of an `<auth-user-pass>` block that starts at `start_line`.
+fn parse_credentials<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    start_line: usize,
+) -> Result<(String, String), ConfigImportError> {
+    let mut values: Vec<&str> = vec![];
+    for (index, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "</auth-user-pass>" {
+            return match values.as_slice() {
+                [username, password] => Ok((username.to_string(), password.to_string())),
+                _ => Err(ConfigImportError::InvalidLine(
+                    index + 1,
+                    "Expected a username and a password".to_string(),
+                )),
+            };
+        }
+        values.push(line);
+    }
+    Err(ConfigImportError::InvalidLine(
+        start_line,
+        "Unterminated <auth-user-pass> block".to_string(),
+    ))
+}
+
+/// Skips the contents of an inline block that starts at `start_line`.
+fn skip_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    name: &str,
+    start_line: usize,
+) -> Result<(), ConfigImportError> {
+    let end_tag = format!("</{}>", name);
+    if lines.any(|(_, line)| line.trim() == end_tag) {
+        Ok(())
+    } else {
+        Err(ConfigImportError::InvalidLine(
+            start_line,
+            format!("Unterminated <{}> block", name),
+        ))
+    }
+}
+
+/// Returns the name of the inline block that `line` starts, if any.
+fn block_name(line: &str) -> Option<&str> {
+    line.strip_prefix('<')?.strip_suffix('>')
+}
+
+/// Splits a line into its directive and arguments, leaving out comments. Returns `None` if the
+/// line has no directive.
+fn split_directive(line: &str) -> Option<(&str, Vec<&str>)> {
+    let mut tokens = line
+        .split_whitespace()
+        .take_while(|token| !token.starts_with('#') && !token.starts_with(';'));
+    let directive = tokens.next()?;
+    Some((directive, tokens.collect()))
+}
+
+/// Returns the path given by an `auth-user-pass <file>` directive, if there is one. Relative paths
+/// are relative to the directory of the config file.
+pub fn credentials_file(config: &str) -> Option<&str> {
+    config
+        .lines()
+        .filter_map(|line| split_directive(line.trim()))
+        .find_map(
+            |(directive, arguments)| match (directive, arguments.as_slice()) {
+                ("auth-user-pass", [path]) => Some(*path),
+                _ => None,
+            },
+        )
+}
+
+/// Replaces the `auth-user-pass <file>` directive in `config` with an `<auth-user-pass>` block,
+/// given the `credentials` read from the file.
+pub fn inline_credentials(config: &str, credentials: &str) -> String {
+    let mut inlined = String::with_capacity(config.len() + credentials.len());
+    for line in config.lines() {
+        match split_directive(line.trim()) {
+            Some(("auth-user-pass", _)) => inlined.push_str("auth-user-pass"),
+            _ => inlined.push_str(line),
+        }
+        inlined.push('\n');
+    }
+    inlined.push_str("<auth-user-pass>\n");
+    for line in credentials.lines() {
+        inlined.push_str(line);
+        inlined.push('\n');
+    }
+    inlined.push_str("</auth-user-pass>\n");
+    inlined
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    port.parse()
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or_else(|| format!("Invalid port: {}", port))
+}
+
+fn parse_protocol(protocol: &str) -> Result<TransportProtocol, String> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Ok(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Ok(TransportProtocol::Tcp)
+        }
+        _ => Err(format!("Unsupported protocol: {}", protocol)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CREDENTIALS: &str = "<auth-user-pass>\n1234567890123456\nm\n</auth-user-pass>\n";
+
+    #[test]
+    fn test_parse_config() {
+        let config = format!(
+            "client\n\
+             dev tun\n\
+             proto tcp\n\
+             remote se-got-001.example.net 443\n\
+             ; comment\n\
+             resolv-retry infinite\n\
+             auth-user-pass\n\
+             {}",
+            CREDENTIALS
+        );
+        let endpoint = parse(&config).unwrap();
+        assert_eq!(endpoint.host, "se-got-001.example.net");
+        match endpoint.config {
+            ConnectionConfig::OpenVpn(config) => {
+                assert_eq!(config.endpoint.address.port(), 443);
+                assert_eq!(config.endpoint.protocol, TransportProtocol::Tcp);
+                assert_eq!(config.username, "1234567890123456");
+                assert_eq!(config.password, "m");
+            }
+            ConnectionConfig::Wireguard(_) => panic!("unexpected WireGuard config"),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let endpoint = parse(&format!("remote 192.0.2.1\n{}", CREDENTIALS)).unwrap();
+        assert_eq!(
+            endpoint.endpoint(),
+            Endpoint::new(Ipv4Addr::UNSPECIFIED, DEFAULT_PORT, TransportProtocol::Udp)
+        );
+    }
+
+    /// A config as generated for Linux by the config generator on the Mullvad website
+    const MULLVAD_CONFIG: &str = "\
+client
+dev tun
+resolv-retry infinite
+nobind
+persist-key
+persist-tun
+verb 3
+remote-cert-tls server
+ping 10
+ping-restart 60
+sndbuf 524288
+rcvbuf 524288
+cipher AES-256-CBC
+tls-cipher TLS-DHE-RSA-WITH-AES-256-GCM-SHA384:TLS-DHE-RSA-WITH-AES-256-CBC-SHA
+proto udp
+auth-user-pass mullvad_userpass.txt
+ca mullvad_ca.crt
+script-security 2
+up /etc/openvpn/update-resolv-conf
+down /etc/openvpn/update-resolv-conf
+fast-io
+remote-random
+remote 185.213.154.68 1301 # se-got-ovpn-001
+remote 185.213.154.69 1301 # se-got-ovpn-002
+remote 185.213.154.70 1301 # se-got-ovpn-003
+";
+
+    #[test]
+    fn test_parse_mullvad_config() {
+        assert_eq!(
+            credentials_file(MULLVAD_CONFIG),
+            Some("mullvad_userpass.txt")
+        );
+        let config = inline_credentials(MULLVAD_CONFIG, "1234567890123456\nm\n");
+        assert_eq!(credentials_file(&config), None);
+
+        let endpoint = parse(&config).unwrap();
+        assert_eq!(endpoint.host, "185.213.154.68");
+        match endpoint.config {
+            ConnectionConfig::OpenVpn(config) => {
+                assert_eq!(config.endpoint.address.port(), 1301);
+                assert_eq!(config.endpoint.protocol, TransportProtocol::Udp);
+                assert_eq!(config.username, "1234567890123456");
+                assert_eq!(config.password, "m");
+            }
+            ConnectionConfig::Wireguard(_) => panic!("unexpected WireGuard config"),
+        }
+    }
+
+    #[test]
+    fn test_multiple_remotes() {
+        let config = format!(
+            "port 1195\n\
+             remote first.example.net\n\
+             remote second.example.net 443 tcp\n\
+             {}",
+            CREDENTIALS
+        );
+        let endpoint = parse(&config).unwrap();
+        assert_eq!(endpoint.host, "first.example.net");
+        assert_eq!(
+            endpoint.endpoint(),
+            Endpoint::new(Ipv4Addr::UNSPECIFIED, 1195, TransportProtocol::Udp)
+        );
+    }
+
+    #[test]
+    fn test_ignored_blocks() {
+        let config = format!(
+            "remote 192.0.2.1\n\
+             <ca>\n\
+             -----BEGIN CERTIFICATE-----\n\
+             -----END CERTIFICATE-----\n\
+             </ca>\n\
+             <cert>\n\
+             </cert>\n\
+             <key>\n\
+             </key>\n\
+             key-direction 1\n\
+             <tls-auth>\n\
+             </tls-auth>\n\
+             {}",
+            CREDENTIALS
+        );
+        assert!(parse(&config).is_ok());
+
+        assert_eq!(
+            parse("remote 192.0.2.1\n<ca>\n-----BEGIN CERTIFICATE-----\n"),
+            Err(ConfigImportError::InvalidLine(
+                2,
+                "Unterminated <ca> block".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_directives() {
+        let config = format!(
+            "remote 192.0.2.1\nhttp-proxy 192.0.2.2 8080\n{}",
+            CREDENTIALS
+        );
+        assert_eq!(
+            parse(&config),
+            Err(ConfigImportError::InvalidLine(
+                2,
+                "`http-proxy` is not supported".to_string()
+            ))
+        );
+
+        let config = "remote 192.0.2.1\n<tls-crypt>\n</tls-crypt>\n";
+        assert_eq!(
+            parse(config),
+            Err(ConfigImportError::InvalidLine(
+                2,
+                "Inline block <tls-crypt> is not supported".to_string()
+            ))
+        );
+
+        let config = "remote 192.0.2.1\nauth-user-pass credentials.txt\n";
+        assert!(matches!(
+            parse(config),
+            Err(ConfigImportError::InvalidLine(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_credentials() {
+        let config = "remote 192.0.2.1\n<auth-user-pass>\nusername\n</auth-user-pass>\n";
+        assert_eq!(
+            parse(config),
+            Err(ConfigImportError::InvalidLine(
+                4,
+                "Expected a username and a password".to_string()
+            ))
+        );
+        assert!(matches!(
+            parse("remote 192.0.2.1\n<auth-user-pass>\nusername\n"),
+            Err(ConfigImportError::InvalidLine(2, _))
+        ));
+    }
+}
@@ -0,0 +1,433 @@
+//! Conversion between WireGuard connection configs and the subset of `wg-quick` config files
+//! that can be expressed as a custom WireGuard endpoint.
+
+use super::{ConfigImportError, ConnectionConfig, CustomTunnelEndpoint};
+use ipnetwork::IpNetwork;
+use std::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use talpid_types::net::{wireguard, TransportProtocol};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    None,
+    Interface,
+    Peer,
+}
+
+/// Parses a config with one `[Interface]` and one `[Peer]` section.
+///
+/// The interface supports `PrivateKey`, `Address`, `DNS` and `MTU`, and the peer supports
+/// `PublicKey`, `PresharedKey`, `AllowedIPs` and `Endpoint`. The first IPv4 and IPv6 DNS servers
+/// are used as the tunnel gateways, which is where DNS requests are sent unless custom DNS
+/// servers are set.
+pub fn parse(config: &str) -> Result<CustomTunnelEndpoint, ConfigImportError> {
+    let mut section = Section::None;
+    let mut has_interface = false;
+    let mut has_peer = false;
+
+    let mut private_key = None;
+    let mut addresses = vec![];
+    let mut dns_servers = vec![];
+    let mut mtu = None;
+    let mut public_key = None;
+    let mut psk = None;
+    let mut allowed_ips = vec![];
+    let mut endpoint = None;
+
+    for (index, line) in config.lines().enumerate() {
+        let line_number = index + 1;
+        let invalid = |reason: String| ConfigImportError::InvalidLine(line_number, reason);
+
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            match line.to_lowercase().as_str() {
+                "[interface]" if has_interface => {
+                    return Err(invalid("Duplicate [Interface] section".to_string()))
+                }
+                "[interface]" => {
+                    has_interface = true;
+                    section = Section::Interface;
+                }
+                "[peer]" if has_peer => {
+                    return Err(invalid("Only one [Peer] section is supported".to_string()))
+                }
+                "[peer]" => {
+                    has_peer = true;
+                    section = Section::Peer;
+                }
+                _ => return Err(invalid(format!("Unknown section {}", line))),
+            }
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(invalid("Expected `Key = Value`".to_string())),
+        };
+
+        match (section, key.to_lowercase().as_str()) {
+            (Section::None, _) => {
+                return Err(invalid(format!("`{}` is outside of any section", key)))
+            }
+            (Section::Interface, "privatekey") => {
+                let key = wireguard::PrivateKey::from_base64(value)
+                    .map_err(|_| invalid("Invalid private key".to_string()))?;
+                set_once(&mut private_key, key, "PrivateKey").map_err(invalid)?;
+            }
+            (Section::Interface, "address") => {
+                for address in split_list(value) {
+                    let network: IpNetwork = address
+                        .parse()
+                        .map_err(|_| invalid(format!("Invalid address: {}", address)))?;
+                    addresses.push(network.ip());
+                }
+            }
+            (Section::Interface, "dns") => {
+                for server in split_list(value) {
+                    let server: IpAddr = server.parse().map_err(|_| {
+                        invalid(format!("DNS search domains are not supported: {}", server))
+                    })?;
+                    dns_servers.push(server);
+                }
+            }
+            (Section::Interface, "mtu") => {
+                let value = value
+                    .parse::<u16>()
+                    .map_err(|_| invalid(format!("Invalid MTU: {}", value)))?;
+                set_once(&mut mtu, value, "MTU").map_err(invalid)?;
+            }
+            (Section::Interface, _) => {
+                return Err(invalid(format!(
+                    "`{}` is not supported in the [Interface] section",
+                    key
+                )))
+            }
+            (Section::Peer, "publickey") => {
+                let key = wireguard::PublicKey::from_base64(value)
+                    .map_err(|_| invalid("Invalid public key".to_string()))?;
+                set_once(&mut public_key, key, "PublicKey").map_err(invalid)?;
+            }
+            (Section::Peer, "presharedkey") => {
+                let key = wireguard::PresharedKey::from_base64(value)
+                    .map_err(|_| invalid("Invalid preshared key".to_string()))?;
+                set_once(&mut psk, key, "PresharedKey").map_err(invalid)?;
+            }
+            (Section::Peer, "allowedips") => {
+                for network in split_list(value) {
+                    allowed_ips.push(
+                        network
+                            .parse::<IpNetwork>()
+                            .map_err(|_| invalid(format!("Invalid allowed IP: {}", network)))?,
+                    );
+                }
+            }
+            (Section::Peer, "endpoint") => {
+                let value = parse_endpoint(value)
+                    .ok_or_else(|| invalid(format!("Invalid endpoint: {}", value)))?;
+                set_once(&mut endpoint, value, "Endpoint").map_err(invalid)?;
+            }
+            (Section::Peer, _) => {
+                return Err(invalid(format!(
+                    "`{}` is not supported in the [Peer] section",
+                    key
+                )))
+            }
+        }
+    }
+
+    let missing = |what: &str| ConfigImportError::Incomplete(format!("Missing {}", what));
+    if !has_interface {
+        return Err(missing("[Interface] section"));
+    }
+    if !has_peer {
+        return Err(missing("[Peer] section"));
+    }
+    let private_key = private_key.ok_or_else(|| missing("PrivateKey"))?;
+    if addresses.is_empty() {
+        return Err(missing("Address"));
+    }
+    let public_key = public_key.ok_or_else(|| missing("PublicKey"))?;
+    if allowed_ips.is_empty() {
+        return Err(missing("AllowedIPs"));
+    }
+    let (host, port) = endpoint.ok_or_else(|| missing("Endpoint"))?;
+    let ipv4_gateway = dns_servers
+        .iter()
+        .find_map(|server| match server {
+            IpAddr::V4(server) => Some(*server),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| {
+            ConfigImportError::Incomplete(
+                "An IPv4 DNS server is required, since it is used as the tunnel gateway"
+                    .to_string(),
+            )
+        })?;
+    let ipv6_gateway = dns_servers.iter().find_map(|server| match server {
+        IpAddr::V4(_) => None,
+        IpAddr::V6(server) => Some(*server),
+    });
+
+    Ok(CustomTunnelEndpoint {
+        host,
+        config: ConnectionConfig::Wireguard(wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key,
+                addresses,
+            },
+            peer: wireguard::PeerConfig {
+                public_key,
+                allowed_ips,
+                // The address is resolved from the host when connecting
+                endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+                protocol: TransportProtocol::Udp,
+                psk,
+            },
+            exit_peer: None,
+            ipv4_gateway,
+            ipv6_gateway,
+        }),
+        mtu,
+    })
+}
+
+/// Formats a connection config as a `wg-quick` config file. The exit peer of multihop configs
+/// cannot be expressed in a single `wg-quick` config, so only single hop configs should be given.
+pub fn format(
+    config: &wireguard::ConnectionConfig,
+    dns_servers: &[IpAddr],
+    mtu: Option<u16>,
+) -> String {
+    let mut output = String::new();
+    let join = |items: Vec<String>| items.join(", ");
+
+    // Writing to a `String` cannot fail
+    let _ = writeln!(output, "[Interface]");
+    let _ = writeln!(
+        output,
+        "PrivateKey = {}",
+        config.tunnel.private_key.to_base64()
+    );
+    let _ = writeln!(
+        output,
+        "Address = {}",
+        join(
+            config
+                .tunnel
+                .addresses
+                .iter()
+                .map(|address| IpNetwork::from(*address).to_string())
+                .collect()
+        )
+    );
+    if !dns_servers.is_empty() {
+        let _ = writeln!(
+            output,
+            "DNS = {}",
+            join(dns_servers.iter().map(IpAddr::to_string).collect())
+        );
+    }
+    if let Some(mtu) = mtu {
+        let _ = writeln!(output, "MTU = {}", mtu);
+    }
+
+    let _ = writeln!(output, "\n[Peer]");
+    let _ = writeln!(output, "PublicKey = {}", config.peer.public_key.to_base64());
+    if let Some(psk) = &config.peer.psk {
+        let _ = writeln!(output, "PresharedKey = {}", psk.to_base64());
+    }
+    let _ = writeln!(
+        output,
+        "AllowedIPs = {}",
+        join(
+            config
+                .peer
+                .allowed_ips
+                .iter()
+                .map(IpNetwork::to_string)
+                .collect()
+        )
+    );
+    let _ = writeln!(output, "Endpoint = {}", config.peer.endpoint);
+
+    output
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T, key: &str) -> Result<(), String> {
+    if slot.is_some() {
+        return Err(format!("Duplicate `{}`", key));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Splits an endpoint into a host and a port. IPv6 addresses must be enclosed in brackets.
+fn parse_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    if let Ok(address) = endpoint.parse::<SocketAddr>() {
+        return Some((address.ip().to_string(), address.port()));
+    }
+    let mut parts = endpoint.rsplitn(2, ':');
+    let port = parts.next()?.parse().ok()?;
+    let host = parts.next()?;
+    if host.is_empty() || host.contains(':') {
+        return None;
+    }
+    Some((host.to_string(), port))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PRIVATE_KEY: &str = "mPYT8gKU6Ld9UlT0tYvSFD2OGmq+l+qtHmkS7W4gqXQ=";
+    const PUBLIC_KEY: &str = "MKj6pVPcDdvXw5fQhW4Kn4NBVH0xzuLBfIBTo6HJmTg=";
+    const PSK: &str = "FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=";
+
+    fn config(peer_extra: &str) -> String {
+        format!(
+            "[Interface]\n\
+             # Device: example\n\
+             PrivateKey = {}\n\
+             Address = 10.66.12.34/32, fc00:bbbb:bbbb:bb01::3:c21/128\n\
+             DNS = 10.64.0.1, fc00:bbbb:bbbb:bb01::1\n\
+             MTU = 1280\n\
+             \n\
+             [Peer]\n\
+             PublicKey = {}\n\
+             AllowedIPs = 0.0.0.0/0, ::0/0\n\
+             Endpoint = se-got-wg-001.example.net:51820\n\
+             {}",
+            PRIVATE_KEY, PUBLIC_KEY, peer_extra
+        )
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let endpoint = parse(&config(&format!("PresharedKey = {}\n", PSK))).unwrap();
+        assert_eq!(endpoint.host, "se-got-wg-001.example.net");
+        assert_eq!(endpoint.mtu, Some(1280));
+
+        let connection = match endpoint.config {
+            ConnectionConfig::Wireguard(connection) => connection,
+            ConnectionConfig::OpenVpn(_) => panic!("unexpected OpenVPN config"),
+        };
+        assert_eq!(
+            connection.tunnel.private_key,
+            wireguard::PrivateKey::from_base64(PRIVATE_KEY).unwrap()
+        );
+        assert_eq!(
+            connection.tunnel.addresses,
+            vec![
+                "10.66.12.34".parse::<IpAddr>().unwrap(),
+                "fc00:bbbb:bbbb:bb01::3:c21".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            connection.peer.public_key,
+            wireguard::PublicKey::from_base64(PUBLIC_KEY).unwrap()
+        );
+        assert_eq!(
+            connection.peer.psk,
+            Some(wireguard::PresharedKey::from_base64(PSK).unwrap())
+        );
+        assert_eq!(connection.peer.allowed_ips.len(), 2);
+        assert_eq!(connection.peer.endpoint.port(), 51820);
+        assert_eq!(connection.ipv4_gateway, Ipv4Addr::new(10, 64, 0, 1));
+        assert_eq!(
+            connection.ipv6_gateway,
+            Some("fc00:bbbb:bbbb:bb01::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_unsupported_key() {
+        assert_eq!(
+            parse(&config("PersistentKeepalive = 25\n")),
+            Err(ConfigImportError::InvalidLine(
+                12,
+                "`PersistentKeepalive` is not supported in the [Peer] section".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_multiple_peers() {
+        let config = config(&format!("\n[Peer]\nPublicKey = {}\n", PUBLIC_KEY));
+        assert_eq!(
+            parse(&config),
+            Err(ConfigImportError::InvalidLine(
+                13,
+                "Only one [Peer] section is supported".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_missing_ipv4_dns() {
+        let config = config("").replace("DNS = 10.64.0.1, ", "DNS = ");
+        assert!(matches!(
+            parse(&config),
+            Err(ConfigImportError::Incomplete(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_config() {
+        let endpoint = parse(&config(&format!("PresharedKey = {}\n", PSK))).unwrap();
+        let mut connection = match &endpoint.config {
+            ConnectionConfig::Wireguard(connection) => connection.clone(),
+            ConnectionConfig::OpenVpn(_) => panic!("unexpected OpenVPN config"),
+        };
+        let dns_servers = [
+            IpAddr::V4(connection.ipv4_gateway),
+            connection.ipv6_gateway.unwrap().into(),
+        ];
+        let port = connection.peer.endpoint.port();
+        connection.peer.endpoint = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), port);
+
+        let formatted = format(&connection, &dns_servers, endpoint.mtu);
+        assert_eq!(
+            formatted.lines().nth(2),
+            Some("Address = 10.66.12.34/32, fc00:bbbb:bbbb:bb01::3:c21/128")
+        );
+
+        let parsed = parse(&formatted).unwrap();
+        assert_eq!(parsed.host, "192.0.2.1");
+        assert_eq!(parsed.mtu, endpoint.mtu);
+        assert_eq!(parsed.config, endpoint.config);
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            parse_endpoint("[2001:db8::1]:51820"),
+            Some(("2001:db8::1".to_string(), 51820))
+        );
+        assert_eq!(
+            parse_endpoint("192.0.2.1:51820"),
+            Some(("192.0.2.1".to_string(), 51820))
+        );
+        assert_eq!(
+            parse_endpoint("example.net:51820"),
+            Some(("example.net".to_string(), 51820))
+        );
+        assert_eq!(parse_endpoint("2001:db8::1:51820"), None);
+        assert_eq!(parse_endpoint("example.net"), None);
+    }
+}
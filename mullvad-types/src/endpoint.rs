@@ -3,7 +3,7 @@ use std::{
     fmt,
     net::{Ipv4Addr, Ipv6Addr},
 };
-use talpid_types::net::{wireguard, Endpoint, TransportProtocol};
+use talpid_types::net::{wireguard, Endpoint, TransportProtocol, TunnelType};
 
 use crate::relay_list::{OpenVpnEndpointData, WireguardEndpointData};
 
@@ -32,6 +32,14 @@ impl MullvadEndpoint {
             ),
         }
     }
+
+    /// Returns the tunnel protocol used by this endpoint.
+    pub fn tunnel_type(&self) -> TunnelType {
+        match self {
+            MullvadEndpoint::OpenVpn(_) => TunnelType::OpenVpn,
+            MullvadEndpoint::Wireguard { .. } => TunnelType::Wireguard,
+        }
+    }
 }
 /// TunnelEndpointData contains data required to connect to a given tunnel endpoint.
 /// Different endpoint types can require different types of data.
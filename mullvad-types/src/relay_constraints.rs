@@ -608,6 +608,25 @@ impl RelaySettingsUpdate {
     }
 }
 
+impl From<RelaySettings> for RelaySettingsUpdate {
+    fn from(settings: RelaySettings) -> Self {
+        match settings {
+            RelaySettings::CustomTunnelEndpoint(endpoint) => {
+                RelaySettingsUpdate::CustomTunnelEndpoint(endpoint)
+            }
+            RelaySettings::Normal(constraints) => {
+                RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                    location: Some(constraints.location),
+                    providers: Some(constraints.providers),
+                    tunnel_protocol: Some(constraints.tunnel_protocol),
+                    wireguard_constraints: Some(constraints.wireguard_constraints),
+                    openvpn_constraints: Some(constraints.openvpn_constraints),
+                })
+            }
+        }
+    }
+}
+
 /// Used in [`RelaySettings`] to change relay constraints in the daemon.
 #[derive(Debug, Default, Deserialize, Serialize)]
 #[cfg_attr(target_os = "android", derive(FromJava))]
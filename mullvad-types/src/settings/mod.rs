@@ -10,13 +10,22 @@ use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::net::IpAddr;
 #[cfg(target_os = "windows")]
-use std::{collections::HashSet, path::PathBuf};
+use std::collections::HashSet;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
 use talpid_types::net::{self, openvpn, GenericTunnelOptions};
 
 mod migrations;
 
+/// Port of the metrics endpoint, on the loopback interface, unless another address is configured.
+pub const DEFAULT_METRICS_PORT: u16 = 9101;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(err_derive::Error, Debug)]
@@ -30,6 +39,18 @@ pub enum Error {
 
     #[error(display = "Unable to read any version of the settings")]
     NoMatchingVersion,
+
+    #[error(display = "Invalid settings: {}", _0)]
+    InvalidValue(&'static str),
+
+    #[error(display = "Invalid key rotation policy")]
+    InvalidRotationPolicy(#[error(source)] wireguard::RotationPolicyError),
+
+    #[error(display = "Invalid custom allow rule")]
+    InvalidAllowRule(#[error(source)] net::InvalidAllowRule),
+
+    #[error(display = "Invalid connectivity check options")]
+    InvalidConnectivityCheck(#[error(source)] net::wireguard::InvalidConnectivityCheckOptions),
 }
 
 
@@ -42,6 +63,9 @@ pub struct Settings {
     account_token: Option<String>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     wireguard: Option<wireguard::WireguardData>,
+    /// Additional WireGuard keys of the account, sorted by name.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    wireguard_devices: Vec<wireguard::WireguardDevice>,
     relay_settings: RelaySettings,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub bridge_settings: BridgeSettings,
@@ -63,6 +87,18 @@ pub struct Settings {
     /// Split tunneling settings
     #[cfg(windows)]
     pub split_tunnel: SplitTunnelSettings,
+    /// Custom rules for traffic that the firewall allows in every tunnel state.
+    #[cfg(target_os = "linux")]
+    pub allow_rules: Vec<AllowRule>,
+    /// Users whose traffic is excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    pub excluded_uids: Vec<u32>,
+    /// Whether the traffic blocked by the firewall is counted and logged.
+    #[cfg(target_os = "linux")]
+    pub audit_blocked_traffic: bool,
+    /// Local endpoint exporting metrics of the daemon.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub metrics: MetricsSettings,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     settings_version: migrations::SettingsVersion,
@@ -82,6 +118,7 @@ impl Default for Settings {
         Settings {
             account_token: None,
             wireguard: None,
+            wireguard_devices: vec![],
             relay_settings: RelaySettings::Normal(RelayConstraints {
                 location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
                 ..Default::default()
@@ -95,6 +132,13 @@ impl Default for Settings {
             show_beta_releases: false,
             #[cfg(windows)]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(target_os = "linux")]
+            allow_rules: vec![],
+            #[cfg(target_os = "linux")]
+            excluded_uids: vec![],
+            #[cfg(target_os = "linux")]
+            audit_blocked_traffic: false,
+            metrics: MetricsSettings::default(),
             settings_version: migrations::CURRENT_SETTINGS_VERSION,
         }
     }
@@ -152,6 +196,52 @@ impl Settings {
         }
     }
 
+    pub fn get_wireguard_devices(&self) -> &[wireguard::WireguardDevice] {
+        &self.wireguard_devices
+    }
+
+    pub fn get_wireguard_device(&self, name: &str) -> Option<&wireguard::WireguardDevice> {
+        self.wireguard_devices
+            .iter()
+            .find(|device| device.name == name)
+    }
+
+    pub fn set_wireguard_devices(&mut self, mut devices: Vec<wireguard::WireguardDevice>) -> bool {
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        if devices != self.wireguard_devices {
+            self.wireguard_devices = devices;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds `device`, or replaces the device with the same name.
+    pub fn set_wireguard_device(&mut self, device: wireguard::WireguardDevice) -> bool {
+        match self
+            .wireguard_devices
+            .binary_search_by(|existing| existing.name.cmp(&device.name))
+        {
+            Ok(index) if self.wireguard_devices[index] == device => false,
+            Ok(index) => {
+                self.wireguard_devices[index] = device;
+                true
+            }
+            Err(index) => {
+                self.wireguard_devices.insert(index, device);
+                true
+            }
+        }
+    }
+
+    pub fn remove_wireguard_device(&mut self, name: &str) -> Option<wireguard::WireguardDevice> {
+        let index = self
+            .wireguard_devices
+            .iter()
+            .position(|device| device.name == name)?;
+        Some(self.wireguard_devices.remove(index))
+    }
+
     pub fn get_relay_settings(&self) -> RelaySettings {
         self.relay_settings.clone()
     }
@@ -175,6 +265,40 @@ impl Settings {
         }
     }
 
+    /// Checks the values that can't be enforced by their types. Settings that were not set through
+    /// the management interface, e.g. imported ones, must be validated before they are applied.
+    pub fn validate(&self) -> Result<()> {
+        self.metrics.listen_address.validate()?;
+
+        self.tunnel_options
+            .wireguard
+            .options
+            .connectivity_check
+            .validate()
+            .map_err(Error::InvalidConnectivityCheck)?;
+
+        #[cfg(target_os = "linux")]
+        for allow_rule in &self.allow_rules {
+            allow_rule.validate().map_err(Error::InvalidAllowRule)?;
+        }
+
+        self.tunnel_options
+            .wireguard
+            .rotation_policy
+            .validate()
+            .map_err(Error::InvalidRotationPolicy)
+    }
+
+    /// Returns a copy of the settings without the account token and the WireGuard keys.
+    pub fn without_secrets(&self) -> Self {
+        Settings {
+            account_token: None,
+            wireguard: None,
+            wireguard_devices: vec![],
+            ..self.clone()
+        }
+    }
+
     pub fn get_bridge_state(&self) -> BridgeState {
         self.bridge_state
     }
@@ -192,6 +316,105 @@ impl Settings {
     }
 }
 
+/// Settings for the local HTTP endpoint that exports metrics in the Prometheus text format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Whether the endpoint is served.
+    pub enabled: bool,
+    /// Where the endpoint listens for scrapes.
+    pub listen_address: MetricsListenAddress,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            enabled: false,
+            listen_address: MetricsListenAddress::Localhost(DEFAULT_METRICS_PORT),
+        }
+    }
+}
+
+/// Address of the metrics endpoint. It can never be reached from other hosts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricsListenAddress {
+    /// A TCP port on the loopback interface.
+    Localhost(u16),
+    /// A Unix domain socket with the given file name in the cache directory of the daemon. Not
+    /// supported on Windows.
+    UnixSocket(PathBuf),
+}
+
+impl MetricsListenAddress {
+    /// Checks that the port is nonzero and that the socket name is a plain file name, so that the
+    /// socket can't be placed outside of the cache directory.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            MetricsListenAddress::Localhost(0) => Err(Error::InvalidValue("invalid metrics port")),
+            MetricsListenAddress::UnixSocket(name) if !is_file_name(name) => Err(
+                Error::InvalidValue("the metrics socket name must be a plain file name"),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the socket address of a TCP endpoint.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            MetricsListenAddress::Localhost(port) => {
+                Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), *port))
+            }
+            MetricsListenAddress::UnixSocket(_) => None,
+        }
+    }
+}
+
+fn is_file_name(path: &Path) -> bool {
+    let mut components = path.components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    )
+}
+
+impl fmt::Display for MetricsListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsListenAddress::Localhost(port) => {
+                write!(
+                    f,
+                    "{}",
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), *port)
+                )
+            }
+            MetricsListenAddress::UnixSocket(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Source of the key used to encrypt the secrets in the settings file at rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretsKeySource {
+    /// A random key stored next to the settings and bound to this machine.
+    KeyFile,
+    /// A key provisioned in the kernel keyring of the daemon user.
+    KernelKeyring,
+    /// A key derived from a passphrase, supplied through the management interface.
+    Passphrase,
+}
+
+/// Describes whether, and how, the secrets in the settings file are encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettingsEncryptionState {
+    /// The key source used for encryption, or `None` if the secrets are stored in plain text.
+    pub key_source: Option<SecretsKeySource>,
+    /// Whether the secrets are encrypted but could not yet be decrypted, e.g. because a
+    /// passphrase has to be supplied.
+    pub locked: bool,
+}
+
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(default)]
@@ -297,6 +520,7 @@ impl Default for TunnelOptions {
             wireguard: wireguard::TunnelOptions {
                 options: net::wireguard::TunnelOptions::default(),
                 rotation_interval: None,
+                rotation_policy: wireguard::RotationPolicy::default(),
             },
             generic: GenericTunnelOptions {
                 // Enable IPv6 be default on Android
@@ -362,4 +586,139 @@ mod test {
 
         let _ = Settings::load_from_bytes(settings).unwrap();
     }
+
+    fn device(name: &str) -> wireguard::WireguardDevice {
+        wireguard::WireguardDevice {
+            name: name.to_string(),
+            data: wireguard::WireguardData {
+                private_key: talpid_types::net::wireguard::PrivateKey::new_from_random(),
+                addresses: wireguard::AssociatedAddresses {
+                    ipv4_address: "10.64.0.2/32".parse().unwrap(),
+                    ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+                },
+                created: chrono::Utc::now(),
+                usage: Default::default(),
+            },
+            rotation_interval: None,
+        }
+    }
+
+    fn device_names(settings: &Settings) -> Vec<&str> {
+        settings
+            .get_wireguard_devices()
+            .iter()
+            .map(|device| device.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Settings::default().validate().is_ok());
+
+        let mut settings = Settings::default();
+        settings.metrics.listen_address = MetricsListenAddress::Localhost(0);
+        assert!(settings.validate().is_err());
+
+        let mut settings = Settings::default();
+        settings.metrics.listen_address = MetricsListenAddress::UnixSocket("metrics.sock".into());
+        assert!(settings.validate().is_ok());
+        for name in [
+            "/run/metrics.sock",
+            "../metrics.sock",
+            "metrics/metrics.sock",
+            "",
+        ] {
+            settings.metrics.listen_address = MetricsListenAddress::UnixSocket(name.into());
+            assert!(settings.validate().is_err(), "{}", name);
+        }
+
+        let mut settings = Settings::default();
+        settings
+            .tunnel_options
+            .wireguard
+            .options
+            .connectivity_check
+            .probe_timeout = std::time::Duration::ZERO;
+        assert!(settings.validate().is_err());
+
+        let mut settings = Settings::default();
+        settings
+            .tunnel_options
+            .wireguard
+            .options
+            .connectivity_check
+            .probe_mode = net::wireguard::ProbeMode::Http(0);
+        assert!(settings.validate().is_err());
+
+        #[cfg(target_os = "linux")]
+        {
+            let mut settings = Settings::default();
+            settings.allow_rules.push(AllowRule {
+                direction: net::AllowRuleDirection::Inbound,
+                network: "192.168.1.0/24".parse().unwrap(),
+                port: None,
+                protocol: None,
+                cgroup: None,
+                uid: Some(1000),
+            });
+            assert!(matches!(
+                settings.validate(),
+                Err(Error::InvalidAllowRule(net::InvalidAllowRule::InboundOwner))
+            ));
+        }
+
+        let mut settings = Settings::default();
+        settings
+            .tunnel_options
+            .wireguard
+            .rotation_policy
+            .max_relay_switches = Some(0);
+        assert!(matches!(
+            settings.validate(),
+            Err(Error::InvalidRotationPolicy(
+                wireguard::RotationPolicyError::NoRelaySwitches
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_set_wireguard_devices() {
+        let mut settings = Settings::default();
+        let (a, b, c) = (device("a"), device("b"), device("c"));
+
+        assert!(settings.set_wireguard_devices(vec![c.clone(), a.clone(), b.clone()]));
+        assert_eq!(device_names(&settings), ["a", "b", "c"]);
+
+        // The same devices in a different order are not a change
+        assert!(!settings.set_wireguard_devices(vec![b, c, a]));
+        assert_eq!(device_names(&settings), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_set_and_remove_wireguard_device() {
+        let mut settings = Settings::default();
+
+        assert!(settings.set_wireguard_device(device("phone")));
+        assert!(settings.set_wireguard_device(device("desktop")));
+        assert!(settings.set_wireguard_device(device("laptop")));
+        assert_eq!(device_names(&settings), ["desktop", "laptop", "phone"]);
+
+        let laptop = settings.get_wireguard_device("laptop").unwrap().clone();
+        assert!(!settings.set_wireguard_device(laptop.clone()));
+
+        let replacement = device("laptop");
+        assert!(settings.set_wireguard_device(replacement.clone()));
+        assert_eq!(device_names(&settings), ["desktop", "laptop", "phone"]);
+        assert_eq!(settings.get_wireguard_device("laptop"), Some(&replacement));
+
+        assert_eq!(
+            settings.remove_wireguard_device("laptop"),
+            Some(replacement)
+        );
+        assert_eq!(settings.remove_wireguard_device("laptop"), None);
+        assert_eq!(device_names(&settings), ["desktop", "phone"]);
+
+        assert!(settings.set_wireguard_device(laptop));
+        assert_eq!(device_names(&settings), ["desktop", "laptop", "phone"]);
+    }
 }
@@ -1,4 +1,4 @@
-use chrono::{offset::Utc, DateTime};
+use chrono::{offset::Utc, DateTime, NaiveTime, TimeZone};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -13,6 +13,12 @@ pub const DEFAULT_ROTATION_INTERVAL: Duration = if cfg!(target_os = "android") {
     Duration::from_secs(7 * 24 * 60 * 60)
 };
 
+/// Smallest amount of data, in bytes, that may be transferred before a key is rotated.
+pub const MIN_ROTATION_BYTES: u64 = 1_000_000_000;
+
+/// Format of the time of day in a rotation policy, as presented to users.
+const ROTATION_TIME_FORMAT: &str = "%H:%M";
+
 /// Contains account specific wireguard data
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct WireguardData {
@@ -20,6 +26,9 @@ pub struct WireguardData {
     pub addresses: AssociatedAddresses,
     #[serde(default = "Utc::now")]
     pub created: DateTime<Utc>,
+    /// How much the key has been used since it was created
+    #[serde(default)]
+    pub usage: KeyUsage,
 }
 
 impl WireguardData {
@@ -32,6 +41,33 @@ impl WireguardData {
     }
 }
 
+/// Maximum length of the name of a WireGuard device. This is the maximum length of network
+/// interface names, so that exported configs can be used with `wg-quick` as they are.
+pub const MAX_DEVICE_NAME_LENGTH: usize = 15;
+
+/// An additional WireGuard key on the account, used by containers or virtual machines on this
+/// machine rather than by the tunnel of the app.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WireguardDevice {
+    pub name: String,
+    pub data: WireguardData,
+    /// Rotation interval of the device key. The interval of the tunnel key is used if unset.
+    #[serde(default)]
+    pub rotation_interval: Option<RotationInterval>,
+}
+
+impl WireguardDevice {
+    /// Returns whether `name` may be used as a device name. Names consist of ASCII letters,
+    /// digits, `-` and `_`.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_DEVICE_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RotationIntervalError {
     TooSmall,
@@ -113,6 +149,118 @@ impl Default for RotationInterval {
     }
 }
 
+/// Counters describing how much a key has been used since it was created.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct KeyUsage {
+    /// Number of bytes sent and received through tunnels using the key.
+    pub bytes_transferred: u64,
+    /// Number of times a tunnel using the key has connected to a different relay than the last
+    /// one.
+    pub relay_switches: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum RotationPolicyError {
+    DataLimitTooSmall,
+    NoRelaySwitches,
+    InvalidTimeOfDay,
+}
+
+impl fmt::Display for RotationPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RotationPolicyError::*;
+
+        match *self {
+            DataLimitTooSmall => write!(
+                f,
+                "Data limit must be at least {} GB",
+                MIN_ROTATION_BYTES / 1_000_000_000
+            ),
+            NoRelaySwitches => write!(f, "Relay switch limit must be at least 1"),
+            InvalidTimeOfDay => write!(f, "Time of day must be given as HH:MM"),
+        }
+    }
+}
+
+impl std::error::Error for RotationPolicyError {}
+
+/// Conditions that trigger automatic key rotation in addition to the rotation interval. The key
+/// is rotated as soon as any of the conditions is met.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationPolicy {
+    /// Rotate the key once this many bytes have been transferred using it.
+    pub max_bytes: Option<u64>,
+    /// Rotate the key once a tunnel using it has switched relays this many times.
+    pub max_relay_switches: Option<u32>,
+    /// Rotate the key every day at this time. The time is in the local time zone.
+    pub time_of_day: Option<NaiveTime>,
+}
+
+impl RotationPolicy {
+    pub fn validate(&self) -> Result<(), RotationPolicyError> {
+        if self.max_bytes.map(|bytes| bytes < MIN_ROTATION_BYTES) == Some(true) {
+            return Err(RotationPolicyError::DataLimitTooSmall);
+        }
+        if self.max_relay_switches == Some(0) {
+            return Err(RotationPolicyError::NoRelaySwitches);
+        }
+        Ok(())
+    }
+
+    /// Parses a time of day formatted as HH:MM.
+    pub fn parse_time_of_day(time: &str) -> Result<NaiveTime, RotationPolicyError> {
+        NaiveTime::parse_from_str(time, ROTATION_TIME_FORMAT)
+            .map_err(|_| RotationPolicyError::InvalidTimeOfDay)
+    }
+
+    /// Returns the time of day formatted as HH:MM, if it is set.
+    pub fn format_time_of_day(&self) -> Option<String> {
+        self.time_of_day
+            .map(|time| time.format(ROTATION_TIME_FORMAT).to_string())
+    }
+
+    /// Returns whether a key created at `created` and used as described by `usage` should be
+    /// rotated at `now`.
+    pub fn is_due<Tz: TimeZone>(
+        &self,
+        created: DateTime<Utc>,
+        usage: &KeyUsage,
+        now: DateTime<Tz>,
+    ) -> bool {
+        if let Some(max_bytes) = self.max_bytes {
+            if usage.bytes_transferred >= max_bytes {
+                return true;
+            }
+        }
+        if let Some(max_relay_switches) = self.max_relay_switches {
+            if usage.relay_switches >= max_relay_switches {
+                return true;
+            }
+        }
+        match self.time_of_day {
+            Some(time_of_day) => Self::last_occurrence(time_of_day, now)
+                .map(|rotation_time| rotation_time.with_timezone(&Utc) > created)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Returns the most recent time, not after `now`, when the clock showed `time_of_day`. Days
+    /// where that time is skipped because of daylight saving time have no occurrence.
+    fn last_occurrence<Tz: TimeZone>(
+        time_of_day: NaiveTime,
+        now: DateTime<Tz>,
+    ) -> Option<DateTime<Tz>> {
+        let today = now.date();
+        match today.and_time(time_of_day) {
+            Some(occurrence) if occurrence <= now => Some(occurrence),
+            _ => today.pred().and_time(time_of_day),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(
@@ -125,6 +273,10 @@ pub struct TunnelOptions {
     /// Interval used for automatic key rotation
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub rotation_interval: Option<RotationInterval>,
+    /// Conditions besides the interval that trigger automatic key rotation
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub rotation_policy: RotationPolicy,
 }
 
 /// Represents a published public key
@@ -166,3 +318,121 @@ impl fmt::Display for KeygenEvent {
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn time_of_day_policy(hour: u32, minute: u32) -> RotationPolicy {
+        RotationPolicy {
+            time_of_day: Some(NaiveTime::from_hms(hour, minute, 0)),
+            ..RotationPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_default_policy_is_never_due() {
+        let usage = KeyUsage {
+            bytes_transferred: u64::MAX,
+            relay_switches: u32::MAX,
+        };
+        let created = Utc.ymd(2021, 3, 1).and_hms(0, 0, 0);
+        assert!(!RotationPolicy::default().is_due(created, &usage, Utc::now()));
+    }
+
+    #[test]
+    fn test_usage_limits() {
+        let policy = RotationPolicy {
+            max_bytes: Some(2 * MIN_ROTATION_BYTES),
+            max_relay_switches: Some(3),
+            time_of_day: None,
+        };
+        let created = Utc::now();
+        let mut usage = KeyUsage {
+            bytes_transferred: 2 * MIN_ROTATION_BYTES - 1,
+            relay_switches: 2,
+        };
+        assert!(!policy.is_due(created, &usage, created));
+
+        usage.bytes_transferred += 1;
+        assert!(policy.is_due(created, &usage, created));
+
+        usage.bytes_transferred = 0;
+        usage.relay_switches += 1;
+        assert!(policy.is_due(created, &usage, created));
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let policy = time_of_day_policy(3, 0);
+        let timezone = FixedOffset::east(2 * 60 * 60);
+        let usage = KeyUsage::default();
+
+        let created = timezone.ymd(2021, 3, 1).and_hms(2, 0, 0);
+        assert!(!policy.is_due(
+            created.with_timezone(&Utc),
+            &usage,
+            timezone.ymd(2021, 3, 1).and_hms(2, 59, 0)
+        ));
+        assert!(policy.is_due(
+            created.with_timezone(&Utc),
+            &usage,
+            timezone.ymd(2021, 3, 1).and_hms(3, 0, 0)
+        ));
+
+        let created = timezone.ymd(2021, 3, 1).and_hms(3, 30, 0);
+        assert!(!policy.is_due(
+            created.with_timezone(&Utc),
+            &usage,
+            timezone.ymd(2021, 3, 2).and_hms(1, 0, 0)
+        ));
+        assert!(policy.is_due(
+            created.with_timezone(&Utc),
+            &usage,
+            timezone.ymd(2021, 3, 2).and_hms(4, 0, 0)
+        ));
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        assert!(RotationPolicy::default().validate().is_ok());
+        assert!(RotationPolicy {
+            max_bytes: Some(MIN_ROTATION_BYTES - 1),
+            ..RotationPolicy::default()
+        }
+        .validate()
+        .is_err());
+        assert!(RotationPolicy {
+            max_relay_switches: Some(0),
+            ..RotationPolicy::default()
+        }
+        .validate()
+        .is_err());
+        assert!(time_of_day_policy(23, 59).validate().is_ok());
+    }
+
+    #[test]
+    fn test_time_of_day_format() {
+        assert_eq!(
+            RotationPolicy::parse_time_of_day("03:30").unwrap(),
+            NaiveTime::from_hms(3, 30, 0)
+        );
+        assert!(RotationPolicy::parse_time_of_day("24:00").is_err());
+        assert!(RotationPolicy::parse_time_of_day("3 am").is_err());
+        assert_eq!(
+            time_of_day_policy(3, 5).format_time_of_day(),
+            Some("03:05".to_string())
+        );
+    }
+
+    #[test]
+    fn test_device_names() {
+        assert!(WireguardDevice::is_valid_name("vm-1"));
+        assert!(WireguardDevice::is_valid_name("build_container"));
+        assert!(!WireguardDevice::is_valid_name(""));
+        assert!(!WireguardDevice::is_valid_name("container/1"));
+        assert!(!WireguardDevice::is_valid_name("a-very-long-name"));
+    }
+}
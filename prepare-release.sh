@@ -75,6 +75,7 @@ git commit -S -m "Updating version in package files" \
     mullvad-problem-report/Cargo.toml \
     mullvad-setup/Cargo.toml \
     mullvad-exclude/Cargo.toml \
+    mullvad-netns/Cargo.toml \
     talpid-openvpn-plugin/Cargo.toml \
     Cargo.lock \
     android/build.gradle \
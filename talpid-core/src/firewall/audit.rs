@@ -0,0 +1,706 @@
+//! Auditing of the traffic that is blocked by the firewall while the tunnel is not connected.
+//!
+//! When enabled, the rules of the blocking policies send every packet they drop to an NFLOG
+//! group. [`BlockedTrafficMonitor`] reads the packets from the group, counts them, keeps the most
+//! recent ones and writes them to a log file.
+
+use super::Result;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::SystemTime,
+};
+use talpid_types::ErrorExt;
+
+/// The NFLOG group that blocked packets are sent to.
+pub const NFLOG_GROUP: u16 = 3047;
+/// Log prefix of packets dropped in the input chain.
+pub const PREFIX_IN: &str = "mullvad-in";
+/// Log prefix of packets dropped in the output chain.
+pub const PREFIX_OUT: &str = "mullvad-out";
+/// Log prefix of packets dropped in the forward chain.
+pub const PREFIX_FORWARD: &str = "mullvad-fwd";
+
+const LOG_FILENAME: &str = "blocked-traffic.log";
+/// The log file is rotated when it grows larger than this.
+const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+/// Number of recent packets that are kept in memory.
+const MAX_SAMPLES: usize = 100;
+/// Number of bytes of each packet that are copied to userspace. Enough for the IP and transport
+/// headers.
+const COPY_RANGE: u32 = 64;
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+const NFNL_SUBSYS_ULOG: u16 = 4;
+const NFULNL_MSG_PACKET: u16 = 0;
+const NFULNL_MSG_CONFIG: u16 = 1;
+const NFULA_CFG_CMD: u16 = 1;
+const NFULA_CFG_MODE: u16 = 2;
+const NFULA_CFG_FLAGS: u16 = 6;
+const NFULNL_CFG_CMD_BIND: u8 = 1;
+const NFULNL_COPY_PACKET: u8 = 2;
+/// Makes the kernel include the owner of the socket in the packet messages.
+const NFULNL_CFG_F_UID_GID: u16 = 0x0002;
+const NFULA_PAYLOAD: u16 = 9;
+const NFULA_PREFIX: u16 = 10;
+const NFULA_UID: u16 = 11;
+const NFULA_GID: u16 = 14;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const NLMSG_HDRLEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+
+/// The chain in which a packet was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockedDirection {
+    Inbound,
+    Outbound,
+    Forward,
+}
+
+/// A packet that was dropped by the firewall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedPacket {
+    pub time: SystemTime,
+    pub direction: BlockedDirection,
+    /// The transport layer protocol (`IPPROTO_*`).
+    pub protocol: u8,
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    /// Owner of the socket that sent the packet. Only known for locally generated packets.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl fmt::Display for BlockedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            BlockedDirection::Inbound => "inbound",
+            BlockedDirection::Outbound => "outbound",
+            BlockedDirection::Forward => "forward",
+        };
+        let protocol = match i32::from(self.protocol) {
+            libc::IPPROTO_TCP => "TCP".to_owned(),
+            libc::IPPROTO_UDP => "UDP".to_owned(),
+            libc::IPPROTO_ICMP => "ICMP".to_owned(),
+            libc::IPPROTO_ICMPV6 => "ICMPv6".to_owned(),
+            protocol => format!("protocol {}", protocol),
+        };
+        write!(
+            f,
+            "{} {} {} -> {}",
+            direction,
+            protocol,
+            SocketDisplay(self.source, self.source_port),
+            SocketDisplay(self.destination, self.destination_port)
+        )?;
+        if let Some(uid) = self.uid {
+            write!(f, " uid {}", uid)?;
+        }
+        if let Some(gid) = self.gid {
+            write!(f, " gid {}", gid)?;
+        }
+        Ok(())
+    }
+}
+
+struct SocketDisplay(IpAddr, Option<u16>);
+
+impl fmt::Display for SocketDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (IpAddr::V4(ip), Some(port)) => write!(f, "{}:{}", ip, port),
+            (IpAddr::V6(ip), Some(port)) => write!(f, "[{}]:{}", ip, port),
+            (ip, None) => write!(f, "{}", ip),
+        }
+    }
+}
+
+/// Counters and samples of the traffic blocked since the firewall was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedTrafficStats {
+    /// Whether blocked traffic is audited. All other fields are empty if it's not.
+    pub enabled: bool,
+    /// When the auditing started.
+    pub since: Option<SystemTime>,
+    pub dropped_packets: u64,
+    /// Number of times the kernel ran out of buffer space for the audit, so that an unknown
+    /// number of dropped packets were not counted.
+    pub overruns: u64,
+    /// The most recently dropped packets, oldest first.
+    pub samples: Vec<BlockedPacket>,
+}
+
+impl BlockedTrafficStats {
+    /// Stats for when blocked traffic is not audited.
+    pub fn disabled() -> Self {
+        BlockedTrafficStats {
+            enabled: false,
+            since: None,
+            dropped_packets: 0,
+            overruns: 0,
+            samples: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stats {
+    since: SystemTime,
+    dropped_packets: u64,
+    overruns: u64,
+    samples: VecDeque<BlockedPacket>,
+}
+
+/// Receives the packets that are dropped by the blocking policies and keeps statistics about
+/// them. The monitor stops when dropped.
+pub struct BlockedTrafficMonitor {
+    stats: Arc<Mutex<Stats>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl BlockedTrafficMonitor {
+    /// Starts listening to the NFLOG group. Blocked packets are logged to a file in `log_dir`, if
+    /// set.
+    pub fn start(log_dir: Option<&Path>) -> Result<Self> {
+        let socket = NflogSocket::open(NFLOG_GROUP).map_err(super::Error::AuditError)?;
+        let log = log_dir.and_then(|dir| match AuditLog::open(dir.join(LOG_FILENAME)) {
+            Ok(log) => Some(log),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to open the blocked traffic log")
+                );
+                None
+            }
+        });
+
+        let stats = Arc::new(Mutex::new(Stats {
+            since: SystemTime::now(),
+            dropped_packets: 0,
+            overruns: 0,
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_stats = stats.clone();
+        let thread_stop = stop.clone();
+        thread::Builder::new()
+            .name("blocked-traffic-monitor".to_owned())
+            .spawn(move || Self::run(socket, log, thread_stats, thread_stop))
+            .map_err(super::Error::AuditError)?;
+
+        Ok(BlockedTrafficMonitor { stats, stop })
+    }
+
+    pub fn stats(&self) -> BlockedTrafficStats {
+        let stats = self.stats.lock().unwrap();
+        BlockedTrafficStats {
+            enabled: true,
+            since: Some(stats.since),
+            dropped_packets: stats.dropped_packets,
+            overruns: stats.overruns,
+            samples: stats.samples.iter().cloned().collect(),
+        }
+    }
+
+    fn run(
+        socket: NflogSocket,
+        mut log: Option<AuditLog>,
+        stats: Arc<Mutex<Stats>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let len = match socket.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut
+                        || error.kind() == io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                Err(error) if error.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // Messages were lost. The exact number is unknown.
+                    log::warn!("The blocked traffic audit could not keep up with the kernel");
+                    stats.lock().unwrap().overruns += 1;
+                    continue;
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to receive blocked packets")
+                    );
+                    return;
+                }
+            };
+
+            let packets = parse_messages(&buffer[..len], SystemTime::now());
+            if let Some(log) = &mut log {
+                for packet in &packets {
+                    log.write(packet);
+                }
+            }
+            let mut stats = stats.lock().unwrap();
+            for packet in packets {
+                stats.dropped_packets += 1;
+                if stats.samples.len() == MAX_SAMPLES {
+                    stats.samples.pop_front();
+                }
+                stats.samples.push_back(packet);
+            }
+        }
+    }
+}
+
+impl Drop for BlockedTrafficMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Log file of blocked packets, which is rotated when it grows too large.
+struct AuditLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Opens the log, appending to it if it already exists.
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog { path, file, size })
+    }
+
+    /// Moves the log aside and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        crate::logging::rotate_log(&self.path)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+
+    fn write(&mut self, packet: &BlockedPacket) {
+        if self.size > MAX_LOG_SIZE {
+            if let Err(error) = self.rotate() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to rotate the blocked traffic log")
+                );
+            }
+        }
+        let time = chrono::DateTime::<chrono::Local>::from(packet.time);
+        let line = format!("[{}] {}\n", time.format("%Y-%m-%d %H:%M:%S%.3f"), packet);
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+/// A netlink socket that is bound to an NFLOG group.
+struct NflogSocket {
+    fd: RawFd,
+}
+
+impl NflogSocket {
+    fn open(group: u16) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = NflogSocket { fd };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        if unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Wake up regularly, so that the monitor can be stopped.
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        if unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        socket.configure(group, NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND])?;
+        let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+        mode.extend_from_slice(&[NFULNL_COPY_PACKET, 0]);
+        socket.configure(group, NFULA_CFG_MODE, &mode)?;
+        socket.configure(group, NFULA_CFG_FLAGS, &NFULNL_CFG_F_UID_GID.to_be_bytes())?;
+        Ok(socket)
+    }
+
+    /// Sends a config message with a single attribute for the group, and waits for the ack.
+    fn configure(&self, group: u16, attr_type: u16, attr: &[u8]) -> io::Result<()> {
+        let message = config_message(group, attr_type, attr);
+        if unsafe {
+            libc::send(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buffer = [0u8; 1024];
+        let len = self.recv(&mut buffer)?;
+        let buffer = &buffer[..len];
+        if len < NLMSG_HDRLEN + 4 || read_u16(buffer, 4) != libc::NLMSG_ERROR as u16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected netlink response",
+            ));
+        }
+        let errno = i32::from_ne_bytes([buffer[16], buffer[17], buffer[18], buffer[19]]);
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(-errno));
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let len = unsafe {
+            libc::recv(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+}
+
+impl Drop for NflogSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn config_message(group: u16, attr_type: u16, attr: &[u8]) -> Vec<u8> {
+    let attr_len = 4 + attr.len();
+    let len = NLMSG_HDRLEN + NFGENMSG_LEN + align(attr_len);
+    let mut message = Vec::with_capacity(len);
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&((NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_CONFIG).to_ne_bytes());
+    message.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16).to_ne_bytes());
+    // Sequence number and port ID
+    message.extend_from_slice(&[0; 8]);
+    // nfgenmsg: family, version and group (resource ID)
+    message.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    message.extend_from_slice(&group.to_be_bytes());
+    message.extend_from_slice(&(attr_len as u16).to_ne_bytes());
+    message.extend_from_slice(&attr_type.to_ne_bytes());
+    message.extend_from_slice(attr);
+    message.resize(len, 0);
+    message
+}
+
+/// Parses the packets in a buffer of NFLOG netlink messages. Messages that aren't packets from
+/// the firewall are ignored.
+fn parse_messages(buffer: &[u8], time: SystemTime) -> Vec<BlockedPacket> {
+    let mut packets = vec![];
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= buffer.len() {
+        let len = read_u32(buffer, offset) as usize;
+        if len < NLMSG_HDRLEN || offset + len > buffer.len() {
+            break;
+        }
+        if read_u16(buffer, offset + 4) == (NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_PACKET {
+            let message = &buffer[offset + NLMSG_HDRLEN..offset + len];
+            if let Some(packet) = parse_packet_message(message, time) {
+                packets.push(packet);
+            }
+        }
+        offset += align(len);
+    }
+    packets
+}
+
+fn parse_packet_message(message: &[u8], time: SystemTime) -> Option<BlockedPacket> {
+    let mut direction = None;
+    let mut payload = None;
+    let mut uid = None;
+    let mut gid = None;
+
+    let mut offset = NFGENMSG_LEN;
+    while offset + 4 <= message.len() {
+        let attr_len = read_u16(message, offset) as usize;
+        if attr_len < 4 || offset + attr_len > message.len() {
+            break;
+        }
+        let data = &message[offset + 4..offset + attr_len];
+        match read_u16(message, offset + 2) & NLA_TYPE_MASK {
+            NFULA_PREFIX => {
+                let prefix = data.split(|byte| *byte == 0).next().unwrap_or_default();
+                direction = match prefix {
+                    prefix if prefix == PREFIX_IN.as_bytes() => Some(BlockedDirection::Inbound),
+                    prefix if prefix == PREFIX_OUT.as_bytes() => Some(BlockedDirection::Outbound),
+                    prefix if prefix == PREFIX_FORWARD.as_bytes() => {
+                        Some(BlockedDirection::Forward)
+                    }
+                    _ => None,
+                };
+            }
+            NFULA_PAYLOAD => payload = Some(data),
+            NFULA_UID if data.len() >= 4 => uid = Some(read_be_u32(data)),
+            NFULA_GID if data.len() >= 4 => gid = Some(read_be_u32(data)),
+            _ => (),
+        }
+        offset += align(attr_len);
+    }
+
+    let (protocol, source, destination, transport) = parse_ip_header(payload?)?;
+    let ports =
+        if i32::from(protocol) == libc::IPPROTO_TCP || i32::from(protocol) == libc::IPPROTO_UDP {
+            if transport.len() >= 4 {
+                Some((
+                    u16::from_be_bytes([transport[0], transport[1]]),
+                    u16::from_be_bytes([transport[2], transport[3]]),
+                ))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+    Some(BlockedPacket {
+        time,
+        direction: direction?,
+        protocol,
+        source,
+        destination,
+        source_port: ports.map(|(source_port, _)| source_port),
+        destination_port: ports.map(|(_, destination_port)| destination_port),
+        uid,
+        gid,
+    })
+}
+
+/// Returns the protocol, the addresses and the transport header of an IPv4 or IPv6 packet.
+fn parse_ip_header(packet: &[u8]) -> Option<(u8, IpAddr, IpAddr, &[u8])> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+            let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+            Some((
+                packet[9],
+                IpAddr::V4(source),
+                IpAddr::V4(destination),
+                packet.get(header_len..).unwrap_or_default(),
+            ))
+        }
+        6 if packet.len() >= 40 => {
+            let mut source = [0u8; 16];
+            source.copy_from_slice(&packet[8..24]);
+            let mut destination = [0u8; 16];
+            destination.copy_from_slice(&packet[24..40]);
+            // Extension headers are not followed, so their transport headers are unknown.
+            Some((
+                packet[6],
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                &packet[40..],
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+fn read_be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attr(attr_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut attr = Vec::new();
+        attr.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        attr.extend_from_slice(&attr_type.to_ne_bytes());
+        attr.extend_from_slice(data);
+        attr.resize(align(attr.len()), 0);
+        attr
+    }
+
+    fn packet_message(attrs: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = vec![libc::AF_INET as u8, 0, 0, 0];
+        for attr in attrs {
+            body.extend_from_slice(attr);
+        }
+        let mut message = Vec::new();
+        message.extend_from_slice(&((NLMSG_HDRLEN + body.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&((NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_PACKET).to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&body);
+        message
+    }
+
+    fn udp_packet() -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = libc::IPPROTO_UDP as u8;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        packet[16..20].copy_from_slice(&[8, 8, 8, 8]);
+        packet[20..22].copy_from_slice(&5353u16.to_be_bytes());
+        packet[22..24].copy_from_slice(&53u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_parse_blocked_packet() {
+        let time = SystemTime::now();
+        let mut buffer = packet_message(&[
+            attr(NFULA_PREFIX, b"mullvad-out\0"),
+            attr(NFULA_UID, &1000u32.to_be_bytes()),
+            attr(NFULA_PAYLOAD, &udp_packet()),
+        ]);
+        // Packets that weren't logged by the firewall are ignored
+        buffer.extend(packet_message(&[
+            attr(NFULA_PREFIX, b"other\0"),
+            attr(NFULA_PAYLOAD, &udp_packet()),
+        ]));
+
+        let packets = parse_messages(&buffer, time);
+        assert_eq!(
+            packets,
+            vec![BlockedPacket {
+                time,
+                direction: BlockedDirection::Outbound,
+                protocol: libc::IPPROTO_UDP as u8,
+                source: "10.0.0.2".parse().unwrap(),
+                destination: "8.8.8.8".parse().unwrap(),
+                source_port: Some(5353),
+                destination_port: Some(53),
+                uid: Some(1000),
+                gid: None,
+            }]
+        );
+        assert_eq!(
+            packets[0].to_string(),
+            "outbound UDP 10.0.0.2:5353 -> 8.8.8.8:53 uid 1000"
+        );
+    }
+
+    #[test]
+    fn test_config_message() {
+        let message = config_message(NFLOG_GROUP, NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND]);
+        assert_eq!(message.len(), 28);
+        assert_eq!(read_u32(&message, 0), 28);
+        assert_eq!(read_u16(&message, 4), 0x0401);
+        assert_eq!(&message[18..20], &NFLOG_GROUP.to_be_bytes());
+        assert_eq!(read_u16(&message, 20), 5);
+        assert_eq!(read_u16(&message, 22), NFULA_CFG_CMD);
+        assert_eq!(message[24], NFULNL_CFG_CMD_BIND);
+    }
+
+    fn blocked_packet() -> BlockedPacket {
+        BlockedPacket {
+            time: SystemTime::now(),
+            direction: BlockedDirection::Outbound,
+            protocol: libc::IPPROTO_UDP as u8,
+            source: "10.0.0.2".parse().unwrap(),
+            destination: "8.8.8.8".parse().unwrap(),
+            source_port: Some(5353),
+            destination_port: Some(53),
+            uid: None,
+            gid: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_log_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOG_FILENAME);
+        let backup = path.with_extension("old.log");
+        fs::write(&path, "previous\n").unwrap();
+
+        // The log is appended to when it is reopened
+        let mut log = AuditLog::open(path.clone()).unwrap();
+        assert_eq!(log.size, 9);
+        log.write(&blocked_packet());
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("previous\n"));
+        assert!(contents.ends_with("outbound UDP 10.0.0.2:5353 -> 8.8.8.8:53\n"));
+        assert_eq!(log.size, contents.len() as u64);
+        assert!(!backup.exists());
+
+        // It is only rotated once it grows too large
+        log.size = MAX_LOG_SIZE + 1;
+        log.write(&blocked_packet());
+        assert_eq!(fs::read_to_string(&backup).unwrap(), contents);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert_eq!(log.size, contents.len() as u64);
+    }
+}
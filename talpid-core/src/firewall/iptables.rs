@@ -0,0 +1,766 @@
+//! Fallback backend for hosts where nftables can't be used. The rules generated by
+//! [`PolicyRules`] are translated into iptables and ip6tables chains, which are installed with
+//! `iptables-restore`. The legacy variants of the tools are preferred, since the nftables based
+//! variants are unlikely to work when nftables doesn't.
+
+use super::{
+    apply_kernel_config, audit, Backend, ChainId, Direction, End, Error, Expr, FirewallPolicy,
+    PolicyRules, Result, RuleSpec, Verdict,
+};
+use std::{fmt::Write, net::IpAddr, path::PathBuf};
+
+/// Installs the rules in chains owned by the firewall, which are jumped to from the built-in
+/// chains.
+pub struct IptablesBackend;
+
+impl Backend for IptablesBackend {
+    fn name(&self) -> &'static str {
+        "iptables"
+    }
+
+    fn apply_policy(&mut self, policy: &FirewallPolicy, rules: &PolicyRules) -> Result<()> {
+        for family in &Family::ALL {
+            let binaries = Binaries::find(*family)?;
+            run(
+                &binaries.restore,
+                &["--noflush"],
+                Some(restore_script(rules, *family)),
+            )?;
+            for (table, builtin_chain, chain) in family.chains() {
+                // The jump is inserted first in the built-in chain, unless it's already there.
+                let check = iptables_args(table, "-C", builtin_chain, &["-j", chain]);
+                if run(&binaries.iptables, &check, None).is_err() {
+                    let insert = iptables_args(table, "-I", builtin_chain, &["1", "-j", chain]);
+                    run(&binaries.iptables, &insert, None)?;
+                }
+            }
+        }
+        apply_kernel_config(policy);
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        for family in &Family::ALL {
+            let binaries = match Binaries::find(*family) {
+                Ok(binaries) => binaries,
+                // Nothing can have been installed without the tools
+                Err(_) => continue,
+            };
+            log::debug!("Removing {} chains", family.iptables_name());
+            remove_chains(*family, |args| run(&binaries.iptables, args, None))?;
+        }
+        Ok(())
+    }
+
+    fn installed_rules(&self) -> Result<String> {
+        let mut rules = String::new();
+        for family in &Family::ALL {
+            let binaries = Binaries::find(*family)?;
+            for (table, _, chain) in family.chains() {
+                let _ = writeln!(
+                    rules,
+                    "# {} -t {} -S {}",
+                    family.iptables_name(),
+                    table,
+                    chain
+                );
+                match run(
+                    &binaries.iptables,
+                    &iptables_args(table, "-S", chain, &[]),
+                    None,
+                ) {
+                    Ok(output) => rules.push_str(&output),
+                    Err(Error::IptablesError(_, message)) => {
+                        let _ = writeln!(rules, "# {}", message);
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    const ALL: [Family; 2] = [Family::V4, Family::V6];
+
+    fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+
+    fn iptables_name(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables",
+            Family::V6 => "ip6tables",
+        }
+    }
+
+    /// The chains of `PolicyRules` that are installed for this family. Chains in the inet table
+    /// are installed for both.
+    fn includes(self, chain: ChainId) -> bool {
+        match chain {
+            ChainId::MangleV4 | ChainId::NatV4 => self == Family::V4,
+            ChainId::MangleV6 | ChainId::NatV6 => self == Family::V6,
+            ChainId::Prerouting | ChainId::Out | ChainId::In | ChainId::Forward => true,
+        }
+    }
+
+    /// The table, built-in chain and chain owned by the firewall for every chain installed for
+    /// this family.
+    fn chains(self) -> impl Iterator<Item = (&'static str, &'static str, &'static str)> {
+        ChainId::ALL
+            .iter()
+            .filter(move |chain| self.includes(**chain))
+            .map(|chain| iptables_chain(*chain))
+    }
+}
+
+/// Returns the table, the built-in chain to jump from and the name of the chain that corresponds
+/// to `chain`.
+fn iptables_chain(chain: ChainId) -> (&'static str, &'static str, &'static str) {
+    match chain {
+        ChainId::Prerouting => ("mangle", "PREROUTING", "mullvad-prerouting"),
+        ChainId::Out => ("filter", "OUTPUT", "mullvad-output"),
+        ChainId::In => ("filter", "INPUT", "mullvad-input"),
+        ChainId::Forward => ("filter", "FORWARD", "mullvad-forward"),
+        ChainId::MangleV4 | ChainId::MangleV6 => ("mangle", "OUTPUT", "mullvad-mangle"),
+        ChainId::NatV4 | ChainId::NatV6 => ("nat", "POSTROUTING", "mullvad-nat"),
+    }
+}
+
+/// Removes the chains owned by the firewall, and every jump to them, for `family`. `iptables` runs
+/// iptables with the given arguments. Chains that don't exist are skipped.
+fn remove_chains(
+    family: Family,
+    mut iptables: impl FnMut(&[&str]) -> Result<String>,
+) -> Result<()> {
+    for (table, builtin_chain, chain) in family.chains() {
+        if iptables(&iptables_args(table, "-S", chain, &[])).is_err() {
+            continue;
+        }
+        let delete_jump = iptables_args(table, "-D", builtin_chain, &["-j", chain]);
+        while iptables(&delete_jump).is_ok() {}
+        iptables(&iptables_args(table, "-F", chain, &[]))?;
+        iptables(&iptables_args(table, "-X", chain, &[]))?;
+    }
+    Ok(())
+}
+
+/// Returns the input to `iptables-restore --noflush` that replaces the contents of the chains
+/// owned by the firewall with `rules`.
+fn restore_script(rules: &PolicyRules, family: Family) -> String {
+    let mut script = String::new();
+    for table in &["filter", "mangle", "nat"] {
+        let chains: Vec<ChainId> = ChainId::ALL
+            .iter()
+            .cloned()
+            .filter(|chain| family.includes(*chain) && iptables_chain(*chain).0 == *table)
+            .collect();
+
+        let _ = writeln!(script, "*{}", table);
+        // Declaring a chain creates it, or flushes it if it already exists
+        for chain in &chains {
+            let _ = writeln!(script, ":{} - [0:0]", iptables_chain(*chain).2);
+        }
+        for chain in &chains {
+            let name = iptables_chain(*chain).2;
+            for spec in rules.chain_rules(*chain) {
+                for rule in translate_rule(spec, family) {
+                    let _ = writeln!(script, "-A {} {}", name, rule);
+                }
+            }
+            // User defined chains don't have a policy, so it's enforced by a final rule.
+            if let Some(nftnl::Policy::Drop) = chain.policy() {
+                let _ = writeln!(script, "-A {} -j DROP", name);
+            }
+        }
+        let _ = writeln!(script, "COMMIT");
+    }
+    script
+}
+
+/// Translates a rule into iptables rule specifications. A rule with several statements becomes
+/// one rule per statement, since iptables rules only have a single target. Rules that can't
+/// match any packet of `family` are left out.
+fn translate_rule(spec: &RuleSpec, family: Family) -> Vec<String> {
+    let mut matches: Vec<String> = vec![];
+    let mut targets: Vec<String> = vec![];
+    let mut protocol = None;
+
+    for (i, expr) in spec.exprs.iter().enumerate() {
+        match expr {
+            Expr::Iface {
+                direction,
+                iface,
+                equal,
+            } => {
+                if !equal {
+                    matches.push("!".to_owned());
+                }
+                let flag = match direction {
+                    Direction::In => "-i",
+                    Direction::Out => "-o",
+                };
+                matches.push(format!("{} {}", flag, iface));
+            }
+            Expr::L3Proto(proto) => {
+                let proto_family = if *proto == libc::NFPROTO_IPV4 as u8 {
+                    Family::V4
+                } else {
+                    Family::V6
+                };
+                if proto_family != family {
+                    return vec![];
+                }
+            }
+            Expr::L4Proto(proto) => {
+                if protocol == Some(*proto) {
+                    continue;
+                }
+                let name = match i32::from(*proto) {
+                    libc::IPPROTO_UDP => "udp",
+                    libc::IPPROTO_TCP => "tcp",
+                    libc::IPPROTO_ICMPV6 if family == Family::V6 => "ipv6-icmp",
+                    _ => return vec![],
+                };
+                protocol = Some(*proto);
+                matches.push(format!("-p {}", name));
+            }
+            Expr::Addr { end, ip } => {
+                if Family::of(*ip) != family {
+                    return vec![];
+                }
+                matches.push(format!("{} {}", addr_flag(*end), ip));
+            }
+            Expr::Net { end, net } => {
+                if Family::of(net.ip()) != family {
+                    return vec![];
+                }
+                matches.push(format!("{} {}", addr_flag(*end), net));
+            }
+            Expr::Port { end, port, .. } => {
+                let flag = match end {
+                    End::Src => "--sport",
+                    End::Dst => "--dport",
+                };
+                matches.push(format!("{} {}", flag, port));
+            }
+            Expr::Icmpv6Type(icmp_type) => match spec.exprs.get(i + 1) {
+                Some(Expr::Icmpv6Code(code)) => {
+                    matches.push(format!("--icmpv6-type {}/{}", icmp_type, code))
+                }
+                _ => matches.push(format!("--icmpv6-type {}", icmp_type)),
+            },
+            // Matched together with the type
+            Expr::Icmpv6Code(_) => (),
+            Expr::CtStateEstablished => {
+                matches.push("-m conntrack --ctstate ESTABLISHED".to_owned())
+            }
+            Expr::CtMark(mark) => matches.push(format!("-m connmark --mark {:#x}", mark)),
+            Expr::MetaMark(mark) => matches.push(format!("-m mark --mark {:#x}", mark)),
+            Expr::Cgroup(classid) => matches.push(format!("-m cgroup --cgroup {}", classid)),
+            Expr::SkUid(uid) => matches.push(format!("-m owner --uid-owner {}", uid)),
+            Expr::SetCtMark(mark) => targets.push(format!("-j CONNMARK --set-mark {:#x}", mark)),
+            Expr::SetMetaMark(mark) => targets.push(format!("-j MARK --set-mark {:#x}", mark)),
+            Expr::Masquerade => targets.push("-j MASQUERADE".to_owned()),
+            // iptables counts packets for every rule
+            Expr::Counter => (),
+            Expr::Log(prefix) => targets.push(format!(
+                "-j NFLOG --nflog-group {} --nflog-prefix {}",
+                audit::NFLOG_GROUP,
+                prefix
+            )),
+            Expr::Verdict(verdict) => targets.push(verdict_target(*verdict, family).to_owned()),
+        }
+    }
+
+    let matches = matches.join(" ");
+    if targets.is_empty() {
+        return vec![matches];
+    }
+    targets
+        .into_iter()
+        .map(|target| {
+            if matches.is_empty() {
+                target
+            } else {
+                format!("{} {}", matches, target)
+            }
+        })
+        .collect()
+}
+
+fn addr_flag(end: End) -> &'static str {
+    match end {
+        End::Src => "-s",
+        End::Dst => "-d",
+    }
+}
+
+fn verdict_target(verdict: Verdict, family: Family) -> &'static str {
+    match (verdict, family) {
+        (Verdict::Accept, _) => "-j ACCEPT",
+        (Verdict::Drop, _) => "-j DROP",
+        (Verdict::Reject, Family::V4) => "-j REJECT --reject-with icmp-port-unreachable",
+        (Verdict::Reject, Family::V6) => "-j REJECT --reject-with icmp6-port-unreachable",
+        (Verdict::RejectTcpRst, _) => "-j REJECT --reject-with tcp-reset",
+    }
+}
+
+fn iptables_args<'a>(
+    table: &'a str,
+    command: &'a str,
+    chain: &'a str,
+    rest: &[&'a str],
+) -> Vec<&'a str> {
+    let mut args = vec!["-w", "-t", table, command, chain];
+    args.extend_from_slice(rest);
+    args
+}
+
+struct Binaries {
+    iptables: PathBuf,
+    restore: PathBuf,
+}
+
+impl Binaries {
+    /// Finds `iptables` and `iptables-restore` for the family, preferring the legacy variants.
+    fn find(family: Family) -> Result<Self> {
+        let candidates: &[(&str, &str)] = match family {
+            Family::V4 => &[
+                ("iptables-legacy", "iptables-legacy-restore"),
+                ("iptables", "iptables-restore"),
+            ],
+            Family::V6 => &[
+                ("ip6tables-legacy", "ip6tables-legacy-restore"),
+                ("ip6tables", "ip6tables-restore"),
+            ],
+        };
+        candidates
+            .iter()
+            .find_map(|(iptables, restore)| {
+                Some(Binaries {
+                    iptables: which::which(iptables).ok()?,
+                    restore: which::which(restore).ok()?,
+                })
+            })
+            .ok_or(Error::IptablesNotFound(family.iptables_name()))
+    }
+}
+
+/// Runs the command and returns its output. Fails if the command exits with an error.
+fn run(binary: &PathBuf, args: &[&str], stdin: Option<String>) -> Result<String> {
+    let command = format!("{} {}", binary.display(), args.join(" "));
+    let mut expression = duct::cmd(binary, args)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked();
+    if let Some(stdin) = stdin {
+        expression = expression.stdin_bytes(stdin);
+    }
+    let output = expression
+        .run()
+        .map_err(|error| Error::RunIptablesError(command.clone(), error))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::IptablesError(
+            command,
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{super::test::all_policies, *};
+    use crate::tunnel::TunnelMetadata;
+    use std::{
+        collections::HashSet,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+    use talpid_types::net::{Endpoint, TransportProtocol};
+
+    /// Returns the rule specifications of the chain in the restore script.
+    fn chain_rules<'a>(script: &'a str, chain: &str) -> Vec<&'a str> {
+        let prefix = format!("-A {} ", chain);
+        script
+            .lines()
+            .filter_map(|line| line.strip_prefix(prefix.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_translate_connected() {
+        let policy = FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 51820, TransportProtocol::Udp),
+            tunnel: TunnelMetadata {
+                interface: "wg-mullvad".to_string(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+            },
+            allow_lan: false,
+            dns_servers: vec![
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+            ],
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+        let rules = PolicyRules::new(&policy);
+        let v4 = restore_script(&rules, Family::V4);
+        let v6 = restore_script(&rules, Family::V6);
+
+        assert_eq!(
+            chain_rules(&v4, "mullvad-output"),
+            vec![
+                "-o lo -j ACCEPT",
+                "-m connmark --mark 0xf41 -j ACCEPT",
+                "-p udp --sport 68 -d 255.255.255.255 --dport 67 -j ACCEPT",
+                "-d 5.6.7.8 -p udp --dport 51820 -m mark --mark 0x6d6f6c65 -j ACCEPT",
+                "! -o wg-mullvad -p udp --dport 53 -d 192.168.1.1 -j ACCEPT",
+                "-o wg-mullvad -p udp --dport 53 -d 10.64.0.1 -j ACCEPT",
+                "! -o wg-mullvad -p tcp --dport 53 -d 192.168.1.1 -j ACCEPT",
+                "-o wg-mullvad -p tcp --dport 53 -d 10.64.0.1 -j ACCEPT",
+                "-p udp --dport 53 -j REJECT --reject-with icmp-port-unreachable",
+                "-p tcp --dport 53 -j REJECT --reject-with tcp-reset",
+                "-o wg-mullvad -j ACCEPT",
+                "-j REJECT --reject-with icmp-port-unreachable",
+                "-j DROP",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&v6, "mullvad-output"),
+            vec![
+                "-o lo -j ACCEPT",
+                "-m connmark --mark 0xf41 -j ACCEPT",
+                "-s fe80::/10 -p udp --sport 546 -d ff02::1:2 --dport 547 -j ACCEPT",
+                "-s fe80::/10 -p udp --sport 546 -d ff05::1:3 --dport 547 -j ACCEPT",
+                "-d ff02::2 -p ipv6-icmp --icmpv6-type 133/0 -j ACCEPT",
+                "-p udp --dport 53 -j REJECT --reject-with icmp6-port-unreachable",
+                "-p tcp --dport 53 -j REJECT --reject-with tcp-reset",
+                "-o wg-mullvad -j ACCEPT",
+                "-j REJECT --reject-with icmp6-port-unreachable",
+                "-j DROP",
+            ]
+        );
+
+        // Rules with several statements are split up
+        assert_eq!(
+            chain_rules(&v4, "mullvad-mangle"),
+            vec![
+                "-m cgroup --cgroup 5087041 -j CONNMARK --set-mark 0xf41",
+                "-m cgroup --cgroup 5087041 -j MARK --set-mark 0x6d6f6c65",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&v4, "mullvad-prerouting"),
+            vec![
+                "! -i wg-mullvad -m connmark --mark 0xf41 -j MARK --set-mark 0x6d6f6c65",
+                "-s 5.6.7.8 -p udp --sport 51820 -j MARK --set-mark 0x6d6f6c65",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&v6, "mullvad-nat"),
+            vec![
+                "-o wg-mullvad -m connmark --mark 0xf41 -j DROP",
+                "! -o lo -m connmark --mark 0xf41 -j MASQUERADE",
+            ]
+        );
+
+        assert!(v4.starts_with(
+            "*filter\n:mullvad-output - [0:0]\n:mullvad-input - [0:0]\n:mullvad-forward - [0:0]\n"
+        ));
+        assert!(
+            v4.contains("COMMIT\n*mangle\n:mullvad-prerouting - [0:0]\n:mullvad-mangle - [0:0]\n")
+        );
+        assert!(v4.ends_with("COMMIT\n"));
+    }
+
+    /// Returns the IP addresses and networks matched by the rule specification.
+    fn matched_addrs(rule: &str) -> Vec<IpAddr> {
+        let words: Vec<&str> = rule.split(' ').collect();
+        words
+            .windows(2)
+            .filter(|pair| pair[0] == "-s" || pair[0] == "-d")
+            .map(|pair| pair[1].split('/').next().unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_all_policies() {
+        for policy in all_policies() {
+            for audit in &[false, true] {
+                let rules = PolicyRules::with_audit(&policy, *audit);
+                for family in &Family::ALL {
+                    let script = restore_script(&rules, *family);
+                    check_restore_script(&script, &rules, *family);
+                }
+            }
+        }
+    }
+
+    fn check_restore_script(script: &str, rules: &PolicyRules, family: Family) {
+        assert_eq!(script.matches("COMMIT\n").count(), 3);
+        for line in script.lines() {
+            assert!(
+                line.starts_with('*')
+                    || line.starts_with(':')
+                    || line.starts_with("-A ")
+                    || line == "COMMIT",
+                "unexpected line \"{}\"",
+                line
+            );
+        }
+
+        for chain in ChainId::ALL.iter().filter(|chain| family.includes(**chain)) {
+            let name = iptables_chain(*chain).2;
+            let script_rules = chain_rules(script, name);
+
+            // Every rule has exactly one target, or none
+            for rule in &script_rules {
+                assert!(rule.matches("-j ").count() <= 1, "\"{}\"", rule);
+                for ip in matched_addrs(rule) {
+                    assert_eq!(Family::of(ip), family, "\"{}\"", rule);
+                }
+            }
+
+            // Rules of the other family are left out, and rules with several statements are
+            // split up into one rule per statement
+            let translated: usize = rules
+                .chain_rules(*chain)
+                .map(|spec| translate_rule(spec, family).len())
+                .sum();
+
+            // The drop policy of the chain is emulated by a final rule
+            match chain.policy() {
+                Some(nftnl::Policy::Drop) => {
+                    assert_eq!(script_rules.last(), Some(&"-j DROP"));
+                    assert_eq!(script_rules.len(), translated + 1);
+                }
+                _ => {
+                    assert_ne!(script_rules.last(), Some(&"-j DROP"));
+                    assert_eq!(script_rules.len(), translated);
+                }
+            }
+        }
+
+        let out_rules = chain_rules(script, "mullvad-output");
+        assert_eq!(out_rules.first(), Some(&"-o lo -j ACCEPT"));
+        assert_eq!(
+            chain_rules(script, "mullvad-input").first(),
+            Some(&"-i lo -j ACCEPT")
+        );
+    }
+
+    #[test]
+    fn test_translate_multiple_targets() {
+        let spec = RuleSpec {
+            chain: ChainId::Out,
+            exprs: vec![
+                Expr::L4Proto(libc::IPPROTO_UDP as u8),
+                Expr::Port {
+                    protocol: TransportProtocol::Udp,
+                    end: End::Dst,
+                    port: 53,
+                },
+                Expr::Counter,
+                Expr::Log(audit::PREFIX_OUT),
+                Expr::Verdict(Verdict::Reject),
+            ],
+        };
+        let nflog = format!(
+            "-p udp --dport 53 -j NFLOG --nflog-group {} --nflog-prefix {}",
+            audit::NFLOG_GROUP,
+            audit::PREFIX_OUT
+        );
+        assert_eq!(
+            translate_rule(&spec, Family::V4),
+            vec![
+                nflog.clone(),
+                "-p udp --dport 53 -j REJECT --reject-with icmp-port-unreachable".to_owned(),
+            ]
+        );
+        assert_eq!(
+            translate_rule(&spec, Family::V6),
+            vec![
+                nflog,
+                "-p udp --dport 53 -j REJECT --reject-with icmp6-port-unreachable".to_owned(),
+            ]
+        );
+
+        // Rules for one family are left out of the other
+        let spec = RuleSpec {
+            chain: ChainId::Out,
+            exprs: vec![
+                Expr::L3Proto(libc::NFPROTO_IPV6 as u8),
+                Expr::Addr {
+                    end: End::Dst,
+                    ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                },
+                Expr::Verdict(Verdict::Accept),
+            ],
+        };
+        assert!(translate_rule(&spec, Family::V4).is_empty());
+        assert_eq!(translate_rule(&spec, Family::V6), vec!["-d ::1 -j ACCEPT"]);
+    }
+
+    #[test]
+    fn test_translate_audit() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+        let script = restore_script(&PolicyRules::with_audit(&policy, true), Family::V4);
+        let nflog = format!(
+            "-j NFLOG --nflog-group {} --nflog-prefix {}",
+            audit::NFLOG_GROUP,
+            audit::PREFIX_OUT
+        );
+        let out_rules = chain_rules(&script, "mullvad-output");
+        assert_eq!(
+            out_rules[out_rules.len() - 4..],
+            [
+                nflog.as_str(),
+                "-j REJECT --reject-with icmp-port-unreachable",
+                nflog.as_str(),
+                "-j DROP",
+            ]
+        );
+    }
+
+    /// Stands in for iptables, keeping track of the chains and jumps that exist. Chains can only
+    /// be deleted once they are empty and no longer referenced, like in iptables.
+    #[derive(Default)]
+    struct FakeIptables {
+        /// Existing chains, and whether they contain rules.
+        chains: Vec<(String, String, bool)>,
+        /// Jumps from built-in chains, as (table, built-in chain, chain).
+        jumps: Vec<(String, String, String)>,
+        commands: Vec<String>,
+    }
+
+    impl FakeIptables {
+        fn with_chains(family: Family) -> Self {
+            let mut fake = FakeIptables::default();
+            for (table, builtin_chain, chain) in family.chains() {
+                fake.chains.push((table.to_owned(), chain.to_owned(), true));
+                fake.jumps
+                    .push((table.to_owned(), builtin_chain.to_owned(), chain.to_owned()));
+            }
+            fake
+        }
+
+        fn run(&mut self, args: &[&str]) -> Result<String> {
+            self.commands.push(args.join(" "));
+            let fail = || {
+                Err(Error::IptablesError(
+                    args.join(" "),
+                    "No chain/target/match by that name.".to_owned(),
+                ))
+            };
+            let (table, command, chain) = (args[2], args[3], args[4]);
+            let chain_index = self
+                .chains
+                .iter()
+                .position(|(t, c, _)| t == table && c == chain);
+
+            match command {
+                "-S" => match chain_index {
+                    Some(_) => Ok(String::new()),
+                    None => fail(),
+                },
+                "-D" => {
+                    let jump = (table.to_owned(), chain.to_owned(), args[6].to_owned());
+                    match self.jumps.iter().position(|existing| *existing == jump) {
+                        Some(index) => {
+                            self.jumps.remove(index);
+                            Ok(String::new())
+                        }
+                        None => fail(),
+                    }
+                }
+                "-F" => match chain_index {
+                    Some(index) => {
+                        self.chains[index].2 = false;
+                        Ok(String::new())
+                    }
+                    None => fail(),
+                },
+                "-X" => {
+                    let referenced = self
+                        .jumps
+                        .iter()
+                        .any(|(t, _, target)| t == table && target == chain);
+                    match chain_index {
+                        Some(index) if !referenced && !self.chains[index].2 => {
+                            self.chains.remove(index);
+                            Ok(String::new())
+                        }
+                        _ => fail(),
+                    }
+                }
+                _ => panic!("unexpected command: {}", args.join(" ")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_chains() {
+        for family in &Family::ALL {
+            let mut fake = FakeIptables::with_chains(*family);
+            // Jumps may have been inserted more than once
+            fake.jumps.push((
+                "filter".to_owned(),
+                "OUTPUT".to_owned(),
+                "mullvad-output".to_owned(),
+            ));
+            // Chains that don't exist are skipped
+            fake.chains.retain(|(table, _, _)| table != "nat");
+            fake.jumps.retain(|(table, _, _)| table != "nat");
+
+            remove_chains(*family, |args| fake.run(args)).unwrap();
+            assert!(fake.chains.is_empty());
+            assert!(fake.jumps.is_empty());
+            assert!(!fake
+                .commands
+                .iter()
+                .any(|command| command.contains("-t nat -F")));
+
+            // Removing them again doesn't do anything
+            fake.commands.clear();
+            remove_chains(*family, |args| fake.run(args)).unwrap();
+            let listed: HashSet<&str> = fake
+                .commands
+                .iter()
+                .map(|command| command.split(' ').nth(3).unwrap())
+                .collect();
+            assert_eq!(listed, ["-S"].iter().cloned().collect());
+        }
+    }
+
+    #[test]
+    fn test_remove_chains_failure() {
+        let mut fake = FakeIptables::with_chains(Family::V4);
+        // A jump from a chain that isn't owned by the firewall prevents the deletion
+        fake.jumps.push((
+            "filter".to_owned(),
+            "custom".to_owned(),
+            "mullvad-output".to_owned(),
+        ));
+        assert!(remove_chains(Family::V4, |args| fake.run(args)).is_err());
+    }
+}
@@ -5,16 +5,27 @@ use lazy_static::lazy_static;
 use libc;
 use nftnl::{
     self,
-    expr::{self, IcmpCode, Payload, RejectionType, Verdict},
+    expr::{self, IcmpCode, Payload, RejectionType},
     nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
     env,
     ffi::{CStr, CString},
-    io,
+    fmt, io,
     net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
 };
-use talpid_types::net::{Endpoint, TransportProtocol};
+use talpid_types::{
+    net::{AllowRule, AllowRuleDirection, Endpoint, TransportProtocol},
+    ErrorExt,
+};
+
+#[path = "audit.rs"]
+mod audit;
+#[path = "iptables.rs"]
+mod iptables;
+
+pub use audit::{BlockedDirection, BlockedPacket, BlockedTrafficStats};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
@@ -53,6 +64,26 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// Unable to run `nft` to list the installed rules.
+    #[error(display = "Unable to run nft to list the installed firewall rules")]
+    ListRulesError(#[error(source)] io::Error),
+
+    /// Unable to find the iptables tools needed by the iptables backend.
+    #[error(display = "Unable to find {}", _0)]
+    IptablesNotFound(&'static str),
+
+    /// Unable to run an iptables tool.
+    #[error(display = "Unable to run \"{}\"", _0)]
+    RunIptablesError(String, #[error(source)] io::Error),
+
+    /// An iptables tool exited with an error.
+    #[error(display = "\"{}\" failed: {}", _0, _1)]
+    IptablesError(String, String),
+
+    /// Unable to start monitoring blocked traffic.
+    #[error(display = "Unable to start monitoring blocked traffic")]
+    AuditError(#[error(source)] io::Error),
 }
 
 lazy_static! {
@@ -96,39 +127,256 @@ enum End {
 }
 
 /// The Linux implementation for the firewall and DNS.
-pub struct Firewall(());
+pub struct Firewall {
+    backend: Box<dyn Backend>,
+    /// The backend that isn't used. It's switched to if `backend` turns out not to work on this
+    /// host, and it's reset together with `backend` in case an earlier instance of the firewall
+    /// used it.
+    inactive_backend: Option<Box<dyn Backend>>,
+    /// Whether `backend` has applied a policy successfully. Until then, any failure to talk to
+    /// netfilter makes the firewall fall back on `inactive_backend`.
+    backend_verified: bool,
+    /// The policy that was last applied successfully.
+    policy: Option<FirewallPolicy>,
+    /// Counts and logs blocked traffic, if enabled.
+    audit: Option<audit::BlockedTrafficMonitor>,
+    /// Directory to write the blocked traffic log to.
+    log_dir: Option<PathBuf>,
+}
 
-struct FirewallTables {
-    main: Table,
-    mangle_v4: Table,
-    mangle_v6: Table,
+/// The applied firewall policy together with its rules, for debugging purposes.
+#[derive(Debug)]
+pub struct FirewallRules {
+    /// Name of the backend that installs the rules.
+    pub backend: &'static str,
+    /// Description of the applied policy, or `None` if no policy is applied.
+    pub policy: Option<String>,
+    /// The rules generated for the applied policy, formatted as an nft ruleset.
+    pub intended_rules: Option<String>,
+    /// The rules that are currently installed in the tables owned by the firewall, as listed by
+    /// the backend.
+    pub installed_rules: Result<String>,
+}
+
+/// A packet filter that installs the rules generated for a firewall policy. The rules are
+/// generated by [`PolicyRules`] independently of the backend, so that they can be inspected
+/// without touching the system.
+trait Backend: Send {
+    fn name(&self) -> &'static str;
+
+    /// Replaces the contents of the tables owned by the firewall with `rules`, and applies any
+    /// other system configuration that `policy` requires.
+    fn apply_policy(&mut self, policy: &FirewallPolicy, rules: &PolicyRules) -> Result<()>;
+
+    /// Removes the tables owned by the firewall.
+    fn reset_policy(&mut self) -> Result<()>;
+
+    /// Lists the rules that are currently installed in the tables owned by the firewall.
+    fn installed_rules(&self) -> Result<String>;
 }
 
 impl FirewallT for Firewall {
     type Error = Error;
 
-    fn new(_args: FirewallArguments) -> Result<Self> {
-        Ok(Firewall(()))
+    fn new(args: FirewallArguments) -> Result<Self> {
+        let mut firewall = Self::with_backend(Box::new(NftablesBackend));
+        firewall.inactive_backend = Some(Box::new(iptables::IptablesBackend));
+        firewall.log_dir = args.log_dir;
+        // The firewall works without the audit, so failing to start it is not fatal here
+        if args.audit_blocked_traffic {
+            if let Err(error) = firewall.start_audit() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start auditing the blocked traffic")
+                );
+            }
+        }
+        Ok(firewall)
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
-        let tables = FirewallTables {
-            main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
-            mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
-            mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
-        };
-        let batch = PolicyBatch::new(&tables).finalize(&policy)?;
+        self.policy = None;
+        let rules = PolicyRules::with_audit(&policy, self.audit.is_some());
+        match self.backend.apply_policy(&policy, &rules) {
+            Err(error) if self.inactive_backend.is_some() && self.should_fall_back(&error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to apply the firewall policy using {}",
+                        self.backend.name()
+                    ))
+                );
+                self.switch_backend();
+                self.backend.apply_policy(&policy, &rules)?;
+            }
+            result => result?,
+        }
+        self.backend_verified = true;
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        self.policy = None;
+        if let Some(backend) = &mut self.inactive_backend {
+            if let Err(error) = backend.reset_policy() {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to reset the {} firewall backend",
+                        backend.name()
+                    ))
+                );
+            }
+        }
+        self.backend.reset_policy()
+    }
+}
+
+impl Firewall {
+    fn with_backend(backend: Box<dyn Backend>) -> Self {
+        Firewall {
+            backend,
+            inactive_backend: None,
+            backend_verified: false,
+            policy: None,
+            audit: None,
+            log_dir: None,
+        }
+    }
+
+    /// Switches to the inactive backend, after removing what the current backend installed.
+    fn switch_backend(&mut self) {
+        if let Some(backend) = self.inactive_backend.take() {
+            log::warn!(
+                "The {} firewall backend does not work on this host. Falling back on {}",
+                self.backend.name(),
+                backend.name()
+            );
+            if let Err(error) = self.backend.reset_policy() {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to reset the {} firewall backend",
+                        self.backend.name()
+                    ))
+                );
+            }
+            self.inactive_backend = Some(std::mem::replace(&mut self.backend, backend));
+            self.backend_verified = false;
+        }
+    }
+
+    /// Returns whether `error`, returned by the active backend, means that the backend doesn't
+    /// work on this host. Any netfilter error does until the backend has applied a policy.
+    fn should_fall_back(&self, error: &Error) -> bool {
+        match error {
+            Error::NetfilterTableNotSetError => true,
+            Error::NetlinkOpenError(_)
+            | Error::NetlinkSendError(_)
+            | Error::NetlinkRecvError(_)
+            | Error::ProcessNetlinkError(_) => !self.backend_verified,
+            _ => false,
+        }
+    }
+
+    /// Returns the rules that would be applied for the given policy, formatted as an nft
+    /// ruleset. Nothing is applied.
+    pub fn render_policy(policy: &FirewallPolicy) -> String {
+        PolicyRules::new(policy).to_string()
+    }
+
+    /// Starts or stops auditing the blocked traffic. The applied policy is reapplied, so that the
+    /// blocking rules start or stop passing dropped packets to the audit.
+    pub fn set_audit_blocked_traffic(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.audit.is_some() {
+            return Ok(());
+        }
+        if enabled {
+            self.start_audit()?;
+        } else {
+            log::info!("Disabling the blocked traffic audit");
+            self.audit = None;
+        }
+        match self.policy.clone() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts auditing the blocked traffic.
+    fn start_audit(&mut self) -> Result<()> {
+        self.audit = Some(audit::BlockedTrafficMonitor::start(
+            self.log_dir.as_deref(),
+        )?);
+        Ok(())
+    }
+
+    /// Returns the applied policy, the rules generated for it and the rules that are actually
+    /// installed.
+    pub fn rules(&self) -> FirewallRules {
+        FirewallRules {
+            backend: self.backend.name(),
+            policy: self.policy.as_ref().map(|policy| policy.to_string()),
+            intended_rules: self
+                .policy
+                .as_ref()
+                .map(|policy| PolicyRules::with_audit(policy, self.audit.is_some()).to_string()),
+            installed_rules: self.backend.installed_rules(),
+        }
+    }
+
+    /// Returns counters and samples of the blocked traffic.
+    pub fn blocked_traffic_stats(&self) -> BlockedTrafficStats {
+        self.audit
+            .as_ref()
+            .map(audit::BlockedTrafficMonitor::stats)
+            .unwrap_or_else(BlockedTrafficStats::disabled)
+    }
+}
+
+fn apply_kernel_config(policy: &FirewallPolicy) {
+    if *DONT_SET_SRC_VALID_MARK {
+        log::debug!("Not setting src_valid_mark");
+        return;
+    }
+
+    if let FirewallPolicy::Connecting { .. } = policy {
+        if let Err(err) = crate::linux::set_src_valid_mark_sysctl() {
+            log::error!("Failed to apply src_valid_mark: {}", err);
+        }
+    }
+}
+
+/// Installs the rules in nftables over netlink.
+struct NftablesBackend;
+
+impl Backend for NftablesBackend {
+    fn name(&self) -> &'static str {
+        "nftables"
+    }
+
+    fn apply_policy(&mut self, policy: &FirewallPolicy, rules: &PolicyRules) -> Result<()> {
+        let batch = rules.to_batch()?;
         self.send_and_process(&batch)?;
-        Self::apply_kernel_config(&policy);
+        apply_kernel_config(policy);
         self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
+            .map_err(|error| {
+                if !matches!(error, Error::NetfilterTableNotSetError) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to list the netfilter tables")
+                    );
+                }
+                Error::NetfilterTableNotSetError
+            })
     }
 
     fn reset_policy(&mut self) -> Result<()> {
-        let tables = [
-            Table::new(&*TABLE_NAME, ProtoFamily::Inet),
-            Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
-            Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
-        ];
+        let tables: Vec<Table> = TableId::ALL
+            .iter()
+            .map(|table| Table::new(table.name(), table.family()))
+            .collect();
         let mut batch = Batch::new();
         for table in &tables {
             // Our batch will add and remove the table even though the goal is just to remove
@@ -139,25 +387,45 @@ impl FirewallT for Firewall {
         }
         let batch = batch.finalize();
         log::debug!("Removing table and chain from netfilter");
-        self.send_and_process(&batch)?;
-        Ok(())
-    }
-}
-
-impl Firewall {
-    fn apply_kernel_config(policy: &FirewallPolicy) {
-        if *DONT_SET_SRC_VALID_MARK {
-            log::debug!("Not setting src_valid_mark");
-            return;
+        match self.send_and_process(&batch) {
+            // Nothing can have been installed if netfilter can't be reached
+            Err(Error::NetlinkOpenError(error)) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to open netlink socket to netfilter")
+                );
+                Ok(())
+            }
+            result => result,
         }
+    }
 
-        if let FirewallPolicy::Connecting { .. } = policy {
-            if let Err(err) = crate::linux::set_src_valid_mark_sysctl() {
-                log::error!("Failed to apply src_valid_mark: {}", err);
+    fn installed_rules(&self) -> Result<String> {
+        let mut rules = String::new();
+        for table in &TableId::ALL {
+            let name = table.name().to_string_lossy().into_owned();
+            let output = duct::cmd!("nft", "list", "table", table.family_name(), &name)
+                .stdout_capture()
+                .stderr_capture()
+                .unchecked()
+                .run()
+                .map_err(Error::ListRulesError)?;
+            if output.status.success() {
+                rules.push_str(&String::from_utf8_lossy(&output.stdout));
+            } else {
+                rules.push_str(&format!(
+                    "# table {} {}: {}\n",
+                    table.family_name(),
+                    name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
             }
         }
+        Ok(rules)
     }
+}
 
+impl NftablesBackend {
     fn send_and_process(&self, batch: &FinalizedBatch) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         socket.send_all(batch).map_err(Error::NetlinkSendError)?;
@@ -226,123 +494,543 @@ impl Firewall {
     }
 }
 
-struct PolicyBatch<'a> {
-    batch: Batch,
-    in_chain: Chain<'a>,
-    out_chain: Chain<'a>,
-    forward_chain: Chain<'a>,
-    prerouting_chain: Chain<'a>,
-    mangle_chain_v4: Chain<'a>,
-    mangle_chain_v6: Chain<'a>,
-    nat_chain_v4: Chain<'a>,
-    nat_chain_v6: Chain<'a>,
+/// The tables owned by the firewall.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TableId {
+    Main,
+    MangleV4,
+    MangleV6,
 }
 
-impl<'a> PolicyBatch<'a> {
-    /// Bootstrap a new nftnl message batch object and add the initial messages creating the
-    /// table and chains.
-    pub fn new(tables: &'a FirewallTables) -> Self {
-        let mut batch = Batch::new();
-        let mut prerouting_chain = Chain::new(&*PREROUTING_CHAIN_NAME, &tables.main);
-        prerouting_chain.set_hook(nftnl::Hook::PreRouting, PREROUTING_CHAIN_PRIORITY);
-        prerouting_chain.set_type(nftnl::ChainType::Filter);
+impl TableId {
+    const ALL: [TableId; 3] = [TableId::Main, TableId::MangleV4, TableId::MangleV6];
+
+    fn name(self) -> &'static CString {
+        match self {
+            TableId::Main => &*TABLE_NAME,
+            TableId::MangleV4 => &*MANGLE_TABLE_NAME_V4,
+            TableId::MangleV6 => &*MANGLE_TABLE_NAME_V6,
+        }
+    }
+
+    fn family(self) -> ProtoFamily {
+        match self {
+            TableId::Main => ProtoFamily::Inet,
+            TableId::MangleV4 => ProtoFamily::Ipv4,
+            TableId::MangleV6 => ProtoFamily::Ipv6,
+        }
+    }
 
-        let mut out_chain = Chain::new(&*OUT_CHAIN_NAME, &tables.main);
-        out_chain.set_hook(nftnl::Hook::Out, 0);
-        out_chain.set_policy(nftnl::Policy::Drop);
+    /// The name of the address family, as used by the `nft` command line tool.
+    fn family_name(self) -> &'static str {
+        match self {
+            TableId::Main => "inet",
+            TableId::MangleV4 => "ip",
+            TableId::MangleV6 => "ip6",
+        }
+    }
+}
+
+/// The chains owned by the firewall, in the order they are created.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ChainId {
+    Prerouting,
+    Out,
+    In,
+    Forward,
+    MangleV4,
+    MangleV6,
+    NatV4,
+    NatV6,
+}
 
-        let mut in_chain = Chain::new(&*IN_CHAIN_NAME, &tables.main);
-        in_chain.set_hook(nftnl::Hook::In, 0);
-        in_chain.set_policy(nftnl::Policy::Drop);
+impl ChainId {
+    const ALL: [ChainId; 8] = [
+        ChainId::Prerouting,
+        ChainId::Out,
+        ChainId::In,
+        ChainId::Forward,
+        ChainId::MangleV4,
+        ChainId::MangleV6,
+        ChainId::NatV4,
+        ChainId::NatV6,
+    ];
+
+    fn table(self) -> TableId {
+        match self {
+            ChainId::Prerouting | ChainId::Out | ChainId::In | ChainId::Forward => TableId::Main,
+            ChainId::MangleV4 | ChainId::NatV4 => TableId::MangleV4,
+            ChainId::MangleV6 | ChainId::NatV6 => TableId::MangleV6,
+        }
+    }
 
-        let mut forward_chain = Chain::new(&*FORWARD_CHAIN_NAME, &tables.main);
-        forward_chain.set_hook(nftnl::Hook::Forward, 0);
-        forward_chain.set_policy(nftnl::Policy::Drop);
+    fn name(self) -> &'static CString {
+        match self {
+            ChainId::Prerouting => &*PREROUTING_CHAIN_NAME,
+            ChainId::Out => &*OUT_CHAIN_NAME,
+            ChainId::In => &*IN_CHAIN_NAME,
+            ChainId::Forward => &*FORWARD_CHAIN_NAME,
+            ChainId::MangleV4 | ChainId::MangleV6 => &*MANGLE_CHAIN_NAME,
+            ChainId::NatV4 | ChainId::NatV6 => &*NAT_CHAIN_NAME,
+        }
+    }
 
-        Self::flush_table(&mut batch, &tables.main);
-        batch.add(&prerouting_chain, nftnl::MsgType::Add);
-        batch.add(&out_chain, nftnl::MsgType::Add);
-        batch.add(&in_chain, nftnl::MsgType::Add);
-        batch.add(&forward_chain, nftnl::MsgType::Add);
+    fn chain_type(self) -> nftnl::ChainType {
+        match self {
+            ChainId::Prerouting | ChainId::Out | ChainId::In | ChainId::Forward => {
+                nftnl::ChainType::Filter
+            }
+            ChainId::MangleV4 | ChainId::MangleV6 => nftnl::ChainType::Route,
+            ChainId::NatV4 | ChainId::NatV6 => nftnl::ChainType::Nat,
+        }
+    }
 
+    fn hook(self) -> (nftnl::Hook, i32) {
+        match self {
+            ChainId::Prerouting => (nftnl::Hook::PreRouting, PREROUTING_CHAIN_PRIORITY),
+            ChainId::Out => (nftnl::Hook::Out, 0),
+            ChainId::In => (nftnl::Hook::In, 0),
+            ChainId::Forward => (nftnl::Hook::Forward, 0),
+            ChainId::MangleV4 | ChainId::MangleV6 => (nftnl::Hook::Out, MANGLE_CHAIN_PRIORITY),
+            ChainId::NatV4 | ChainId::NatV6 => (nftnl::Hook::PostRouting, libc::NF_IP_PRI_NAT_SRC),
+        }
+    }
 
-        Self::flush_table(&mut batch, &tables.mangle_v4);
-        Self::flush_table(&mut batch, &tables.mangle_v6);
+    /// The policy of the chain, or `None` if the default policy (accept) is used.
+    fn policy(self) -> Option<nftnl::Policy> {
+        match self {
+            ChainId::Prerouting => None,
+            ChainId::Out | ChainId::In | ChainId::Forward => Some(nftnl::Policy::Drop),
+            ChainId::MangleV4 | ChainId::MangleV6 | ChainId::NatV4 | ChainId::NatV6 => {
+                Some(nftnl::Policy::Accept)
+            }
+        }
+    }
 
-        let mut add_mangle_chain = |table| {
-            let mut chain = Chain::new(&*MANGLE_CHAIN_NAME, table);
-            chain.set_hook(nftnl::Hook::Out, MANGLE_CHAIN_PRIORITY);
-            chain.set_type(nftnl::ChainType::Route);
-            chain.set_policy(nftnl::Policy::Accept);
-            batch.add(&chain, nftnl::MsgType::Add);
+    fn to_nftnl<'a>(self, table: &'a Table) -> Chain<'a> {
+        let mut chain = Chain::new(self.name(), table);
+        let (hook, priority) = self.hook();
+        chain.set_hook(hook, priority);
+        chain.set_type(self.chain_type());
+        if let Some(policy) = self.policy() {
+            chain.set_policy(policy);
+        }
+        chain
+    }
 
-            chain
+    /// Formats the chain declaration the same way as `nft list`.
+    fn header(self) -> String {
+        let chain_type = match self.chain_type() {
+            nftnl::ChainType::Filter => "filter",
+            nftnl::ChainType::Route => "route",
+            nftnl::ChainType::Nat => "nat",
         };
-        let mangle_chain_v4 = add_mangle_chain(&tables.mangle_v4);
-        let mangle_chain_v6 = add_mangle_chain(&tables.mangle_v6);
+        let (hook, priority) = self.hook();
+        let hook = match hook {
+            nftnl::Hook::PreRouting => "prerouting",
+            nftnl::Hook::In => "input",
+            nftnl::Hook::Forward => "forward",
+            nftnl::Hook::Out => "output",
+            nftnl::Hook::PostRouting => "postrouting",
+        };
+        let policy = match self.policy() {
+            Some(nftnl::Policy::Drop) => "drop",
+            Some(nftnl::Policy::Accept) | None => "accept",
+        };
+        format!(
+            "type {} hook {} priority {}; policy {};",
+            chain_type, hook, priority, policy
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Verdict {
+    Accept,
+    Drop,
+    /// Reject with an ICMP port unreachable message.
+    Reject,
+    /// Reject with a TCP reset.
+    RejectTcpRst,
+}
+
+impl Verdict {
+    fn to_nftnl(self) -> expr::Verdict {
+        match self {
+            Verdict::Accept => expr::Verdict::Accept,
+            Verdict::Drop => expr::Verdict::Drop,
+            Verdict::Reject => expr::Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
+            Verdict::RejectTcpRst => expr::Verdict::Reject(RejectionType::TcpRst),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Accept => f.write_str("accept"),
+            Verdict::Drop => f.write_str("drop"),
+            Verdict::Reject => f.write_str("reject with icmpx type port-unreachable"),
+            Verdict::RejectTcpRst => f.write_str("reject with tcp reset"),
+        }
+    }
+}
+
+/// A single match or statement in a firewall rule. Each variant corresponds to one or more
+/// nftnl expressions, but unlike those it can be inspected and formatted.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Expr {
+    /// Matches packets on the interface, or on any other interface if `equal` is false.
+    Iface {
+        direction: Direction,
+        iface: String,
+        equal: bool,
+    },
+    /// Matches the network layer protocol (`NFPROTO_*`).
+    L3Proto(u8),
+    /// Matches the transport layer protocol (`IPPROTO_*`).
+    L4Proto(u8),
+    Addr {
+        end: End,
+        ip: IpAddr,
+    },
+    Net {
+        end: End,
+        net: IpNetwork,
+    },
+    Port {
+        protocol: TransportProtocol,
+        end: End,
+        port: u16,
+    },
+    Icmpv6Type(u8),
+    Icmpv6Code(u8),
+    CtStateEstablished,
+    CtMark(u32),
+    MetaMark(u32),
+    Cgroup(u32),
+    /// Matches the UID of the owner of the socket.
+    SkUid(u32),
+    SetCtMark(u32),
+    SetMetaMark(u32),
+    Masquerade,
+    Counter,
+    /// Sends the packet to the audit NFLOG group, with the given prefix.
+    Log(&'static str),
+    Verdict(Verdict),
+}
+
+impl Expr {
+    fn add_to(&self, rule: &mut Rule<'_>) -> Result<()> {
+        match self {
+            Expr::Iface {
+                direction,
+                iface,
+                equal,
+            } => {
+                let iface_index = crate::linux::iface_index(iface)
+                    .map_err(|e| Error::LookupIfaceIndexError(iface.to_owned(), e))?;
+                rule.add_expr(&match direction {
+                    Direction::In => nft_expr!(meta iif),
+                    Direction::Out => nft_expr!(meta oif),
+                });
+                if *equal {
+                    rule.add_expr(&nft_expr!(cmp == iface_index));
+                } else {
+                    rule.add_expr(&nft_expr!(cmp != iface_index));
+                }
+            }
+            Expr::L3Proto(proto) => {
+                rule.add_expr(&nft_expr!(meta nfproto));
+                rule.add_expr(&nft_expr!(cmp == *proto));
+            }
+            Expr::L4Proto(proto) => {
+                rule.add_expr(&nft_expr!(meta l4proto));
+                rule.add_expr(&nft_expr!(cmp == *proto));
+            }
+            Expr::Addr { end, ip } => {
+                rule.add_expr(&addr_payload(*ip, *end));
+                match ip {
+                    IpAddr::V4(addr) => rule.add_expr(&nft_expr!(cmp == *addr)),
+                    IpAddr::V6(addr) => rule.add_expr(&nft_expr!(cmp == *addr)),
+                }
+            }
+            Expr::Net { end, net } => {
+                rule.add_expr(&addr_payload(net.ip(), *end));
+                match net {
+                    IpNetwork::V4(_) => {
+                        rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32))
+                    }
+                    IpNetwork::V6(_) => {
+                        rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor &[0u16; 8][..]))
+                    }
+                };
+                rule.add_expr(&nft_expr!(cmp == net.ip()));
+            }
+            Expr::Port {
+                protocol,
+                end,
+                port,
+            } => {
+                rule.add_expr(&match (protocol, end) {
+                    (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
+                    (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
+                    (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
+                    (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+                });
+                rule.add_expr(&nft_expr!(cmp == port.to_be()));
+            }
+            Expr::Icmpv6Type(icmp_type) => {
+                rule.add_expr(&Payload::Transport(
+                    nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
+                ));
+                rule.add_expr(&nft_expr!(cmp == *icmp_type));
+            }
+            Expr::Icmpv6Code(code) => {
+                rule.add_expr(&Payload::Transport(
+                    nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
+                ));
+                rule.add_expr(&nft_expr!(cmp == *code));
+            }
+            Expr::CtStateEstablished => {
+                rule.add_expr(&nft_expr!(ct state));
+                let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+                rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+                rule.add_expr(&nft_expr!(cmp != 0u32));
+            }
+            Expr::CtMark(mark) => {
+                rule.add_expr(&nft_expr!(ct mark));
+                rule.add_expr(&nft_expr!(cmp == *mark));
+            }
+            Expr::MetaMark(mark) => {
+                rule.add_expr(&nft_expr!(meta mark));
+                rule.add_expr(&nft_expr!(cmp == *mark));
+            }
+            Expr::Cgroup(classid) => {
+                rule.add_expr(&nft_expr!(meta cgroup));
+                rule.add_expr(&nft_expr!(cmp == *classid));
+            }
+            Expr::SkUid(uid) => {
+                rule.add_expr(&nft_expr!(meta skuid));
+                rule.add_expr(&nft_expr!(cmp == *uid));
+            }
+            Expr::SetCtMark(mark) => {
+                rule.add_expr(&nft_expr!(immediate data *mark));
+                rule.add_expr(&nft_expr!(ct mark set));
+            }
+            Expr::SetMetaMark(mark) => {
+                rule.add_expr(&nft_expr!(immediate data *mark));
+                rule.add_expr(&nft_expr!(meta mark set));
+            }
+            Expr::Masquerade => rule.add_expr(&nft_expr!(masquerade)),
+            Expr::Counter => rule.add_expr(&nft_expr!(counter)),
+            Expr::Log(prefix) => rule.add_expr(
+                &expr::Log::new(Some(audit::NFLOG_GROUP), Some(*prefix))
+                    .expect("Log prefix is valid"),
+            ),
+            Expr::Verdict(verdict) => rule.add_expr(&verdict.to_nftnl()),
+        }
+        Ok(())
+    }
+}
 
-        let mut add_nat_chain = |table| {
-            let mut chain = Chain::new(&*NAT_CHAIN_NAME, table);
-            chain.set_hook(nftnl::Hook::PostRouting, libc::NF_IP_PRI_NAT_SRC);
-            chain.set_type(nftnl::ChainType::Nat);
-            chain.set_policy(nftnl::Policy::Accept);
-            batch.add(&chain, nftnl::MsgType::Add);
+fn addr_payload(ip: IpAddr, end: End) -> Payload {
+    match (ip, end) {
+        (IpAddr::V4(..), End::Src) => nft_expr!(payload ipv4 saddr),
+        (IpAddr::V4(..), End::Dst) => nft_expr!(payload ipv4 daddr),
+        (IpAddr::V6(..), End::Src) => nft_expr!(payload ipv6 saddr),
+        (IpAddr::V6(..), End::Dst) => nft_expr!(payload ipv6 daddr),
+    }
+}
 
-            chain
+/// Formats the expression using the syntax of the `nft` command line tool.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end_name = |end: &End| match end {
+            End::Src => "saddr",
+            End::Dst => "daddr",
+        };
+        let ip_family = |ip: IpAddr| match ip {
+            IpAddr::V4(_) => "ip",
+            IpAddr::V6(_) => "ip6",
         };
-        let nat_chain_v4 = add_nat_chain(&tables.mangle_v4);
-        let nat_chain_v6 = add_nat_chain(&tables.mangle_v6);
+        match self {
+            Expr::Iface {
+                direction,
+                iface,
+                equal,
+            } => write!(
+                f,
+                "{} {}\"{}\"",
+                match direction {
+                    Direction::In => "iif",
+                    Direction::Out => "oif",
+                },
+                if *equal { "" } else { "!= " },
+                iface
+            ),
+            Expr::L3Proto(proto) => match i32::from(*proto) {
+                libc::NFPROTO_IPV4 => f.write_str("meta nfproto ipv4"),
+                libc::NFPROTO_IPV6 => f.write_str("meta nfproto ipv6"),
+                proto => write!(f, "meta nfproto {}", proto),
+            },
+            Expr::L4Proto(proto) => match i32::from(*proto) {
+                libc::IPPROTO_UDP => f.write_str("meta l4proto udp"),
+                libc::IPPROTO_TCP => f.write_str("meta l4proto tcp"),
+                libc::IPPROTO_ICMPV6 => f.write_str("meta l4proto ipv6-icmp"),
+                proto => write!(f, "meta l4proto {}", proto),
+            },
+            Expr::Addr { end, ip } => write!(f, "{} {} {}", ip_family(*ip), end_name(end), ip),
+            Expr::Net { end, net } => {
+                write!(f, "{} {} {}", ip_family(net.ip()), end_name(end), net)
+            }
+            Expr::Port {
+                protocol,
+                end,
+                port,
+            } => write!(
+                f,
+                "{} {} {}",
+                match protocol {
+                    TransportProtocol::Udp => "udp",
+                    TransportProtocol::Tcp => "tcp",
+                },
+                match end {
+                    End::Src => "sport",
+                    End::Dst => "dport",
+                },
+                port
+            ),
+            Expr::Icmpv6Type(icmp_type) => write!(f, "icmpv6 type {}", icmp_type),
+            Expr::Icmpv6Code(code) => write!(f, "icmpv6 code {}", code),
+            Expr::CtStateEstablished => f.write_str("ct state established"),
+            Expr::CtMark(mark) => write!(f, "ct mark {:#010x}", mark),
+            Expr::MetaMark(mark) => write!(f, "meta mark {:#010x}", mark),
+            Expr::Cgroup(classid) => write!(f, "meta cgroup {}", classid),
+            Expr::SkUid(uid) => write!(f, "meta skuid {}", uid),
+            Expr::SetCtMark(mark) => write!(f, "ct mark set {:#010x}", mark),
+            Expr::SetMetaMark(mark) => write!(f, "meta mark set {:#010x}", mark),
+            Expr::Masquerade => f.write_str("masquerade"),
+            Expr::Counter => f.write_str("counter"),
+            Expr::Log(prefix) => {
+                write!(f, "log prefix \"{}\" group {}", prefix, audit::NFLOG_GROUP)
+            }
+            Expr::Verdict(verdict) => write!(f, "{}", verdict),
+        }
+    }
+}
+
+/// A rule to be added to one of the chains owned by the firewall.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct RuleSpec {
+    chain: ChainId,
+    exprs: Vec<Expr>,
+}
+
+impl fmt::Display for RuleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exprs: Vec<String> = self.exprs.iter().map(|expr| expr.to_string()).collect();
+        f.write_str(&exprs.join(" "))
+    }
+}
+
+/// Every rule needed to satisfy a firewall policy, in the order they are added to their chains.
+/// The rules can be converted into an nftnl batch, or formatted as an nft ruleset without
+/// touching the system.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct PolicyRules {
+    rules: Vec<RuleSpec>,
+}
+
+impl PolicyRules {
+    pub fn new(policy: &FirewallPolicy) -> Self {
+        Self::with_audit(policy, false)
+    }
+
+    /// Returns the rules for `policy`, where the blocking rules also pass the dropped packets to
+    /// the audit if `audit` is set.
+    pub fn with_audit(policy: &FirewallPolicy, audit: bool) -> Self {
+        let mut rules = PolicyRules { rules: vec![] };
+        rules.add_loopback_rules();
+        rules.add_split_tunneling_rules(policy);
+        rules.add_dhcp_client_rules();
+        rules.add_policy_specific_rules(policy);
+        if audit {
+            rules.add_audit_rules(policy);
+        }
+        rules
+    }
 
-        PolicyBatch {
-            batch,
-            in_chain,
-            out_chain,
-            forward_chain,
-            prerouting_chain,
-            mangle_chain_v4,
-            mangle_chain_v6,
-            nat_chain_v4,
-            nat_chain_v6,
+    /// Creates an nftnl batch that flushes the tables and chains owned by the firewall and adds
+    /// every rule to them.
+    pub fn to_batch(&self) -> Result<FinalizedBatch> {
+        let tables: Vec<Table> = TableId::ALL
+            .iter()
+            .map(|table| Table::new(table.name(), table.family()))
+            .collect();
+        let chains: Vec<Chain<'_>> = ChainId::ALL
+            .iter()
+            .map(|chain| chain.to_nftnl(&tables[chain.table() as usize]))
+            .collect();
+
+        let mut batch = Batch::new();
+        for table in &tables {
+            Self::flush_table(&mut batch, table);
+        }
+        for chain in &chains {
+            batch.add(chain, nftnl::MsgType::Add);
+        }
+        for spec in &self.rules {
+            let mut rule = Rule::new(&chains[spec.chain as usize]);
+            for expr in &spec.exprs {
+                expr.add_to(&mut rule)?;
+            }
+            batch.add(&rule, nftnl::MsgType::Add);
         }
+        Ok(batch.finalize())
     }
 
     /// Creates the table if it does not exist and clears it otherwise.
-    fn flush_table(batch: &mut Batch, table: &'a Table) {
+    fn flush_table(batch: &mut Batch, table: &Table) {
         batch.add(table, nftnl::MsgType::Add);
         batch.add(table, nftnl::MsgType::Del);
         batch.add(table, nftnl::MsgType::Add);
     }
 
-    /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
-    /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
-        self.add_loopback_rules()?;
-        self.add_split_tunneling_rules(policy)?;
-        self.add_dhcp_client_rules();
-        self.add_policy_specific_rules(policy)?;
+    fn chain_rules(&self, chain: ChainId) -> impl Iterator<Item = &RuleSpec> {
+        self.rules.iter().filter(move |rule| rule.chain == chain)
+    }
 
-        Ok(self.batch.finalize())
+    fn add(&mut self, chain: ChainId, exprs: Vec<Expr>) {
+        self.rules.push(RuleSpec { chain, exprs });
     }
 
-    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let mangle_chains = [&self.mangle_chain_v4, &self.mangle_chain_v6];
-        for chain in &mangle_chains {
-            let mut rule = Rule::new(chain);
-            rule.add_expr(&nft_expr!(meta cgroup));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
-            rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
-            rule.add_expr(&nft_expr!(ct mark set));
-            rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
-            rule.add_expr(&nft_expr!(meta mark set));
-            self.batch.add(&rule, nftnl::MsgType::Add);
+    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy) {
+        for chain in &[ChainId::MangleV4, ChainId::MangleV6] {
+            self.add(
+                *chain,
+                vec![
+                    Expr::Cgroup(split_tunnel::NET_CLS_CLASSID),
+                    Expr::SetCtMark(split_tunnel::MARK as u32),
+                    Expr::SetMetaMark(crate::linux::TUNNEL_FW_MARK),
+                ],
+            );
+            for uid in policy.excluded_uids() {
+                self.add(
+                    *chain,
+                    vec![
+                        Expr::SkUid(*uid),
+                        Expr::SetCtMark(split_tunnel::MARK as u32),
+                        Expr::SetMetaMark(crate::linux::TUNNEL_FW_MARK),
+                    ],
+                );
+            }
         }
 
-        for chain in &[&self.in_chain, &self.out_chain] {
-            let mut rule = Rule::new(chain);
-            rule.add_expr(&nft_expr!(ct mark));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        for chain in &[ChainId::In, ChainId::Out] {
+            let mut rule = vec![Expr::CtMark(split_tunnel::MARK as u32)];
+            add_verdict(&mut rule, Verdict::Accept);
+            self.add(*chain, rule);
         }
 
         // Allow some DNS requests to pass through the tunnel
@@ -354,8 +1042,8 @@ impl<'a> PolicyBatch<'a> {
         {
             let gateway = IpAddr::V4(tunnel.ipv4_gateway);
             if dns_servers.contains(&gateway) {
-                self.add_nat_tunnel_dns_rule(&tunnel.interface, TransportProtocol::Udp, gateway)?;
-                self.add_nat_tunnel_dns_rule(&tunnel.interface, TransportProtocol::Tcp, gateway)?;
+                self.add_nat_tunnel_dns_rule(&tunnel.interface, TransportProtocol::Udp, gateway);
+                self.add_nat_tunnel_dns_rule(&tunnel.interface, TransportProtocol::Tcp, gateway);
             }
 
             if let Some(ref gateway) = tunnel.ipv6_gateway {
@@ -365,63 +1053,50 @@ impl<'a> PolicyBatch<'a> {
                         &tunnel.interface,
                         TransportProtocol::Udp,
                         gateway,
-                    )?;
+                    );
                     self.add_nat_tunnel_dns_rule(
                         &tunnel.interface,
                         TransportProtocol::Tcp,
                         gateway,
-                    )?;
+                    );
                 }
             }
         }
 
-        let nat_chains = [&self.nat_chain_v4, &self.nat_chain_v6];
-        for chain in &nat_chains {
+        for chain in &[ChainId::NatV4, ChainId::NatV6] {
             // Block remaining marked outgoing in-tunnel traffic
             if let FirewallPolicy::Connected { tunnel, .. } = policy {
-                let mut block_tunnel_rule = Rule::new(chain);
-                check_iface(&mut block_tunnel_rule, Direction::Out, &tunnel.interface)?;
-                block_tunnel_rule.add_expr(&nft_expr!(ct mark));
-                block_tunnel_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-                add_verdict(&mut block_tunnel_rule, &Verdict::Drop);
-                self.batch.add(&block_tunnel_rule, nftnl::MsgType::Add);
+                let mut block_tunnel_rule = vec![];
+                check_iface(&mut block_tunnel_rule, Direction::Out, &tunnel.interface);
+                block_tunnel_rule.push(Expr::CtMark(split_tunnel::MARK as u32));
+                add_verdict(&mut block_tunnel_rule, Verdict::Drop);
+                self.add(*chain, block_tunnel_rule);
             }
 
             // Replace source IP address in rerouted packets.
             // Don't masquerade packets on the loopback device.
-            let mut rule = Rule::new(chain);
-
-            let iface_index = crate::linux::iface_index("lo")
-                .map_err(|e| Error::LookupIfaceIndexError("lo".to_string(), e))?;
-            rule.add_expr(&nft_expr!(meta oif));
-            rule.add_expr(&nft_expr!(cmp != iface_index));
-
-            rule.add_expr(&nft_expr!(ct mark));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-
-            rule.add_expr(&nft_expr!(masquerade));
+            let mut rule = vec![];
+            check_not_iface(&mut rule, Direction::Out, "lo");
+            rule.push(Expr::CtMark(split_tunnel::MARK as u32));
+            rule.push(Expr::Masquerade);
             if *ADD_COUNTERS {
-                rule.add_expr(&nft_expr!(counter));
+                rule.push(Expr::Counter);
             }
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            self.add(*chain, rule);
         }
 
         // Route incoming traffic correctly to prevent strict rpf from rejecting packets
         // for excluded processes
         if let FirewallPolicy::Connected { tunnel, .. } = policy {
-            let mut prerouting_rule = Rule::new(&self.prerouting_chain);
-            check_not_iface(&mut prerouting_rule, Direction::In, &tunnel.interface)?;
-            prerouting_rule.add_expr(&nft_expr!(ct mark));
-            prerouting_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-            prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
-            prerouting_rule.add_expr(&nft_expr!(meta mark set));
+            let mut prerouting_rule = vec![];
+            check_not_iface(&mut prerouting_rule, Direction::In, &tunnel.interface);
+            prerouting_rule.push(Expr::CtMark(split_tunnel::MARK as u32));
+            prerouting_rule.push(Expr::SetMetaMark(crate::linux::TUNNEL_FW_MARK));
             if *ADD_COUNTERS {
-                prerouting_rule.add_expr(&nft_expr!(counter));
+                prerouting_rule.push(Expr::Counter);
             }
-            self.batch.add(&prerouting_rule, nftnl::MsgType::Add);
+            self.add(ChainId::Prerouting, prerouting_rule);
         }
-
-        Ok(())
     }
 
     fn add_nat_tunnel_dns_rule(
@@ -429,157 +1104,198 @@ impl<'a> PolicyBatch<'a> {
         interface: &str,
         protocol: TransportProtocol,
         host: IpAddr,
-    ) -> Result<()> {
-        let mut allow_rule = match host {
-            IpAddr::V4(_) => Rule::new(&self.nat_chain_v4),
-            IpAddr::V6(_) => Rule::new(&self.nat_chain_v6),
-        };
-        let daddr = match host {
-            IpAddr::V4(_) => nft_expr!(payload ipv4 daddr),
-            IpAddr::V6(_) => nft_expr!(payload ipv6 daddr),
+    ) {
+        let chain = match host {
+            IpAddr::V4(_) => ChainId::NatV4,
+            IpAddr::V6(_) => ChainId::NatV6,
         };
 
-        check_iface(&mut allow_rule, Direction::Out, interface)?;
+        let mut allow_rule = vec![];
+        check_iface(&mut allow_rule, Direction::Out, interface);
         check_port(&mut allow_rule, protocol, End::Dst, 53);
-
-        allow_rule.add_expr(&daddr);
-        allow_rule.add_expr(&nft_expr!(cmp == host));
-
-        allow_rule.add_expr(&nft_expr!(ct mark));
-        allow_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-
-        add_verdict(&mut allow_rule, &Verdict::Accept);
-
-        self.batch.add(&allow_rule, nftnl::MsgType::Add);
-        Ok(())
+        allow_rule.push(Expr::Addr {
+            end: End::Dst,
+            ip: host,
+        });
+        allow_rule.push(Expr::CtMark(split_tunnel::MARK as u32));
+        add_verdict(&mut allow_rule, Verdict::Accept);
+
+        self.add(chain, allow_rule);
     }
 
-    fn add_loopback_rules(&mut self) -> Result<()> {
+    fn add_loopback_rules(&mut self) {
         const LOOPBACK_IFACE_NAME: &str = "lo";
-        self.batch.add(
-            &allow_interface_rule(&self.out_chain, Direction::Out, LOOPBACK_IFACE_NAME)?,
-            nftnl::MsgType::Add,
+        self.add(
+            ChainId::Out,
+            allow_interface_rule(Direction::Out, LOOPBACK_IFACE_NAME),
         );
-        self.batch.add(
-            &allow_interface_rule(&self.in_chain, Direction::In, LOOPBACK_IFACE_NAME)?,
-            nftnl::MsgType::Add,
+        self.add(
+            ChainId::In,
+            allow_interface_rule(Direction::In, LOOPBACK_IFACE_NAME),
         );
-        Ok(())
     }
 
     fn add_dhcp_client_rules(&mut self) {
         use self::TransportProtocol::Udp;
         // Outgoing DHCPv4 request
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut out_v4 = Rule::new(chain);
+        for chain in &[ChainId::Out, ChainId::Forward] {
+            let mut out_v4 = vec![];
             check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
             check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
             check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
+            add_verdict(&mut out_v4, Verdict::Accept);
+            self.add(*chain, out_v4);
         }
         // Incoming DHCPv4 response
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v4 = Rule::new(chain);
+        for chain in &[ChainId::In, ChainId::Forward] {
+            let mut in_v4 = vec![];
             check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
             check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+            add_verdict(&mut in_v4, Verdict::Accept);
+            self.add(*chain, in_v4);
         }
 
-        for chain in &[&self.out_chain, &self.forward_chain] {
+        for chain in &[ChainId::Out, ChainId::Forward] {
             for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
-                let mut out_v6 = Rule::new(chain);
+                let mut out_v6 = vec![];
                 check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
                 check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
                 check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
                 check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
-                add_verdict(&mut out_v6, &Verdict::Accept);
-                self.batch.add(&out_v6, nftnl::MsgType::Add);
+                add_verdict(&mut out_v6, Verdict::Accept);
+                self.add(*chain, out_v6);
             }
         }
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v6 = Rule::new(chain);
+        for chain in &[ChainId::In, ChainId::Forward] {
+            let mut in_v6 = vec![];
             check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
             check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
             check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
             check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
-            add_verdict(&mut in_v6, &Verdict::Accept);
-            self.batch.add(&in_v6, nftnl::MsgType::Add);
+            add_verdict(&mut in_v6, Verdict::Accept);
+            self.add(*chain, in_v6);
         }
         // Outgoing Router solicitation (part of NDP)
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
+        for chain in &[ChainId::Out, ChainId::Forward] {
+            let mut rule = vec![];
             check_ip(
                 &mut rule,
                 End::Dst,
                 *super::ROUTER_SOLICITATION_OUT_DST_ADDR,
             );
-
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
-
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 133u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
-
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            check_icmpv6(&mut rule, 133, 0);
+            add_verdict(&mut rule, Verdict::Accept);
+            self.add(*chain, rule);
         }
         // Incoming Router advertisement (part of NDP)
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
+        for chain in &[ChainId::In, ChainId::Forward] {
+            let mut rule = vec![];
             check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
-
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
-
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 134u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
-
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            check_icmpv6(&mut rule, 134, 0);
+            add_verdict(&mut rule, Verdict::Accept);
+            self.add(*chain, rule);
         }
         // Incoming Redirect (part of NDP)
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
+        for chain in &[ChainId::In, ChainId::Forward] {
+            let mut rule = vec![];
             check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_icmpv6(&mut rule, 137, 0);
+            add_verdict(&mut rule, Verdict::Accept);
+            self.add(*chain, rule);
+        }
+    }
 
-            rule.add_expr(&nft_expr!(meta l4proto));
-            rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
+    /// Allows the connections described by the custom rules. Replies are only allowed for
+    /// established connections.
+    fn add_custom_allow_rules(&mut self, allow_rules: &[AllowRule]) {
+        for allow_rule in allow_rules {
+            // E.g. the owner of inbound packets is unknown, so such a rule would never match
+            if let Err(error) = allow_rule.validate() {
+                log::warn!("Ignoring custom allow rule \"{}\": {}", allow_rule, error);
+                continue;
+            }
+            let (request_chain, request_end, reply_chain, reply_end) = match allow_rule.direction {
+                AllowRuleDirection::Outbound => (ChainId::Out, End::Dst, ChainId::In, End::Src),
+                AllowRuleDirection::Inbound => (ChainId::In, End::Src, ChainId::Out, End::Dst),
+            };
 
-            rule.add_expr(&Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 137u8));
-            rule.add_expr(&nftnl::expr::Payload::Transport(
-                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
-            ));
-            rule.add_expr(&nft_expr!(cmp == 0u8));
+            let mut request_rule = vec![];
+            if let Some(cgroup) = allow_rule.cgroup {
+                request_rule.push(Expr::Cgroup(cgroup));
+            }
+            if let Some(uid) = allow_rule.uid {
+                request_rule.push(Expr::SkUid(uid));
+            }
+            check_net(&mut request_rule, request_end, allow_rule.network);
+            let mut reply_rule = vec![];
+            check_net(&mut reply_rule, reply_end, allow_rule.network);
+
+            if let Some(protocol) = allow_rule.protocol {
+                // Connections are made to the port regardless of the direction
+                match allow_rule.port {
+                    Some(port) => {
+                        check_port(&mut request_rule, protocol, End::Dst, port);
+                        check_port(&mut reply_rule, protocol, End::Src, port);
+                    }
+                    None => {
+                        check_l4proto(&mut request_rule, protocol);
+                        check_l4proto(&mut reply_rule, protocol);
+                    }
+                }
+            }
+            reply_rule.push(Expr::CtStateEstablished);
 
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            add_verdict(&mut request_rule, Verdict::Accept);
+            self.add(request_chain, request_rule);
+            add_verdict(&mut reply_rule, Verdict::Accept);
+            self.add(reply_chain, reply_rule);
         }
     }
 
-    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
+    /// Logs the packets that are dropped by the blocking policies. They are logged by every rule
+    /// that drops packets, and at the end of each chain with a drop policy.
+    fn add_audit_rules(&mut self, policy: &FirewallPolicy) {
+        if let FirewallPolicy::Connected { .. } = policy {
+            return;
+        }
+        let audit_prefix = |chain: ChainId| match chain {
+            ChainId::In => Some(audit::PREFIX_IN),
+            ChainId::Out => Some(audit::PREFIX_OUT),
+            ChainId::Forward => Some(audit::PREFIX_FORWARD),
+            _ => None,
+        };
+
+        for rule in &mut self.rules {
+            let prefix = match audit_prefix(rule.chain) {
+                Some(prefix) => prefix,
+                None => continue,
+            };
+            if let Some(Expr::Verdict(verdict)) = rule.exprs.last() {
+                if *verdict != Verdict::Accept {
+                    let mut position = rule.exprs.len() - 1;
+                    if position > 0 && rule.exprs[position - 1] == Expr::Counter {
+                        position -= 1;
+                    }
+                    rule.exprs.insert(position, Expr::Log(prefix));
+                }
+            }
+        }
+        // Packets that reach the end of these chains are dropped by the chain policy
+        for chain in &[ChainId::In, ChainId::Out, ChainId::Forward] {
+            if let Some(prefix) = audit_prefix(*chain) {
+                self.add(*chain, vec![Expr::Log(prefix)]);
+            }
+        }
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
         let allow_lan = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_endpoint_rules(allowed_endpoint);
@@ -587,9 +1303,11 @@ impl<'a> PolicyBatch<'a> {
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 self.add_drop_dns_rule();
+                // Custom rules must not let DNS leak either
+                self.add_custom_allow_rules(policy.allow_rules());
 
                 if let Some(tunnel) = tunnel {
-                    self.add_allow_tunnel_rules(&tunnel.interface)?;
+                    self.add_allow_tunnel_rules(&tunnel.interface);
                 }
                 *allow_lan
             }
@@ -598,14 +1316,16 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
-                self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp)?;
-                self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Tcp)?;
+                self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Udp);
+                self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Tcp);
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
-                self.add_allow_tunnel_rules(&tunnel.interface)?;
+                self.add_custom_allow_rules(policy.allow_rules());
+                self.add_allow_tunnel_rules(&tunnel.interface);
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
@@ -614,11 +1334,13 @@ impl<'a> PolicyBatch<'a> {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 self.add_allow_endpoint_rules(allowed_endpoint);
 
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
+                self.add_custom_allow_rules(policy.allow_rules());
                 *allow_lan
             }
         };
@@ -628,62 +1350,45 @@ impl<'a> PolicyBatch<'a> {
         }
 
         // Reject any remaining outgoing traffic
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut reject_rule = Rule::new(chain);
-            add_verdict(
-                &mut reject_rule,
-                &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
-            );
-            self.batch.add(&reject_rule, nftnl::MsgType::Add);
+        for chain in &[ChainId::Out, ChainId::Forward] {
+            let mut reject_rule = vec![];
+            add_verdict(&mut reject_rule, Verdict::Reject);
+            self.add(*chain, reject_rule);
         }
-
-        Ok(())
     }
 
     fn add_allow_tunnel_endpoint_rules(&mut self, endpoint: &Endpoint) {
-        let mut prerouting_rule = Rule::new(&self.prerouting_chain);
+        let mut prerouting_rule = vec![];
         check_endpoint(&mut prerouting_rule, End::Src, endpoint);
-        prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
-        prerouting_rule.add_expr(&nft_expr!(meta mark set));
-
+        prerouting_rule.push(Expr::SetMetaMark(crate::linux::TUNNEL_FW_MARK));
         if *ADD_COUNTERS {
-            prerouting_rule.add_expr(&nft_expr!(counter));
+            prerouting_rule.push(Expr::Counter);
         }
+        self.add(ChainId::Prerouting, prerouting_rule);
 
-        self.batch.add(&prerouting_rule, nftnl::MsgType::Add);
-
-        let mut in_rule = Rule::new(&self.in_chain);
+        let mut in_rule = vec![];
         check_endpoint(&mut in_rule, End::Src, endpoint);
+        in_rule.push(Expr::CtStateEstablished);
+        add_verdict(&mut in_rule, Verdict::Accept);
+        self.add(ChainId::In, in_rule);
 
-        in_rule.add_expr(&nft_expr!(ct state));
-        let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
-        in_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
-        in_rule.add_expr(&nft_expr!(cmp != 0u32));
-        add_verdict(&mut in_rule, &Verdict::Accept);
-
-        self.batch.add(&in_rule, nftnl::MsgType::Add);
-
-        let mut out_rule = Rule::new(&self.out_chain);
+        let mut out_rule = vec![];
         check_endpoint(&mut out_rule, End::Dst, endpoint);
-        out_rule.add_expr(&nft_expr!(meta mark));
-        out_rule.add_expr(&nft_expr!(cmp == crate::linux::TUNNEL_FW_MARK));
-        add_verdict(&mut out_rule, &Verdict::Accept);
-
-        self.batch.add(&out_rule, nftnl::MsgType::Add);
+        out_rule.push(Expr::MetaMark(crate::linux::TUNNEL_FW_MARK));
+        add_verdict(&mut out_rule, Verdict::Accept);
+        self.add(ChainId::Out, out_rule);
     }
 
     fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
-        let mut in_rule = Rule::new(&self.in_chain);
+        let mut in_rule = vec![];
         check_endpoint(&mut in_rule, End::Src, endpoint);
-        add_verdict(&mut in_rule, &Verdict::Accept);
+        add_verdict(&mut in_rule, Verdict::Accept);
+        self.add(ChainId::In, in_rule);
 
-        self.batch.add(&in_rule, nftnl::MsgType::Add);
-
-        let mut out_rule = Rule::new(&self.out_chain);
+        let mut out_rule = vec![];
         check_endpoint(&mut out_rule, End::Dst, endpoint);
-        add_verdict(&mut out_rule, &Verdict::Accept);
-
-        self.batch.add(&out_rule, nftnl::MsgType::Add);
+        add_verdict(&mut out_rule, Verdict::Accept);
+        self.add(ChainId::Out, out_rule);
     }
 
     fn add_allow_dns_rules(
@@ -691,7 +1396,7 @@ impl<'a> PolicyBatch<'a> {
         tunnel: &tunnel::TunnelMetadata,
         dns_servers: &[IpAddr],
         protocol: TransportProtocol,
-    ) -> Result<()> {
+    ) {
         let (local_resolvers, remote_resolvers): (Vec<IpAddr>, Vec<IpAddr>) =
             dns_servers.iter().partition(|server| {
                 super::is_local_address(server)
@@ -703,14 +1408,12 @@ impl<'a> PolicyBatch<'a> {
             });
 
         for resolver in &local_resolvers {
-            self.add_allow_local_dns_rule(&tunnel.interface, protocol, *resolver)?;
+            self.add_allow_local_dns_rule(&tunnel.interface, protocol, *resolver);
         }
 
         for resolver in &remote_resolvers {
-            self.add_allow_tunnel_dns_rule(&tunnel.interface, protocol, *resolver)?;
+            self.add_allow_tunnel_dns_rule(&tunnel.interface, protocol, *resolver);
         }
-
-        Ok(())
     }
 
     fn add_allow_tunnel_dns_rule(
@@ -718,26 +1421,15 @@ impl<'a> PolicyBatch<'a> {
         interface: &str,
         protocol: TransportProtocol,
         host: IpAddr,
-    ) -> Result<()> {
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut allow_rule = Rule::new(chain);
-            let daddr = match host {
-                IpAddr::V4(_) => nft_expr!(payload ipv4 daddr),
-                IpAddr::V6(_) => nft_expr!(payload ipv6 daddr),
-            };
-
-            check_iface(&mut allow_rule, Direction::Out, interface)?;
+    ) {
+        for chain in &[ChainId::Out, ChainId::Forward] {
+            let mut allow_rule = vec![];
+            check_iface(&mut allow_rule, Direction::Out, interface);
             check_port(&mut allow_rule, protocol, End::Dst, 53);
-            check_l3proto(&mut allow_rule, host);
-
-            allow_rule.add_expr(&daddr);
-            allow_rule.add_expr(&nft_expr!(cmp == host));
-            add_verdict(&mut allow_rule, &Verdict::Accept);
-
-            self.batch.add(&allow_rule, nftnl::MsgType::Add);
+            check_ip(&mut allow_rule, End::Dst, host);
+            add_verdict(&mut allow_rule, Verdict::Accept);
+            self.add(*chain, allow_rule);
         }
-
-        Ok(())
     }
 
     fn add_allow_local_dns_rule(
@@ -745,84 +1437,63 @@ impl<'a> PolicyBatch<'a> {
         tunnel_interface: &str,
         protocol: TransportProtocol,
         host: IpAddr,
-    ) -> Result<()> {
+    ) {
         let chains = [
-            (&self.out_chain, Direction::Out),
-            (&self.forward_chain, Direction::Out),
-            (&self.in_chain, Direction::In),
-            (&self.forward_chain, Direction::In),
+            (ChainId::Out, Direction::Out),
+            (ChainId::Forward, Direction::Out),
+            (ChainId::In, Direction::In),
+            (ChainId::Forward, Direction::In),
         ];
 
         for (chain, direction) in &chains {
-            let mut allow_rule = Rule::new(chain);
-            let addr = match (host, direction) {
-                (IpAddr::V4(_), Direction::Out) => nft_expr!(payload ipv4 daddr),
-                (IpAddr::V6(_), Direction::Out) => nft_expr!(payload ipv6 daddr),
-                (IpAddr::V4(_), Direction::In) => nft_expr!(payload ipv4 saddr),
-                (IpAddr::V6(_), Direction::In) => nft_expr!(payload ipv6 saddr),
-            };
-
-            let port_dir = match direction {
+            let end = match direction {
                 Direction::In => End::Src,
                 Direction::Out => End::Dst,
             };
 
-            check_not_iface(&mut allow_rule, *direction, tunnel_interface)?;
-            check_port(&mut allow_rule, protocol, port_dir, 53);
-            check_l3proto(&mut allow_rule, host);
-
-            allow_rule.add_expr(&addr);
-            allow_rule.add_expr(&nft_expr!(cmp == host));
-            add_verdict(&mut allow_rule, &Verdict::Accept);
-
-            self.batch.add(&allow_rule, nftnl::MsgType::Add);
+            let mut allow_rule = vec![];
+            check_not_iface(&mut allow_rule, *direction, tunnel_interface);
+            check_port(&mut allow_rule, protocol, end, 53);
+            check_ip(&mut allow_rule, end, host);
+            add_verdict(&mut allow_rule, Verdict::Accept);
+            self.add(*chain, allow_rule);
         }
-
-        Ok(())
     }
 
     /// Blocks all outgoing DNS (port 53) on both TCP and UDP
     fn add_drop_dns_rule(&mut self) {
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut block_udp_rule = Rule::new(chain);
+        for chain in &[ChainId::Out, ChainId::Forward] {
+            let mut block_udp_rule = vec![];
             check_port(&mut block_udp_rule, TransportProtocol::Udp, End::Dst, 53);
-            add_verdict(
-                &mut block_udp_rule,
-                &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
-            );
-            self.batch.add(&block_udp_rule, nftnl::MsgType::Add);
+            add_verdict(&mut block_udp_rule, Verdict::Reject);
+            self.add(*chain, block_udp_rule);
 
-            let mut block_tcp_rule = Rule::new(chain);
+            let mut block_tcp_rule = vec![];
             check_port(&mut block_tcp_rule, TransportProtocol::Tcp, End::Dst, 53);
-            add_verdict(&mut block_tcp_rule, &Verdict::Reject(RejectionType::TcpRst));
-            self.batch.add(&block_tcp_rule, nftnl::MsgType::Add);
+            add_verdict(&mut block_tcp_rule, Verdict::RejectTcpRst);
+            self.add(*chain, block_tcp_rule);
         }
     }
 
-    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str) -> Result<()> {
-        self.batch.add(
-            &allow_interface_rule(&self.out_chain, Direction::Out, tunnel_interface)?,
-            nftnl::MsgType::Add,
+    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str) {
+        self.add(
+            ChainId::Out,
+            allow_interface_rule(Direction::Out, tunnel_interface),
         );
-        self.batch.add(
-            &allow_interface_rule(&self.forward_chain, Direction::Out, tunnel_interface)?,
-            nftnl::MsgType::Add,
+        self.add(
+            ChainId::Forward,
+            allow_interface_rule(Direction::Out, tunnel_interface),
         );
-        self.batch.add(
-            &allow_interface_rule(&self.in_chain, Direction::In, tunnel_interface)?,
-            nftnl::MsgType::Add,
+        self.add(
+            ChainId::In,
+            allow_interface_rule(Direction::In, tunnel_interface),
         );
 
-        let mut interface_rule = Rule::new(&self.forward_chain);
-        check_iface(&mut interface_rule, Direction::In, tunnel_interface)?;
-        interface_rule.add_expr(&nft_expr!(ct state));
-        let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
-        interface_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
-        interface_rule.add_expr(&nft_expr!(cmp != 0u32));
-        add_verdict(&mut interface_rule, &Verdict::Accept);
-        self.batch.add(&interface_rule, nftnl::MsgType::Add);
-
-        Ok(())
+        let mut interface_rule = vec![];
+        check_iface(&mut interface_rule, Direction::In, tunnel_interface);
+        interface_rule.push(Expr::CtStateEstablished);
+        add_verdict(&mut interface_rule, Verdict::Accept);
+        self.add(ChainId::Forward, interface_rule);
     }
 
     /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
@@ -832,40 +1503,40 @@ impl<'a> PolicyBatch<'a> {
     /// after the rule allowing the tunnel, otherwise even the tunnel can't talk to that IP.
     fn add_block_cve_2019_14899(&mut self, tunnel: &tunnel::TunnelMetadata) {
         for tunnel_ip in &tunnel.ips {
-            let mut rule = Rule::new(&self.in_chain);
+            let mut rule = vec![];
             check_ip(&mut rule, End::Dst, *tunnel_ip);
-            add_verdict(&mut rule, &Verdict::Drop);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+            add_verdict(&mut rule, Verdict::Drop);
+            self.add(ChainId::In, rule);
         }
     }
 
     fn add_allow_lan_rules(&mut self) {
         // Output and forward chains
-        for chain in &[&self.out_chain, &self.forward_chain] {
+        for chain in &[ChainId::Out, ChainId::Forward] {
             // LAN -> LAN
             for net in &*super::ALLOWED_LAN_NETS {
-                let mut out_rule = Rule::new(chain);
+                let mut out_rule = vec![];
                 check_net(&mut out_rule, End::Dst, *net);
-                add_verdict(&mut out_rule, &Verdict::Accept);
-                self.batch.add(&out_rule, nftnl::MsgType::Add);
+                add_verdict(&mut out_rule, Verdict::Accept);
+                self.add(*chain, out_rule);
             }
 
             // LAN -> Multicast
             for net in &*super::ALLOWED_LAN_MULTICAST_NETS {
-                let mut rule = Rule::new(chain);
+                let mut rule = vec![];
                 check_net(&mut rule, End::Dst, *net);
-                add_verdict(&mut rule, &Verdict::Accept);
-                self.batch.add(&rule, nftnl::MsgType::Add);
+                add_verdict(&mut rule, Verdict::Accept);
+                self.add(*chain, rule);
             }
         }
 
         // Input chain
         // LAN -> LAN
         for net in &*super::ALLOWED_LAN_NETS {
-            let mut in_rule = Rule::new(&self.in_chain);
+            let mut in_rule = vec![];
             check_net(&mut in_rule, End::Src, *net);
-            add_verdict(&mut in_rule, &Verdict::Accept);
-            self.batch.add(&in_rule, nftnl::MsgType::Add);
+            add_verdict(&mut in_rule, Verdict::Accept);
+            self.add(ChainId::In, in_rule);
         }
         self.add_dhcp_server_rules();
     }
@@ -874,117 +1545,115 @@ impl<'a> PolicyBatch<'a> {
         use TransportProtocol::Udp;
         // Outgoing DHCPv4 response
         {
-            let mut out_v4 = Rule::new(&self.out_chain);
+            let mut out_v4 = vec![];
             check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
             check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
+            add_verdict(&mut out_v4, Verdict::Accept);
+            self.add(ChainId::Out, out_v4);
         }
         // Incoming DHCPv4 request
         {
-            let mut in_v4 = Rule::new(&self.in_chain);
+            let mut in_v4 = vec![];
             check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
             check_endpoint(
                 &mut in_v4,
                 End::Dst,
                 &Endpoint::new(Ipv4Addr::BROADCAST, super::DHCPV4_SERVER_PORT, Udp),
             );
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+            add_verdict(&mut in_v4, Verdict::Accept);
+            self.add(ChainId::In, in_v4);
         }
     }
 }
 
-fn allow_interface_rule<'a>(
-    chain: &'a Chain<'_>,
-    direction: Direction,
-    iface: &str,
-) -> Result<Rule<'a>> {
-    let mut rule = Rule::new(&chain);
-    check_iface(&mut rule, direction, iface)?;
-    add_verdict(&mut rule, &Verdict::Accept);
+/// Formats the rules as an nft ruleset, similar to the output of `nft list ruleset`.
+impl fmt::Display for PolicyRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for table in &TableId::ALL {
+            writeln!(
+                f,
+                "table {} {} {{",
+                table.family_name(),
+                table.name().to_string_lossy()
+            )?;
+            let chains = ChainId::ALL.iter().filter(|chain| chain.table() == *table);
+            for (i, chain) in chains.enumerate() {
+                if i > 0 {
+                    writeln!(f)?;
+                }
+                writeln!(f, "\tchain {} {{", chain.name().to_string_lossy())?;
+                writeln!(f, "\t\t{}", chain.header())?;
+                for rule in self.chain_rules(*chain) {
+                    writeln!(f, "\t\t{}", rule)?;
+                }
+                writeln!(f, "\t}}")?;
+            }
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
 
-    Ok(rule)
+fn allow_interface_rule(direction: Direction, iface: &str) -> Vec<Expr> {
+    let mut rule = vec![];
+    check_iface(&mut rule, direction, iface);
+    add_verdict(&mut rule, Verdict::Accept);
+    rule
 }
 
-fn check_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result<()> {
-    let iface_index = crate::linux::iface_index(iface)
-        .map_err(|e| Error::LookupIfaceIndexError(iface.to_owned(), e))?;
-    rule.add_expr(&match direction {
-        Direction::In => nft_expr!(meta iif),
-        Direction::Out => nft_expr!(meta oif),
+fn check_iface(rule: &mut Vec<Expr>, direction: Direction, iface: &str) {
+    rule.push(Expr::Iface {
+        direction,
+        iface: iface.to_owned(),
+        equal: true,
     });
-    rule.add_expr(&nft_expr!(cmp == iface_index));
-    Ok(())
 }
 
-fn check_not_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result<()> {
-    let iface_index = crate::linux::iface_index(iface)
-        .map_err(|e| Error::LookupIfaceIndexError(iface.to_owned(), e))?;
-    rule.add_expr(&match direction {
-        Direction::In => nft_expr!(meta iif),
-        Direction::Out => nft_expr!(meta oif),
+fn check_not_iface(rule: &mut Vec<Expr>, direction: Direction, iface: &str) {
+    rule.push(Expr::Iface {
+        direction,
+        iface: iface.to_owned(),
+        equal: false,
     });
-    rule.add_expr(&nft_expr!(cmp != iface_index));
-    Ok(())
 }
 
-fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
+fn check_net(rule: &mut Vec<Expr>, end: End, net: impl Into<IpNetwork>) {
     let net = net.into();
     // Must check network layer protocol before loading network layer payload
     check_l3proto(rule, net.ip());
-
-    rule.add_expr(&match (net, end) {
-        (IpNetwork::V4(_), End::Src) => nft_expr!(payload ipv4 saddr),
-        (IpNetwork::V4(_), End::Dst) => nft_expr!(payload ipv4 daddr),
-        (IpNetwork::V6(_), End::Src) => nft_expr!(payload ipv6 saddr),
-        (IpNetwork::V6(_), End::Dst) => nft_expr!(payload ipv6 daddr),
-    });
-    match net {
-        IpNetwork::V4(_) => rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32)),
-        IpNetwork::V6(_) => rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor &[0u16; 8][..])),
-    };
-    rule.add_expr(&nft_expr!(cmp == net.ip()));
+    rule.push(Expr::Net { end, net });
 }
 
-fn check_endpoint(rule: &mut Rule<'_>, end: End, endpoint: &Endpoint) {
+fn check_endpoint(rule: &mut Vec<Expr>, end: End, endpoint: &Endpoint) {
     check_ip(rule, end, endpoint.address.ip());
     check_port(rule, endpoint.protocol, end, endpoint.address.port());
 }
 
-fn check_ip(rule: &mut Rule<'_>, end: End, ip: impl Into<IpAddr>) {
+fn check_ip(rule: &mut Vec<Expr>, end: End, ip: impl Into<IpAddr>) {
     let ip = ip.into();
     // Must check network layer protocol before loading network layer payload
     check_l3proto(rule, ip);
-
-    rule.add_expr(&match (ip, end) {
-        (IpAddr::V4(..), End::Src) => nft_expr!(payload ipv4 saddr),
-        (IpAddr::V4(..), End::Dst) => nft_expr!(payload ipv4 daddr),
-        (IpAddr::V6(..), End::Src) => nft_expr!(payload ipv6 saddr),
-        (IpAddr::V6(..), End::Dst) => nft_expr!(payload ipv6 daddr),
-    });
-    match ip {
-        IpAddr::V4(addr) => rule.add_expr(&nft_expr!(cmp == addr)),
-        IpAddr::V6(addr) => rule.add_expr(&nft_expr!(cmp == addr)),
-    }
+    rule.push(Expr::Addr { end, ip });
 }
 
-fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port: u16) {
+fn check_port(rule: &mut Vec<Expr>, protocol: TransportProtocol, end: End, port: u16) {
     // Must check transport layer protocol before loading transport layer payload
     check_l4proto(rule, protocol);
-
-    rule.add_expr(&match (protocol, end) {
-        (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
-        (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
-        (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
-        (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+    rule.push(Expr::Port {
+        protocol,
+        end,
+        port,
     });
-    rule.add_expr(&nft_expr!(cmp == port.to_be()));
 }
 
-fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
-    rule.add_expr(&nft_expr!(meta nfproto));
-    rule.add_expr(&nft_expr!(cmp == l3proto(ip)));
+fn check_icmpv6(rule: &mut Vec<Expr>, icmp_type: u8, code: u8) {
+    rule.push(Expr::L4Proto(libc::IPPROTO_ICMPV6 as u8));
+    rule.push(Expr::Icmpv6Type(icmp_type));
+    rule.push(Expr::Icmpv6Code(code));
+}
+
+fn check_l3proto(rule: &mut Vec<Expr>, ip: IpAddr) {
+    rule.push(Expr::L3Proto(l3proto(ip)));
 }
 
 fn l3proto(addr: IpAddr) -> u8 {
@@ -994,9 +1663,8 @@ fn l3proto(addr: IpAddr) -> u8 {
     }
 }
 
-fn check_l4proto(rule: &mut Rule<'_>, protocol: TransportProtocol) {
-    rule.add_expr(&nft_expr!(meta l4proto));
-    rule.add_expr(&nft_expr!(cmp == l4proto(protocol)));
+fn check_l4proto(rule: &mut Vec<Expr>, protocol: TransportProtocol) {
+    rule.push(Expr::L4Proto(l4proto(protocol)));
 }
 
 fn l4proto(protocol: TransportProtocol) -> u8 {
@@ -1006,9 +1674,833 @@ fn l4proto(protocol: TransportProtocol) -> u8 {
     }
 }
 
-fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
+fn add_verdict(rule: &mut Vec<Expr>, verdict: Verdict) {
     if *ADD_COUNTERS {
-        rule.add_expr(&nft_expr!(counter));
+        rule.push(Expr::Counter);
+    }
+    rule.push(Expr::Verdict(verdict));
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        net::Ipv6Addr,
+        sync::{Arc, Mutex},
+    };
+
+    /// Records the rules instead of installing them.
+    #[derive(Clone, Default)]
+    struct RecordingBackend {
+        rules: Arc<Mutex<Option<PolicyRules>>>,
+    }
+
+    impl RecordingBackend {
+        fn recorded(&self) -> Option<PolicyRules> {
+            self.rules.lock().unwrap().clone()
+        }
+    }
+
+    impl Backend for RecordingBackend {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn apply_policy(&mut self, _policy: &FirewallPolicy, rules: &PolicyRules) -> Result<()> {
+            *self.rules.lock().unwrap() = Some(rules.clone());
+            Ok(())
+        }
+
+        fn reset_policy(&mut self) -> Result<()> {
+            *self.rules.lock().unwrap() = None;
+            Ok(())
+        }
+
+        fn installed_rules(&self) -> Result<String> {
+            Ok(self
+                .recorded()
+                .map(|rules| rules.to_string())
+                .unwrap_or_default())
+        }
+    }
+
+    fn chain_rules(policy: &FirewallPolicy, chain: ChainId) -> Vec<String> {
+        PolicyRules::new(policy)
+            .chain_rules(chain)
+            .map(|rule| rule.to_string())
+            .collect()
+    }
+
+    fn tunnel() -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: "wg-mullvad".to_string(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+        }
+    }
+
+    /// Rules that are added to the output chain regardless of the policy.
+    const COMMON_OUT_RULES: [&str; 6] = [
+        "oif \"lo\" accept",
+        "ct mark 0x00000f41 accept",
+        "meta l4proto udp udp sport 68 meta nfproto ipv4 ip daddr 255.255.255.255 meta l4proto udp udp dport 67 accept",
+        "meta nfproto ipv6 ip6 saddr fe80::/10 meta l4proto udp udp sport 546 meta nfproto ipv6 ip6 daddr ff02::1:2 meta l4proto udp udp dport 547 accept",
+        "meta nfproto ipv6 ip6 saddr fe80::/10 meta l4proto udp udp sport 546 meta nfproto ipv6 ip6 daddr ff05::1:3 meta l4proto udp udp dport 547 accept",
+        "meta nfproto ipv6 ip6 daddr ff02::2 meta l4proto ipv6-icmp icmpv6 type 133 icmpv6 code 0 accept",
+    ];
+
+    const DROP_DNS_RULES: [&str; 2] = [
+        "meta l4proto udp udp dport 53 reject with icmpx type port-unreachable",
+        "meta l4proto tcp tcp dport 53 reject with tcp reset",
+    ];
+
+    const REJECT_RULE: &str = "reject with icmpx type port-unreachable";
+
+    #[test]
+    fn test_render_blocked() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+
+        let mut expected = COMMON_OUT_RULES.to_vec();
+        expected.push("meta nfproto ipv4 ip daddr 1.2.3.4 meta l4proto tcp tcp dport 443 accept");
+        expected.extend(&DROP_DNS_RULES);
+        expected.push(REJECT_RULE);
+        assert_eq!(chain_rules(&policy, ChainId::Out), expected);
+
+        assert_eq!(
+            chain_rules(&policy, ChainId::MangleV4),
+            vec!["meta cgroup 5087041 ct mark set 0x00000f41 meta mark set 0x6d6f6c65"]
+        );
+        assert!(chain_rules(&policy, ChainId::Prerouting).is_empty());
+
+        let rendered = Firewall::render_policy(&policy);
+        assert!(rendered.starts_with(
+            "table inet mullvad {\n\tchain prerouting {\n\t\ttype filter hook prerouting priority -199; policy accept;\n\t}\n\n\tchain output {\n\t\ttype filter hook output priority 0; policy drop;\n\t\toif \"lo\" accept\n"
+        ));
+        assert!(rendered.contains(
+            "table ip mullvadmangle4 {\n\tchain mangle {\n\t\ttype route hook output priority -150; policy accept;\n"
+        ));
+        assert!(rendered.contains(
+            "\tchain nat {\n\t\ttype nat hook postrouting priority 100; policy accept;\n\t\toif != \"lo\" ct mark 0x00000f41 masquerade\n\t}\n}\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_connecting() {
+        let peer_endpoint = Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 51820, TransportProtocol::Udp);
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint,
+            tunnel: Some(tunnel()),
+            allow_lan: true,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+
+        let out_rules = chain_rules(&policy, ChainId::Out);
+        assert_eq!(out_rules[..COMMON_OUT_RULES.len()], COMMON_OUT_RULES[..]);
+        let position = |rule: &str| {
+            out_rules
+                .iter()
+                .position(|out_rule| out_rule == rule)
+                .unwrap_or_else(|| panic!("missing rule: {}", rule))
+        };
+
+        let relay = position("meta nfproto ipv4 ip daddr 5.6.7.8 meta l4proto udp udp dport 51820 meta mark 0x6d6f6c65 accept");
+        let drop_dns = position(DROP_DNS_RULES[0]);
+        let tunnel_iface = position("oif \"wg-mullvad\" accept");
+        let lan = position("meta nfproto ipv4 ip daddr 10.0.0.0/8 accept");
+        let dhcp_server =
+            position("meta l4proto udp udp sport 67 meta l4proto udp udp dport 68 accept");
+        assert!(relay < drop_dns);
+        assert!(drop_dns < tunnel_iface);
+        assert!(tunnel_iface < lan);
+        assert!(lan < dhcp_server);
+        assert_eq!(out_rules.last().unwrap(), REJECT_RULE);
+
+        assert_eq!(
+            chain_rules(&policy, ChainId::Prerouting),
+            vec!["meta nfproto ipv4 ip saddr 5.6.7.8 meta l4proto udp udp sport 51820 meta mark set 0x6d6f6c65"]
+        );
+        assert!(chain_rules(&policy, ChainId::In).contains(
+            &"meta nfproto ipv4 ip saddr 5.6.7.8 meta l4proto udp udp sport 51820 ct state established accept".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_render_connected() {
+        let policy = FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 51820, TransportProtocol::Udp),
+            tunnel: tunnel(),
+            allow_lan: false,
+            dns_servers: vec![
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+            ],
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+
+        let mut expected = COMMON_OUT_RULES.to_vec();
+        expected.extend(&[
+            "meta nfproto ipv4 ip daddr 5.6.7.8 meta l4proto udp udp dport 51820 meta mark 0x6d6f6c65 accept",
+            "oif != \"wg-mullvad\" meta l4proto udp udp dport 53 meta nfproto ipv4 ip daddr 192.168.1.1 accept",
+            "oif \"wg-mullvad\" meta l4proto udp udp dport 53 meta nfproto ipv4 ip daddr 10.64.0.1 accept",
+            "oif != \"wg-mullvad\" meta l4proto tcp tcp dport 53 meta nfproto ipv4 ip daddr 192.168.1.1 accept",
+            "oif \"wg-mullvad\" meta l4proto tcp tcp dport 53 meta nfproto ipv4 ip daddr 10.64.0.1 accept",
+        ]);
+        expected.extend(&DROP_DNS_RULES);
+        expected.push("oif \"wg-mullvad\" accept");
+        expected.push(REJECT_RULE);
+        assert_eq!(chain_rules(&policy, ChainId::Out), expected);
+
+        assert_eq!(
+            chain_rules(&policy, ChainId::NatV4),
+            vec![
+                "oif \"wg-mullvad\" meta l4proto udp udp dport 53 ip daddr 10.64.0.1 ct mark 0x00000f41 accept",
+                "oif \"wg-mullvad\" meta l4proto tcp tcp dport 53 ip daddr 10.64.0.1 ct mark 0x00000f41 accept",
+                "oif \"wg-mullvad\" ct mark 0x00000f41 drop",
+                "oif != \"lo\" ct mark 0x00000f41 masquerade",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&policy, ChainId::NatV6),
+            vec![
+                "oif \"wg-mullvad\" ct mark 0x00000f41 drop",
+                "oif != \"lo\" ct mark 0x00000f41 masquerade",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&policy, ChainId::Prerouting),
+            vec![
+                "iif != \"wg-mullvad\" ct mark 0x00000f41 meta mark set 0x6d6f6c65",
+                "meta nfproto ipv4 ip saddr 5.6.7.8 meta l4proto udp udp sport 51820 meta mark set 0x6d6f6c65",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_allow_rules() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![
+                AllowRule {
+                    direction: AllowRuleDirection::Outbound,
+                    network: "192.168.5.0/24".parse().unwrap(),
+                    port: Some(22),
+                    protocol: Some(TransportProtocol::Tcp),
+                    cgroup: None,
+                    uid: Some(1000),
+                },
+                AllowRule {
+                    direction: AllowRuleDirection::Inbound,
+                    network: "2001:db8::/32".parse().unwrap(),
+                    port: None,
+                    protocol: None,
+                    cgroup: None,
+                    uid: None,
+                },
+            ],
+            excluded_uids: vec![],
+        };
+        assert!(policy.to_string().ends_with(
+            ". Custom allow rules: outbound to 192.168.5.0/24 over TCP port 22 uid 1000, inbound from 2001:db8::/32"
+        ));
+
+        // The custom rules come after the DNS rules, so they can't allow DNS to leak
+        let mut expected = COMMON_OUT_RULES.to_vec();
+        expected.push("meta nfproto ipv4 ip daddr 1.2.3.4 meta l4proto tcp tcp dport 443 accept");
+        expected.extend(&DROP_DNS_RULES);
+        expected.extend(&[
+            "meta skuid 1000 meta nfproto ipv4 ip daddr 192.168.5.0/24 meta l4proto tcp tcp dport 22 accept",
+            "meta nfproto ipv6 ip6 daddr 2001:db8::/32 ct state established accept",
+        ]);
+        expected.push(REJECT_RULE);
+        assert_eq!(chain_rules(&policy, ChainId::Out), expected);
+
+        let in_rules = chain_rules(&policy, ChainId::In);
+        assert!(in_rules.contains(&"meta nfproto ipv4 ip saddr 192.168.5.0/24 meta l4proto tcp tcp sport 22 ct state established accept".to_string()));
+        assert!(in_rules.contains(&"meta nfproto ipv6 ip6 saddr 2001:db8::/32 accept".to_string()));
+    }
+
+    #[test]
+    fn test_allow_rules_dont_allow_dns() {
+        let allow_all = AllowRule {
+            direction: AllowRuleDirection::Outbound,
+            network: "0.0.0.0/0".parse().unwrap(),
+            port: None,
+            protocol: None,
+            cgroup: None,
+            uid: None,
+        };
+        for mut policy in all_policies() {
+            match &mut policy {
+                FirewallPolicy::Connecting { allow_rules, .. }
+                | FirewallPolicy::Connected { allow_rules, .. }
+                | FirewallPolicy::Blocked { allow_rules, .. } => {
+                    allow_rules.push(allow_all.clone())
+                }
+            }
+            let out_rules = chain_rules(&policy, ChainId::Out);
+            let position = |rule: &str| out_rules.iter().position(|r| r == rule).unwrap();
+            assert!(
+                position(DROP_DNS_RULES[1])
+                    < position("meta nfproto ipv4 ip daddr 0.0.0.0/0 accept"),
+                "{}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn test_ignore_inbound_owner_rules() {
+        let inbound_rule = |cgroup, uid| AllowRule {
+            direction: AllowRuleDirection::Inbound,
+            network: "192.168.5.0/24".parse().unwrap(),
+            port: Some(22),
+            protocol: Some(TransportProtocol::Tcp),
+            cgroup,
+            uid,
+        };
+        let policy = |allow_rules| FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules,
+            excluded_uids: vec![],
+        };
+        let without_rules = PolicyRules::new(&policy(vec![]));
+        for rule in &[
+            inbound_rule(None, Some(1000)),
+            inbound_rule(Some(0x100001), None),
+        ] {
+            assert_eq!(PolicyRules::new(&policy(vec![rule.clone()])), without_rules);
+        }
+        assert_ne!(
+            PolicyRules::new(&policy(vec![inbound_rule(None, None)])),
+            without_rules
+        );
+    }
+
+    #[test]
+    fn test_audit_rules() {
+        let mut policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+        // Nothing is logged unless the audit is enabled
+        assert!(PolicyRules::new(&policy)
+            .rules
+            .iter()
+            .all(|rule| !rule.exprs.iter().any(|expr| matches!(expr, Expr::Log(_)))));
+
+        let rules = PolicyRules::with_audit(&policy, true);
+        let out_rules: Vec<String> = rules
+            .chain_rules(ChainId::Out)
+            .map(|rule| rule.to_string())
+            .collect();
+
+        let mut expected = COMMON_OUT_RULES.to_vec();
+        expected.extend(&[
+            "meta nfproto ipv4 ip daddr 1.2.3.4 meta l4proto tcp tcp dport 443 accept",
+            "meta l4proto udp udp dport 53 log prefix \"mullvad-out\" group 3047 reject with icmpx type port-unreachable",
+            "meta l4proto tcp tcp dport 53 log prefix \"mullvad-out\" group 3047 reject with tcp reset",
+            "log prefix \"mullvad-out\" group 3047 reject with icmpx type port-unreachable",
+            "log prefix \"mullvad-out\" group 3047",
+        ]);
+        assert_eq!(out_rules, expected);
+        assert_eq!(
+            rules.chain_rules(ChainId::In).last().unwrap().to_string(),
+            "log prefix \"mullvad-in\" group 3047"
+        );
+        assert!(rules
+            .chain_rules(ChainId::MangleV4)
+            .all(|rule| !rule.exprs.iter().any(|expr| matches!(expr, Expr::Log(_)))));
+
+        // Nothing is logged while connected
+        policy = FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1234, TransportProtocol::Udp),
+            tunnel: tunnel(),
+            allow_lan: false,
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            allow_rules: vec![],
+            excluded_uids: vec![],
+        };
+        assert_eq!(
+            PolicyRules::with_audit(&policy, true),
+            PolicyRules::new(&policy)
+        );
+    }
+
+    #[test]
+    fn test_render_excluded_uids() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp),
+            allow_rules: vec![],
+            excluded_uids: vec![998, 999],
+        };
+        assert!(policy.to_string().ends_with(". Excluded UIDs: 998, 999"));
+
+        for chain in &[ChainId::MangleV4, ChainId::MangleV6] {
+            assert_eq!(
+                chain_rules(&policy, *chain),
+                vec![
+                    "meta cgroup 5087041 ct mark set 0x00000f41 meta mark set 0x6d6f6c65",
+                    "meta skuid 998 ct mark set 0x00000f41 meta mark set 0x6d6f6c65",
+                    "meta skuid 999 ct mark set 0x00000f41 meta mark set 0x6d6f6c65",
+                ]
+            );
+        }
+    }
+
+    fn tunnel_v4_only() -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: "tun0".to_string(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+            ipv6_gateway: None,
+        }
+    }
+
+    /// Returns every combination of policy state, LAN access, IP version of the relay, tunnel
+    /// and DNS servers.
+    pub(super) fn all_policies() -> Vec<FirewallPolicy> {
+        let allowed_endpoint =
+            Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 443, TransportProtocol::Tcp);
+        let peer_endpoints = [
+            Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 51820, TransportProtocol::Udp),
+            Endpoint::new(
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+                53,
+                TransportProtocol::Tcp,
+            ),
+        ];
+        let dns_server_sets: Vec<Vec<IpAddr>> = vec![
+            vec![],
+            vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+                IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+            ],
+            vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))],
+            vec![
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0, 0, 0, 0, 0, 0x1111)),
+            ],
+            vec![IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53))],
+        ];
+
+        let mut policies = vec![];
+        for allow_lan in &[false, true] {
+            policies.push(FirewallPolicy::Blocked {
+                allow_lan: *allow_lan,
+                allowed_endpoint,
+                allow_rules: vec![],
+                excluded_uids: vec![],
+            });
+            for peer_endpoint in &peer_endpoints {
+                policies.push(FirewallPolicy::Connecting {
+                    peer_endpoint: *peer_endpoint,
+                    tunnel: None,
+                    allow_lan: *allow_lan,
+                    allowed_endpoint,
+                    allow_rules: vec![],
+                    excluded_uids: vec![],
+                });
+                for tunnel in &[tunnel(), tunnel_v4_only()] {
+                    policies.push(FirewallPolicy::Connecting {
+                        peer_endpoint: *peer_endpoint,
+                        tunnel: Some(tunnel.clone()),
+                        allow_lan: *allow_lan,
+                        allowed_endpoint,
+                        allow_rules: vec![],
+                        excluded_uids: vec![],
+                    });
+                    for dns_servers in &dns_server_sets {
+                        policies.push(FirewallPolicy::Connected {
+                            peer_endpoint: *peer_endpoint,
+                            tunnel: tunnel.clone(),
+                            allow_lan: *allow_lan,
+                            dns_servers: dns_servers.clone(),
+                            allow_rules: vec![],
+                            excluded_uids: vec![],
+                        });
+                    }
+                }
+            }
+        }
+        policies
+    }
+
+    fn rules_in(rules: &PolicyRules, chain: ChainId) -> Vec<&RuleSpec> {
+        rules.chain_rules(chain).collect()
+    }
+
+    fn accepts(rule: &RuleSpec) -> bool {
+        rule.exprs.last() == Some(&Expr::Verdict(Verdict::Accept))
+    }
+
+    fn dst_addr(rule: &RuleSpec) -> Option<IpAddr> {
+        rule.exprs.iter().find_map(|expr| match expr {
+            Expr::Addr { end: End::Dst, ip } => Some(*ip),
+            _ => None,
+        })
+    }
+
+    fn has_dns_port(rule: &RuleSpec) -> bool {
+        rule.exprs.iter().any(|expr| match expr {
+            Expr::Port { end, port, .. } => *end == End::Dst && *port == 53,
+            _ => false,
+        })
+    }
+
+    /// Asserts that network and transport layer payloads are only matched after the protocol of
+    /// the corresponding layer, as required by the inet table.
+    fn assert_protocol_checked(rule: &RuleSpec) {
+        for (i, expr) in rule.exprs.iter().enumerate() {
+            let required = match expr {
+                Expr::Addr { ip, .. } => Expr::L3Proto(l3proto(*ip)),
+                Expr::Net { net, .. } => Expr::L3Proto(l3proto(net.ip())),
+                Expr::Port { protocol, .. } => Expr::L4Proto(l4proto(*protocol)),
+                Expr::Icmpv6Type(_) | Expr::Icmpv6Code(_) => {
+                    Expr::L4Proto(libc::IPPROTO_ICMPV6 as u8)
+                }
+                _ => continue,
+            };
+            assert!(
+                rule.exprs[..i].contains(&required),
+                "\"{}\" is not checked in \"{}\"",
+                required,
+                rule
+            );
+        }
+    }
+
+    fn check_policy_rules(policy: &FirewallPolicy, rules: &PolicyRules) {
+        let out_rules = rules_in(rules, ChainId::Out);
+        let in_rules = rules_in(rules, ChainId::In);
+        let loopback_out = allow_interface_rule(Direction::Out, "lo");
+        let loopback_in = allow_interface_rule(Direction::In, "lo");
+        let reject = vec![Expr::Verdict(Verdict::Reject)];
+
+        // Loopback traffic is always allowed, and any remaining traffic is always rejected
+        assert_eq!(out_rules.first().unwrap().exprs, loopback_out);
+        assert_eq!(in_rules.first().unwrap().exprs, loopback_in);
+        assert_eq!(out_rules.last().unwrap().exprs, reject);
+        assert_eq!(
+            rules_in(rules, ChainId::Forward).last().unwrap().exprs,
+            reject
+        );
+
+        // Excluded processes are always marked and allowed
+        let split_tunnel_mark = Expr::CtMark(split_tunnel::MARK as u32);
+        for chain in &[ChainId::MangleV4, ChainId::MangleV6] {
+            assert!(rules_in(rules, *chain)[0]
+                .exprs
+                .contains(&Expr::Cgroup(split_tunnel::NET_CLS_CLASSID)));
+        }
+        for specs in &[&in_rules, &out_rules] {
+            assert!(specs
+                .iter()
+                .any(|rule| rule.exprs
+                    == [split_tunnel_mark.clone(), Expr::Verdict(Verdict::Accept)]));
+        }
+        for chain in &[ChainId::NatV4, ChainId::NatV6] {
+            let nat_rules = rules_in(rules, *chain);
+            assert!(nat_rules.last().unwrap().exprs.contains(&Expr::Masquerade));
+        }
+
+        for chain in &[
+            ChainId::Prerouting,
+            ChainId::Out,
+            ChainId::In,
+            ChainId::Forward,
+        ] {
+            for rule in rules_in(rules, *chain) {
+                assert_protocol_checked(rule);
+            }
+        }
+
+        let (allow_lan, peer_endpoint, tunnel, dns_servers) = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                ..
+            } => (*allow_lan, Some(peer_endpoint), tunnel.as_ref(), &[][..]),
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                dns_servers,
+                ..
+            } => (
+                *allow_lan,
+                Some(peer_endpoint),
+                Some(tunnel),
+                &dns_servers[..],
+            ),
+            FirewallPolicy::Blocked { allow_lan, .. } => (*allow_lan, None, None, &[][..]),
+        };
+        let allowed_endpoint = match policy {
+            FirewallPolicy::Connecting {
+                allowed_endpoint, ..
+            }
+            | FirewallPolicy::Blocked {
+                allowed_endpoint, ..
+            } => Some(allowed_endpoint),
+            FirewallPolicy::Connected { .. } => None,
+        };
+
+        // LAN networks are only reachable if LAN access is allowed
+        let lan_net = Expr::Net {
+            end: End::Dst,
+            net: super::super::ALLOWED_LAN_NETS[2],
+        };
+        let lan_rule = out_rules
+            .iter()
+            .position(|rule| rule.exprs.contains(&lan_net));
+        assert_eq!(lan_rule.is_some(), allow_lan, "{}", policy);
+
+        // DNS is rejected before LAN and tunnel traffic is accepted. Only the relay, the allowed
+        // endpoint and the configured DNS servers may be reached on port 53 before that.
+        let drop_dns = out_rules
+            .iter()
+            .position(|rule| has_dns_port(rule) && !accepts(rule))
+            .expect("DNS is not rejected");
+        if let Some(lan_rule) = lan_rule {
+            assert!(drop_dns < lan_rule);
+        }
+        let mut allowed_hosts: Vec<IpAddr> = dns_servers.to_vec();
+        allowed_hosts.extend(peer_endpoint.map(|endpoint| endpoint.address.ip()));
+        allowed_hosts.extend(allowed_endpoint.map(|endpoint| endpoint.address.ip()));
+        for rule in &out_rules[..drop_dns] {
+            if !accepts(rule) || rule.exprs.contains(&split_tunnel_mark) {
+                continue;
+            }
+            let is_loopback = rule.exprs == loopback_out;
+            let is_dhcp_or_ndp = rule.exprs.iter().any(|expr| match expr {
+                Expr::Port { port, .. } => {
+                    *port == super::super::DHCPV4_SERVER_PORT
+                        || *port == super::super::DHCPV6_SERVER_PORT
+                }
+                Expr::Icmpv6Type(_) => true,
+                _ => false,
+            });
+            let to_allowed_host = dst_addr(rule)
+                .map(|ip| allowed_hosts.contains(&ip))
+                .unwrap_or(false);
+            assert!(
+                is_loopback || is_dhcp_or_ndp || to_allowed_host,
+                "\"{}\" may leak DNS for policy {}",
+                rule,
+                policy
+            );
+        }
+
+        // Traffic to the relay is only allowed for packets marked by the tunnel
+        let relay_rules: Vec<_> = out_rules
+            .iter()
+            .filter(|rule| {
+                rule.exprs
+                    .contains(&Expr::MetaMark(crate::linux::TUNNEL_FW_MARK))
+            })
+            .collect();
+        match peer_endpoint {
+            Some(peer_endpoint) => {
+                assert_eq!(relay_rules.len(), 1);
+                let ip = peer_endpoint.address.ip();
+                assert_eq!(dst_addr(relay_rules[0]), Some(ip));
+                assert!(relay_rules[0].exprs.contains(&Expr::L3Proto(l3proto(ip))));
+                assert!(rules_in(rules, ChainId::Prerouting)
+                    .iter()
+                    .any(|rule| rule.exprs.contains(&Expr::Addr { end: End::Src, ip })));
+            }
+            None => {
+                assert!(relay_rules.is_empty());
+                assert!(rules_in(rules, ChainId::Prerouting).is_empty());
+            }
+        }
+
+        // The tunnel interface is only allowed once it exists
+        let tunnel_rules = out_rules
+            .iter()
+            .filter(|rule| match rule.exprs.first() {
+                Some(Expr::Iface { iface, .. }) => iface != "lo",
+                _ => false,
+            })
+            .count();
+        match tunnel {
+            Some(tunnel) => {
+                let allow_tunnel = allow_interface_rule(Direction::Out, &tunnel.interface);
+                let position = out_rules
+                    .iter()
+                    .position(|rule| rule.exprs == allow_tunnel)
+                    .expect("tunnel is not allowed");
+                assert!(drop_dns < position);
+            }
+            None => assert_eq!(tunnel_rules, 0),
+        }
+
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            for server in dns_servers {
+                let is_gateway = *server == IpAddr::V4(tunnel.ipv4_gateway)
+                    || tunnel.ipv6_gateway.map(IpAddr::V6) == Some(*server);
+                let is_local = super::super::is_local_address(server) && !is_gateway;
+                for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    let expected = Expr::Iface {
+                        direction: Direction::Out,
+                        iface: tunnel.interface.clone(),
+                        equal: !is_local,
+                    };
+                    let port = Expr::Port {
+                        protocol: *protocol,
+                        end: End::Dst,
+                        port: 53,
+                    };
+                    assert!(out_rules.iter().any(|rule| rule.exprs.contains(&expected)
+                        && rule.exprs.contains(&port)
+                        && dst_addr(rule) == Some(*server)));
+                }
+
+                let nat_chain = match server {
+                    IpAddr::V4(_) => ChainId::NatV4,
+                    IpAddr::V6(_) => ChainId::NatV6,
+                };
+                let nat_dns_allowed = rules_in(rules, nat_chain)
+                    .iter()
+                    .any(|rule| dst_addr(rule) == Some(*server));
+                assert_eq!(nat_dns_allowed, is_gateway);
+            }
+
+            // CVE-2019-14899 is only mitigated when LAN traffic is allowed
+            for ip in &tunnel.ips {
+                let block_rule = in_rules.iter().any(|rule| {
+                    rule.exprs.contains(&Expr::Addr {
+                        end: End::Dst,
+                        ip: *ip,
+                    }) && rule.exprs.last() == Some(&Expr::Verdict(Verdict::Drop))
+                });
+                assert_eq!(block_rule, allow_lan);
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_policies() {
+        let backend = RecordingBackend::default();
+        let mut firewall = Firewall::with_backend(Box::new(backend.clone()));
+
+        for policy in all_policies() {
+            firewall.apply_policy(policy.clone()).unwrap();
+
+            let rules = backend.recorded().unwrap();
+            assert_eq!(rules, PolicyRules::new(&policy));
+            check_policy_rules(&policy, &rules);
+
+            let firewall_rules = firewall.rules();
+            assert_eq!(firewall_rules.policy, Some(policy.to_string()));
+            assert_eq!(
+                firewall_rules.intended_rules,
+                Some(firewall_rules.installed_rules.unwrap())
+            );
+        }
+
+        firewall.reset_policy().unwrap();
+        assert_eq!(backend.recorded(), None);
+        assert_eq!(firewall.rules().policy, None);
+    }
+
+    /// Backend whose tables never show up, like nftables on hosts that don't support it.
+    struct BrokenBackend;
+
+    impl Backend for BrokenBackend {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn apply_policy(&mut self, _policy: &FirewallPolicy, _rules: &PolicyRules) -> Result<()> {
+            Err(Error::NetfilterTableNotSetError)
+        }
+
+        fn reset_policy(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn installed_rules(&self) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    /// Backend that can't talk to netfilter at all.
+    struct UnreachableBackend;
+
+    impl Backend for UnreachableBackend {
+        fn name(&self) -> &'static str {
+            "unreachable"
+        }
+
+        fn apply_policy(&mut self, _policy: &FirewallPolicy, _rules: &PolicyRules) -> Result<()> {
+            Err(Error::NetlinkSendError(io::Error::from_raw_os_error(
+                libc::EPROTONOSUPPORT,
+            )))
+        }
+
+        fn reset_policy(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn installed_rules(&self) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn test_fallback_on_netlink_error() {
+        let policy = all_policies().remove(0);
+
+        let fallback = RecordingBackend::default();
+        let mut firewall = Firewall::with_backend(Box::new(UnreachableBackend));
+        firewall.inactive_backend = Some(Box::new(fallback.clone()));
+        firewall.apply_policy(policy.clone()).unwrap();
+        assert_eq!(fallback.recorded(), Some(PolicyRules::new(&policy)));
+        assert_eq!(firewall.rules().backend, "recording");
+
+        // Netlink errors only cause a fallback until the backend has worked once
+        let mut firewall = Firewall::with_backend(Box::new(UnreachableBackend));
+        firewall.inactive_backend = Some(Box::new(fallback.clone()));
+        firewall.backend_verified = true;
+        assert!(firewall.apply_policy(policy).is_err());
+        assert_eq!(firewall.rules().backend, "unreachable");
+    }
+
+    #[test]
+    fn test_fallback_backend() {
+        let policy = all_policies().remove(0);
+
+        let mut firewall = Firewall::with_backend(Box::new(BrokenBackend));
+        assert!(firewall.apply_policy(policy.clone()).is_err());
+
+        let fallback = RecordingBackend::default();
+        let mut firewall = Firewall::with_backend(Box::new(BrokenBackend));
+        firewall.inactive_backend = Some(Box::new(fallback.clone()));
+        firewall.apply_policy(policy.clone()).unwrap();
+        assert_eq!(fallback.recorded(), Some(PolicyRules::new(&policy)));
+        assert_eq!(firewall.rules().backend, "recording");
+
+        // Both backends are reset
+        let mut firewall = Firewall::with_backend(Box::new(BrokenBackend));
+        firewall.inactive_backend = Some(Box::new(fallback.clone()));
+        firewall.reset_policy().unwrap();
+        assert_eq!(fallback.recorded(), None);
     }
-    rule.add_expr(verdict);
 }
@@ -7,8 +7,10 @@ use std::fmt;
 use std::net::IpAddr;
 #[cfg(unix)]
 use std::net::{Ipv4Addr, Ipv6Addr};
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
 use talpid_types::net::Endpoint;
 
 
@@ -29,6 +31,10 @@ mod imp;
 mod imp;
 
 pub use self::imp::Error;
+#[cfg(target_os = "linux")]
+pub use self::imp::FirewallRules;
+#[cfg(target_os = "linux")]
+pub use self::imp::{BlockedDirection, BlockedPacket, BlockedTrafficStats};
 
 #[cfg(unix)]
 lazy_static! {
@@ -110,6 +116,12 @@ pub enum FirewallPolicy {
         allow_lan: bool,
         /// Host that should be reachable by the tunnel client while connecting.
         allowed_endpoint: Endpoint,
+        /// Custom rules for traffic that should be allowed.
+        #[cfg(target_os = "linux")]
+        allow_rules: Vec<AllowRule>,
+        /// Users whose traffic should be excluded from the tunnel.
+        #[cfg(target_os = "linux")]
+        excluded_uids: Vec<u32>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -126,6 +138,12 @@ pub enum FirewallPolicy {
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Custom rules for traffic that should be allowed.
+        #[cfg(target_os = "linux")]
+        allow_rules: Vec<AllowRule>,
+        /// Users whose traffic should be excluded from the tunnel.
+        #[cfg(target_os = "linux")]
+        excluded_uids: Vec<u32>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -137,9 +155,37 @@ pub enum FirewallPolicy {
         allow_lan: bool,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: Endpoint,
+        /// Custom rules for traffic that should be allowed.
+        #[cfg(target_os = "linux")]
+        allow_rules: Vec<AllowRule>,
+        /// Users whose traffic should be excluded from the tunnel.
+        #[cfg(target_os = "linux")]
+        excluded_uids: Vec<u32>,
     },
 }
 
+impl FirewallPolicy {
+    /// Returns the custom rules for traffic that should be allowed regardless of the policy.
+    #[cfg(target_os = "linux")]
+    pub fn allow_rules(&self) -> &[AllowRule] {
+        match self {
+            FirewallPolicy::Connecting { allow_rules, .. }
+            | FirewallPolicy::Connected { allow_rules, .. }
+            | FirewallPolicy::Blocked { allow_rules, .. } => allow_rules,
+        }
+    }
+
+    /// Returns the users whose traffic should be excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    pub fn excluded_uids(&self) -> &[u32] {
+        match self {
+            FirewallPolicy::Connecting { excluded_uids, .. }
+            | FirewallPolicy::Connected { excluded_uids, .. }
+            | FirewallPolicy::Blocked { excluded_uids, .. } => excluded_uids,
+        }
+    }
+}
+
 impl fmt::Display for FirewallPolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -197,13 +243,43 @@ impl fmt::Display for FirewallPolicy {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => write!(
                 f,
                 "Blocked. {} LAN. Allowing endpoint {}",
                 if *allow_lan { "Allowing" } else { "Blocking" },
                 allowed_endpoint,
             ),
+        }?;
+
+        #[cfg(target_os = "linux")]
+        {
+            let allow_rules = self.allow_rules();
+            if !allow_rules.is_empty() {
+                write!(
+                    f,
+                    ". Custom allow rules: {}",
+                    allow_rules
+                        .iter()
+                        .map(|rule| rule.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+            }
+            let excluded_uids = self.excluded_uids();
+            if !excluded_uids.is_empty() {
+                write!(
+                    f,
+                    ". Excluded UIDs: {}",
+                    excluded_uids
+                        .iter()
+                        .map(|uid| uid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -221,6 +297,12 @@ pub struct FirewallArguments {
     pub allow_lan: bool,
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allowed_endpoint: Option<Endpoint>,
+    /// Directory to write the blocked traffic log to, if blocked traffic is audited.
+    #[cfg(target_os = "linux")]
+    pub log_dir: Option<PathBuf>,
+    /// Whether the packets dropped by the blocking policies should be counted and logged.
+    #[cfg(target_os = "linux")]
+    pub audit_blocked_traffic: bool,
 }
 
 impl Firewall {
@@ -244,6 +326,32 @@ impl Firewall {
         log::info!("Resetting firewall policy");
         self.inner.reset_policy()
     }
+
+    /// Returns the rules that would be applied for the given policy, without applying them.
+    #[cfg(target_os = "linux")]
+    pub fn render_policy(policy: &FirewallPolicy) -> String {
+        imp::Firewall::render_policy(policy)
+    }
+
+    /// Returns the currently applied policy, its intended rules and the rules that are actually
+    /// installed.
+    #[cfg(target_os = "linux")]
+    pub fn rules(&self) -> FirewallRules {
+        self.inner.rules()
+    }
+
+    /// Returns counters and samples of the traffic that has been blocked while the audit was
+    /// enabled.
+    #[cfg(target_os = "linux")]
+    pub fn blocked_traffic_stats(&self) -> BlockedTrafficStats {
+        self.inner.blocked_traffic_stats()
+    }
+
+    /// Starts or stops counting and logging the traffic that is blocked.
+    #[cfg(target_os = "linux")]
+    pub fn set_audit_blocked_traffic(&mut self, enabled: bool) -> Result<(), Error> {
+        self.inner.set_audit_blocked_traffic(enabled)
+    }
 }
 
 /// Abstract firewall interaction trait. Used by the OS specific implementations.
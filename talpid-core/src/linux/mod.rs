@@ -1,3 +1,5 @@
+pub mod netns;
+
 use std::{
     ffi::{self, CString},
     fs, io,
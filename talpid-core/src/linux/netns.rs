@@ -0,0 +1,240 @@
+//! Named network namespaces, compatible with the ones managed by `ip netns`.
+
+use nix::{
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+};
+use std::{
+    fs, io,
+    net::IpAddr,
+    os::unix::io::{AsRawFd, RawFd},
+    thread,
+};
+use talpid_types::netns::{
+    is_created_by_daemon, is_valid_name, netns_path, resolv_conf_path, write_marker, NETNS_RUN_DIR,
+};
+
+/// Errors related to network namespaces.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The namespace name cannot be used as a file name.
+    #[error(display = "Invalid network namespace name: {}", _0)]
+    InvalidName(String),
+
+    /// Failed to create the directory in which namespaces are mounted.
+    #[error(display = "Failed to create {}", NETNS_RUN_DIR)]
+    CreateRunDir(#[error(source)] io::Error),
+
+    /// Failed to create or open the file referring to the namespace.
+    #[error(display = "Failed to open network namespace {}", _0)]
+    Open(String, #[error(source)] io::Error),
+
+    /// A namespace with the name exists, but it was not created by the daemon.
+    #[error(
+        display = "Network namespace {} was not created by the daemon. Remove it with \"ip netns \
+                   delete {}\"",
+        _0,
+        _0
+    )]
+    NotCreatedByDaemon(String),
+
+    /// Failed to read or write the marker that records that the daemon created the namespace.
+    #[error(display = "Failed to mark network namespace {}", _0)]
+    Marker(String, #[error(source)] io::Error),
+
+    /// Failed to create a new network namespace.
+    #[error(display = "Failed to create a network namespace")]
+    Unshare(#[error(source)] nix::Error),
+
+    /// Failed to bind mount the new namespace.
+    #[error(display = "Failed to mount network namespace {}", _0)]
+    Mount(String, #[error(source)] nix::Error),
+
+    /// Failed to enter the namespace.
+    #[error(display = "Failed to enter network namespace {}", _0)]
+    Enter(String, #[error(source)] nix::Error),
+
+    /// The thread running in the namespace panicked.
+    #[error(display = "Network namespace thread panicked")]
+    ThreadPanicked,
+
+    /// Failed to write or remove the resolv.conf of the namespace.
+    #[error(display = "Failed to update resolv.conf for network namespace {}", _0)]
+    ResolvConf(String, #[error(source)] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A named network namespace, referred to by the file in `/run/netns`.
+pub struct NetNs {
+    name: String,
+    file: fs::File,
+}
+
+impl NetNs {
+    /// Opens the named network namespace, creating it if it does not exist yet. An existing
+    /// namespace is only opened if it was created by the daemon.
+    pub fn open_or_create(name: &str) -> Result<Self> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidName(name.to_owned()));
+        }
+        let file = if netns_path(name).exists() {
+            let file =
+                fs::File::open(netns_path(name)).map_err(|e| Error::Open(name.to_owned(), e))?;
+            if !is_created_by_daemon(name, &file).map_err(|e| Error::Marker(name.to_owned(), e))? {
+                return Err(Error::NotCreatedByDaemon(name.to_owned()));
+            }
+            file
+        } else {
+            log::debug!("Creating network namespace {}", name);
+            create(name)?
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            file,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs `f` on a thread that has entered the namespace, and waits for it to return. Sockets
+    /// created by `f` belong to the namespace, even after the thread has exited.
+    pub fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let file = self
+            .file
+            .try_clone()
+            .map_err(|e| Error::Open(self.name.clone(), e))?;
+        let name = self.name.clone();
+        thread::spawn(move || {
+            setns(file.as_raw_fd(), CloneFlags::CLONE_NEWNET).map_err(|e| Error::Enter(name, e))?;
+            Ok(f())
+        })
+        .join()
+        .map_err(|_| Error::ThreadPanicked)?
+    }
+
+    /// Points processes in the namespace at the given DNS servers. The file is picked up by
+    /// `mullvad-netns exec` and `ip netns exec`.
+    pub fn set_dns(&self, servers: &[IpAddr]) -> Result<()> {
+        let path = resolv_conf_path(&self.name);
+        let contents: String = servers
+            .iter()
+            .map(|server| format!("nameserver {}\n", server))
+            .collect();
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir),
+            None => Ok(()),
+        }
+        .and_then(|()| fs::write(&path, contents));
+        result.map_err(|e| Error::ResolvConf(self.name.clone(), e))
+    }
+
+    /// Clears the DNS configuration of the namespace. The file is truncated rather than removed,
+    /// since `mullvad-netns exec` bind mounts it over `/etc/resolv.conf`.
+    pub fn reset_dns(&self) -> Result<()> {
+        let path = resolv_conf_path(&self.name);
+        if !path.exists() {
+            return Ok(());
+        }
+        fs::write(&path, b"").map_err(|e| Error::ResolvConf(self.name.clone(), e))
+    }
+}
+
+impl AsRawFd for NetNs {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Creates a new network namespace, keeps it alive by bind mounting it in `/run/netns` and marks
+/// it as created by the daemon.
+fn create(name: &str) -> Result<fs::File> {
+    fs::create_dir_all(NETNS_RUN_DIR).map_err(Error::CreateRunDir)?;
+    let path = netns_path(name);
+    fs::File::create(&path).map_err(|e| Error::Open(name.to_owned(), e))?;
+
+    let mount_path = path.clone();
+    let owned_name = name.to_owned();
+    let result = thread::spawn(move || {
+        // Namespaces are per thread, so only this thread ends up in the new namespace
+        unshare(CloneFlags::CLONE_NEWNET).map_err(Error::Unshare)?;
+        mount(
+            Some("/proc/thread-self/ns/net"),
+            mount_path.as_path(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(|e| Error::Mount(owned_name, e))
+    })
+    .join()
+    .map_err(|_| Error::ThreadPanicked)
+    .and_then(|result| result)
+    .and_then(|()| {
+        let file = fs::File::open(&path).map_err(|e| Error::Open(name.to_owned(), e))?;
+        write_marker(name, &file).map_err(|e| Error::Marker(name.to_owned(), e))?;
+        Ok(file)
+    });
+
+    if result.is_err() {
+        let _ = umount2(path.as_path(), MntFlags::MNT_DETACH);
+        let _ = fs::remove_file(&path);
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use talpid_types::netns::marker_path;
+
+    /// Returns the inode of the network namespace of the current thread.
+    fn current_netns_inode() -> u64 {
+        fs::metadata("/proc/thread-self/ns/net").unwrap().ino()
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        assert!(matches!(
+            NetNs::open_or_create("a/b"),
+            Err(Error::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    #[ignore] // Creating network namespaces requires root
+    fn test_open_or_create_and_run() {
+        let name = format!("mullvad-test-{}", std::process::id());
+        let netns = NetNs::open_or_create(&name).unwrap();
+        let netns_inode = fs::metadata(netns_path(&name)).unwrap().ino();
+
+        assert_ne!(current_netns_inode(), netns_inode);
+        assert_eq!(netns.run(current_netns_inode).unwrap(), netns_inode);
+        assert_ne!(current_netns_inode(), netns_inode);
+
+        // Opening an existing namespace does not create a new one
+        let reopened = NetNs::open_or_create(&name).unwrap();
+        assert_eq!(reopened.run(current_netns_inode).unwrap(), netns_inode);
+
+        // Namespaces that were not created by the daemon are not taken over
+        fs::remove_file(marker_path(&name)).unwrap();
+        assert!(matches!(
+            NetNs::open_or_create(&name),
+            Err(Error::NotCreatedByDaemon(_))
+        ));
+
+        drop(netns);
+        drop(reopened);
+        umount2(netns_path(&name).as_path(), MntFlags::MNT_DETACH).unwrap();
+        fs::remove_file(netns_path(&name)).unwrap();
+    }
+}
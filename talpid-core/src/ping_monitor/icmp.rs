@@ -5,13 +5,18 @@ use socket2::{Domain, Protocol, Socket, Type};
 use std::ffi::CString;
 use std::{
     io::{self, Write},
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
     thread,
     time::Duration,
 };
 
 const SEND_RETRY_ATTEMPTS: u32 = 10;
 
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
 /// Pinger errors
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -50,13 +55,22 @@ pub struct Pinger {
     seq: u16,
 }
 
+fn open_socket(addr: &SocketAddr) -> Result<Socket> {
+    let sock = if addr.is_ipv4() {
+        Socket::new(Domain::ipv4(), Type::raw(), Some(Protocol::icmpv4()))
+    } else {
+        Socket::new(Domain::ipv6(), Type::raw(), Some(Protocol::icmpv6()))
+    }
+    .map_err(Error::OpenError)?;
+    sock.set_nonblocking(true).map_err(Error::OpenError)?;
+    Ok(sock)
+}
+
 impl Pinger {
     #[cfg(target_os = "windows")]
-    pub fn new(addr: Ipv4Addr, _interface_name: String) -> Result<Self> {
-        let addr = SocketAddr::new(addr.into(), 0);
-        let sock = Socket::new(Domain::ipv4(), Type::raw(), Some(Protocol::icmpv4()))
-            .map_err(Error::OpenError)?;
-        sock.set_nonblocking(true).map_err(Error::OpenError)?;
+    pub fn new(addr: IpAddr, _interface_name: String) -> Result<Self> {
+        let addr = SocketAddr::new(addr, 0);
+        let sock = open_socket(&addr)?;
 
         Ok(Self {
             sock,
@@ -67,11 +81,9 @@ impl Pinger {
     }
 
     #[cfg(target_os = "linux")]
-    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self> {
-        let addr = SocketAddr::new(addr.into(), 0);
-        let sock = Socket::new(Domain::ipv4(), Type::raw(), Some(Protocol::icmpv4()))
-            .map_err(Error::OpenError)?;
-        sock.set_nonblocking(true).map_err(Error::OpenError)?;
+    pub fn new(addr: IpAddr, interface_name: String) -> Result<Self> {
+        let addr = SocketAddr::new(addr, 0);
+        let sock = open_socket(&addr)?;
 
         let cname = CString::new(interface_name.as_bytes().to_vec())
             .map_err(|_| Error::InterfaceNameContainsNull)?;
@@ -107,8 +119,13 @@ impl Pinger {
         result
     }
 
-    fn construct_icmpv4_packet(&mut self, buffer: &mut [u8]) -> Result<()> {
-        if !construct_icmpv4_packet_inner(buffer, self) {
+    fn construct_icmp_packet(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let constructed = if self.addr.is_ipv4() {
+            construct_icmpv4_packet_inner(buffer, self)
+        } else {
+            construct_icmpv6_packet_inner(buffer, self)
+        };
+        if !constructed {
             return Err(Error::BufferTooSmall);
         }
         Ok(())
@@ -116,11 +133,25 @@ impl Pinger {
 }
 
 impl super::Pinger for Pinger {
-    fn send_icmp(&mut self) -> Result<()> {
+    fn send_probe(&mut self) -> Result<()> {
         let mut message = [0u8; 50];
-        self.construct_icmpv4_packet(&mut message)?;
+        self.construct_icmp_packet(&mut message)?;
         self.send_ping_request(&message, self.addr)
     }
+
+    fn received_reply(&mut self) -> Result<bool> {
+        let mut received = false;
+        let mut buffer = [0u8; 1500];
+        loop {
+            match self.sock.recv(&mut buffer) {
+                Ok(len) => {
+                    received |= is_echo_reply(&buffer[..len], self.addr.is_ipv6(), self.id);
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(received),
+                Err(error) => return Err(Error::ReadError(error)),
+            }
+        }
+    }
 }
 
 trait PayloadWriter {
@@ -136,7 +167,7 @@ impl PayloadWriter for Pinger {
 
     fn sequence_num(&mut self) -> u16 {
         let seq = self.seq;
-        self.seq += 1;
+        self.seq = self.seq.wrapping_add(1);
         seq
     }
 
@@ -150,13 +181,39 @@ fn construct_icmpv4_packet_inner(
     packet_writer: &mut impl PayloadWriter,
 ) -> bool {
     const ICMP_CHECKSUM_OFFSET: usize = 2;
+    if !construct_echo_request(buffer, packet_writer, ICMPV4_ECHO_REQUEST) {
+        return false;
+    }
+
+    let checksum = internet_checksum::checksum(buffer);
+    (&mut buffer[ICMP_CHECKSUM_OFFSET..])
+        .write(&checksum)
+        .unwrap();
+
+    true
+}
+
+/// ICMPv6 checksums cover a pseudo-header with the source address, so they are left for the
+/// kernel to fill in.
+fn construct_icmpv6_packet_inner(
+    buffer: &mut [u8],
+    packet_writer: &mut impl PayloadWriter,
+) -> bool {
+    construct_echo_request(buffer, packet_writer, ICMPV6_ECHO_REQUEST)
+}
+
+fn construct_echo_request(
+    buffer: &mut [u8],
+    packet_writer: &mut impl PayloadWriter,
+    icmp_type: u8,
+) -> bool {
     if buffer.len() < 14 {
         return false;
     }
 
     let mut writer = &mut buffer[..];
     // ICMP type - Echo (ping) request
-    writer.write_u8(0x08).unwrap();
+    writer.write_u8(icmp_type).unwrap();
     // Code - 0
     writer.write_u8(0x00).unwrap();
     // Checksum -filled in later
@@ -172,14 +229,27 @@ fn construct_icmpv4_packet_inner(
     // payload
     packet_writer.write_payload(writer);
 
-    let checksum = internet_checksum::checksum(buffer);
-    (&mut buffer[ICMP_CHECKSUM_OFFSET..])
-        .write(&checksum)
-        .unwrap();
-
     true
 }
 
+/// Returns whether `packet` is an echo reply with the given ID. Packets received on raw ICMPv4
+/// sockets include the IP header, while those received on ICMPv6 sockets do not.
+fn is_echo_reply(packet: &[u8], ipv6: bool, id: u16) -> bool {
+    let (icmp_packet, reply_type) = if ipv6 {
+        (packet, ICMPV6_ECHO_REPLY)
+    } else {
+        let header_len = match packet.first() {
+            Some(first_byte) => usize::from(first_byte & 0x0f) * 4,
+            None => return false,
+        };
+        if header_len < 20 {
+            return false;
+        }
+        (packet.get(header_len..).unwrap_or(&[]), ICMPV4_ECHO_REPLY)
+    };
+    icmp_packet.len() >= 8 && icmp_packet[0] == reply_type && icmp_packet[4..6] == id.to_be_bytes()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -236,4 +306,33 @@ mod test {
             &mut TestPayload {}
         ));
     }
+
+    #[test]
+    fn test_icmpv6_packet() {
+        let mut buffer = [0u8; 64];
+        assert!(construct_icmpv6_packet_inner(
+            &mut buffer[..],
+            &mut TestPayload {}
+        ));
+        // Echo request, code 0 and a checksum left for the kernel
+        assert_eq!(buffer[..4], [0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(buffer[4..8], [0x1d, 0xcd, 0x00, 0x01]);
+        assert_eq!(buffer[8..10], [0xb6, 0xe0]);
+    }
+
+    #[test]
+    fn test_echo_reply() {
+        let reply = [0x00, 0x00, 0x00, 0x00, 0x1d, 0xcd, 0x00, 0x01];
+        let mut ipv4_reply = [0u8; 28];
+        ipv4_reply[0] = 0x45;
+        ipv4_reply[20..].copy_from_slice(&reply);
+        assert!(is_echo_reply(&ipv4_reply, false, 0x1dcd));
+        assert!(!is_echo_reply(&ipv4_reply, false, 0x1dce));
+        assert!(!is_echo_reply(&reply, false, 0x1dcd));
+
+        let mut ipv6_reply = reply;
+        ipv6_reply[0] = 0x81;
+        assert!(is_echo_reply(&ipv6_reply, true, 0x1dcd));
+        assert!(!is_echo_reply(&ipv6_reply[..7], true, 0x1dcd));
+    }
 }
@@ -7,20 +7,30 @@ mod imp;
 #[path = "icmp.rs"]
 mod imp;
 
+mod tcp;
+
 pub use imp::Error;
 
-/// Trait for sending ICMP requests to get some traffic from a remote server
+/// Trait for sending probes to get some traffic from a remote server
 pub trait Pinger: Send {
-    /// Sends an ICMP packet
-    fn send_icmp(&mut self) -> Result<(), Error>;
+    /// Sends a probe without waiting for a response
+    fn send_probe(&mut self) -> Result<(), Error>;
+    /// Returns whether a response to any of the probes has been received since the last call
+    fn received_reply(&mut self) -> Result<bool, Error>;
     /// Clears all resources used by the pinger.
     fn reset(&mut self) {}
 }
 
-/// Create a new pinger
+/// Create a new pinger that sends ICMP or ICMPv6 echo requests, depending on the address family
 pub fn new_pinger(
-    addr: std::net::Ipv4Addr,
+    addr: std::net::IpAddr,
     interface_name: String,
 ) -> Result<Box<dyn Pinger>, Error> {
     Ok(Box::new(imp::Pinger::new(addr, interface_name)?))
 }
+
+/// Create a new pinger that opens TCP connections to `addr`. If `http` is true, an HTTP request
+/// is sent once the connection has been established.
+pub fn new_tcp_pinger(addr: std::net::SocketAddr, http: bool) -> Box<dyn Pinger> {
+    Box::new(tcp::Pinger::new(addr, http))
+}
@@ -0,0 +1,66 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Time after which an unanswered probe is abandoned.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A pinger that generates traffic by opening TCP connections, for networks that drop ICMP.
+/// Whether the connection succeeds does not matter, since only the traffic it causes is observed.
+/// The target must be routed through the tunnel.
+pub struct Pinger {
+    addr: SocketAddr,
+    http: bool,
+    received_reply: Arc<AtomicBool>,
+}
+
+impl Pinger {
+    pub fn new(addr: SocketAddr, http: bool) -> Self {
+        Self {
+            addr,
+            http,
+            received_reply: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl super::Pinger for Pinger {
+    // Connect on a separate thread, so that the connectivity monitor is not blocked
+    fn send_probe(&mut self) -> Result<(), super::Error> {
+        let addr = self.addr;
+        let http = self.http;
+        let received_reply = self.received_reply.clone();
+        thread::spawn(move || match probe(addr, http) {
+            Ok(()) => received_reply.store(true, Ordering::SeqCst),
+            Err(error) => log::trace!("Probe to {} failed: {}", addr, error),
+        });
+        Ok(())
+    }
+
+    fn received_reply(&mut self) -> Result<bool, super::Error> {
+        Ok(self.received_reply.swap(false, Ordering::SeqCst))
+    }
+}
+
+fn probe(addr: SocketAddr, http: bool) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)?;
+    if !http {
+        return Ok(());
+    }
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    write!(
+        stream,
+        "HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        addr.ip()
+    )?;
+    let mut response = [0u8; 512];
+    stream.read(&mut response).map(|_| ())
+}
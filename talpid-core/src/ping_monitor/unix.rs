@@ -1,4 +1,4 @@
-use std::{io, net::Ipv4Addr};
+use std::{io, net::IpAddr};
 
 /// Pinger errors
 #[derive(err_derive::Error, Debug)]
@@ -14,36 +14,44 @@ pub enum Error {
 
 /// A pinger that sends ICMP requests without waiting for responses
 pub struct Pinger {
-    addr: Ipv4Addr,
+    addr: IpAddr,
     interface_name: String,
     processes: Vec<duct::Handle>,
+    received_reply: bool,
 }
 
 impl Pinger {
     /// Creates a new pinger that will send ICMP requests only through the specified interface
-    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self, Error> {
+    pub fn new(addr: IpAddr, interface_name: String) -> Result<Self, Error> {
         Ok(Self {
             processes: vec![],
             addr,
             interface_name,
+            received_reply: false,
         })
     }
 
 
     fn try_deplete_process_list(&mut self) {
+        let mut received_reply = false;
         self.processes.retain(|child| {
             match child.try_wait() {
                 // child has terminated, doesn't have to be retained
-                Ok(Some(_)) => false,
+                Ok(Some(output)) => {
+                    // ping only exits successfully if it received a reply
+                    received_reply |= output.status.success();
+                    false
+                }
                 _ => true,
             }
         });
+        self.received_reply |= received_reply;
     }
 }
 
 impl super::Pinger for Pinger {
     // Send an ICMP packet without waiting for a reply
-    fn send_icmp(&mut self) -> Result<(), Error> {
+    fn send_probe(&mut self) -> Result<(), Error> {
         self.try_deplete_process_list();
 
         let cmd = ping_cmd(self.addr, 1, &self.interface_name);
@@ -52,6 +60,11 @@ impl super::Pinger for Pinger {
         Ok(())
     }
 
+    fn received_reply(&mut self) -> Result<bool, Error> {
+        self.try_deplete_process_list();
+        Ok(std::mem::replace(&mut self.received_reply, false))
+    }
+
     fn reset(&mut self) {
         let processes = std::mem::replace(&mut self.processes, vec![]);
         for proc in processes {
@@ -79,11 +92,15 @@ impl Drop for Pinger {
 }
 
 
-fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expression {
+fn ping_cmd(ip: IpAddr, timeout_secs: u16, interface: &str) -> duct::Expression {
     let mut args = vec!["-n", "-i", "1"];
 
+    // `ping6` on macOS has no timeout option, so it sends `timeout_secs` requests instead, one
+    // every second
     let timeout_flag = if cfg!(target_os = "linux") || cfg!(target_os = "android") {
         "-w"
+    } else if ip.is_ipv6() {
+        "-c"
     } else {
         "-t"
     };
@@ -91,7 +108,7 @@ fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expressio
 
     args.extend_from_slice(&[timeout_flag, &timeout_secs]);
 
-    let interface_flag = if cfg!(target_os = "linux") {
+    let interface_flag = if cfg!(target_os = "linux") || ip.is_ipv6() {
         Some("-I")
     } else if cfg!(target_os = "macos") {
         Some("-b")
@@ -103,10 +120,12 @@ fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expressio
         args.extend_from_slice(&[interface_flag, interface]);
     }
 
-    let ip = ip.to_string();
-    args.push(&ip);
+    let program = if ip.is_ipv6() { "ping6" } else { "ping" };
+
+    let ip_str = ip.to_string();
+    args.push(&ip_str);
 
-    duct::cmd("ping", args)
+    duct::cmd(program, args)
         .stdin_null()
         .stdout_null()
         .unchecked()
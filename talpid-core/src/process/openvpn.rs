@@ -85,6 +85,7 @@ pub struct OpenVpnCommand {
     iproute_bin: Option<OsString>,
     plugin: Option<(PathBuf, Vec<String>)>,
     log: Option<PathBuf>,
+    management: Option<(OsString, OsString, Option<PathBuf>)>,
     tunnel_options: net::openvpn::TunnelOptions,
     proxy_settings: Option<net::openvpn::ProxySettings>,
     #[cfg(windows)]
@@ -109,6 +110,7 @@ impl OpenVpnCommand {
             iproute_bin: None,
             plugin: None,
             log: None,
+            management: None,
             tunnel_options: net::openvpn::TunnelOptions::default(),
             proxy_settings: None,
             #[cfg(windows)]
@@ -175,6 +177,23 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Makes OpenVPN connect to a management interface client listening on `address`. `port` is
+    /// either a TCP port or `unix`, in which case `address` is the path to a Unix domain socket.
+    /// If `password_file` is set, the client must send the password in it before any commands.
+    pub fn management(
+        &mut self,
+        address: impl AsRef<OsStr>,
+        port: impl AsRef<OsStr>,
+        password_file: Option<PathBuf>,
+    ) -> &mut Self {
+        self.management = Some((
+            address.as_ref().to_os_string(),
+            port.as_ref().to_os_string(),
+            password_file,
+        ));
+        self
+    }
+
     /// Sets extra options
     pub fn tunnel_options(&mut self, tunnel_options: &net::openvpn::TunnelOptions) -> &mut Self {
         self.tunnel_options = tunnel_options.clone();
@@ -257,6 +276,16 @@ impl OpenVpnCommand {
             args.push(OsString::from(path))
         }
 
+        if let Some((ref address, ref port, ref password_file)) = self.management {
+            args.push(OsString::from("--management"));
+            args.push(address.clone());
+            args.push(port.clone());
+            if let Some(password_file) = password_file {
+                args.push(OsString::from(password_file));
+            }
+            args.push(OsString::from("--management-client"));
+        }
+
         if let Some(mssfix) = self.tunnel_options.mssfix {
             args.push(OsString::from("--mssfix"));
             args.push(OsString::from(mssfix.to_string()));
@@ -489,4 +518,15 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_management_address() {
+        let testee_args = OpenVpnCommand::new("")
+            .management("./management.sock", "unix", None)
+            .get_arguments();
+        assert!(testee_args.contains(&OsString::from("--management")));
+        assert!(testee_args.contains(&OsString::from("./management.sock")));
+        assert!(testee_args.contains(&OsString::from("unix")));
+        assert!(testee_args.contains(&OsString::from("--management-client")));
+    }
 }
@@ -31,6 +31,9 @@ impl RouteManagerImpl {
                 RouteManagerCommand::AddRoutes(_routes, tx) => {
                     let _ = tx.send(Ok(()));
                 }
+                RouteManagerCommand::DeleteRoutes(_routes, tx) => {
+                    let _ = tx.send(Ok(()));
+                }
                 RouteManagerCommand::ClearRoutes => (),
             }
         }
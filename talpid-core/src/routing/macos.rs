@@ -102,6 +102,10 @@ impl RouteManagerImpl {
                             let result = self.add_required_routes(routes).await;
                             let _ = result_tx.send(result);
                         },
+                        Some(RouteManagerCommand::DeleteRoutes(routes, result_tx)) => {
+                            let result = self.delete_required_routes(routes).await;
+                            let _ = result_tx.send(result);
+                        },
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
                         },
@@ -168,6 +172,31 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    async fn delete_required_routes(
+        &mut self,
+        required_routes: HashSet<RequiredRoute>,
+    ) -> Result<()> {
+        for required_route in required_routes {
+            self.default_destinations.remove(&required_route.prefix);
+
+            let routes: Vec<_> = self
+                .applied_routes
+                .iter()
+                .filter(|route| route.prefix == required_route.prefix)
+                .cloned()
+                .collect();
+            for route in routes {
+                let status = Self::delete_route(route.prefix).await?;
+                if !status.success() {
+                    log::debug!("Failed to remove route to {}", route.prefix);
+                }
+                self.applied_routes.remove(&route);
+            }
+        }
+
+        Ok(())
+    }
+
     // Retrieves the node that's currently used to reach 0.0.0.0/0
     // Arguments can be either -inet or -inet6
     async fn get_default_node_cmd(if_family: &'static str) -> Result<Option<Node>> {
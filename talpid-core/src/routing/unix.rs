@@ -70,6 +70,20 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Removes the given routes, if they were applied through [`RouteManagerHandle::add_routes`]
+    /// or [`RouteManager::add_routes`].
+    #[cfg(not(target_os = "linux"))]
+    pub async fn delete_routes(&self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::DeleteRoutes(routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
     /// Ensure that packets are routed using the correct tables.
     #[cfg(target_os = "linux")]
     pub async fn create_routing_rules(&self, enable_ipv6: bool) -> Result<(), Error> {
@@ -138,6 +152,11 @@ pub(crate) enum RouteManagerCommand {
         HashSet<RequiredRoute>,
         oneshot::Sender<Result<(), PlatformError>>,
     ),
+    #[cfg(not(target_os = "linux"))]
+    DeleteRoutes(
+        HashSet<RequiredRoute>,
+        oneshot::Sender<Result<(), PlatformError>>,
+    ),
     ClearRoutes,
     Shutdown(oneshot::Sender<()>),
     #[cfg(target_os = "linux")]
@@ -21,6 +21,9 @@ pub enum Error {
     /// Failure to add routes
     #[error(display = "Failed to add routes")]
     AddRoutesFailed(#[error(source)] winnet::Error),
+    /// Failure to delete routes
+    #[error(display = "Failed to delete routes")]
+    DeleteRoutesFailed,
     /// Failure to clear routes
     #[error(display = "Failed to clear applied routes")]
     ClearRoutesFailed,
@@ -55,11 +58,22 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)?;
         response_rx.await.map_err(|_| Error::ManagerChannelDown)?
     }
+
+    /// Removes the given routes, if they were applied through [`RouteManagerHandle::add_routes`]
+    /// or [`RouteManager::add_routes`].
+    pub async fn delete_routes(&self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::DeleteRoutes(routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)?
+    }
 }
 
 #[derive(Debug)]
 pub enum RouteManagerCommand {
     AddRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
+    DeleteRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
     Shutdown,
 }
 
@@ -102,26 +116,20 @@ impl RouteManager {
         while let Some(command) = manage_rx.next().await {
             match command {
                 RouteManagerCommand::AddRoutes(routes, tx) => {
-                    let routes: Vec<_> = routes
-                        .iter()
-                        .map(|route| {
-                            let destination = winnet::WinNetIpNetwork::from(route.prefix);
-                            match &route.node {
-                                NetNode::DefaultNode => {
-                                    winnet::WinNetRoute::through_default_node(destination)
-                                }
-                                NetNode::RealNode(node) => winnet::WinNetRoute::new(
-                                    winnet::WinNetNode::from(node),
-                                    destination,
-                                ),
-                            }
-                        })
-                        .collect();
-
+                    let routes = Self::to_winnet_routes(&routes);
                     let _ = tx.send(
                         winnet::routing_manager_add_routes(&routes).map_err(Error::AddRoutesFailed),
                     );
                 }
+                RouteManagerCommand::DeleteRoutes(routes, tx) => {
+                    let routes = Self::to_winnet_routes(&routes);
+                    let result = if winnet::routing_manager_delete_routes(&routes) {
+                        Ok(())
+                    } else {
+                        Err(Error::DeleteRoutesFailed)
+                    };
+                    let _ = tx.send(result);
+                }
                 RouteManagerCommand::Shutdown => {
                     break;
                 }
@@ -129,6 +137,21 @@ impl RouteManager {
         }
     }
 
+    fn to_winnet_routes(routes: &HashSet<RequiredRoute>) -> Vec<winnet::WinNetRoute> {
+        routes
+            .iter()
+            .map(|route| {
+                let destination = winnet::WinNetIpNetwork::from(route.prefix);
+                match &route.node {
+                    NetNode::DefaultNode => winnet::WinNetRoute::through_default_node(destination),
+                    NetNode::RealNode(node) => {
+                        winnet::WinNetRoute::new(winnet::WinNetNode::from(node), destination)
+                    }
+                }
+            })
+            .collect()
+    }
+
     /// Sets a callback that is called whenever the default route changes.
     pub fn add_default_route_callback<T: 'static>(
         &mut self,
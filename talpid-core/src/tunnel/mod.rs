@@ -2,8 +2,9 @@ use self::tun_provider::TunProvider;
 use crate::{logging, routing::RouteManager};
 use std::{
     io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
@@ -77,6 +78,10 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down.
     Down,
+    /// Sent when IPv6 traffic stops flowing through the tunnel while IPv4 traffic still works.
+    Ipv6Degraded,
+    /// Sent when IPv6 traffic flows through the tunnel again after `Ipv6Degraded`.
+    Ipv6Restored,
 }
 
 /// Information about a VPN tunnel.
@@ -92,6 +97,29 @@ pub struct TunnelMetadata {
     pub ipv6_gateway: Option<Ipv6Addr>,
 }
 
+/// Traffic counters of a running tunnel.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TrafficStats {
+    /// Number of bytes received from the relay.
+    pub rx_bytes: u64,
+    /// Number of bytes sent to the relay.
+    pub tx_bytes: u64,
+    /// Time of the most recent handshake with the relay, if the tunnel protocol reports it.
+    pub last_handshake: Option<SystemTime>,
+}
+
+/// Details about the connection to the relay, as reported by the tunnel. Only OpenVPN reports
+/// them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConnectionDetails {
+    /// Address of the relay that the tunnel is connected to.
+    pub remote: Option<SocketAddr>,
+    /// Cipher used to encrypt the data channel.
+    pub data_channel_cipher: Option<String>,
+    /// TLS version and cipher suite of the control channel.
+    pub control_channel: Option<String>,
+}
+
 /// Abstraction for monitoring a generic VPN tunnel.
 pub struct TunnelMonitor {
     monitor: InternalTunnelMonitor,
@@ -267,6 +295,23 @@ impl TunnelMonitor {
         self.monitor.close_handle()
     }
 
+    /// Creates a handle for reading the traffic counters of the tunnel.
+    pub fn stats_handle(&self) -> StatsHandle {
+        self.monitor.stats_handle()
+    }
+
+    /// Creates a handle for switching the tunnel to a different relay without recreating the
+    /// tunnel device. Only WireGuard tunnels support this.
+    pub fn reconfigure_handle(&self) -> Option<wireguard::ReconfigureHandle> {
+        self.monitor.reconfigure_handle()
+    }
+
+    /// Creates a handle for reconnecting the tunnel to the same relay without restarting the
+    /// process that manages it. Only OpenVPN tunnels support this.
+    pub fn restart_handle(&self) -> Option<RestartHandle> {
+        self.monitor.restart_handle()
+    }
+
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
         self.monitor.wait().map_err(Error::from)
@@ -297,6 +342,62 @@ impl CloseHandle {
     }
 }
 
+/// A handle to a `TunnelMonitor` for reconnecting it
+#[derive(Clone)]
+pub enum RestartHandle {
+    #[cfg(not(target_os = "android"))]
+    /// OpenVpn restart handle
+    OpenVpn(openvpn::OpenVpnRestartHandle),
+}
+
+impl RestartHandle {
+    /// Reconnects the tunnel. The tunnel reports `TunnelEvent::Down` and `TunnelEvent::Up` again
+    /// as it reconnects.
+    pub fn restart(&self) -> io::Result<()> {
+        match *self {
+            #[cfg(not(target_os = "android"))]
+            RestartHandle::OpenVpn(ref handle) => handle.restart(),
+        }
+    }
+}
+
+/// A handle for reading the traffic counters of a `TunnelMonitor`
+#[derive(Clone)]
+pub enum StatsHandle {
+    #[cfg(not(target_os = "android"))]
+    /// OpenVpn stats handle
+    OpenVpn(openvpn::OpenVpnStatsHandle),
+    /// Wireguard stats handle
+    Wireguard(wireguard::StatsHandle),
+    /// Returns whatever counters the test has stored
+    #[cfg(test)]
+    Fake(std::sync::Arc<std::sync::Mutex<Option<TrafficStats>>>),
+}
+
+impl StatsHandle {
+    /// Returns the current traffic counters of the tunnel, or `None` if they are unavailable.
+    pub fn get_stats(&self) -> Option<TrafficStats> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            StatsHandle::OpenVpn(handle) => handle.get_stats(),
+            StatsHandle::Wireguard(handle) => handle.get_stats(),
+            #[cfg(test)]
+            StatsHandle::Fake(stats) => *stats.lock().unwrap(),
+        }
+    }
+
+    /// Returns the details about the connection to the relay that the tunnel has reported.
+    pub fn get_connection_details(&self) -> ConnectionDetails {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            StatsHandle::OpenVpn(handle) => handle.get_connection_details(),
+            StatsHandle::Wireguard(_) => ConnectionDetails::default(),
+            #[cfg(test)]
+            StatsHandle::Fake(_) => ConnectionDetails::default(),
+        }
+    }
+}
+
 enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(openvpn::OpenVpnMonitor),
@@ -312,6 +413,32 @@ impl InternalTunnelMonitor {
         }
     }
 
+    fn stats_handle(&self) -> StatsHandle {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(tun) => StatsHandle::OpenVpn(tun.stats_handle()),
+            InternalTunnelMonitor::Wireguard(tun) => StatsHandle::Wireguard(tun.stats_handle()),
+        }
+    }
+
+    fn reconfigure_handle(&self) -> Option<wireguard::ReconfigureHandle> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(_) => None,
+            InternalTunnelMonitor::Wireguard(tun) => Some(tun.reconfigure_handle()),
+        }
+    }
+
+    fn restart_handle(&self) -> Option<RestartHandle> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(tun) => {
+                Some(RestartHandle::OpenVpn(tun.restart_handle()))
+            }
+            InternalTunnelMonitor::Wireguard(_) => None,
+        }
+    }
+
     fn wait(self) -> Result<()> {
         match self {
             #[cfg(not(target_os = "android"))]
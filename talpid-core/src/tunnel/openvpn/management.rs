@@ -0,0 +1,523 @@
+//! Client for the OpenVPN management interface. OpenVPN is started with `--management-client`, so
+//! it connects to a socket that the monitor listens on, rather than the other way around.
+
+use crate::tunnel::ConnectionDetails;
+use futures::{channel::mpsc, future, StreamExt};
+#[cfg(windows)]
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    ffi::OsString,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// How often OpenVPN reports its traffic counters, in seconds.
+const BYTECOUNT_INTERVAL_SECS: u32 = 1;
+
+/// Length of the password of the management interface on Windows.
+#[cfg(windows)]
+const PASSWORD_LENGTH: usize = 32;
+
+/// Where OpenVPN connects to the management interface.
+#[derive(Debug, Clone)]
+pub enum ManagementAddress {
+    /// Unix domain socket at the given path.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// TCP socket on the loopback interface. Unix domain sockets are not supported by OpenVPN on
+    /// Windows. Any local process can connect to it, so OpenVPN is given a file with the password
+    /// that the listener sends before any commands.
+    #[cfg(windows)]
+    Tcp {
+        address: SocketAddr,
+        password_file: PathBuf,
+    },
+}
+
+impl ManagementAddress {
+    /// Returns the address, port and password file arguments of `--management`.
+    pub fn arguments(&self) -> (OsString, OsString, Option<PathBuf>) {
+        match self {
+            #[cfg(unix)]
+            ManagementAddress::Unix(path) => (path.clone().into_os_string(), "unix".into(), None),
+            #[cfg(windows)]
+            ManagementAddress::Tcp {
+                address,
+                password_file,
+            } => (
+                address.ip().to_string().into(),
+                address.port().to_string().into(),
+                Some(password_file.clone()),
+            ),
+        }
+    }
+}
+
+/// Listens for the management connection from OpenVPN.
+pub enum ManagementListener {
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+    #[cfg(windows)]
+    Tcp {
+        listener: tokio::net::TcpListener,
+        password: String,
+    },
+}
+
+impl ManagementListener {
+    /// Binds a new listener. Must be called from within a tokio runtime.
+    #[cfg(unix)]
+    pub fn bind(path: PathBuf) -> io::Result<(Self, ManagementAddress)> {
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        Ok((Self::Unix(listener), ManagementAddress::Unix(path)))
+    }
+
+    /// Binds a new listener on an unused port, and writes a new random password to
+    /// `password_file`. Must be called from within a tokio runtime.
+    #[cfg(windows)]
+    pub fn bind(password_file: PathBuf) -> io::Result<(Self, ManagementAddress)> {
+        let password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(PASSWORD_LENGTH)
+            .collect();
+        std::fs::write(&password_file, format!("{}\n", password))?;
+
+        let listener =
+            std::net::TcpListener::bind(SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), 0))?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        Ok((
+            Self::Tcp {
+                listener: tokio::net::TcpListener::from_std(listener)?,
+                password,
+            },
+            ManagementAddress::Tcp {
+                address,
+                password_file,
+            },
+        ))
+    }
+
+    /// Accepts the connection from OpenVPN and serves it until OpenVPN disconnects.
+    pub async fn serve(
+        self,
+        status: Arc<Mutex<Status>>,
+        signal_rx: mpsc::UnboundedReceiver<Signal>,
+    ) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                // The socket lives in a shared directory, so ignore clients that aren't running as
+                // the same user as us
+                let uid = nix::unistd::geteuid().as_raw();
+                loop {
+                    let (stream, _) = listener.accept().await?;
+                    match stream.peer_cred() {
+                        Ok(cred) if cred.uid() == uid => {
+                            return serve_connection(stream, status, signal_rx, None).await;
+                        }
+                        Ok(cred) => {
+                            log::warn!("Rejecting management connection from user {}", cred.uid())
+                        }
+                        Err(error) => log::warn!(
+                            "Rejecting management connection with unknown owner: {}",
+                            error
+                        ),
+                    }
+                }
+            }
+            #[cfg(windows)]
+            Self::Tcp { listener, password } => {
+                let (stream, _) = listener.accept().await?;
+                serve_connection(stream, status, signal_rx, Some(password)).await
+            }
+        }
+    }
+}
+
+/// Signals that can be sent to OpenVPN through the management interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Reconnects to the server without restarting the process (`SIGUSR1`).
+    Restart,
+}
+
+impl Signal {
+    fn command(self) -> &'static str {
+        match self {
+            Signal::Restart => "signal SIGUSR1\n",
+        }
+    }
+}
+
+/// State of the tunnel as reported through the management interface.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Most recent OpenVPN state, such as `WAIT`, `AUTH`, `ASSIGN_IP`, `CONNECTED` or
+    /// `RECONNECTING`.
+    pub state: Option<String>,
+    /// Address of the server that OpenVPN is connected to.
+    pub remote: Option<SocketAddr>,
+    /// TLS version and cipher suite of the control channel.
+    pub control_channel: Option<String>,
+    /// Cipher used to encrypt the data channel.
+    pub data_channel_cipher: Option<String>,
+    /// Bytes received and sent by the tunnel.
+    pub bytecount: Option<(u64, u64)>,
+}
+
+impl Status {
+    fn update(&mut self, message: Message) {
+        match message {
+            Message::State { state, remote } => {
+                let connected = state == "CONNECTED";
+                if state == "RECONNECTING" {
+                    self.control_channel = None;
+                    self.data_channel_cipher = None;
+                    self.bytecount = None;
+                }
+                self.state = Some(state);
+                if remote.is_some() {
+                    self.remote = remote;
+                }
+                if connected {
+                    log::info!(
+                        "OpenVPN connected to {}. Control channel: {}. Data channel cipher: {}",
+                        display_or_unknown(&self.remote),
+                        display_or_unknown(&self.control_channel),
+                        display_or_unknown(&self.data_channel_cipher),
+                    );
+                }
+            }
+            Message::ByteCount { rx_bytes, tx_bytes } => {
+                self.bytecount = Some((rx_bytes, tx_bytes));
+            }
+            Message::ControlChannel(description) => self.control_channel = Some(description),
+            Message::DataChannelCipher(cipher) => self.data_channel_cipher = Some(cipher),
+        }
+    }
+}
+
+fn display_or_unknown<T: std::fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// A handle for reading the status reported by OpenVPN and for signaling it.
+#[derive(Debug, Clone)]
+pub struct ManagementHandle {
+    status: Arc<Mutex<Status>>,
+    signal_tx: mpsc::UnboundedSender<Signal>,
+}
+
+impl ManagementHandle {
+    pub fn new(status: Arc<Mutex<Status>>, signal_tx: mpsc::UnboundedSender<Signal>) -> Self {
+        Self { status, signal_tx }
+    }
+
+    /// Sends a signal to OpenVPN. Fails if the management connection is closed.
+    pub fn signal(&self, signal: Signal) -> io::Result<()> {
+        self.signal_tx.unbounded_send(signal).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "OpenVPN management interface is closed",
+            )
+        })?;
+        if signal == Signal::Restart {
+            // OpenVPN starts counting from zero once it has reconnected, so the old counters must
+            // not be read again in the meantime
+            self.status.lock().expect("status lock poisoned").bytecount = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the most recent status reported by OpenVPN.
+    pub fn status(&self) -> Status {
+        self.status.lock().expect("status lock poisoned").clone()
+    }
+
+    /// Returns the details about the connection to the relay that OpenVPN has reported.
+    pub fn connection_details(&self) -> ConnectionDetails {
+        let status = self.status();
+        ConnectionDetails {
+            remote: status.remote,
+            data_channel_cipher: status.data_channel_cipher,
+            control_channel: status.control_channel,
+        }
+    }
+}
+
+/// Sends the password, if any, and the commands that enable the notifications that the status is
+/// built from. Then reads notifications and sends the signals that are received on `signal_rx`
+/// until OpenVPN disconnects.
+async fn serve_connection<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    status: Arc<Mutex<Status>>,
+    mut signal_rx: mpsc::UnboundedReceiver<Signal>,
+    password: Option<String>,
+) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    let read_messages = async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            match parse_line(&line) {
+                Some(message) => status.lock().expect("status lock poisoned").update(message),
+                None => {
+                    if line.starts_with("ERROR:") {
+                        log::warn!("OpenVPN management interface: {}", line);
+                    }
+                }
+            }
+        }
+        // OpenVPN has exited, so nothing reported so far is valid anymore
+        *status.lock().expect("status lock poisoned") = Status::default();
+        Ok::<(), io::Error>(())
+    };
+
+    let write_commands = async move {
+        // OpenVPN reads the first line as the password, even if it arrives before the prompt
+        if let Some(password) = password {
+            writer
+                .write_all(format!("{}\n", password).as_bytes())
+                .await?;
+        }
+        writer.write_all(b"state on\n").await?;
+        writer
+            .write_all(format!("bytecount {}\n", BYTECOUNT_INTERVAL_SECS).as_bytes())
+            .await?;
+        writer.write_all(b"log on\n").await?;
+        while let Some(signal) = signal_rx.next().await {
+            log::debug!("Sending {:?} to OpenVPN", signal);
+            writer.write_all(signal.command().as_bytes()).await?;
+        }
+        // Keep reading until OpenVPN disconnects, even if there is nothing more to write
+        future::pending::<()>().await;
+        Ok::<(), io::Error>(())
+    };
+
+    match future::select(Box::pin(read_messages), Box::pin(write_commands)).await {
+        future::Either::Left((result, _)) | future::Either::Right((result, _)) => result,
+    }
+}
+
+/// Messages from OpenVPN that affect the status.
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    State {
+        state: String,
+        remote: Option<SocketAddr>,
+    },
+    ByteCount {
+        rx_bytes: u64,
+        tx_bytes: u64,
+    },
+    ControlChannel(String),
+    DataChannelCipher(String),
+}
+
+/// Parses a real-time notification. Returns `None` for command responses and notifications that
+/// don't affect the status.
+fn parse_line(line: &str) -> Option<Message> {
+    if let Some(state) = line.strip_prefix(">STATE:") {
+        // time,state,description,tunnel IPv4,remote address,remote port,...
+        let mut fields = state.split(',').skip(1);
+        let state = fields.next()?.to_owned();
+        let _description = fields.next();
+        let _tunnel_ip = fields.next();
+        let remote_ip = fields.next().and_then(|ip| ip.parse::<IpAddr>().ok());
+        let remote_port = fields.next().and_then(|port| port.parse::<u16>().ok());
+        let remote = match (remote_ip, remote_port) {
+            (Some(ip), Some(port)) => Some(SocketAddr::new(ip, port)),
+            _ => None,
+        };
+        return Some(Message::State { state, remote });
+    }
+
+    if let Some(bytecount) = line.strip_prefix(">BYTECOUNT:") {
+        let mut fields = bytecount.split(',');
+        let rx_bytes = fields.next()?.trim().parse().ok()?;
+        let tx_bytes = fields.next()?.trim().parse().ok()?;
+        return Some(Message::ByteCount { rx_bytes, tx_bytes });
+    }
+
+    if let Some(log) = line.strip_prefix(">LOG:") {
+        // time,flags,message
+        let message = log.splitn(3, ',').nth(2)?;
+        return parse_log_message(message);
+    }
+
+    None
+}
+
+fn parse_log_message(message: &str) -> Option<Message> {
+    if let Some(description) = message.strip_prefix("Control Channel: ") {
+        let description = match description.find(", peer certificate") {
+            Some(end) => &description[..end],
+            None => description,
+        };
+        return Some(Message::ControlChannel(description.to_owned()));
+    }
+
+    if let Some(cipher) = message.strip_prefix("Data Channel: using negotiated cipher '") {
+        return Some(Message::DataChannelCipher(
+            cipher.trim_end_matches('\'').to_owned(),
+        ));
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_state() {
+        assert_eq!(
+            parse_line(">STATE:1625577600,CONNECTED,SUCCESS,10.8.0.2,185.65.134.66,1194,,"),
+            Some(Message::State {
+                state: "CONNECTED".to_owned(),
+                remote: Some("185.65.134.66:1194".parse().unwrap()),
+            })
+        );
+        assert_eq!(
+            parse_line(">STATE:1625577600,WAIT,,,,,,"),
+            Some(Message::State {
+                state: "WAIT".to_owned(),
+                remote: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_bytecount() {
+        assert_eq!(
+            parse_line(">BYTECOUNT:1234,5678"),
+            Some(Message::ByteCount {
+                rx_bytes: 1234,
+                tx_bytes: 5678,
+            })
+        );
+        assert_eq!(parse_line(">BYTECOUNT:1234"), None);
+    }
+
+    #[test]
+    fn parses_ciphers_from_log() {
+        assert_eq!(
+            parse_line(
+                ">LOG:1625577600,I,Control Channel: TLSv1.3, cipher TLSv1.3 \
+                 TLS_AES_256_GCM_SHA384, peer certificate: 4096 bit RSA, signature: RSA-SHA512"
+            ),
+            Some(Message::ControlChannel(
+                "TLSv1.3, cipher TLSv1.3 TLS_AES_256_GCM_SHA384".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_line(">LOG:1625577600,I,Data Channel: using negotiated cipher 'AES-256-GCM'"),
+            Some(Message::DataChannelCipher("AES-256-GCM".to_owned()))
+        );
+        assert_eq!(
+            parse_line(">LOG:1625577600,I,Initialization Sequence Completed"),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_responses() {
+        assert_eq!(
+            parse_line("SUCCESS: real-time state notification set to ON"),
+            None
+        );
+        assert_eq!(
+            parse_line(">INFO:OpenVPN Management Interface Version 3 -- type 'help' for more info"),
+            None
+        );
+    }
+
+    #[test]
+    fn serves_connection() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (client, openvpn) = tokio::io::duplex(1024);
+            let status = Arc::new(Mutex::new(Status::default()));
+            let (signal_tx, signal_rx) = mpsc::unbounded();
+            let handle = ManagementHandle::new(status.clone(), signal_tx);
+            let server = tokio::spawn(serve_connection(
+                client,
+                status,
+                signal_rx,
+                Some("password".to_owned()),
+            ));
+
+            let (openvpn_reader, mut openvpn_writer) = tokio::io::split(openvpn);
+            let mut commands = BufReader::new(openvpn_reader).lines();
+            assert_eq!(commands.next_line().await.unwrap().unwrap(), "password");
+            assert_eq!(commands.next_line().await.unwrap().unwrap(), "state on");
+            assert_eq!(commands.next_line().await.unwrap().unwrap(), "bytecount 1");
+            assert_eq!(commands.next_line().await.unwrap().unwrap(), "log on");
+
+            openvpn_writer
+                .write_all(
+                    b">LOG:1625577600,I,Data Channel: using negotiated cipher 'AES-256-GCM'\r\n\
+                      >STATE:1625577600,CONNECTED,SUCCESS,10.8.0.2,185.65.134.66,1194,,\r\n\
+                      >BYTECOUNT:1234,5678\r\n",
+                )
+                .await
+                .unwrap();
+
+            // Wait for the notifications to be read
+            while handle.status().bytecount.is_none() {
+                tokio::task::yield_now().await;
+            }
+            let status = handle.status();
+            assert_eq!(status.state.as_deref(), Some("CONNECTED"));
+            assert_eq!(status.bytecount, Some((1234, 5678)));
+            assert_eq!(
+                handle.connection_details(),
+                ConnectionDetails {
+                    remote: Some("185.65.134.66:1194".parse().unwrap()),
+                    data_channel_cipher: Some("AES-256-GCM".to_owned()),
+                    control_channel: None,
+                }
+            );
+
+            handle.signal(Signal::Restart).unwrap();
+            assert_eq!(
+                commands.next_line().await.unwrap().unwrap(),
+                "signal SIGUSR1"
+            );
+            assert_eq!(handle.status().bytecount, None);
+
+            drop(openvpn_writer);
+            drop(commands);
+            server.await.unwrap().unwrap();
+            assert_eq!(handle.status(), Status::default());
+        });
+    }
+
+    #[test]
+    fn reconnecting_clears_ciphers_and_counters() {
+        let mut status = Status::default();
+        status.update(Message::DataChannelCipher("AES-256-GCM".to_owned()));
+        status.update(Message::ByteCount {
+            rx_bytes: 1234,
+            tx_bytes: 5678,
+        });
+        status.update(Message::State {
+            state: "RECONNECTING".to_owned(),
+            remote: None,
+        });
+        assert_eq!(status.state.as_deref(), Some("RECONNECTING"));
+        assert_eq!(status.data_channel_cipher, None);
+        assert_eq!(status.bytecount, None);
+    }
+}
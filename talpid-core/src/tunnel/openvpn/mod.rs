@@ -1,4 +1,4 @@
-use super::TunnelEvent;
+use super::{ConnectionDetails, TrafficStats, TunnelEvent};
 #[cfg(target_os = "linux")]
 use crate::routing::RequiredRoute;
 use crate::{
@@ -53,6 +53,7 @@ use winapi::shared::{
 #[cfg(windows)]
 use winreg::enums::{KEY_READ, KEY_WRITE};
 
+pub mod management;
 #[cfg(windows)]
 mod windows;
 
@@ -116,6 +117,10 @@ pub enum Error {
     #[error(display = "Unable to start the event dispatcher IPC server")]
     EventDispatcherError(#[error(source)] event_server::Error),
 
+    /// Unable to listen for the OpenVPN management interface connection.
+    #[error(display = "Failed to start the OpenVPN management interface")]
+    ManagementInterfaceError(#[error(source)] io::Error),
+
     /// The OpenVPN event dispatcher exited unexpectedly
     #[error(display = "The OpenVPN event dispatcher exited unexpectedly")]
     EventDispatcherExited,
@@ -267,6 +272,13 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     _user_pass_file: mktemp::TempFile,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Socket that OpenVPN connects to for the management interface. Removed on drop.
+    #[cfg(unix)]
+    _management_socket: mktemp::TempFile,
+    /// Password of the management interface, read by OpenVPN on startup. Removed on drop.
+    #[cfg(windows)]
+    _management_password_file: mktemp::TempFile,
+    management: management::ManagementHandle,
 
     runtime: tokio::runtime::Runtime,
     event_server_abort_tx: triggered::Trigger,
@@ -549,11 +561,40 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
                 .unwrap_err());
         }
 
+        #[cfg(unix)]
+        let management_socket = mktemp::TempFile::new();
+        #[cfg(windows)]
+        let management_password_file = mktemp::TempFile::new();
+        let (management_listener, management_address) = {
+            let _guard = runtime.enter();
+            #[cfg(unix)]
+            let result = management::ManagementListener::bind(management_socket.to_path_buf());
+            #[cfg(windows)]
+            let result =
+                management::ManagementListener::bind(management_password_file.to_path_buf());
+            result.map_err(Error::ManagementInterfaceError)?
+        };
+        let management_status = Arc::new(Mutex::new(management::Status::default()));
+        let (signal_tx, signal_rx) = futures::channel::mpsc::unbounded();
+        let management = management::ManagementHandle::new(management_status.clone(), signal_tx);
+        runtime.spawn(async move {
+            if let Err(error) = management_listener
+                .serve(management_status, signal_rx)
+                .await
+            {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("OpenVPN management interface failed")
+                );
+            }
+        });
+
         #[cfg(windows)]
         let wintun = Arc::new(wintun);
 
         cmd.plugin(plugin_path, vec![ipc_path])
-            .log(log_path.as_ref().map(|p| p.as_path()));
+            .log(log_path.as_ref().map(|p| p.as_path()))
+            .management(&management_address);
         let (spawn_task, abort_spawn) = futures::future::abortable(Self::prepare_process(
             cmd,
             #[cfg(windows)]
@@ -569,6 +610,11 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
             closed: Arc::new(AtomicBool::new(false)),
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            #[cfg(unix)]
+            _management_socket: management_socket,
+            #[cfg(windows)]
+            _management_password_file: management_password_file,
+            management,
 
             runtime,
             event_server_abort_tx,
@@ -601,6 +647,20 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         }
     }
 
+    /// Creates a handle for reading the traffic counters of the tunnel.
+    pub fn stats_handle(&self) -> OpenVpnStatsHandle {
+        OpenVpnStatsHandle {
+            management: self.management.clone(),
+        }
+    }
+
+    /// Creates a handle for reconnecting the tunnel without restarting OpenVPN.
+    pub fn restart_handle(&self) -> OpenVpnRestartHandle {
+        OpenVpnRestartHandle {
+            management: self.management.clone(),
+        }
+    }
+
     /// Consumes the monitor and waits for both proxy and tunnel, as applicable.
     pub fn wait(mut self) -> Result<()> {
         if let Some(mut proxy_monitor) = self.proxy_monitor.take() {
@@ -880,6 +940,45 @@ impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
     }
 }
 
+/// A handle to an `OpenVpnMonitor` for reading the traffic counters of the tunnel.
+#[derive(Debug, Clone)]
+pub struct OpenVpnStatsHandle {
+    management: management::ManagementHandle,
+}
+
+impl OpenVpnStatsHandle {
+    /// Returns the counters most recently reported through the management interface. Returns
+    /// `None` if none have been reported since OpenVPN (re)connected or if the tunnel is gone.
+    pub fn get_stats(&self) -> Option<TrafficStats> {
+        let (rx_bytes, tx_bytes) = self.management.status().bytecount?;
+        Some(TrafficStats {
+            rx_bytes,
+            tx_bytes,
+            last_handshake: None,
+        })
+    }
+
+    /// Returns the details about the connection that OpenVPN has reported through the management
+    /// interface.
+    pub fn get_connection_details(&self) -> ConnectionDetails {
+        self.management.connection_details()
+    }
+}
+
+/// A handle to an `OpenVpnMonitor` for reconnecting the tunnel.
+#[derive(Debug, Clone)]
+pub struct OpenVpnRestartHandle {
+    management: management::ManagementHandle,
+}
+
+impl OpenVpnRestartHandle {
+    /// Makes OpenVPN reconnect to the server by sending it `SIGUSR1` through the management
+    /// interface. The process keeps running, so it's not killed and respawned.
+    pub fn restart(&self) -> io::Result<()> {
+        self.management.signal(management::Signal::Restart)
+    }
+}
+
 /// Internal enum to differentiate between if the child process or the event dispatcher died first.
 #[derive(Debug)]
 enum WaitResult {
@@ -899,6 +998,9 @@ pub trait OpenVpnBuilder {
     /// Set the OpenVPN log file path to use.
     fn log(&mut self, log_path: Option<impl AsRef<Path>>) -> &mut Self;
 
+    /// Set the address that OpenVPN connects to for the management interface.
+    fn management(&mut self, address: &management::ManagementAddress) -> &mut Self;
+
     /// Spawn the subprocess and return a handle.
     fn start(&self) -> io::Result<Self::ProcessHandle>;
 }
@@ -927,6 +1029,11 @@ impl OpenVpnBuilder for OpenVpnCommand {
         }
     }
 
+    fn management(&mut self, address: &management::ManagementAddress) -> &mut Self {
+        let (address, port, password_file) = address.arguments();
+        self.management(address, port, password_file)
+    }
+
     fn start(&self) -> io::Result<OpenVpnProcHandle> {
         OpenVpnProcHandle::new(self.build())
     }
@@ -1425,6 +1532,10 @@ mod tests {
             self
         }
 
+        fn management(&mut self, _address: &management::ManagementAddress) -> &mut Self {
+            self
+        }
+
         fn start(&self) -> io::Result<Self::ProcessHandle> {
             self.process_handle
                 .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to start"))
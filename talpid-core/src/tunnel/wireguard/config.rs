@@ -17,12 +17,17 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Timing and probes used by the connectivity monitor
+    pub connectivity_check: wireguard::ConnectivityCheckOptions,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
     /// Enable IPv6 routing rules
     #[cfg(target_os = "linux")]
     pub enable_ipv6: bool,
+    /// Network namespace to move the tunnel interface into
+    #[cfg(target_os = "linux")]
+    pub netns: Option<String>,
 }
 
 const DEFAULT_MTU: u16 = 1380;
@@ -105,10 +110,13 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            connectivity_check: wg_options.connectivity_check.clone(),
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
             #[cfg(target_os = "linux")]
             enable_ipv6: generic_options.enable_ipv6,
+            #[cfg(target_os = "linux")]
+            netns: wg_options.netns.clone(),
         })
     }
 
@@ -125,19 +133,39 @@ impl Config {
         wg_conf.add("fwmark", self.fwmark.to_string().as_str());
 
         wg_conf.add("replace_peers", "true");
+        self.add_userspace_peers(&mut wg_conf);
+
+        let bytes = wg_conf.into_config();
+        CString::new(bytes).expect("null bytes inside config")
+    }
+
+    /// Returns a CString that replaces the private key and peers of a running WireGuard-go
+    /// tunnel. The listen port and firewall mark are left out, since changing them would rebind
+    /// the sockets of the tunnel.
+    pub fn to_userspace_reconfig_format(&self) -> CString {
+        let mut wg_conf = WgConfigBuffer::new();
+        wg_conf
+            .add("private_key", self.tunnel.private_key.to_bytes().as_ref())
+            .add("replace_peers", "true");
+        self.add_userspace_peers(&mut wg_conf);
+
+        let bytes = wg_conf.into_config();
+        CString::new(bytes).expect("null bytes inside config")
+    }
 
+    fn add_userspace_peers(&self, wg_conf: &mut WgConfigBuffer) {
         for peer in &self.peers {
+            wg_conf.add("public_key", peer.public_key.as_bytes().as_ref());
+            if let Some(psk) = &peer.psk {
+                wg_conf.add("preshared_key", psk.as_bytes().as_ref());
+            }
             wg_conf
-                .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
         }
-
-        let bytes = wg_conf.into_config();
-        CString::new(bytes).expect("null bytes inside config")
     }
 }
 
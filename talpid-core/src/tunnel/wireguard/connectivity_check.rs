@@ -1,12 +1,16 @@
 use crate::{
-    ping_monitor::{new_pinger, Pinger},
+    ping_monitor::{new_pinger, new_tcp_pinger, Pinger},
     tunnel::wireguard::stats::Stats,
 };
 use std::{
-    net::Ipv4Addr,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::{mpsc, Mutex, Weak},
     time::{Duration, Instant},
 };
+use talpid_types::{
+    net::wireguard::{ConnectivityCheckOptions, ProbeMode},
+    ErrorExt,
+};
 
 use super::{Tunnel, TunnelError};
 
@@ -15,20 +19,6 @@ const DELAY_ON_INITIAL_SETUP: Duration = Duration::from_millis(50);
 /// Sleep time used when checking if an established connection is still working.
 const REGULAR_LOOP_SLEEP: Duration = Duration::from_secs(1);
 
-
-/// Timeout for waiting on receiving traffic after sending outgoing traffic.  Once this timeout is
-/// hit, a ping will be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached, or traffic
-/// is received.
-const BYTES_RX_TIMEOUT: Duration = Duration::from_secs(5);
-/// Timeout for waiting on receiving or sending any traffic.  Once this timeout is hit, a ping will
-/// be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached or traffic is received.
-const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
-/// Timeout for waiting on receiving traffic after sending the first ICMP packet.  Once this
-/// timeout is reached, it is assumed that the connection is lost.
-const PING_TIMEOUT: Duration = Duration::from_secs(15);
-/// Number of seconds to wait between sending ICMP packets
-const SECONDS_PER_PING: Duration = Duration::from_secs(3);
-
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -41,6 +31,16 @@ pub enum Error {
     PingError(#[error(source)] crate::ping_monitor::Error),
 }
 
+/// Requests for a running [`ConnectivityMonitor`], sent after the tunnel has been reconfigured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    /// The private key was replaced. Probes are sent right away so that an idle tunnel
+    /// handshakes using the new key, without restarting the timeouts.
+    Probe,
+    /// The peers were replaced, so connectivity has to be established anew.
+    Reestablish,
+}
+
 
 /// Verifies if a connection to a tunnel is working.
 /// The connectivity monitor is biased to receiving traffic - it is expected that all outgoing
@@ -55,43 +55,81 @@ pub enum Error {
 /// timeout. A connection is considered to be established the first time an increase in incoming
 /// traffic is observed.
 ///
-/// The connectivity monitor will start sending pings and start the countdown to `PING_TIMEOUT` in
-/// the following cases:
+/// The connectivity monitor will start sending probes to every probe target and start the
+/// countdown to `probe_timeout` in the following cases:
 /// - In case that we have observed a bump in the outgoing traffic but no coressponding incoming
-/// traffic for longer than `BYTES_RX_TIMEOUT`, then the monitor will start pinging.
+/// traffic for longer than `rx_timeout`, then the monitor will start pinging.
 /// - In case that no increase in outgoing or incoming traffic has been observed for longer than
-/// `TRAFFIC_TIMEOUT`, then the monitor will start pinging as well.
+/// `traffic_timeout`, then the monitor will start pinging as well.
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
-/// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+/// monitor has started pinging and no traffic has been received for a duration of
+/// `probe_timeout`. The timeouts are configured through [`ConnectivityCheckOptions`].
+///
+/// If the tunnel has an IPv6 gateway, it is pinged separately once the connection has been
+/// established. Losing IPv6 connectivity does not break the connection, but is reported through
+/// the callback passed to [`ConnectivityMonitor::run`].
+///
+/// Once the monitor is running, it handles the [`Request`]s sent by the tunnel after it has been
+/// reconfigured.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
+    options: ConnectivityCheckOptions,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
-    pinger: Box<dyn Pinger>,
+    pingers: Vec<Box<dyn Pinger>>,
+    ipv6_check: Option<Ipv6Check>,
     close_receiver: mpsc::Receiver<()>,
+    request_receiver: mpsc::Receiver<Request>,
 }
 
 
 impl ConnectivityMonitor {
     pub(super) fn new(
-        addr: Ipv4Addr,
+        gateway: Ipv4Addr,
+        ipv6_gateway: Option<Ipv6Addr>,
         interface: String,
+        options: ConnectivityCheckOptions,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
+        request_receiver: mpsc::Receiver<Request>,
     ) -> Result<Self, Error> {
-        let pinger = new_pinger(addr, interface).map_err(Error::PingError)?;
+        let targets = if options.probe_targets.is_empty() {
+            vec![gateway]
+        } else {
+            options.probe_targets.clone()
+        };
+        let pingers = targets
+            .into_iter()
+            .map(|target| new_prober(target, options.probe_mode, interface.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
 
         let now = Instant::now();
 
+        let ipv6_check = ipv6_gateway.and_then(|ipv6_gateway| {
+            match new_pinger(ipv6_gateway.into(), interface.clone()) {
+                Ok(pinger) => Some(Ipv6Check::new(pinger, now)),
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to set up IPv6 connectivity check")
+                    );
+                    None
+                }
+            }
+        });
+
         Ok(Self {
             tunnel_handle,
             conn_state: ConnState::new(now, Default::default()),
+            options,
             initial_ping_timestamp: None,
             num_pings_sent: 0,
-            pinger,
+            pingers,
+            ipv6_check,
             close_receiver,
+            request_receiver,
         })
     }
 
@@ -103,7 +141,7 @@ impl ConnectivityMonitor {
         }
 
         let start = Instant::now();
-        while start.elapsed() < PING_TIMEOUT {
+        while start.elapsed() < self.options.probe_timeout {
             if self.check_connectivity(Instant::now())? {
                 #[cfg(target_os = "linux")]
                 self.tunnel_handle.upgrade().and_then::<(), _>(|tunnel| {
@@ -120,8 +158,43 @@ impl ConnectivityMonitor {
         Ok(false)
     }
 
-    pub(super) fn run(&mut self) -> Result<(), Error> {
-        self.wait_loop(REGULAR_LOOP_SLEEP)
+    /// Monitors the connection until it is lost or the monitor is shut down.
+    /// `on_ipv6_degraded` is called with `true` when IPv6 connectivity is lost, and with `false`
+    /// once it has been restored. `on_reestablished` is called when connectivity has been
+    /// established anew after a [`Request::Reestablish`].
+    pub(super) fn run(
+        &mut self,
+        on_ipv6_degraded: impl FnMut(bool),
+        on_reestablished: impl FnMut(),
+    ) -> Result<(), Error> {
+        if let Some(ipv6_check) = &mut self.ipv6_check {
+            ipv6_check.reset(Instant::now());
+        }
+        self.wait_loop(REGULAR_LOOP_SLEEP, on_ipv6_degraded, on_reestablished)
+    }
+
+    /// Handles a request sent after the tunnel was reconfigured. Returns false if connectivity
+    /// could not be established anew.
+    fn handle_request(&mut self, request: Request) -> Result<bool, Error> {
+        match request {
+            Request::Probe => {
+                self.send_probes()?;
+                Ok(true)
+            }
+            Request::Reestablish => {
+                // Only traffic received from the new peers counts
+                let stats = self.get_stats().transpose()?.unwrap_or_default();
+                let now = Instant::now();
+                self.conn_state = ConnState::new(now, stats);
+                self.reset_pinger();
+
+                self.send_probes()?;
+                self.initial_ping_timestamp = Some(now);
+                self.num_pings_sent = 1;
+
+                self.establish_connectivity()
+            }
+        }
     }
 
     /// Returns true if monitor should be shut down
@@ -132,15 +205,36 @@ impl ConnectivityMonitor {
         }
     }
 
-    fn wait_loop(&mut self, iter_delay: Duration) -> Result<(), Error> {
+    fn wait_loop(
+        &mut self,
+        iter_delay: Duration,
+        mut on_ipv6_degraded: impl FnMut(bool),
+        mut on_reestablished: impl FnMut(),
+    ) -> Result<(), Error> {
         let mut last_iteration = Instant::now();
         while !self.should_shut_down(iter_delay) {
+            if let Ok(request) = self.request_receiver.try_recv() {
+                if !self.handle_request(request)? {
+                    return Ok(());
+                }
+                if request == Request::Reestablish {
+                    on_reestablished();
+                }
+                last_iteration = Instant::now();
+                continue;
+            }
+
             let mut current_iteration = Instant::now();
             let time_slept = current_iteration - last_iteration;
             if time_slept < (iter_delay * 2) {
                 if !self.check_connectivity(Instant::now())? {
                     return Ok(());
                 }
+                if let Some(ipv6_check) = &mut self.ipv6_check {
+                    if let Some(degraded) = ipv6_check.update(Instant::now(), &self.options) {
+                        on_ipv6_degraded(degraded);
+                    }
+                }
 
                 let end = Instant::now();
                 if end - current_iteration > Duration::from_secs(1) {
@@ -151,6 +245,9 @@ impl ConnectivityMonitor {
                 // connectivity.
                 self.reset_pinger();
                 self.conn_state.reset_after_suspension(current_iteration);
+                if let Some(ipv6_check) = &mut self.ipv6_check {
+                    ipv6_check.reset(current_iteration);
+                }
             }
             last_iteration = current_iteration;
         }
@@ -188,17 +285,19 @@ impl ConnectivityMonitor {
 
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
-        // in the last 2 minutes, but if a ping already has been sent out, only send one out every
-        // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        // in a while, but if a ping already has been sent out, only send one out every
+        // `probe_interval`.
+        if (self.conn_state.rx_timed_out(&self.options)
+            || self.conn_state.traffic_timed_out(&self.options))
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
-                    initial_ping_timestamp.elapsed() / self.num_pings_sent < SECONDS_PER_PING
+                    initial_ping_timestamp.elapsed() / self.num_pings_sent
+                        >= self.options.probe_interval
                 })
                 .unwrap_or(true)
         {
-            self.pinger.send_icmp().map_err(Error::PingError)?;
+            self.send_probes()?;
             if self.initial_ping_timestamp.is_none() {
                 self.initial_ping_timestamp = Some(now);
             }
@@ -207,9 +306,18 @@ impl ConnectivityMonitor {
         Ok(())
     }
 
+    fn send_probes(&mut self) -> Result<(), Error> {
+        for pinger in &mut self.pingers {
+            pinger.send_probe().map_err(Error::PingError)?;
+        }
+        Ok(())
+    }
+
     fn ping_timed_out(&self) -> bool {
         self.initial_ping_timestamp
-            .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > PING_TIMEOUT)
+            .map(|initial_ping_timestamp| {
+                initial_ping_timestamp.elapsed() > self.options.probe_timeout
+            })
             .unwrap_or(false)
     }
 
@@ -217,10 +325,91 @@ impl ConnectivityMonitor {
     fn reset_pinger(&mut self) {
         self.initial_ping_timestamp = None;
         self.num_pings_sent = 0;
+        for pinger in &mut self.pingers {
+            pinger.reset();
+        }
+    }
+}
+
+/// Pings the IPv6 gateway of the tunnel, to detect IPv6 breaking while IPv4 keeps working.
+struct Ipv6Check {
+    pinger: Box<dyn Pinger>,
+    last_probe: Option<Instant>,
+    last_reply: Instant,
+    degraded: bool,
+}
+
+impl Ipv6Check {
+    fn new(pinger: Box<dyn Pinger>, now: Instant) -> Self {
+        Ipv6Check {
+            pinger,
+            last_probe: None,
+            last_reply: now,
+            degraded: false,
+        }
+    }
+
+    /// Pings the gateway if `probe_interval` has passed since the last ping. Returns the new
+    /// state if IPv6 became degraded or was restored. IPv6 is considered degraded if no reply has
+    /// been received for `probe_timeout`, and restored once a reply is received.
+    fn update(&mut self, now: Instant, options: &ConnectivityCheckOptions) -> Option<bool> {
+        let received_reply = self.pinger.received_reply().unwrap_or_else(|error| {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to read IPv6 replies")
+            );
+            false
+        });
+        if received_reply {
+            self.last_reply = now;
+        }
+
+        let should_probe = self
+            .last_probe
+            .map(|last_probe| now.saturating_duration_since(last_probe) >= options.probe_interval)
+            .unwrap_or(true);
+        if should_probe {
+            if let Err(error) = self.pinger.send_probe() {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to ping IPv6 gateway")
+                );
+            }
+            self.last_probe = Some(now);
+        }
+
+        let degraded = if received_reply {
+            false
+        } else {
+            self.degraded || now.saturating_duration_since(self.last_reply) > options.probe_timeout
+        };
+        if degraded == self.degraded {
+            return None;
+        }
+        self.degraded = degraded;
+        Some(degraded)
+    }
+
+    /// Restarts the timeout, without changing the current state.
+    fn reset(&mut self, now: Instant) {
+        self.last_probe = None;
+        self.last_reply = now;
         self.pinger.reset();
     }
 }
 
+fn new_prober(
+    target: Ipv4Addr,
+    mode: ProbeMode,
+    interface: String,
+) -> Result<Box<dyn Pinger>, Error> {
+    match mode {
+        ProbeMode::Icmp => new_pinger(target.into(), interface).map_err(Error::PingError),
+        ProbeMode::Tcp(port) => Ok(new_tcp_pinger(SocketAddr::new(target.into(), port), false)),
+        ProbeMode::Http(port) => Ok(new_tcp_pinger(SocketAddr::new(target.into(), port), true)),
+    }
+}
+
 enum ConnState {
     Connecting {
         start: Instant,
@@ -251,7 +440,7 @@ impl ConnState {
                 stats,
                 tx_timestamp,
             } => {
-                if new_stats.rx_bytes > 0 {
+                if new_stats.rx_bytes > stats.rx_bytes {
                     let tx_timestamp = tx_timestamp.unwrap_or(*start);
                     let connected_state = ConnState::Connected {
                         rx_timestamp: now,
@@ -307,9 +496,9 @@ impl ConnState {
     }
 
     // check if last time data was received is too long ago
-    pub fn rx_timed_out(&self) -> bool {
+    pub fn rx_timed_out(&self, options: &ConnectivityCheckOptions) -> bool {
         match self {
-            ConnState::Connecting { start, .. } => start.elapsed() >= BYTES_RX_TIMEOUT,
+            ConnState::Connecting { start, .. } => start.elapsed() >= options.rx_timeout,
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
@@ -317,23 +506,23 @@ impl ConnState {
             } => {
                 // if last sent bytes were sent after or at the same time as last received bytes
                 tx_timestamp >= rx_timestamp &&
-                    // and the response hasn't been seen for BYTES_RX_TIMEOUT
-                    rx_timestamp.elapsed() >= BYTES_RX_TIMEOUT
+                    // and the response hasn't been seen for rx_timeout
+                    rx_timestamp.elapsed() >= options.rx_timeout
             }
         }
     }
 
     // check if no bytes have been sent or received in a while
-    pub fn traffic_timed_out(&self) -> bool {
+    pub fn traffic_timed_out(&self, options: &ConnectivityCheckOptions) -> bool {
         match self {
-            ConnState::Connecting { .. } => self.rx_timed_out(),
+            ConnState::Connecting { .. } => self.rx_timed_out(options),
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
                 ..
             } => {
-                rx_timestamp.elapsed() >= TRAFFIC_TIMEOUT
-                    || tx_timestamp.elapsed() >= TRAFFIC_TIMEOUT
+                rx_timestamp.elapsed() >= options.traffic_timeout
+                    || tx_timestamp.elapsed() >= options.traffic_timeout
             }
         }
     }
@@ -349,10 +538,10 @@ impl ConnState {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::tunnel::wireguard::{stats, TunnelError};
+    use crate::tunnel::wireguard::{stats, Config, TunnelError};
     use std::{
         sync::{
-            atomic::{AtomicBool, Ordering},
+            atomic::{AtomicBool, AtomicUsize, Ordering},
             Arc, Mutex,
         },
         time::{Duration, Instant},
@@ -361,30 +550,33 @@ mod test {
     /// Test if a newly created ConnState won't have timed out or consider itself connected
     #[test]
     fn test_conn_state_no_timeout_on_start() {
+        let options = ConnectivityCheckOptions::default();
         let now = Instant::now();
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&options));
+        assert!(!conn_state.traffic_timed_out(&options));
     }
 
     /// Test if ConnState::Connecting will timeout after not receiving any traffic after
-    /// BYTES_RX_TIMEOUT
+    /// `rx_timeout`
     #[test]
     fn test_conn_state_timeout_after_rx_timeout() {
-        let now = Instant::now().checked_sub(BYTES_RX_TIMEOUT).unwrap();
+        let options = ConnectivityCheckOptions::default();
+        let now = Instant::now().checked_sub(options.rx_timeout).unwrap();
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(&options));
+        assert!(conn_state.traffic_timed_out(&options));
     }
 
     /// Test if ConnState::Connecting correctly transitions into ConnState::Connected if traffic is
     /// received
     #[test]
     fn test_conn_state_connects() {
+        let options = ConnectivityCheckOptions::default();
         let start = Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
         let mut conn_state = ConnState::new(start, Default::default());
         conn_state.update(
@@ -392,43 +584,47 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&options));
+        assert!(!conn_state.traffic_timed_out(&options));
     }
 
-    /// Test if ConnState::Connected correctly times out after TRAFFIC_TIMEOUT when no traffic is
+    /// Test if ConnState::Connected correctly times out after `traffic_timeout` when no traffic is
     /// observed
     #[test]
     fn test_conn_state_traffic_times_out_after_connecting() {
+        let options = ConnectivityCheckOptions::default();
         let start = Instant::now()
-            .checked_sub(TRAFFIC_TIMEOUT + Duration::from_secs(1))
+            .checked_sub(options.traffic_timeout + Duration::from_secs(1))
             .unwrap();
         let mut conn_state = ConnState::new(start, Default::default());
 
-        let connect_time = Instant::now().checked_sub(TRAFFIC_TIMEOUT).unwrap();
+        let connect_time = Instant::now().checked_sub(options.traffic_timeout).unwrap();
         conn_state.update(
             connect_time,
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&options));
+        assert!(conn_state.traffic_timed_out(&options));
     }
 
-    /// Test if ConnState::Connected correctly times out after BYTES_RX_TIMEOUT when no incoming
+    /// Test if ConnState::Connected correctly times out after `rx_timeout` when no incoming
     /// traffic is observed
     #[test]
     fn test_conn_state_rx_times_out_after_connecting() {
+        let options = ConnectivityCheckOptions::default();
         let start = Instant::now()
-            .checked_sub(BYTES_RX_TIMEOUT + Duration::from_secs(1))
+            .checked_sub(options.rx_timeout + Duration::from_secs(1))
             .unwrap();
         let mut conn_state = ConnState::new(start, Default::default());
 
@@ -437,35 +633,46 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
 
-        let update_time = Instant::now().checked_sub(BYTES_RX_TIMEOUT).unwrap();
+        let update_time = Instant::now().checked_sub(options.rx_timeout).unwrap();
         conn_state.update(
             update_time,
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                last_handshake: None,
             },
         );
 
         assert!(conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(&options));
+        assert!(!conn_state.traffic_timed_out(&options));
     }
 
     #[derive(Default)]
     struct MockPinger {
         on_send_ping: Option<Box<dyn FnMut() + Send>>,
+        on_received_reply: Option<Box<dyn FnMut() -> bool + Send>>,
     }
 
     impl Pinger for MockPinger {
-        fn send_icmp(&mut self) -> Result<(), crate::ping_monitor::Error> {
+        fn send_probe(&mut self) -> Result<(), crate::ping_monitor::Error> {
             if let Some(callback) = self.on_send_ping.as_mut() {
                 (callback)();
             }
             Ok(())
         }
+
+        fn received_reply(&mut self) -> Result<bool, crate::ping_monitor::Error> {
+            Ok(self
+                .on_received_reply
+                .as_mut()
+                .map(|callback| (callback)())
+                .unwrap_or(false))
+        }
     }
 
     struct MockTunnel {
@@ -483,6 +690,7 @@ mod test {
             let traffic = Mutex::new(stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             });
             Self {
                 on_get_stats: Box::new(move || {
@@ -501,6 +709,7 @@ mod test {
                     Ok(stats::Stats {
                         tx_bytes: 0,
                         rx_bytes: 0,
+                        last_handshake: None,
                     })
                 }),
             }
@@ -533,6 +742,10 @@ mod test {
             Ok(())
         }
 
+        fn set_config(&self, _config: &Config) -> Result<(), TunnelError> {
+            Ok(())
+        }
+
         fn get_tunnel_stats(&self) -> Result<stats::Stats, TunnelError> {
             (self.on_get_stats)()
         }
@@ -546,10 +759,13 @@ mod test {
     ) -> ConnectivityMonitor {
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
+            options: ConnectivityCheckOptions::default(),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
-            pinger,
+            pingers: vec![pinger],
+            ipv6_check: None,
             close_receiver,
+            request_receiver: mpsc::channel().1,
             tunnel_handle,
         }
     }
@@ -561,6 +777,7 @@ mod test {
             stats: stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         }
     }
@@ -568,13 +785,14 @@ mod test {
 
     #[test]
     /// Verify that `check_connectivity()` returns `false` if the tunnel is connected and traffic is
-    /// not flowing after `BYTES_RX_TIMEOUT` and `PING_TIMEOUT`.
+    /// not flowing after `rx_timeout` and `probe_timeout`.
     fn test_ping_times_out() {
+        let options = ConnectivityCheckOptions::default();
         let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
         let (_tx, rx) = mpsc::channel();
         let pinger = MockPinger::default();
         let now = Instant::now();
-        let start = now - (BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(10));
+        let start = now - (options.rx_timeout + options.probe_timeout + Duration::from_secs(10));
         let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
 
         // Mock the state - connectivity has been established
@@ -631,7 +849,7 @@ mod test {
             let start_result = monitor.establish_connectivity();
             result_tx.send(start_result).unwrap();
 
-            let result = monitor.run().map(|_| true);
+            let result = monitor.run(|_| (), || ()).map(|_| true);
             result_tx.send(result).unwrap();
         });
 
@@ -644,14 +862,16 @@ mod test {
 
     #[test]
     /// Verify that the connectivity monitor detects the tunnel timing out after no longer than
-    /// `BYTES_RX_TIMEOUT` and `PING_TIMEOUT` combined.
+    /// `rx_timeout` and `probe_timeout` combined.
     fn test_wait_loop_timeout() {
+        let options = ConnectivityCheckOptions::default();
         let should_stop = Arc::new(AtomicBool::new(false));
         let should_stop_inner = should_stop.clone();
 
         let tunnel_stats = Mutex::new(stats::Stats {
             rx_bytes: 0,
             tx_bytes: 0,
+            last_handshake: None,
         });
 
         let pinger = MockPinger::default();
@@ -674,7 +894,7 @@ mod test {
             let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, stop_rx);
             let start_result = monitor.establish_connectivity();
             result_tx.send(start_result).unwrap();
-            let end_result = monitor.run().map(|_| true);
+            let end_result = monitor.run(|_| (), || ()).map(|_| true);
             result_tx.send(end_result).expect("Failed to send result");
         });
         assert!(result_rx
@@ -683,8 +903,164 @@ mod test {
             .unwrap());
         should_stop.store(true, Ordering::SeqCst);
         assert!(result_rx
-            .recv_timeout(BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(2))
+            .recv_timeout(options.rx_timeout + options.probe_timeout + Duration::from_secs(2))
             .unwrap()
             .is_ok());
     }
+
+    #[test]
+    /// Verify that a shorter `probe_timeout` and `rx_timeout` make `check_connectivity()` give up
+    /// sooner than the defaults would.
+    fn test_custom_timeouts() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(4);
+        let mut monitor = mock_monitor(start, Box::new(MockPinger::default()), tunnel, rx);
+        monitor.conn_state = connected_state(start);
+
+        monitor.maybe_send_ping(start).unwrap();
+        assert!(monitor.initial_ping_timestamp.is_none());
+        assert!(monitor.check_connectivity(now).unwrap());
+
+        monitor.options = ConnectivityCheckOptions {
+            rx_timeout: Duration::from_secs(1),
+            probe_timeout: Duration::from_secs(2),
+            ..ConnectivityCheckOptions::default()
+        };
+        monitor.maybe_send_ping(start).unwrap();
+        assert!(!monitor.check_connectivity(now).unwrap());
+    }
+
+    #[test]
+    /// Verify that every probe target is probed, and that probes are not sent more often than
+    /// every `probe_interval`.
+    fn test_probes_all_targets() {
+        let probes_sent = Arc::new(AtomicUsize::new(0));
+        let counting_pinger = || {
+            let probes_sent = probes_sent.clone();
+            Box::new(MockPinger {
+                on_send_ping: Some(Box::new(move || {
+                    probes_sent.fetch_add(1, Ordering::SeqCst);
+                })),
+                ..MockPinger::default()
+            }) as Box<dyn Pinger>
+        };
+
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(10);
+        let mut monitor = mock_monitor(start, counting_pinger(), tunnel, rx);
+        monitor.pingers.push(counting_pinger());
+        monitor.conn_state = connected_state(start);
+        monitor.options.probe_interval = Duration::from_secs(60);
+
+        monitor.maybe_send_ping(now).unwrap();
+        assert_eq!(probes_sent.load(Ordering::SeqCst), 2);
+
+        monitor.maybe_send_ping(now).unwrap();
+        assert_eq!(probes_sent.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    /// Verify that IPv6 is reported as degraded after `probe_timeout` without replies, and as
+    /// restored once a reply is received.
+    fn test_ipv6_degraded() {
+        let options = ConnectivityCheckOptions::default();
+        let reply = Arc::new(AtomicBool::new(false));
+        let reply_inner = reply.clone();
+        let pinger = MockPinger {
+            on_received_reply: Some(Box::new(move || reply_inner.swap(false, Ordering::SeqCst))),
+            ..MockPinger::default()
+        };
+        let start = Instant::now();
+        let mut ipv6_check = Ipv6Check::new(Box::new(pinger), start);
+
+        assert_eq!(ipv6_check.update(start, &options), None);
+        let timed_out = start + options.probe_timeout + Duration::from_secs(1);
+        assert_eq!(ipv6_check.update(timed_out, &options), Some(true));
+        assert_eq!(ipv6_check.update(timed_out, &options), None);
+
+        // Resetting after a suspension does not restore IPv6 by itself
+        ipv6_check.reset(timed_out);
+        assert_eq!(ipv6_check.update(timed_out, &options), None);
+
+        reply.store(true, Ordering::SeqCst);
+        assert_eq!(ipv6_check.update(timed_out, &options), Some(false));
+    }
+
+    #[test]
+    /// Verify that a probe request sends probes without starting the probe timeout.
+    fn test_probe_request() {
+        let probes_sent = Arc::new(AtomicUsize::new(0));
+        let probes_sent_inner = probes_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || {
+                probes_sent_inner.fetch_add(1, Ordering::SeqCst);
+            })),
+            ..MockPinger::default()
+        };
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let mut monitor = mock_monitor(now, Box::new(pinger), tunnel, rx);
+        monitor.conn_state = connected_state(now);
+
+        assert!(monitor.handle_request(Request::Probe).unwrap());
+        assert_eq!(probes_sent.load(Ordering::SeqCst), 1);
+        assert!(monitor.initial_ping_timestamp.is_none());
+        assert!(monitor.conn_state.connected());
+    }
+
+    #[test]
+    /// Verify that connectivity is only reestablished once traffic is received after the request,
+    /// and that traffic received before it does not count.
+    fn test_reestablish_request() {
+        let replied = Arc::new(AtomicBool::new(false));
+        let replied_inner = replied.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || {
+                replied_inner.store(true, Ordering::SeqCst)
+            })),
+            ..MockPinger::default()
+        };
+        let tunnel_stats = Mutex::new(stats::Stats {
+            rx_bytes: 100,
+            tx_bytes: 100,
+            last_handshake: None,
+        });
+        let replied_inner = replied.clone();
+        let (_tunnel_anchor, tunnel) = MockTunnel::new(move || {
+            let mut tunnel_stats = tunnel_stats.lock().unwrap();
+            if replied_inner.load(Ordering::SeqCst) {
+                tunnel_stats.rx_bytes += 1;
+            }
+            Ok(*tunnel_stats)
+        })
+        .into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let mut monitor = mock_monitor(now, Box::new(pinger), tunnel, rx);
+        monitor.conn_state = connected_state(now);
+
+        assert!(monitor.handle_request(Request::Reestablish).unwrap());
+        assert!(replied.load(Ordering::SeqCst));
+        assert!(monitor.conn_state.connected());
+    }
+
+    #[test]
+    /// Verify that reestablishing connectivity fails if no traffic is received within
+    /// `probe_timeout`.
+    fn test_reestablish_request_timeout() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let mut monitor = mock_monitor(now, Box::new(MockPinger::default()), tunnel, rx);
+        monitor.conn_state = connected_state(now);
+        monitor.options.probe_timeout = Duration::from_millis(200);
+
+        assert!(!monitor.handle_request(Request::Reestablish).unwrap());
+        assert!(!monitor.conn_state.connected());
+    }
 }
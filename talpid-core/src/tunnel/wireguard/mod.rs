@@ -1,7 +1,7 @@
 use self::config::Config;
 #[cfg(not(windows))]
 use super::tun_provider;
-use super::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
+use super::{tun_provider::TunProvider, TrafficStats, TunnelEvent, TunnelMetadata};
 use crate::routing::{self, RequiredRoute};
 use futures::future::abortable;
 #[cfg(target_os = "linux")]
@@ -14,9 +14,12 @@ use std::{
     collections::HashSet,
     net::SocketAddr,
     path::Path,
-    sync::{mpsc, Arc, Mutex},
+    sync::{mpsc, Arc, Mutex, Weak},
+};
+use talpid_types::{
+    net::{wireguard as wireguard_types, TransportProtocol},
+    ErrorExt,
 };
-use talpid_types::{net::TransportProtocol, ErrorExt};
 use udp_over_tcp::{TcpOptions, Udp2Tcp};
 
 /// WireGuard config data-types
@@ -60,6 +63,24 @@ pub enum Error {
     #[error(display = "Connectivity monitor failed")]
     ConnectivityMonitorError(#[error(source)] connectivity_check::Error),
 
+    /// Failed to create a config from new tunnel parameters
+    #[error(display = "Invalid WireGuard tunnel parameters")]
+    InvalidConfigError(#[error(source)] config::Error),
+
+    /// The tunnel has already been stopped
+    #[error(display = "The WireGuard tunnel is not running")]
+    TunnelStoppedError,
+
+    /// Failed to set up the kernel WireGuard device in a network namespace
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set up WireGuard device in network namespace")]
+    NetnsTunnelError(#[error(source)] wireguard_kernel::Error),
+
+    /// Failed to enter the network namespace of the tunnel
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to enter network namespace")]
+    NetnsError(#[error(source)] crate::linux::netns::Error),
+
     /// Failed to set up IP interfaces.
     #[cfg(windows)]
     #[error(display = "Failed while waiting on IP interfaces")]
@@ -89,6 +110,11 @@ pub struct WireguardMonitor {
     #[cfg(target_os = "windows")]
     stop_setup_tx: Option<futures::channel::oneshot::Sender<()>>,
     pinger_stop_sender: mpsc::Sender<()>,
+    /// Used to tell the connectivity monitor that the tunnel has been reconfigured
+    connectivity_request_sender: mpsc::Sender<connectivity_check::Request>,
+    /// Used to route the endpoints of new peers outside the tunnel
+    #[cfg(not(target_os = "linux"))]
+    route_handle: routing::RouteManagerHandle,
     _tcp_proxies: Vec<TcpProxy>,
     #[cfg(target_os = "windows")]
     _callback_handle: Option<crate::winnet::WinNetCallbackHandle>,
@@ -198,9 +224,12 @@ impl WireguardMonitor {
             log::warn!("Failed to register default route callback");
         }
 
+        let route_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
+
         let event_callback = Box::new(on_event.clone());
         let (close_msg_sender, close_msg_receiver) = mpsc::channel();
         let (pinger_tx, pinger_rx) = mpsc::channel();
+        let (connectivity_request_tx, connectivity_request_rx) = mpsc::channel();
         #[cfg(target_os = "windows")]
         let (stop_setup_tx, stop_setup_rx) = futures::channel::oneshot::channel();
         let monitor = WireguardMonitor {
@@ -212,22 +241,22 @@ impl WireguardMonitor {
             #[cfg(target_os = "windows")]
             stop_setup_tx: Some(stop_setup_tx),
             pinger_stop_sender: pinger_tx,
+            connectivity_request_sender: connectivity_request_tx,
+            #[cfg(not(target_os = "linux"))]
+            route_handle: route_handle.clone(),
             _tcp_proxies: tcp_proxies,
             #[cfg(target_os = "windows")]
             _callback_handle: callback_handle,
         };
 
-        let gateway = config.ipv4_gateway;
         let close_sender = monitor.close_msg_sender.clone();
-        let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
-            gateway,
-            iface_name.clone(),
-            Arc::downgrade(&monitor.tunnel),
+        let mut connectivity_monitor = Self::create_connectivity_monitor(
+            &config,
+            &iface_name,
+            &monitor.tunnel,
             pinger_rx,
-        )
-        .map_err(Error::ConnectivityMonitorError)?;
-
-        let route_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
+            connectivity_request_rx,
+        )?;
 
         let metadata = Self::tunnel_metadata(&iface_name, &config);
 
@@ -269,6 +298,12 @@ impl WireguardMonitor {
                     return Err(Error::SetIpAddressesError);
                 }
 
+                // Routes inside the network namespace are set up along with the interface
+                #[cfg(target_os = "linux")]
+                if config.netns.is_some() {
+                    return Ok(());
+                }
+
                 runtime.block_on(async move {
                     #[cfg(target_os = "linux")]
                     route_handle
@@ -290,9 +325,22 @@ impl WireguardMonitor {
 
             match connectivity_monitor.establish_connectivity() {
                 Ok(true) => {
-                    runtime.block_on((on_event)(TunnelEvent::Up(metadata)));
-
-                    if let Err(error) = connectivity_monitor.run() {
+                    runtime.block_on((on_event)(TunnelEvent::Up(metadata.clone())));
+
+                    let on_ipv6_degraded = |degraded| {
+                        let event = if degraded {
+                            TunnelEvent::Ipv6Degraded
+                        } else {
+                            TunnelEvent::Ipv6Restored
+                        };
+                        runtime.block_on((on_event)(event));
+                    };
+                    // Sent once the peers of a reconfigured tunnel respond
+                    let on_reestablished = || {
+                        runtime.block_on((on_event)(TunnelEvent::Up(metadata.clone())));
+                    };
+                    if let Err(error) = connectivity_monitor.run(on_ipv6_degraded, on_reestablished)
+                    {
                         log::error!(
                             "{}",
                             error.display_chain_with_msg("Connectivity monitor failed")
@@ -314,6 +362,58 @@ impl WireguardMonitor {
         Ok(monitor)
     }
 
+    fn create_connectivity_monitor(
+        config: &Config,
+        iface_name: &str,
+        tunnel: &Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        pinger_rx: mpsc::Receiver<()>,
+        request_rx: mpsc::Receiver<connectivity_check::Request>,
+    ) -> Result<connectivity_check::ConnectivityMonitor> {
+        let gateway = config.ipv4_gateway;
+        let ipv6_gateway = config.ipv6_gateway;
+        let iface_name = iface_name.to_string();
+        let options = config.connectivity_check.clone();
+        let tunnel = Arc::downgrade(tunnel);
+
+        #[cfg(target_os = "linux")]
+        if let Some(netns_name) = &config.netns {
+            let mut options = options;
+            // TCP probes are sent from threads of their own, which would not be in the namespace
+            if options.probe_mode != wireguard_types::ProbeMode::Icmp {
+                log::warn!("Using ICMP probes since the tunnel is in a network namespace");
+                options.probe_mode = wireguard_types::ProbeMode::Icmp;
+            }
+            // The ICMP sockets must be created inside the namespace to reach the interface
+            let netns = crate::linux::netns::NetNs::open_or_create(netns_name)
+                .map_err(Error::NetnsError)?;
+            return netns
+                .run(move || {
+                    connectivity_check::ConnectivityMonitor::new(
+                        gateway,
+                        ipv6_gateway,
+                        iface_name,
+                        options,
+                        tunnel,
+                        pinger_rx,
+                        request_rx,
+                    )
+                })
+                .map_err(Error::NetnsError)?
+                .map_err(Error::ConnectivityMonitorError);
+        }
+
+        connectivity_check::ConnectivityMonitor::new(
+            gateway,
+            ipv6_gateway,
+            iface_name,
+            options,
+            tunnel,
+            pinger_rx,
+            request_rx,
+        )
+        .map_err(Error::ConnectivityMonitorError)
+    }
+
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
     fn open_tunnel(
         config: &Config,
@@ -321,6 +421,20 @@ impl WireguardMonitor {
         tun_provider: &mut TunProvider,
         route_manager: &mut routing::RouteManager,
     ) -> Result<Box<dyn Tunnel>> {
+        #[cfg(target_os = "linux")]
+        if let Some(netns_name) = &config.netns {
+            // Only devices created through netlink can be moved into a namespace, so there is no
+            // fallback here
+            let tunnel =
+                wireguard_kernel::NetlinkTunnel::new(route_manager.runtime_handle(), config)
+                    .map_err(Error::NetnsTunnelError)?;
+            log::debug!(
+                "Using kernel WireGuard implementation in network namespace {}",
+                netns_name
+            );
+            return Ok(Box::new(tunnel));
+        }
+
         #[cfg(target_os = "linux")]
         if !*FORCE_USERSPACE_WIREGUARD {
             if crate::dns::will_use_nm() {
@@ -376,6 +490,25 @@ impl WireguardMonitor {
         }
     }
 
+    /// Returns a handle for reading the traffic counters of the tunnel
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            tunnel: Arc::downgrade(&self.tunnel),
+        }
+    }
+
+    /// Returns a handle for switching the tunnel to different peers
+    pub fn reconfigure_handle(&self) -> ReconfigureHandle {
+        ReconfigureHandle {
+            #[cfg(not(target_os = "linux"))]
+            runtime: self.runtime.clone(),
+            tunnel: Arc::downgrade(&self.tunnel),
+            connectivity_request_sender: self.connectivity_request_sender.clone(),
+            #[cfg(not(target_os = "linux"))]
+            route_handle: self.route_handle.clone(),
+        }
+    }
+
     /// Blocks the current thread until tunnel disconnects
     pub fn wait(mut self) -> Result<()> {
         let wait_result = match self.close_msg_receiver.recv() {
@@ -459,12 +592,7 @@ impl WireguardMonitor {
         };
 
         // route endpoints with specific routes
-        for peer in config.peers.iter() {
-            routes.insert(RequiredRoute::new(
-                peer.endpoint.ip().into(),
-                routing::NetNode::DefaultNode,
-            ));
-        }
+        routes.extend(Self::get_endpoint_routes(config));
 
         routes
     }
@@ -512,16 +640,18 @@ impl WireguardMonitor {
             .collect();
 
         // route endpoints with specific routes
-        for peer in config.peers.iter() {
-            routes.insert(RequiredRoute::new(
-                peer.endpoint.ip().into(),
-                routing::NetNode::DefaultNode,
-            ));
-        }
+        routes.extend(Self::get_endpoint_routes(config));
 
         routes
     }
 
+    #[cfg(not(target_os = "linux"))]
+    fn get_endpoint_routes(config: &Config) -> impl Iterator<Item = RequiredRoute> + '_ {
+        config.peers.iter().map(|peer| {
+            RequiredRoute::new(peer.endpoint.ip().into(), routing::NetNode::DefaultNode)
+        })
+    }
+
     fn tunnel_metadata(interface_name: &str, config: &Config) -> TunnelMetadata {
         TunnelMetadata {
             interface: interface_name.to_string(),
@@ -553,11 +683,142 @@ impl CloseHandle {
     }
 }
 
+/// Handle for reading the traffic counters of a WireGuard tunnel.
+#[derive(Clone)]
+pub struct StatsHandle {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+}
+
+impl StatsHandle {
+    /// Returns the current traffic counters, or `None` if the tunnel has been stopped.
+    pub fn get_stats(&self) -> Option<TrafficStats> {
+        let tunnel = self.tunnel.upgrade()?;
+        let tunnel = tunnel.lock().ok()?;
+        match tunnel.as_ref()?.get_tunnel_stats() {
+            Ok(stats) => Some(TrafficStats {
+                rx_bytes: stats.rx_bytes,
+                tx_bytes: stats.tx_bytes,
+                last_handshake: stats.last_handshake,
+            }),
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read WireGuard tunnel stats")
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Handle for switching a running WireGuard tunnel to different peers, without recreating the
+/// tunnel device.
+#[derive(Clone)]
+pub struct ReconfigureHandle {
+    #[cfg(not(target_os = "linux"))]
+    runtime: tokio::runtime::Handle,
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    connectivity_request_sender: mpsc::Sender<connectivity_check::Request>,
+    #[cfg(not(target_os = "linux"))]
+    route_handle: routing::RouteManagerHandle,
+}
+
+impl ReconfigureHandle {
+    /// Replaces the peers in `current` with the ones in `new`. The parameters must be compatible,
+    /// see [`can_reconfigure`]. Connectivity is then established anew, and [`TunnelEvent::Up`]
+    /// is sent once the new peers respond. If they don't, the tunnel is closed.
+    #[cfg_attr(target_os = "linux", allow(unused_variables))]
+    pub fn reconfigure(
+        &self,
+        current: &wireguard_types::TunnelParameters,
+        new: &wireguard_types::TunnelParameters,
+    ) -> Result<()> {
+        let config = Config::from_parameters(new).map_err(Error::InvalidConfigError)?;
+
+        // The new endpoints must not be routed through the tunnel before the handshake
+        #[cfg(not(target_os = "linux"))]
+        let old_endpoint_routes = {
+            let current_config =
+                Config::from_parameters(current).map_err(Error::InvalidConfigError)?;
+            let new_endpoint_routes: HashSet<_> =
+                WireguardMonitor::get_endpoint_routes(&config).collect();
+            self.runtime
+                .block_on(self.route_handle.add_routes(new_endpoint_routes.clone()))
+                .map_err(Error::SetupRoutingError)?;
+            WireguardMonitor::get_endpoint_routes(&current_config)
+                .filter(|route| !new_endpoint_routes.contains(route))
+                .collect::<HashSet<_>>()
+        };
+
+        self.set_config(&config)?;
+        self.send_connectivity_request(connectivity_check::Request::Reestablish);
+
+        #[cfg(not(target_os = "linux"))]
+        if let Err(error) = self
+            .runtime
+            .block_on(self.route_handle.delete_routes(old_endpoint_routes))
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove routes to the previous relay")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the private key of the tunnel with the one in `params`, keeping its peers.
+    /// Probes are sent right away, so that an idle tunnel handshakes using the new key.
+    pub fn replace_key(&self, params: &wireguard_types::TunnelParameters) -> Result<()> {
+        let config = Config::from_parameters(params).map_err(Error::InvalidConfigError)?;
+        self.set_config(&config)?;
+        self.send_connectivity_request(connectivity_check::Request::Probe);
+        Ok(())
+    }
+
+    fn set_config(&self, config: &Config) -> Result<()> {
+        let tunnel = self.tunnel.upgrade().ok_or(Error::TunnelStoppedError)?;
+        let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        let tunnel = tunnel.as_ref().ok_or(Error::TunnelStoppedError)?;
+        tunnel.set_config(config).map_err(Error::TunnelError)
+    }
+
+    fn send_connectivity_request(&self, request: connectivity_check::Request) {
+        if self.connectivity_request_sender.send(request).is_err() {
+            log::warn!("The connectivity monitor of the tunnel has stopped");
+        }
+    }
+}
+
+/// Returns whether a tunnel started with `current` can be switched to `new` by only replacing
+/// its peers. The tunnel addresses, gateways and options must be unchanged, and no peer may be
+/// reached through a UDP-over-TCP proxy, since the proxies are set up when the tunnel starts.
+pub fn can_reconfigure(
+    current: &wireguard_types::TunnelParameters,
+    new: &wireguard_types::TunnelParameters,
+) -> bool {
+    let uses_tcp = |params: &wireguard_types::TunnelParameters| {
+        std::iter::once(&params.connection.peer)
+            .chain(params.connection.exit_peer.iter())
+            .any(|peer| peer.protocol == TransportProtocol::Tcp)
+    };
+
+    current.connection.tunnel.addresses == new.connection.tunnel.addresses
+        && current.connection.ipv4_gateway == new.connection.ipv4_gateway
+        && current.connection.ipv6_gateway == new.connection.ipv6_gateway
+        && current.options == new.options
+        && current.generic_options == new.generic_options
+        && !uses_tcp(current)
+        && !uses_tcp(new)
+}
+
 pub(crate) trait Tunnel: Send {
     fn get_interface_name(&self) -> String;
     #[cfg(target_os = "windows")]
     fn get_interface_luid(&self) -> u64;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
+    /// Replaces the private key and peers of the running tunnel, keeping the tunnel device.
+    fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::Stats, TunnelError>;
     #[cfg(target_os = "linux")]
     fn slow_stats_refresh_rate(&self) {}
@@ -597,6 +858,10 @@ pub enum TunnelError {
     #[error(display = "Failed to get config of WireGuard tunnel")]
     GetConfigError,
 
+    /// Error whilst trying to apply a new config to a running WireGuard tunnel
+    #[error(display = "Failed to set config of WireGuard tunnel")]
+    SetConfigError,
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
@@ -625,3 +890,195 @@ pub enum TunnelError {
     #[error(display = "Failed to set up logging")]
     LoggingError(#[error(source)] logging::Error),
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use talpid_types::net::{
+        wireguard::{
+            ConnectionConfig, PeerConfig, PrivateKey, PublicKey, TunnelConfig, TunnelOptions,
+            TunnelParameters,
+        },
+        GenericTunnelOptions,
+    };
+
+    /// Stands in for the kernel and userspace implementations, recording every applied config
+    struct MockTunnel {
+        applied_peers: Arc<Mutex<Vec<Vec<(PublicKey, SocketAddr)>>>>,
+    }
+
+    impl Tunnel for MockTunnel {
+        fn get_interface_name(&self) -> String {
+            "mock-tunnel".to_string()
+        }
+
+        #[cfg(windows)]
+        fn get_interface_luid(&self) -> u64 {
+            0
+        }
+
+        fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError> {
+            Ok(())
+        }
+
+        fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError> {
+            self.applied_peers.lock().unwrap().push(
+                config
+                    .peers
+                    .iter()
+                    .map(|peer| (peer.public_key.clone(), peer.endpoint))
+                    .collect(),
+            );
+            Ok(())
+        }
+
+        fn get_tunnel_stats(&self) -> std::result::Result<stats::Stats, TunnelError> {
+            Ok(stats::Stats {
+                tx_bytes: 0,
+                rx_bytes: 0,
+                last_handshake: None,
+            })
+        }
+    }
+
+    fn tunnel_parameters(endpoint: SocketAddr) -> TunnelParameters {
+        TunnelParameters {
+            connection: ConnectionConfig {
+                tunnel: TunnelConfig {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: vec!["10.64.0.2".parse().unwrap()],
+                },
+                peer: PeerConfig {
+                    public_key: PrivateKey::new_from_random().public_key(),
+                    allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                    endpoint,
+                    protocol: TransportProtocol::Udp,
+                    psk: None,
+                },
+                exit_peer: None,
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            },
+            options: TunnelOptions::default(),
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+        }
+    }
+
+    #[test]
+    fn test_can_reconfigure_different_relay() {
+        let current = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+        let new = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        assert!(can_reconfigure(&current, &new));
+    }
+
+    #[test]
+    fn test_cannot_reconfigure_changed_tunnel() {
+        let current = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+
+        let mut new = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        new.connection.tunnel.addresses = vec!["10.64.0.3".parse().unwrap()];
+        assert!(!can_reconfigure(&current, &new));
+
+        let mut new = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        new.options.mtu = Some(1280);
+        assert!(!can_reconfigure(&current, &new));
+
+        let mut new = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        new.generic_options.enable_ipv6 = true;
+        assert!(!can_reconfigure(&current, &new));
+    }
+
+    #[test]
+    fn test_cannot_reconfigure_tcp_proxy() {
+        let current = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+        let mut new = tunnel_parameters("5.6.7.8:443".parse().unwrap());
+        new.connection.peer.protocol = TransportProtocol::Tcp;
+        assert!(!can_reconfigure(&current, &new));
+        assert!(!can_reconfigure(&new, &current));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reconfigure_replaces_peers() {
+        let applied_peers = Arc::new(Mutex::new(vec![]));
+        let tunnel: Box<dyn Tunnel> = Box::new(MockTunnel {
+            applied_peers: applied_peers.clone(),
+        });
+        let tunnel = Arc::new(Mutex::new(Some(tunnel)));
+        let (request_tx, request_rx) = mpsc::channel();
+        let handle = ReconfigureHandle {
+            tunnel: Arc::downgrade(&tunnel),
+            connectivity_request_sender: request_tx,
+        };
+
+        let current = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+        let params = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        handle
+            .reconfigure(&current, &params)
+            .expect("Failed to reconfigure tunnel");
+
+        assert_eq!(
+            *applied_peers.lock().unwrap(),
+            vec![vec![(
+                params.connection.peer.public_key.clone(),
+                params.connection.peer.endpoint
+            )]]
+        );
+        assert_eq!(
+            request_rx.try_recv(),
+            Ok(connectivity_check::Request::Reestablish)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_replace_key_probes() {
+        let applied_peers = Arc::new(Mutex::new(vec![]));
+        let tunnel: Box<dyn Tunnel> = Box::new(MockTunnel {
+            applied_peers: applied_peers.clone(),
+        });
+        let tunnel = Arc::new(Mutex::new(Some(tunnel)));
+        let (request_tx, request_rx) = mpsc::channel();
+        let handle = ReconfigureHandle {
+            tunnel: Arc::downgrade(&tunnel),
+            connectivity_request_sender: request_tx,
+        };
+
+        let params = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+        handle
+            .replace_key(&params)
+            .expect("Failed to replace the key");
+
+        assert_eq!(applied_peers.lock().unwrap().len(), 1);
+        assert_eq!(
+            request_rx.try_recv(),
+            Ok(connectivity_check::Request::Probe)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reconfigure_stopped_tunnel() {
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> = Arc::new(Mutex::new(None));
+        let (request_tx, request_rx) = mpsc::channel();
+        let handle = ReconfigureHandle {
+            tunnel: Arc::downgrade(&tunnel),
+            connectivity_request_sender: request_tx,
+        };
+        let current = tunnel_parameters("1.2.3.4:51820".parse().unwrap());
+        let params = tunnel_parameters("5.6.7.8:51820".parse().unwrap());
+        assert!(matches!(
+            handle.reconfigure(&current, &params),
+            Err(Error::TunnelStoppedError)
+        ));
+
+        drop(tunnel);
+        assert!(matches!(
+            handle.reconfigure(&current, &params),
+            Err(Error::TunnelStoppedError)
+        ));
+        assert!(request_rx.try_recv().is_err());
+    }
+}
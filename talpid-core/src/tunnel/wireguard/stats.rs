@@ -1,5 +1,6 @@
 #[cfg(target_os = "linux")]
 use super::wireguard_kernel::wg_message::{DeviceMessage, DeviceNla, PeerNla};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 
 #[derive(err_derive::Error, Debug, PartialEq)]
@@ -19,12 +20,16 @@ pub enum Error {
 pub struct Stats {
     pub tx_bytes: u64,
     pub rx_bytes: u64,
+    /// Time of the most recent handshake with any peer
+    pub last_handshake: Option<SystemTime>,
 }
 
 impl Stats {
     pub fn parse_config_str(config: &str) -> Result<Self, Error> {
         let mut tx_bytes = None;
         let mut rx_bytes = None;
+        let mut last_handshake = None;
+        let mut handshake_secs = 0;
 
         // parts iterates over keys and values
         let parts = config.split('\n').filter_map(|line| {
@@ -52,13 +57,32 @@ impl Stats {
                             .map_err(|err| Error::IntParseError(value.to_string(), err))?,
                     );
                 }
+                "last_handshake_time_sec" => {
+                    handshake_secs = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParseError(value.to_string(), err))?;
+                }
+                // The nanoseconds always follow the seconds of the same peer
+                "last_handshake_time_nsec" => {
+                    let nanos = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParseError(value.to_string(), err))?;
+                    last_handshake =
+                        latest_handshake(last_handshake, handshake_time(handshake_secs, nanos));
+                }
 
                 _ => continue,
             }
         }
 
         match (tx_bytes, rx_bytes) {
-            (Some(tx_bytes), Some(rx_bytes)) => Ok(Self { tx_bytes, rx_bytes }),
+            (Some(tx_bytes), Some(rx_bytes)) => Ok(Self {
+                tx_bytes,
+                rx_bytes,
+                last_handshake,
+            }),
             _ => Err(Error::KeyNotFoundError),
         }
     }
@@ -68,6 +92,7 @@ impl Stats {
         // iterate over device attributes
         let mut tx_bytes = 0;
         let mut rx_bytes = 0;
+        let mut last_handshake = None;
         for nla in &message.nlas {
             if let DeviceNla::Peers(peers) = nla {
                 // iterate over all peer attributes
@@ -77,13 +102,38 @@ impl Stats {
                     match peer_nla {
                         PeerNla::TxBytes(bytes) => tx_bytes += *bytes,
                         PeerNla::RxBytes(bytes) => rx_bytes += *bytes,
+                        PeerNla::LastHandshakeTime(time) => {
+                            last_handshake = latest_handshake(
+                                last_handshake,
+                                handshake_time(time.tv_sec() as u64, time.tv_nsec() as u32),
+                            );
+                        }
                         _ => continue,
                     };
                 }
             }
         }
 
-        Self { tx_bytes, rx_bytes }
+        Self {
+            tx_bytes,
+            rx_bytes,
+            last_handshake,
+        }
+    }
+}
+
+/// A handshake time of zero means that no handshake has been completed.
+fn handshake_time(secs: u64, nanos: u32) -> Option<SystemTime> {
+    if secs == 0 && nanos == 0 {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+fn latest_handshake(a: Option<SystemTime>, b: Option<SystemTime>) -> Option<SystemTime> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
     }
 }
 
@@ -91,6 +141,7 @@ impl Stats {
 #[cfg(test)]
 mod test {
     use super::{Error, Stats};
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn test_parsing() {
@@ -99,6 +150,10 @@ mod test {
         let stats = Stats::parse_config_str(valid_input).expect("Failed to parse valid input");
         assert_eq!(stats.rx_bytes, 2396);
         assert_eq!(stats.tx_bytes, 2740);
+        assert_eq!(
+            stats.last_handshake,
+            Some(UNIX_EPOCH + Duration::new(1578420649, 369416131))
+        );
     }
 
     #[test]
@@ -335,6 +335,17 @@ impl Tunnel for WgGoTunnel {
         result
     }
 
+    fn set_config(&self, config: &Config) -> Result<()> {
+        let wg_config_str = config.to_userspace_reconfig_format();
+        let status =
+            unsafe { wgSetConfig(self.handle.unwrap(), wg_config_str.as_ptr() as *const i8) };
+        if status < 0 {
+            log::error!("Failed to set config of wireguard-go tunnel: {}", status);
+            return Err(TunnelError::SetConfigError);
+        }
+        Ok(())
+    }
+
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
@@ -392,6 +403,9 @@ extern "C" {
     // Returns the file descriptor of the tunnel IPv4 socket.
     fn wgGetConfig(handle: i32) -> *mut std::os::raw::c_char;
 
+    // Applies a UAPI configuration to the tunnel. Returns a negative value on failure.
+    fn wgSetConfig(handle: i32, settings: *const i8) -> i32;
+
     // Frees a pointer allocated by the go runtime - useful to free return value of wgGetConfig
     fn wgFreePtr(ptr: *mut c_void);
 
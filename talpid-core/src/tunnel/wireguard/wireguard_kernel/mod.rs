@@ -5,7 +5,7 @@ use netlink_packet_route::{
     rtnl::{
         address::nlas::Nla as AddressNla,
         link::nlas::{Info, InfoKind, Nla as LinkNla},
-        AddressMessage, LinkMessage, RtnlMessage, RT_SCOPE_UNIVERSE,
+        AddressMessage, LinkMessage, RtnlMessage, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE,
     },
     NetlinkMessage, NetlinkPayload,
 };
@@ -14,7 +14,7 @@ use netlink_proto::{
     sys::{protocols::NETLINK_GENERIC, SocketAddr},
     ConnectionHandle, Error as NetlinkError,
 };
-use std::{ffi::CString, net::IpAddr};
+use std::{ffi::CString, net::IpAddr, os::unix::io::RawFd};
 use tokio_stream::StreamExt;
 
 mod parsers;
@@ -83,6 +83,21 @@ pub enum Error {
 
     #[error(display = "NetworkManager error")]
     NetworkManager(#[error(source)] nm_tunnel::Error),
+
+    #[error(display = "Failed to move device to network namespace")]
+    SetNetnsError(rtnetlink::Error),
+
+    #[error(display = "Failed to bring up device")]
+    SetLinkUpError(rtnetlink::Error),
+
+    #[error(display = "Failed to add default route")]
+    AddRouteError(rtnetlink::Error),
+
+    #[error(display = "Network namespace error")]
+    Netns(#[error(source)] crate::linux::netns::Error),
+
+    #[error(display = "Failed to find interface in network namespace")]
+    NetnsInterfaceLookup(#[error(source)] crate::linux::IfaceIndexLookupError),
 }
 
 pub(crate) const MULLVAD_INTERFACE_NAME: &str = "wg-mullvad";
@@ -195,14 +210,18 @@ impl Handle {
         }
 
         // fetch interface index of new device
-        let new_device = self.wg_handle.get_by_name(name).await?;
-        for nla in new_device.nlas {
+        self.get_device_index(name).await
+    }
+
+    /// Returns the interface index of the WireGuard device with the given name.
+    pub async fn get_device_index(&mut self, name: String) -> Result<u32, Error> {
+        let device = self.wg_handle.get_by_name(name).await?;
+        for nla in device.nlas {
             if let DeviceNla::IfIndex(index) = nla {
                 return Ok(index);
             }
         }
 
-
         Err(Error::NoDevice)
     }
 
@@ -223,6 +242,77 @@ impl Handle {
         Ok(())
     }
 
+    /// Moves a link into the network namespace referred to by `netns_fd`. The link loses its
+    /// addresses and is brought down in the process.
+    pub async fn set_link_netns(&mut self, index: u32, netns_fd: RawFd) -> Result<(), Error> {
+        let mut link_message = LinkMessage::default();
+        link_message.header.index = index;
+        link_message.nlas.push(LinkNla::NetNsFd(netns_fd));
+
+        self.set_link(link_message, Error::SetNetnsError).await
+    }
+
+    pub async fn set_link_up(&mut self, index: u32) -> Result<(), Error> {
+        let mut link_message = LinkMessage::default();
+        link_message.header.index = index;
+        link_message.header.flags = netlink_packet_route::IFF_UP;
+        link_message.header.change_mask = netlink_packet_route::IFF_UP;
+
+        self.set_link(link_message, Error::SetLinkUpError).await
+    }
+
+    async fn set_link(
+        &mut self,
+        link_message: LinkMessage,
+        err_constructor: fn(rtnetlink::Error) -> Error,
+    ) -> Result<(), Error> {
+        let mut request = NetlinkMessage::from(RtnlMessage::SetLink(link_message));
+        request.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+        let mut response = self.route_handle.request(request).map_err(err_constructor)?;
+        while let Some(message) = response.next().await {
+            consume_netlink_error(message, err_constructor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a default route via the given link to the main routing table.
+    pub async fn add_default_route(&mut self, index: u32, ipv6: bool) -> Result<(), Error> {
+        let message = if ipv6 {
+            self.route_handle
+                .route()
+                .add()
+                .v6()
+                .output_interface(index)
+                .scope(RT_SCOPE_LINK)
+                .message_mut()
+                .clone()
+        } else {
+            self.route_handle
+                .route()
+                .add()
+                .v4()
+                .output_interface(index)
+                .scope(RT_SCOPE_LINK)
+                .message_mut()
+                .clone()
+        };
+
+        let mut request = NetlinkMessage::from(RtnlMessage::NewRoute(message));
+        request.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
+
+        let mut response = self
+            .route_handle
+            .request(request)
+            .map_err(Error::AddRouteError)?;
+        while let Some(message) = response.next().await {
+            consume_netlink_error(message, Error::AddRouteError)?;
+        }
+
+        Ok(())
+    }
+
     pub async fn delete_device(&mut self, index: u32) -> Result<(), Error> {
         let mut link_message = LinkMessage::default();
         link_message.header.index = index;
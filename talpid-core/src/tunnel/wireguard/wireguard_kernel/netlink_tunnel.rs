@@ -2,6 +2,8 @@ use super::{
     super::stats::Stats, wg_message::DeviceNla, Config, Error, Handle, Tunnel, TunnelError,
     MULLVAD_INTERFACE_NAME,
 };
+use crate::linux::{iface_index, netns::NetNs};
+use std::os::unix::io::AsRawFd;
 
 
 pub struct NetlinkTunnel {
@@ -24,7 +26,14 @@ impl NetlinkTunnel {
                 tokio_handle,
             };
 
-            if let Err(err) = tunnel.setup(config).await {
+            let result = match &config.netns {
+                Some(netns_name) => tunnel.setup_in_netns(config, netns_name).await,
+                None => tunnel.setup(config).await,
+            };
+            if let Err(err) = result {
+                // The device may have been moved into the namespace, so the index is read back from
+                // the tunnel rather than reusing the one it was created with
+                let interface_index = tunnel.interface_index;
                 if let Err(teardown_err) = tunnel
                     .netlink_connections
                     .delete_device(interface_index)
@@ -57,6 +66,65 @@ impl NetlinkTunnel {
 
         Ok(())
     }
+
+    /// Configures the device and moves it into the named network namespace. The device is
+    /// created in the initial namespace, so its UDP socket keeps using the host's network.
+    async fn setup_in_netns(&mut self, config: &Config, netns_name: &str) -> Result<(), Error> {
+        self.netlink_connections
+            .wg_handle
+            .set_config(self.interface_index, config)
+            .await?;
+
+        let netns = NetNs::open_or_create(netns_name).map_err(Error::Netns)?;
+        let tokio_handle = self.tokio_handle.clone();
+        let (mut netns_connections, stale_index, loopback_index) = netns
+            .run(move || -> Result<_, Error> {
+                let connections = tokio_handle.block_on(Handle::connect())?;
+                let stale_index = iface_index(MULLVAD_INTERFACE_NAME).ok();
+                let loopback_index = iface_index("lo").map_err(Error::NetnsInterfaceLookup)?;
+                Ok((connections, stale_index, loopback_index))
+            })
+            .map_err(Error::Netns)??;
+
+        if let Some(stale_index) = stale_index {
+            log::debug!("Removing stale WireGuard device from {}", netns.name());
+            netns_connections.delete_device(stale_index).await?;
+        }
+
+        self.netlink_connections
+            .set_link_netns(self.interface_index, netns.as_raw_fd())
+            .await?;
+        // The device gets a new index in the namespace. The connections and the index are only
+        // replaced together, so that teardown always refers to the device through the right
+        // namespace.
+        let interface_index = netns_connections
+            .get_device_index(MULLVAD_INTERFACE_NAME.to_string())
+            .await?;
+        self.netlink_connections = netns_connections;
+        self.interface_index = interface_index;
+
+        for tunnel_ip in config.tunnel.addresses.iter() {
+            self.netlink_connections
+                .set_ip_address(self.interface_index, *tunnel_ip)
+                .await?;
+        }
+
+        self.netlink_connections.set_link_up(loopback_index).await?;
+        self.netlink_connections
+            .set_link_up(self.interface_index)
+            .await?;
+
+        self.netlink_connections
+            .add_default_route(self.interface_index, false)
+            .await?;
+        if config.tunnel.addresses.iter().any(|ip| ip.is_ipv6()) {
+            self.netlink_connections
+                .add_default_route(self.interface_index, true)
+                .await?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Tunnel for NetlinkTunnel {
@@ -97,6 +165,17 @@ impl Tunnel for NetlinkTunnel {
         })
     }
 
+    fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError> {
+        let mut wg = self.netlink_connections.wg_handle.clone();
+        let interface_index = self.interface_index;
+        self.tokio_handle.block_on(async move {
+            wg.set_config(interface_index, config).await.map_err(|err| {
+                log::error!("Failed to set WireGuard device config: {}", err);
+                TunnelError::SetConfigError
+            })
+        })
+    }
+
     fn get_tunnel_stats(&self) -> std::result::Result<Stats, TunnelError> {
         let mut wg = self.netlink_connections.wg_handle.clone();
         let interface_index = self.interface_index;
@@ -79,6 +79,13 @@ impl Tunnel for NetworkManagerTunnel {
         }
     }
 
+    fn set_config(&self, _config: &Config) -> std::result::Result<(), TunnelError> {
+        // NetworkManager owns the device config, so it can only be replaced by recreating the
+        // tunnel.
+        log::debug!("Reconfiguring a WireGuard tunnel managed by NetworkManager is not supported");
+        Err(TunnelError::SetConfigError)
+    }
+
     fn get_tunnel_stats(&self) -> std::result::Result<Stats, TunnelError> {
         let tunnel = self
             .tunnel
@@ -89,7 +96,11 @@ impl Tunnel for NetworkManagerTunnel {
             .get_tunnel_stats(tunnel)
             .map_err(|_| TunnelError::StatsError(StatsError::KeyNotFoundError))?;
 
-        Ok(Stats { tx_bytes, rx_bytes })
+        Ok(Stats {
+            tx_bytes,
+            rx_bytes,
+            last_handshake: None,
+        })
     }
 
     fn slow_stats_refresh_rate(&self) {
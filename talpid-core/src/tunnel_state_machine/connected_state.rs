@@ -5,7 +5,10 @@ use super::{
 };
 use crate::{
     firewall::FirewallPolicy,
-    tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
+    tunnel::{
+        wireguard::{self, ReconfigureHandle},
+        CloseHandle, RestartHandle, StatsHandle, TunnelEvent, TunnelMetadata,
+    },
 };
 use cfg_if::cfg_if;
 use futures::{
@@ -15,11 +18,13 @@ use futures::{
 };
 use std::net::IpAddr;
 use talpid_types::{
-    net::TunnelParameters,
+    net::{wireguard as wireguard_types, TunnelParameters},
     tunnel::{ErrorStateCause, FirewallPolicyError},
     BoxedError, ErrorExt,
 };
 
+#[cfg(target_os = "linux")]
+use crate::linux::netns::NetNs;
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
 
@@ -35,6 +40,9 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub close_handle: Option<CloseHandle>,
+    pub stats_handle: StatsHandle,
+    pub reconfigure_handle: Option<ReconfigureHandle>,
+    pub restart_handle: Option<RestartHandle>,
 }
 
 /// The tunnel is up and working.
@@ -44,6 +52,12 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    reconfigure_handle: Option<ReconfigureHandle>,
+    restart_handle: Option<RestartHandle>,
+    /// Set while waiting for the relay that the tunnel was switched to to respond
+    switching_relay: bool,
+    /// Set while the tunnel is being restarted, until it is up again
+    restarting: bool,
 }
 
 impl ConnectedState {
@@ -54,6 +68,10 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            reconfigure_handle: bootstrap.reconfigure_handle,
+            restart_handle: bootstrap.restart_handle,
+            switching_relay: false,
+            restarting: false,
         }
     }
 
@@ -113,6 +131,10 @@ impl ConnectedState {
             allow_lan: shared_values.allow_lan,
             #[cfg(not(target_os = "android"))]
             dns_servers: self.get_dns_servers(shared_values),
+            #[cfg(target_os = "linux")]
+            allow_rules: shared_values.allow_rules.clone(),
+            #[cfg(target_os = "linux")]
+            excluded_uids: shared_values.excluded_uids.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -123,6 +145,14 @@ impl ConnectedState {
 
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
         let dns_ips = self.get_dns_servers(shared_values);
+
+        #[cfg(target_os = "linux")]
+        if let Some(netns_name) = super::tunnel_netns(&self.tunnel_parameters) {
+            return NetNs::open_or_create(netns_name)
+                .and_then(|netns| netns.set_dns(&dns_ips))
+                .map_err(BoxedError::new);
+        }
+
         shared_values
             .dns_monitor
             .set(&self.metadata.interface, &dns_ips)
@@ -131,7 +161,17 @@ impl ConnectedState {
         Ok(())
     }
 
-    fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
+    fn reset_dns(&self, shared_values: &mut SharedTunnelStateValues) {
+        #[cfg(target_os = "linux")]
+        if let Some(netns_name) = super::tunnel_netns(&self.tunnel_parameters) {
+            if let Err(error) =
+                NetNs::open_or_create(netns_name).and_then(|netns| netns.reset_dns())
+            {
+                log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
+            }
+            return;
+        }
+
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
@@ -158,7 +198,8 @@ impl ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
-        Self::reset_dns(shared_values);
+        shared_values.tunnel_stats.tunnel_closed();
+        self.reset_dns(shared_values);
         Self::reset_routes(shared_values);
 
         EventConsequence::NewState(DisconnectingState::enter(
@@ -167,6 +208,133 @@ impl ConnectedState {
         ))
     }
 
+    /// Switches a WireGuard tunnel to newly generated parameters by replacing its peers, keeping
+    /// the tunnel device, routes and DNS. Reconnects if the parameters can't be applied to the
+    /// running tunnel. The tunnel is reported as connecting until the new relay responds. OpenVPN
+    /// tunnels are restarted instead.
+    fn switch_relay(mut self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        let (current_parameters, reconfigure_handle) =
+            match (&self.tunnel_parameters, &self.reconfigure_handle) {
+                (TunnelParameters::Wireguard(params), Some(handle)) => {
+                    (params.clone(), handle.clone())
+                }
+                (TunnelParameters::OpenVpn(_), _) => return self.restart(shared_values),
+                _ => return self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+            };
+
+        let new_parameters = match shared_values.tunnel_parameters_generator.generate(0) {
+            Ok(TunnelParameters::Wireguard(params))
+                if wireguard::can_reconfigure(&current_parameters, &params) =>
+            {
+                params
+            }
+            _ => return self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+        };
+
+        log::info!(
+            "Switching tunnel to relay at {} without reconnecting",
+            new_parameters.connection.get_endpoint()
+        );
+
+        // Only the new peer endpoint is allowed from here on
+        self.tunnel_parameters = TunnelParameters::Wireguard(new_parameters.clone());
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+            );
+        }
+
+        shared_values.tunnel_stats.relay_switched();
+
+        if let Err(error) = reconfigure_handle.reconfigure(&current_parameters, &new_parameters) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to switch relay, reconnecting")
+            );
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        self.switching_relay = true;
+        let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+        EventConsequence::NewState((
+            TunnelStateWrapper::from(self),
+            TunnelStateTransition::Connecting(tunnel_endpoint),
+        ))
+    }
+
+    /// Reconnects an OpenVPN tunnel to the same relay by signaling OpenVPN to restart, which keeps
+    /// the process running. Reconnects from scratch if the newly generated parameters differ from
+    /// the current ones, since those only take effect in a new process.
+    fn restart(mut self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        let restart_handle = match &self.restart_handle {
+            Some(handle) => handle.clone(),
+            None => return self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+        };
+        match shared_values.tunnel_parameters_generator.generate(0) {
+            Ok(parameters) if parameters == self.tunnel_parameters => (),
+            _ => return self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+        }
+
+        log::info!("Restarting tunnel");
+        shared_values.tunnel_stats.relay_switched();
+
+        if let Err(error) = restart_handle.restart() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to restart tunnel, reconnecting")
+            );
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        self.restarting = true;
+        let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+        EventConsequence::NewState((
+            TunnelStateWrapper::from(self),
+            TunnelStateTransition::Connecting(tunnel_endpoint),
+        ))
+    }
+
+    /// Replaces the private key of a WireGuard tunnel in place, and returns whether it was
+    /// replaced. The tunnel addresses must be unchanged, since they can't be replaced without
+    /// recreating the tunnel.
+    fn replace_key(
+        &mut self,
+        tunnel_config: wireguard_types::TunnelConfig,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> bool {
+        let (params, reconfigure_handle) =
+            match (&mut self.tunnel_parameters, &self.reconfigure_handle) {
+                (TunnelParameters::Wireguard(params), Some(handle)) => (params, handle),
+                _ => return false,
+            };
+        if params.connection.tunnel.addresses != tunnel_config.addresses {
+            log::debug!("Tunnel addresses changed, so the key can't be replaced in place");
+            return false;
+        }
+
+        let mut new_params = params.clone();
+        new_params.connection.tunnel = tunnel_config;
+
+        // Replacing the peer resets its counters, so they must be read before
+        shared_values.tunnel_stats.refresh();
+        match reconfigure_handle.replace_key(&new_params) {
+            Ok(()) => {
+                log::info!("Replaced the private key of the tunnel");
+                shared_values.tunnel_stats.tunnel_reconfigured();
+                *params = new_params;
+                true
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to replace the private key of the tunnel")
+                );
+                false
+            }
+        }
+    }
+
     fn handle_commands(
         self,
         command: Option<TunnelCommand>,
@@ -246,9 +414,7 @@ impl ConnectedState {
                     SameState(self.into())
                 }
             }
-            Some(TunnelCommand::Connect) => {
-                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
-            }
+            Some(TunnelCommand::Connect) => self.switch_relay(shared_values),
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
@@ -265,20 +431,115 @@ impl ConnectedState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                shared_values.get_tunnel_stats(stats_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::ReplaceKey(tunnel_config, result_tx)) => {
+                let replaced = self.replace_key(tunnel_config, shared_values);
+                let _ = result_tx.send(replaced);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                shared_values.get_firewall_rules(rules_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                shared_values.get_blocked_traffic_stats(stats_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                shared_values.set_audit_blocked_traffic(enabled);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                if shared_values.set_allow_rules(allow_rules) {
+                    if let Err(error) = self.set_firewall_policy(shared_values) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                if shared_values.set_excluded_uids(excluded_uids) {
+                    if let Err(error) = self.set_firewall_policy(shared_values) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
         }
     }
 
     fn handle_tunnel_events(
-        self,
+        mut self,
         event: Option<(TunnelEvent, oneshot::Sender<()>)>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
         match event {
+            Some((TunnelEvent::Down, _)) if self.restarting => {
+                log::debug!("Tunnel is down while restarting");
+                SameState(self.into())
+            }
+            Some((TunnelEvent::Up(metadata), _)) if self.restarting => {
+                log::info!("Restarted tunnel");
+                self.restarting = false;
+                // The tunnel device may have been recreated, so apply the policy and DNS again
+                self.metadata = metadata;
+                if let Err(error) = self.set_firewall_policy(shared_values) {
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    );
+                }
+                if let Err(error) = self.set_dns(shared_values) {
+                    log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                    );
+                }
+                let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+                NewState((
+                    TunnelStateWrapper::from(self),
+                    TunnelStateTransition::Connected(tunnel_endpoint),
+                ))
+            }
             Some((TunnelEvent::Down, _)) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some((TunnelEvent::Up(_), _)) if self.switching_relay => {
+                log::info!("Switched tunnel to the new relay");
+                self.switching_relay = false;
+                let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+                NewState((
+                    TunnelStateWrapper::from(self),
+                    TunnelStateTransition::Connected(tunnel_endpoint),
+                ))
+            }
+            Some((TunnelEvent::Ipv6Degraded, _)) => {
+                log::warn!("IPv6 connectivity inside the tunnel was lost");
+                shared_values.tunnel_stats.set_ipv6_degraded(true);
+                SameState(self.into())
+            }
+            Some((TunnelEvent::Ipv6Restored, _)) => {
+                log::info!("IPv6 connectivity inside the tunnel was restored");
+                shared_values.tunnel_stats.set_ipv6_degraded(false);
+                SameState(self.into())
+            }
             Some(_) => SameState(self.into()),
         }
     }
@@ -290,14 +551,16 @@ impl ConnectedState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
+        shared_values.tunnel_stats.tunnel_closed();
+
         if let Some(block_reason) = block_reason {
-            Self::reset_dns(shared_values);
+            self.reset_dns(shared_values);
             Self::reset_routes(shared_values);
             return NewState(ErrorState::enter(shared_values, block_reason));
         }
 
         log::info!("Tunnel closed. Reconnecting.");
-        Self::reset_dns(shared_values);
+        self.reset_dns(shared_values);
         Self::reset_routes(shared_values);
         NewState(ConnectingState::enter(shared_values, 0))
     }
@@ -311,6 +574,7 @@ impl TunnelState for ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        let stats_handle = bootstrap.stats_handle.clone();
         let connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
 
@@ -334,6 +598,7 @@ impl TunnelState for ConnectedState {
                 ),
             )
         } else {
+            shared_values.tunnel_stats.tunnel_connected(stats_handle);
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -7,7 +7,8 @@ use crate::{
     firewall::FirewallPolicy,
     routing::RouteManager,
     tunnel::{
-        self, tun_provider::TunProvider, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
+        self, tun_provider::TunProvider, wireguard::ReconfigureHandle, CloseHandle, RestartHandle,
+        StatsHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
     },
 };
 use cfg_if::cfg_if;
@@ -49,6 +50,9 @@ pub struct ConnectingState {
     tunnel_metadata: Option<TunnelMetadata>,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    stats_handle: StatsHandle,
+    reconfigure_handle: Option<ReconfigureHandle>,
+    restart_handle: Option<RestartHandle>,
     retry_attempt: u32,
 }
 
@@ -68,6 +72,10 @@ impl ConnectingState {
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            #[cfg(target_os = "linux")]
+            allow_rules: shared_values.allow_rules.clone(),
+            #[cfg(target_os = "linux")]
+            excluded_uids: shared_values.excluded_uids.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
@@ -118,6 +126,9 @@ impl ConnectingState {
             route_manager,
         )?;
         let close_handle = Some(monitor.close_handle());
+        let stats_handle = monitor.stats_handle();
+        let reconfigure_handle = monitor.reconfigure_handle();
+        let restart_handle = monitor.restart_handle();
         let tunnel_close_event = Self::spawn_tunnel_monitor_wait_thread(Some(monitor));
 
         Ok(ConnectingState {
@@ -126,6 +137,9 @@ impl ConnectingState {
             tunnel_metadata: None,
             tunnel_close_event,
             close_handle,
+            stats_handle,
+            reconfigure_handle,
+            restart_handle,
             retry_attempt,
         })
     }
@@ -197,6 +211,9 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             close_handle: self.close_handle,
+            stats_handle: self.stats_handle,
+            reconfigure_handle: self.reconfigure_handle,
+            restart_handle: self.restart_handle,
         }
     }
 
@@ -320,6 +337,61 @@ impl ConnectingState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                shared_values.get_tunnel_stats(stats_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::ReplaceKey(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                shared_values.get_firewall_rules(rules_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                shared_values.get_blocked_traffic_stats(stats_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                shared_values.set_audit_blocked_traffic(enabled);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                if shared_values.set_allow_rules(allow_rules) {
+                    if let Err(error) = Self::set_firewall_policy(
+                        shared_values,
+                        &self.tunnel_parameters,
+                        &self.tunnel_metadata,
+                    ) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                if shared_values.set_excluded_uids(excluded_uids) {
+                    if let Err(error) = Self::set_firewall_policy(
+                        shared_values,
+                        &self.tunnel_parameters,
+                        &self.tunnel_metadata,
+                    ) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
         }
     }
 
@@ -369,7 +441,9 @@ impl ConnectingState {
                 shared_values,
                 self.into_connected_state_bootstrap(metadata),
             )),
-            Some((TunnelEvent::Down, _)) => SameState(self.into()),
+            Some((TunnelEvent::Down, _))
+            | Some((TunnelEvent::Ipv6Degraded, _))
+            | Some((TunnelEvent::Ipv6Restored, _)) => SameState(self.into()),
             None => {
                 // The channel was closed
                 debug!("The tunnel disconnected unexpectedly");
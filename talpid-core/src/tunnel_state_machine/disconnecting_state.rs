@@ -64,6 +64,39 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Nothing
                 }
+                Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    shared_values.get_tunnel_stats(stats_tx);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::ReplaceKey(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                    shared_values.get_firewall_rules(rules_tx);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                    shared_values.get_blocked_traffic_stats(stats_tx);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                    shared_values.set_audit_blocked_traffic(enabled);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                    let _ = shared_values.set_allow_rules(allow_rules);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                    let _ = shared_values.set_excluded_uids(excluded_uids);
+                    AfterDisconnect::Nothing
+                }
             },
             AfterDisconnect::Block(reason) => match command {
                 Some(TunnelCommand::AllowLan(allow_lan)) => {
@@ -106,6 +139,39 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Block(reason)
                 }
+                Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    shared_values.get_tunnel_stats(stats_tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::ReplaceKey(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                    shared_values.get_firewall_rules(rules_tx);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                    shared_values.get_blocked_traffic_stats(stats_tx);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                    shared_values.set_audit_blocked_traffic(enabled);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                    let _ = shared_values.set_allow_rules(allow_rules);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                    let _ = shared_values.set_excluded_uids(excluded_uids);
+                    AfterDisconnect::Block(reason)
+                }
                 None => AfterDisconnect::Block(reason),
             },
             AfterDisconnect::Reconnect(retry_attempt) => match command {
@@ -149,6 +215,39 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    shared_values.get_tunnel_stats(stats_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::ReplaceKey(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                    shared_values.get_firewall_rules(rules_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                    shared_values.get_blocked_traffic_stats(stats_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                    shared_values.set_audit_blocked_traffic(enabled);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                    let _ = shared_values.set_allow_rules(allow_rules);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                    let _ = shared_values.set_excluded_uids(excluded_uids);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
             },
         };
 
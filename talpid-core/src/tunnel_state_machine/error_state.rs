@@ -22,6 +22,10 @@ impl ErrorState {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            #[cfg(target_os = "linux")]
+            allow_rules: shared_values.allow_rules.clone(),
+            #[cfg(target_os = "linux")]
+            excluded_uids: shared_values.excluded_uids.clone(),
         };
 
         #[cfg(target_os = "linux")]
@@ -171,6 +175,43 @@ impl TunnelState for ErrorState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            Some(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                shared_values.get_tunnel_stats(stats_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::ReplaceKey(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetFirewallRules(rules_tx)) => {
+                shared_values.get_firewall_rules(rules_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::GetBlockedTrafficStats(stats_tx)) => {
+                shared_values.get_blocked_traffic_stats(stats_tx);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAuditBlockedTraffic(enabled)) => {
+                shared_values.set_audit_blocked_traffic(enabled);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetAllowRules(allow_rules)) => {
+                if shared_values.set_allow_rules(allow_rules) {
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetExcludedUids(excluded_uids)) => {
+                if shared_values.set_excluded_uids(excluded_uids) {
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
         }
     }
 }
@@ -3,6 +3,9 @@ mod connecting_state;
 mod disconnected_state;
 mod disconnecting_state;
 mod error_state;
+mod tunnel_stats;
+
+pub use self::tunnel_stats::TunnelStats;
 
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
@@ -10,7 +13,10 @@ use self::{
     disconnected_state::DisconnectedState,
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
+    tunnel_stats::TunnelStatsTracker,
 };
+#[cfg(target_os = "linux")]
+use crate::firewall::{BlockedTrafficStats, FirewallRules};
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
@@ -38,9 +44,13 @@ use std::{
     sync::{mpsc as sync_mpsc, Arc},
 };
 #[cfg(target_os = "android")]
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowRule;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use talpid_types::ErrorExt;
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
+    net::{wireguard, Endpoint, TunnelParameters},
     tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
 };
 
@@ -92,6 +102,9 @@ pub async fn spawn(
     reset_firewall: bool,
     #[cfg(target_os = "android")] android_context: AndroidContext,
     #[cfg(windows)] exclude_paths: Vec<OsString>,
+    #[cfg(target_os = "linux")] allow_rules: Vec<AllowRule>,
+    #[cfg(target_os = "linux")] excluded_uids: Vec<u32>,
+    #[cfg(target_os = "linux")] audit_blocked_traffic: bool,
 ) -> Result<Arc<mpsc::UnboundedSender<TunnelCommand>>, Error> {
     let (command_tx, command_rx) = mpsc::unbounded();
     let command_tx = Arc::new(command_tx);
@@ -130,6 +143,12 @@ pub async fn spawn(
             android_context,
             #[cfg(windows)]
             exclude_paths,
+            #[cfg(target_os = "linux")]
+            allow_rules,
+            #[cfg(target_os = "linux")]
+            excluded_uids,
+            #[cfg(target_os = "linux")]
+            audit_blocked_traffic,
         ));
         let state_machine = match state_machine {
             Ok(state_machine) => {
@@ -183,6 +202,26 @@ pub enum TunnelCommand {
         oneshot::Sender<Result<(), split_tunnel::Error>>,
         Vec<OsString>,
     ),
+    /// Return traffic statistics of the current tunnel and all previous tunnels.
+    GetTunnelStats(oneshot::Sender<TunnelStats>),
+    /// Replace the private key of a connected WireGuard tunnel without reconnecting. Responds
+    /// with whether the key was replaced.
+    ReplaceKey(wireguard::TunnelConfig, oneshot::Sender<bool>),
+    /// Return the applied firewall policy along with the intended and installed firewall rules.
+    #[cfg(target_os = "linux")]
+    GetFirewallRules(oneshot::Sender<FirewallRules>),
+    /// Return counters and samples of the traffic blocked by the firewall.
+    #[cfg(target_os = "linux")]
+    GetBlockedTrafficStats(oneshot::Sender<BlockedTrafficStats>),
+    /// Set custom rules for traffic that should never be blocked.
+    #[cfg(target_os = "linux")]
+    SetAllowRules(Vec<AllowRule>),
+    /// Set the users whose traffic should be excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    SetExcludedUids(Vec<u32>),
+    /// Start or stop counting and logging the traffic blocked by the firewall.
+    #[cfg(target_os = "linux")]
+    SetAuditBlockedTraffic(bool),
 }
 
 type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>>;
@@ -222,6 +261,9 @@ impl TunnelStateMachine {
         reset_firewall: bool,
         #[cfg(target_os = "android")] android_context: AndroidContext,
         #[cfg(windows)] exclude_paths: Vec<OsString>,
+        #[cfg(target_os = "linux")] allow_rules: Vec<AllowRule>,
+        #[cfg(target_os = "linux")] excluded_uids: Vec<u32>,
+        #[cfg(target_os = "linux")] audit_blocked_traffic: bool,
     ) -> Result<Self, Error> {
         #[cfg(windows)]
         let split_tunnel = split_tunnel::SplitTunnel::new(command_tx.clone())
@@ -231,6 +273,10 @@ impl TunnelStateMachine {
             initialize_blocked: block_when_disconnected || !reset_firewall,
             allow_lan,
             allowed_endpoint: Some(allowed_endpoint),
+            #[cfg(target_os = "linux")]
+            log_dir: log_dir.clone(),
+            #[cfg(target_os = "linux")]
+            audit_blocked_traffic,
         };
 
         let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
@@ -277,10 +323,15 @@ impl TunnelStateMachine {
             is_offline,
             dns_servers,
             allowed_endpoint,
+            #[cfg(target_os = "linux")]
+            allow_rules,
+            #[cfg(target_os = "linux")]
+            excluded_uids,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider,
             log_dir,
             resource_dir,
+            tunnel_stats: TunnelStatsTracker::default(),
             #[cfg(target_os = "linux")]
             connectivity_check_was_enabled: None,
         };
@@ -335,6 +386,17 @@ pub trait TunnelParametersGenerator: Send + 'static {
     ) -> Result<TunnelParameters, ParameterGenerationError>;
 }
 
+/// Returns the network namespace that the tunnel interface is moved into, if any. Such a tunnel
+/// is only used by processes inside the namespace, so the host keeps its own DNS settings. The
+/// host is still kept behind the usual firewall policy, so its traffic does not leak either.
+#[cfg(target_os = "linux")]
+fn tunnel_netns(params: &TunnelParameters) -> Option<&str> {
+    match params {
+        TunnelParameters::Wireguard(params) => params.options.netns.as_deref(),
+        TunnelParameters::OpenVpn(_) => None,
+    }
+}
+
 /// Values that are common to all tunnel states.
 struct SharedTunnelStateValues {
     /// Management of excluded apps.
@@ -357,6 +419,12 @@ struct SharedTunnelStateValues {
     dns_servers: Option<Vec<IpAddr>>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: Endpoint,
+    /// Custom rules for traffic that should not be blocked by the firewall.
+    #[cfg(target_os = "linux")]
+    allow_rules: Vec<AllowRule>,
+    /// Users whose traffic should be excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    excluded_uids: Vec<u32>,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
@@ -365,6 +433,8 @@ struct SharedTunnelStateValues {
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Traffic counters of the connected tunnel and previous tunnels.
+    tunnel_stats: TunnelStatsTracker,
 
     /// NetworkManager's connecitivity check state.
     #[cfg(target_os = "linux")]
@@ -408,6 +478,26 @@ impl SharedTunnelStateValues {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    pub fn set_allow_rules(&mut self, allow_rules: Vec<AllowRule>) -> bool {
+        if self.allow_rules != allow_rules {
+            self.allow_rules = allow_rules;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_excluded_uids(&mut self, excluded_uids: Vec<u32>) -> bool {
+        if self.excluded_uids != excluded_uids {
+            self.excluded_uids = excluded_uids;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn set_dns_servers(
         &mut self,
         dns_servers: Option<Vec<IpAddr>>,
@@ -467,6 +557,32 @@ impl SharedTunnelStateValues {
         }
         let _ = tx.send(());
     }
+
+    pub fn get_tunnel_stats(&mut self, tx: oneshot::Sender<TunnelStats>) {
+        let _ = tx.send(self.tunnel_stats.stats());
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn get_firewall_rules(&self, tx: oneshot::Sender<FirewallRules>) {
+        let _ = tx.send(self.firewall.rules());
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn get_blocked_traffic_stats(&self, tx: oneshot::Sender<BlockedTrafficStats>) {
+        let _ = tx.send(self.firewall.blocked_traffic_stats());
+    }
+
+    /// Starts or stops auditing the blocked traffic. The firewall reapplies its current policy
+    /// by itself, so the state doesn't need to.
+    #[cfg(target_os = "linux")]
+    pub fn set_audit_blocked_traffic(&mut self, enabled: bool) {
+        if let Err(error) = self.firewall.set_audit_blocked_traffic(enabled) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to start or stop the blocked traffic audit")
+            );
+        }
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
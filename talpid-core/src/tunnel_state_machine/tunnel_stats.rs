@@ -0,0 +1,266 @@
+use crate::tunnel::{ConnectionDetails, StatsHandle, TrafficStats};
+use std::time::SystemTime;
+
+/// Traffic statistics of the current tunnel, along with totals for all tunnels that have been
+/// connected since the state machine was started.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TunnelStats {
+    /// Number of bytes received through the current tunnel.
+    pub rx_bytes: u64,
+    /// Number of bytes sent through the current tunnel.
+    pub tx_bytes: u64,
+    /// Number of bytes received through all tunnels, including the current one.
+    pub total_rx_bytes: u64,
+    /// Number of bytes sent through all tunnels, including the current one.
+    pub total_tx_bytes: u64,
+    /// Time of the most recent handshake with the relay, if the tunnel protocol reports it.
+    pub last_handshake: Option<SystemTime>,
+    /// Time when the current tunnel was connected. `None` unless in the connected state.
+    pub connected_since: Option<SystemTime>,
+    /// Number of times the tunnel has been reconnected since it was last connected from the
+    /// disconnected state.
+    pub reconnects: u32,
+    /// Whether IPv6 traffic has stopped flowing through the current tunnel, while IPv4 traffic
+    /// still works.
+    pub ipv6_degraded: bool,
+    /// Details about the connection to the relay, as reported by the current tunnel.
+    pub connection_details: ConnectionDetails,
+}
+
+/// Keeps track of the traffic counters of the connected tunnel and accumulates them across
+/// tunnels.
+#[derive(Default)]
+pub(crate) struct TunnelStatsTracker {
+    handle: Option<StatsHandle>,
+    connected_since: Option<SystemTime>,
+    last_sample: TrafficStats,
+    closed_rx_bytes: u64,
+    closed_tx_bytes: u64,
+    has_connected: bool,
+    reconnects: u32,
+    ipv6_degraded: bool,
+}
+
+impl TunnelStatsTracker {
+    /// Starts tracking a tunnel that just entered the connected state.
+    pub fn tunnel_connected(&mut self, handle: StatsHandle) {
+        if self.has_connected {
+            self.reconnects += 1;
+        }
+        self.has_connected = true;
+        self.handle = Some(handle);
+        self.connected_since = Some(SystemTime::now());
+        self.last_sample = TrafficStats::default();
+        self.ipv6_degraded = false;
+        self.refresh();
+    }
+
+    /// Stops tracking the current tunnel and adds its final counters to the totals. If the tunnel
+    /// is already gone, the most recently read counters are used.
+    pub fn tunnel_closed(&mut self) {
+        self.refresh();
+        if self.handle.take().is_some() {
+            self.closed_rx_bytes += self.last_sample.rx_bytes;
+            self.closed_tx_bytes += self.last_sample.tx_bytes;
+        }
+        self.last_sample = TrafficStats::default();
+        self.connected_since = None;
+        self.ipv6_degraded = false;
+    }
+
+    /// Adds the most recently read counters of the current tunnel to the totals and starts over,
+    /// since replacing the peers of a tunnel resets its counters. Should be called once the peers
+    /// have been replaced, with the counters read by [`Self::refresh`] right before.
+    pub fn tunnel_reconfigured(&mut self) {
+        self.closed_rx_bytes += self.last_sample.rx_bytes;
+        self.closed_tx_bytes += self.last_sample.tx_bytes;
+        self.last_sample = TrafficStats::default();
+    }
+
+    /// Like [`Self::tunnel_reconfigured`], but also counts switching the current tunnel to a
+    /// different relay, or restarting it, as a reconnect. Should be called right before the tunnel
+    /// is switched or restarted.
+    pub fn relay_switched(&mut self) {
+        self.refresh();
+        self.tunnel_reconfigured();
+        self.connected_since = Some(SystemTime::now());
+        self.reconnects += 1;
+        self.ipv6_degraded = false;
+    }
+
+    /// Records whether IPv6 connectivity of the current tunnel is degraded.
+    pub fn set_ipv6_degraded(&mut self, degraded: bool) {
+        self.ipv6_degraded = degraded;
+    }
+
+    /// Resets the reconnect counter. Should be called when entering the disconnected state.
+    pub fn reset_reconnects(&mut self) {
+        self.has_connected = false;
+        self.reconnects = 0;
+    }
+
+    /// Reads the counters of the current tunnel, if any, and returns the statistics.
+    pub fn stats(&mut self) -> TunnelStats {
+        self.refresh();
+        TunnelStats {
+            rx_bytes: self.last_sample.rx_bytes,
+            tx_bytes: self.last_sample.tx_bytes,
+            total_rx_bytes: self.closed_rx_bytes + self.last_sample.rx_bytes,
+            total_tx_bytes: self.closed_tx_bytes + self.last_sample.tx_bytes,
+            last_handshake: self.last_sample.last_handshake,
+            connected_since: self.connected_since,
+            reconnects: self.reconnects,
+            ipv6_degraded: self.ipv6_degraded,
+            connection_details: self
+                .handle
+                .as_ref()
+                .map(StatsHandle::get_connection_details)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Reads the counters of the current tunnel, if any.
+    pub fn refresh(&mut self) {
+        if let Some(stats) = self.handle.as_ref().and_then(StatsHandle::get_stats) {
+            self.last_sample = stats;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Counters of a fake tunnel, which can be changed while it is tracked.
+    #[derive(Clone, Default)]
+    struct FakeTunnel(Arc<Mutex<Option<TrafficStats>>>);
+
+    impl FakeTunnel {
+        fn set(&self, rx_bytes: u64, tx_bytes: u64) {
+            *self.0.lock().unwrap() = Some(TrafficStats {
+                rx_bytes,
+                tx_bytes,
+                last_handshake: None,
+            });
+        }
+
+        /// Makes the counters unavailable, like when the tunnel is gone.
+        fn stop(&self) {
+            *self.0.lock().unwrap() = None;
+        }
+
+        fn handle(&self) -> StatsHandle {
+            StatsHandle::Fake(self.0.clone())
+        }
+    }
+
+    fn counters(stats: &TunnelStats) -> (u64, u64, u64, u64) {
+        (
+            stats.rx_bytes,
+            stats.tx_bytes,
+            stats.total_rx_bytes,
+            stats.total_tx_bytes,
+        )
+    }
+
+    #[test]
+    fn test_totals_and_reconnects() {
+        let mut tracker = TunnelStatsTracker::default();
+        let first = FakeTunnel::default();
+        first.set(100, 50);
+        tracker.tunnel_connected(first.handle());
+
+        let stats = tracker.stats();
+        assert_eq!(counters(&stats), (100, 50, 100, 50));
+        assert_eq!(stats.reconnects, 0);
+        assert!(stats.connected_since.is_some());
+
+        first.set(150, 60);
+        tracker.tunnel_closed();
+        let stats = tracker.stats();
+        assert_eq!(counters(&stats), (0, 0, 150, 60));
+        assert_eq!(stats.connected_since, None);
+
+        // The counters of the next tunnel are added to the totals
+        let second = FakeTunnel::default();
+        second.set(10, 5);
+        tracker.tunnel_connected(second.handle());
+        let stats = tracker.stats();
+        assert_eq!(counters(&stats), (10, 5, 160, 65));
+        assert_eq!(stats.reconnects, 1);
+    }
+
+    #[test]
+    fn test_tunnel_closed_twice() {
+        let mut tracker = TunnelStatsTracker::default();
+        let tunnel = FakeTunnel::default();
+        tunnel.set(100, 50);
+        tracker.tunnel_connected(tunnel.handle());
+
+        // The most recently read counters are used once the tunnel is gone
+        tunnel.set(120, 70);
+        tracker.stats();
+        tunnel.stop();
+        tracker.tunnel_closed();
+        assert_eq!(counters(&tracker.stats()), (0, 0, 120, 70));
+
+        tracker.tunnel_closed();
+        assert_eq!(counters(&tracker.stats()), (0, 0, 120, 70));
+    }
+
+    #[test]
+    fn test_relay_switched() {
+        let mut tracker = TunnelStatsTracker::default();
+        let tunnel = FakeTunnel::default();
+        tunnel.set(100, 50);
+        tracker.tunnel_connected(tunnel.handle());
+        tracker.set_ipv6_degraded(true);
+        let connected_since = tracker.stats().connected_since.unwrap();
+
+        // Switching relays resets the counters of the tunnel, but not the totals
+        tracker.relay_switched();
+        tunnel.set(0, 0);
+        let stats = tracker.stats();
+        assert_eq!(counters(&stats), (0, 0, 100, 50));
+        assert_eq!(stats.reconnects, 1);
+        assert!(!stats.ipv6_degraded);
+        assert!(stats.connected_since.unwrap() >= connected_since);
+
+        tunnel.set(20, 10);
+        assert_eq!(counters(&tracker.stats()), (20, 10, 120, 60));
+
+        // Replacing the peers doesn't count as a reconnect
+        tunnel.set(30, 15);
+        tracker.refresh();
+        tunnel.set(0, 0);
+        tracker.tunnel_reconfigured();
+        let stats = tracker.stats();
+        assert_eq!(counters(&stats), (0, 0, 130, 65));
+        assert_eq!(stats.reconnects, 1);
+
+        // The counters are kept if the peers couldn't be replaced
+        tunnel.set(5, 5);
+        tracker.refresh();
+        assert_eq!(counters(&tracker.stats()), (5, 5, 135, 70));
+    }
+
+    #[test]
+    fn test_reset_reconnects() {
+        let mut tracker = TunnelStatsTracker::default();
+        let tunnel = FakeTunnel::default();
+        tunnel.set(0, 0);
+        tracker.tunnel_connected(tunnel.handle());
+        tracker.tunnel_closed();
+        tracker.tunnel_connected(tunnel.handle());
+        assert_eq!(tracker.stats().reconnects, 1);
+
+        // Connecting from the disconnected state is not a reconnect
+        tracker.tunnel_closed();
+        tracker.reset_reconnects();
+        assert_eq!(tracker.stats().reconnects, 0);
+        tracker.tunnel_connected(tunnel.handle());
+        assert_eq!(tracker.stats().reconnects, 0);
+    }
+}
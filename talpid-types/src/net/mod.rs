@@ -252,6 +252,81 @@ pub struct GenericTunnelOptions {
     pub enable_ipv6: bool,
 }
 
+/// Traffic that the firewall allows in every state, in addition to what the current policy
+/// allows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AllowRule {
+    pub direction: AllowRuleDirection,
+    /// The network on the remote side.
+    pub network: ipnetwork::IpNetwork,
+    /// The port that connections are made to, i.e. the remote port of outbound rules and the
+    /// local port of inbound rules. Any port matches if this is `None`.
+    pub port: Option<u16>,
+    /// Any protocol matches if this is `None`. Required if `port` is set.
+    pub protocol: Option<TransportProtocol>,
+    /// Only matches traffic from processes in the net_cls cgroup with this class ID.
+    pub cgroup: Option<u32>,
+    /// Only matches traffic from sockets owned by this user.
+    pub uid: Option<u32>,
+}
+
+/// Which side opens the connections that are allowed by an [`AllowRule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllowRuleDirection {
+    /// Connections from this host to the network.
+    Outbound,
+    /// Connections from the network to this host.
+    Inbound,
+}
+
+impl AllowRule {
+    /// Checks that the rule can be enforced.
+    pub fn validate(&self) -> Result<(), InvalidAllowRule> {
+        if self.port.is_some() && self.protocol.is_none() {
+            return Err(InvalidAllowRule::PortWithoutProtocol);
+        }
+        if self.direction == AllowRuleDirection::Inbound
+            && (self.cgroup.is_some() || self.uid.is_some())
+        {
+            return Err(InvalidAllowRule::InboundOwner);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AllowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.direction {
+            AllowRuleDirection::Outbound => write!(f, "outbound to {}", self.network)?,
+            AllowRuleDirection::Inbound => write!(f, "inbound from {}", self.network)?,
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " over {}", protocol)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, " port {}", port)?;
+        }
+        if let Some(cgroup) = self.cgroup {
+            write!(f, " cgroup {}", cgroup)?;
+        }
+        if let Some(uid) = self.uid {
+            write!(f, " uid {}", uid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reasons why an [`AllowRule`] can't be enforced.
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidAllowRule {
+    #[error(display = "A port can only be matched together with a protocol")]
+    PortWithoutProtocol,
+
+    #[error(display = "Only outbound traffic can be matched by cgroup or user")]
+    InboundOwner,
+}
+
 /// Returns a vector of IP networks representing all of the internet, 0.0.0.0/0.
 /// This may be used in [`crate::net::wireguard::PeerConfig`] to route all traffic
 /// to the tunnel interface.
@@ -8,6 +8,7 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 
 
@@ -59,6 +60,9 @@ pub struct PeerConfig {
     /// If this is set to TCP, then traffic is proxied using [`udp_to_tcp::Udp2Tcp`].
     #[serde(default = "default_peer_transport")]
     pub protocol: TransportProtocol,
+    /// Preshared key mixed into the handshake with the peer, if any.
+    #[serde(default)]
+    pub psk: Option<PresharedKey>,
 }
 
 fn default_peer_transport() -> TransportProtocol {
@@ -86,6 +90,94 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Timing and probes used to verify that the tunnel is working
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub connectivity_check: ConnectivityCheckOptions,
+    /// Name of a network namespace to move the tunnel interface into. The tunnel is then only
+    /// used by processes in that namespace. Only supported by the kernel implementation on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub netns: Option<String>,
+}
+
+/// Options for the connectivity monitor, which verifies that traffic is flowing through the
+/// tunnel and reconnects if it stops.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectivityCheckOptions {
+    /// Timeout for receiving traffic after sending outgoing traffic. Once this timeout is hit,
+    /// probes are sent every `probe_interval` until `probe_timeout` is reached, or traffic is
+    /// received.
+    pub rx_timeout: Duration,
+    /// Timeout for receiving or sending any traffic. Once this timeout is hit, probes are sent
+    /// every `probe_interval` until `probe_timeout` is reached, or traffic is received.
+    pub traffic_timeout: Duration,
+    /// Timeout for receiving traffic after sending the first probe. Once this timeout is
+    /// reached, the connection is assumed to be lost.
+    pub probe_timeout: Duration,
+    /// Time to wait between sending probes.
+    pub probe_interval: Duration,
+    /// Addresses inside the tunnel to probe. If empty, the tunnel's IPv4 gateway is probed.
+    pub probe_targets: Vec<Ipv4Addr>,
+    /// How the probe targets are probed.
+    pub probe_mode: ProbeMode,
+}
+
+impl Default for ConnectivityCheckOptions {
+    fn default() -> Self {
+        ConnectivityCheckOptions {
+            rx_timeout: Duration::from_secs(5),
+            traffic_timeout: Duration::from_secs(120),
+            probe_timeout: Duration::from_secs(15),
+            probe_interval: Duration::from_secs(3),
+            probe_targets: vec![],
+            probe_mode: ProbeMode::Icmp,
+        }
+    }
+}
+
+impl ConnectivityCheckOptions {
+    /// Checks that all timeouts are positive and that the probe port is nonzero.
+    pub fn validate(&self) -> Result<(), InvalidConnectivityCheckOptions> {
+        let timeouts = [
+            self.rx_timeout,
+            self.traffic_timeout,
+            self.probe_timeout,
+            self.probe_interval,
+        ];
+        if timeouts.contains(&Duration::ZERO) {
+            return Err(InvalidConnectivityCheckOptions::ZeroTimeout);
+        }
+        match self.probe_mode {
+            ProbeMode::Tcp(0) | ProbeMode::Http(0) => {
+                Err(InvalidConnectivityCheckOptions::InvalidPort)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reasons why [`ConnectivityCheckOptions`] can't be used.
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidConnectivityCheckOptions {
+    #[error(display = "Connectivity check timeouts must be positive")]
+    ZeroTimeout,
+
+    #[error(display = "Invalid connectivity check probe port")]
+    InvalidPort,
+}
+
+/// Type of probe sent by the connectivity monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeMode {
+    /// Send ICMP echo requests.
+    Icmp,
+    /// Open a TCP connection to the given port.
+    Tcp(u16),
+    /// Send an HTTP `HEAD` request to the given port.
+    Http(u16),
 }
 
 /// Wireguard x25519 private key
@@ -110,6 +202,16 @@ impl PrivateKey {
     pub fn to_base64(&self) -> String {
         base64::encode(self.0.to_bytes())
     }
+
+    pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
+        let bytes = base64::decode(key).map_err(|_| InvalidKeyError(()))?;
+        if bytes.len() != 32 {
+            return Err(InvalidKeyError(()));
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+        Ok(From::from(key))
+    }
 }
 
 impl From<[u8; 32]> for PrivateKey {
@@ -242,6 +344,61 @@ impl fmt::Display for PublicKey {
     }
 }
 
+/// Wireguard preshared key, used as an additional symmetric secret in the handshake
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Get the preshared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.0)
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
+        let bytes = base64::decode(key).map_err(|_| InvalidKeyError(()))?;
+        if bytes.len() != 32 {
+            return Err(InvalidKeyError(()));
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+        Ok(From::from(key))
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(key: [u8; 32]) -> PresharedKey {
+        PresharedKey(key)
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey(..)")
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
 fn serialize_key<S>(key: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
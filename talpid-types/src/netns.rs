@@ -0,0 +1,112 @@
+use std::{
+    fs, io,
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Name of the network namespace used when no other name is configured.
+pub const DEFAULT_NETNS_NAME: &str = "mullvad";
+
+/// Directory where named network namespaces are bind mounted, as used by `ip netns`.
+pub const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// Directory with per-namespace configuration files, as used by `ip netns exec`.
+pub const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// Directory in which the daemon marks the network namespaces that it created. Only root may
+/// write to it.
+pub const MARKER_DIR: &str = "/run/mullvad-netns";
+
+/// Returns whether `name` can be used as the name of a network namespace.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+/// Returns the path of the file that refers to the named network namespace.
+pub fn netns_path(name: &str) -> PathBuf {
+    PathBuf::from(NETNS_RUN_DIR).join(name)
+}
+
+/// Returns the path of the `resolv.conf` used by processes in the named network namespace.
+pub fn resolv_conf_path(name: &str) -> PathBuf {
+    PathBuf::from(NETNS_ETC_DIR).join(name).join("resolv.conf")
+}
+
+/// Returns the path of the marker that records which namespace the daemon created under `name`.
+pub fn marker_path(name: &str) -> PathBuf {
+    PathBuf::from(MARKER_DIR).join(name)
+}
+
+/// Records that the daemon created the namespace referred to by `netns` under `name`.
+pub fn write_marker(name: &str, netns: &fs::File) -> io::Result<()> {
+    fs::create_dir_all(MARKER_DIR)?;
+    write_marker_file(&marker_path(name), netns)
+}
+
+/// Returns whether `netns` refers to the namespace that the daemon created under `name`. A
+/// namespace with the same name that was created by anyone else is rejected.
+pub fn is_created_by_daemon(name: &str, netns: &fs::File) -> io::Result<bool> {
+    check_marker_file(&marker_path(name), netns)
+}
+
+fn write_marker_file(path: &Path, netns: &fs::File) -> io::Result<()> {
+    let id = namespace_id(&netns.metadata()?);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o644);
+    io::Write::write_all(&mut options.open(path)?, id.as_bytes())
+}
+
+/// The marker must be a regular file that is owned by root and that only root may write to.
+fn check_marker_file(path: &Path, netns: &fs::File) -> io::Result<bool> {
+    let mut marker = match fs::File::open(path) {
+        Ok(marker) => marker,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    let metadata = marker.metadata()?;
+    if !metadata.is_file() || metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+        return Ok(false);
+    }
+    let mut contents = String::new();
+    io::Read::read_to_string(&mut marker, &mut contents)?;
+    Ok(contents == namespace_id(&netns.metadata()?))
+}
+
+/// Identifies a namespace by the device and inode of the file that refers to it.
+fn namespace_id(metadata: &fs::Metadata) -> String {
+    format!("{}:{}", metadata.dev(), metadata.ino())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_names() {
+        assert!(is_valid_name(DEFAULT_NETNS_NAME));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+    }
+    #[test]
+    fn test_marker() {
+        let dir = std::env::temp_dir().join(format!("talpid-netns-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let netns = fs::File::create(dir.join("netns")).unwrap();
+        let other_netns = fs::File::create(dir.join("other")).unwrap();
+        let marker = dir.join("marker");
+
+        assert!(!check_marker_file(&marker, &netns).unwrap());
+        write_marker_file(&marker, &netns).unwrap();
+        let is_root = fs::metadata(&marker).unwrap().uid() == 0;
+        assert_eq!(check_marker_file(&marker, &netns).unwrap(), is_root);
+        assert!(!check_marker_file(&marker, &other_netns).unwrap());
+
+        fs::set_permissions(&marker, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(!check_marker_file(&marker, &netns).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -14,6 +14,7 @@ INCLUDED_CRATES=(
     "mullvad-problem-report"
     "mullvad-setup"
     "mullvad-exclude"
+    "mullvad-netns"
     "talpid-openvpn-plugin"
 )
 MANIFESTS=( "${INCLUDED_CRATES[@]/%//Cargo.toml}" )